@@ -0,0 +1,12 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        // Use a bundled protoc so that building doesn't depend on one being
+        // installed.
+        std::env::set_var(
+            "PROTOC",
+            protoc_bin_vendored::protoc_bin_path().expect("protoc is bundled for this platform"),
+        );
+        tonic_build::compile_protos("proto/transactions.proto").expect("failed to compile protos");
+    }
+}
@@ -0,0 +1,85 @@
+/*
+ * C interface to the transactions engine, implemented by the
+ * transactions-ffi library. See ffi/src/lib.rs for details.
+ *
+ * An engine must only be used by one thread at a time.
+ */
+#ifndef TRANSACTIONS_H
+#define TRANSACTIONS_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct TxEngine TxEngine;
+
+/* Amounts are integer numbers of ten-thousandths, e.g. 15000 is 1.5. */
+typedef struct TxBalance {
+    uint64_t available;
+    uint64_t held;
+    uint64_t total;
+    bool locked;
+    /* Whether available and total are below zero; they're their magnitudes
+       either way. */
+    bool available_negative;
+    bool total_negative;
+} TxBalance;
+
+typedef enum TxStatus {
+    TX_OK = 0,
+    /* The transaction was rejected, e.g. for insufficient funds. */
+    TX_REJECTED = 1,
+    /* The record or currency code couldn't be parsed, or wasn't exactly one
+       record. */
+    TX_INVALID = 2,
+    /* No such client. */
+    TX_NOT_FOUND = 3,
+    /* A required pointer was null, or a string wasn't UTF-8. */
+    TX_BAD_ARGUMENT = 4,
+    /* Anything else, e.g. a failure to write the summary. */
+    TX_FAILED = 5,
+} TxStatus;
+
+/* An empty engine, kept in memory. Free it with tx_engine_free. */
+TxEngine *tx_engine_new(void);
+
+void tx_engine_free(TxEngine *engine);
+
+/*
+ * Process one CSV record, without a header, with the columns
+ * type,client,tx,amount,currency, e.g. "deposit,1,1,2.5".
+ */
+TxStatus tx_engine_submit(TxEngine *engine, const char *record);
+
+/*
+ * The balance of a client in a currency, or NULL for transactions without
+ * one, written to *balance.
+ */
+TxStatus tx_engine_balance(TxEngine *engine,
+                           uint16_t client,
+                           const char *currency,
+                           TxBalance *balance);
+
+/*
+ * The summary of every client as CSV, exactly as `transactions process`
+ * writes it, or NULL if it couldn't be written. Free it with
+ * tx_string_free.
+ */
+char *tx_engine_summary(TxEngine *engine);
+
+/*
+ * Why the last call on the engine failed, or NULL if it succeeded. Valid
+ * until the next call on the engine.
+ */
+const char *tx_engine_last_error(const TxEngine *engine);
+
+void tx_string_free(char *string);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,286 @@
+//! A C interface to the engine, so that other languages can embed exactly
+//! the same logic. The declarations are in `include/transactions.h`.
+//!
+//! Each function takes the engine by pointer, and reports failure with a
+//! `TxStatus`, leaving the reason in `tx_engine_last_error`.
+
+use std::ffi::{c_char, CStr, CString};
+use std::panic::AssertUnwindSafe;
+
+use transactions::clients::{Clients, ProcessError};
+use transactions::transaction::{parse_record, ClientId};
+use transactions::Currency;
+
+pub struct TxEngine {
+    clients: Clients,
+    last_error: Option<CString>,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TxBalance {
+    pub available: u64,
+    pub held: u64,
+    pub total: u64,
+    pub locked: bool,
+    // Whether `available` and `total` are below zero, if a policy let them
+    // go there; they're their magnitudes either way.
+    pub available_negative: bool,
+    pub total_negative: bool,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxStatus {
+    Ok = 0,
+    Rejected = 1,
+    Invalid = 2,
+    NotFound = 3,
+    BadArgument = 4,
+    Failed = 5,
+}
+
+impl TxEngine {
+    /// Note why the call failed, returning `status`.
+    fn fail(&mut self, status: TxStatus, error: impl std::fmt::Display) -> TxStatus {
+        // Messages never contain a nul, but don't panic if one does.
+        self.last_error = CString::new(error.to_string().replace('\0', " ")).ok();
+        status
+    }
+}
+
+/// The string at `s`, if it's not null and is UTF-8.
+///
+/// # Safety
+///
+/// `s` must be null or point to a nul-terminated string.
+unsafe fn str_arg<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok()
+}
+
+#[no_mangle]
+pub extern "C" fn tx_engine_new() -> *mut TxEngine {
+    Box::into_raw(Box::new(TxEngine {
+        clients: Clients::new(),
+        last_error: None,
+    }))
+}
+
+/// # Safety
+///
+/// `engine` must be null or have come from `tx_engine_new`, and not have
+/// been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn tx_engine_free(engine: *mut TxEngine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+/// # Safety
+///
+/// `engine` must have come from `tx_engine_new`, and `record` must be null or
+/// point to a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tx_engine_submit(
+    engine: *mut TxEngine,
+    record: *const c_char,
+) -> TxStatus {
+    let Some(engine) = engine.as_mut() else {
+        return TxStatus::BadArgument;
+    };
+    engine.last_error = None;
+    let Some(record) = str_arg(record) else {
+        return engine.fail(TxStatus::BadArgument, "record is null or not UTF-8");
+    };
+    // A panic mustn't unwind into the caller, which would abort it, so it's
+    // reported as a failure instead.
+    let clients = &mut engine.clients;
+    match std::panic::catch_unwind(AssertUnwindSafe(|| submit(clients, record))) {
+        Ok(Ok(())) => TxStatus::Ok,
+        Ok(Err((status, error))) => engine.fail(status, error),
+        Err(_) => engine.fail(TxStatus::Failed, "panicked processing the record"),
+    }
+}
+
+/// Parse and process `record`, returning the status and reason if it fails.
+fn submit(clients: &mut Clients, record: &str) -> Result<(), (TxStatus, String)> {
+    let transaction = parse_record(record).map_err(|e| (TxStatus::Invalid, e.to_string()))?;
+    match clients.process_transaction(transaction) {
+        Ok(_) => Ok(()),
+        Err(e @ ProcessError::Rejected(_)) => Err((TxStatus::Rejected, e.to_string())),
+        Err(e) => Err((TxStatus::Failed, e.to_string())),
+    }
+}
+
+/// # Safety
+///
+/// `engine` must have come from `tx_engine_new`, `currency` must be null or
+/// point to a nul-terminated string, and `balance` must be null or point to
+/// a `TxBalance`.
+#[no_mangle]
+pub unsafe extern "C" fn tx_engine_balance(
+    engine: *mut TxEngine,
+    client: u16,
+    currency: *const c_char,
+    balance: *mut TxBalance,
+) -> TxStatus {
+    let Some(engine) = engine.as_mut() else {
+        return TxStatus::BadArgument;
+    };
+    engine.last_error = None;
+    let Some(balance) = balance.as_mut() else {
+        return engine.fail(TxStatus::BadArgument, "balance is null");
+    };
+    let currency = if currency.is_null() {
+        None
+    } else {
+        match str_arg(currency).map(Currency::try_from) {
+            Some(Ok(currency)) => Some(currency),
+            Some(Err(e)) => return engine.fail(TxStatus::Invalid, e),
+            None => return engine.fail(TxStatus::BadArgument, "currency is not UTF-8"),
+        }
+    };
+    let client = match engine.clients.get(ClientId::from(client)) {
+        Ok(Some(client)) => client,
+        Ok(None) => return engine.fail(TxStatus::NotFound, "unknown client"),
+        Err(e) => return engine.fail(TxStatus::Failed, e),
+    };
+    *balance = TxBalance {
+        available: client.available(currency).magnitude().to_minor_units(),
+        held: client.held(currency).to_minor_units(),
+        total: client.total(currency).magnitude().to_minor_units(),
+        locked: client.locked(),
+        available_negative: client.available(currency).is_negative(),
+        total_negative: client.total(currency).is_negative(),
+    };
+    TxStatus::Ok
+}
+
+/// # Safety
+///
+/// `engine` must have come from `tx_engine_new`.
+#[no_mangle]
+pub unsafe extern "C" fn tx_engine_summary(engine: *mut TxEngine) -> *mut c_char {
+    let Some(engine) = engine.as_mut() else {
+        return std::ptr::null_mut();
+    };
+    engine.last_error = None;
+    let mut summary = Vec::new();
+    if let Err(e) = engine.clients.write(&mut summary) {
+        engine.fail(TxStatus::Failed, e);
+        return std::ptr::null_mut();
+    }
+    // CSV written from the clients never contains a nul.
+    CString::new(summary).map_or(std::ptr::null_mut(), CString::into_raw)
+}
+
+/// # Safety
+///
+/// `engine` must have come from `tx_engine_new`.
+#[no_mangle]
+pub unsafe extern "C" fn tx_engine_last_error(engine: *const TxEngine) -> *const c_char {
+    engine
+        .as_ref()
+        .and_then(|engine| engine.last_error.as_ref())
+        .map_or(std::ptr::null(), |error| error.as_ptr())
+}
+
+/// # Safety
+///
+/// `string` must be null or have come from this library, and not have been
+/// freed already.
+#[no_mangle]
+pub unsafe extern "C" fn tx_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn submit(engine: *mut TxEngine, record: &str) -> TxStatus {
+        let record = CString::new(record).unwrap();
+        unsafe { tx_engine_submit(engine, record.as_ptr()) }
+    }
+
+    fn last_error(engine: *mut TxEngine) -> Option<String> {
+        let error = unsafe { tx_engine_last_error(engine) };
+        (!error.is_null()).then(|| {
+            unsafe { CStr::from_ptr(error) }
+                .to_str()
+                .unwrap()
+                .to_string()
+        })
+    }
+
+    #[test]
+    fn test_engine() {
+        let engine = tx_engine_new();
+        assert_eq!(submit(engine, "deposit,1,1,2.5"), TxStatus::Ok);
+        assert_eq!(submit(engine, "deposit,1,2,1.0,GBP"), TxStatus::Ok);
+        assert_eq!(submit(engine, "withdrawal,1,3,5.0"), TxStatus::Rejected);
+        assert_eq!(last_error(engine).as_deref(), Some("insufficient funds"));
+        assert_eq!(submit(engine, "deposit,1,x"), TxStatus::Invalid);
+        assert_eq!(submit(engine, "dispute,1,1"), TxStatus::Ok);
+        assert_eq!(last_error(engine), None);
+        assert_eq!(
+            unsafe { tx_engine_submit(engine, std::ptr::null()) },
+            TxStatus::BadArgument
+        );
+
+        let mut balance = TxBalance::default();
+        let status = unsafe { tx_engine_balance(engine, 1, std::ptr::null(), &mut balance) };
+        assert_eq!(status, TxStatus::Ok);
+        assert_eq!(
+            balance,
+            TxBalance {
+                available: 0,
+                held: 25000,
+                total: 25000,
+                locked: false,
+                available_negative: false,
+                total_negative: false,
+            }
+        );
+        let gbp = CString::new("GBP").unwrap();
+        let status = unsafe { tx_engine_balance(engine, 1, gbp.as_ptr(), &mut balance) };
+        assert_eq!((status, balance.available), (TxStatus::Ok, 10000));
+        let status = unsafe { tx_engine_balance(engine, 2, gbp.as_ptr(), &mut balance) };
+        assert_eq!(status, TxStatus::NotFound);
+        let bad = CString::new("gbp").unwrap();
+        let status = unsafe { tx_engine_balance(engine, 1, bad.as_ptr(), &mut balance) };
+        assert_eq!(status, TxStatus::Invalid);
+
+        let summary = unsafe { tx_engine_summary(engine) };
+        assert_eq!(
+            unsafe { CStr::from_ptr(summary) }.to_str().unwrap(),
+            "client,currency,available,held,total,locked\n\
+             1,,0.0000,2.5000,2.5000,false\n\
+             1,GBP,1.0000,0.0000,1.0000,false\n"
+        );
+        unsafe {
+            tx_string_free(summary);
+            tx_engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn test_not_single_record() {
+        // Anything but one record is invalid, and leaves the engine as it was.
+        let engine = tx_engine_new();
+        for record in ["", "\n", "trailer,,0,0", "deposit,1,1,2.5\ndeposit,1,2,1.0"] {
+            assert_eq!(submit(engine, record), TxStatus::Invalid);
+            assert!(last_error(engine).is_some());
+        }
+        let mut balance = TxBalance::default();
+        let status = unsafe { tx_engine_balance(engine, 1, std::ptr::null(), &mut balance) };
+        assert_eq!(status, TxStatus::NotFound);
+        unsafe { tx_engine_free(engine) };
+    }
+}
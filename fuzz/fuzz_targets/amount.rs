@@ -0,0 +1,22 @@
+//! Parse arbitrary strings as amounts in every locale. Parsing should never
+//! panic, and any amount parsed should be written out as something that
+//! parses back to the same amount.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use transactions::amount::AmountLocale;
+use transactions::Amount;
+
+fuzz_target!(|s: &str| {
+    for locale in [
+        AmountLocale::Strict,
+        AmountLocale::DotDecimal,
+        AmountLocale::CommaDecimal,
+    ] {
+        if let Ok(amount) = Amount::parse(s, locale) {
+            let written = amount.to_string();
+            assert_eq!(Amount::try_from(written.as_str()), Ok(amount));
+        }
+    }
+});
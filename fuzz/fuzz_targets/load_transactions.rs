@@ -0,0 +1,18 @@
+//! Load arbitrary bytes as a CSV file of transactions, with and without the
+//! stricter options. Every record should be either a transaction or an
+//! error, never a panic.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use transactions::transaction::{load_transactions, load_transactions_with, LoadOptions};
+
+fuzz_target!(|data: &[u8]| {
+    for _ in load_transactions(data) {}
+    let options = LoadOptions {
+        strict_schema: true,
+        require_trailer: true,
+        ..LoadOptions::default()
+    };
+    for _ in load_transactions_with(data, options) {}
+});
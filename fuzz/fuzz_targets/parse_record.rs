@@ -0,0 +1,11 @@
+//! Parse arbitrary strings as single records, as received over the network.
+//! Every string should be either a transaction or an error, never a panic.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use transactions::transaction::parse_record;
+
+fuzz_target!(|s: &str| {
+    let _ = parse_record(s);
+});
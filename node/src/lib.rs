@@ -0,0 +1,138 @@
+//! Node.js bindings, so that Node services can run the engine in-process.
+//! Build with `npm run build`, which also writes the TypeScript
+//! declarations:
+//!
+//! ```js
+//! const { Engine } = require("transactions");
+//! const engine = new Engine();
+//! engine.submit("deposit,1,1,2.5"); // true
+//! engine.balance(1); // { available: "2.5000", held: "0.0000", ... }
+//! engine.summary(); // the summary as CSV
+//! ```
+
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+
+use transactions::clients::{Clients, ProcessError};
+use transactions::transaction::{parse_record, ClientId};
+use transactions::Currency;
+
+/// A client's balance in one currency. Amounts are decimal strings, so that
+/// they're never rounded through a JavaScript number.
+#[napi(object)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Balance {
+    pub available: String,
+    pub held: String,
+    pub total: String,
+    pub locked: bool,
+}
+
+/// A set of clients, kept in memory.
+#[napi]
+pub struct Engine {
+    clients: Clients,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[napi]
+impl Engine {
+    #[napi(constructor)]
+    pub fn new() -> Self {
+        Self {
+            clients: Clients::new(),
+        }
+    }
+
+    /// Process one CSV record, without a header, with the columns
+    /// `type,client,tx,amount,currency`, e.g. `"deposit,1,1,2.5"`. Returns
+    /// whether it was accepted, e.g. false for insufficient funds, and throws
+    /// if it can't be parsed.
+    #[napi]
+    pub fn submit(&mut self, record: String) -> Result<bool> {
+        let transaction = parse_record(&record).map_err(invalid_arg)?;
+        match self.clients.process_transaction(transaction) {
+            Ok(_) => Ok(true),
+            Err(ProcessError::Rejected(_)) => Ok(false),
+            Err(e) => Err(failure(e)),
+        }
+    }
+
+    /// The balance of `client` in `currency`, or in transactions without a
+    /// currency if it's not given, or null if there's no such client.
+    #[napi]
+    pub fn balance(&self, client: u16, currency: Option<String>) -> Result<Option<Balance>> {
+        let currency = currency
+            .map(|code| Currency::try_from(code.as_str()))
+            .transpose()
+            .map_err(invalid_arg)?;
+        let client = self.clients.get(ClientId::from(client)).map_err(failure)?;
+        Ok(client.map(|client| Balance {
+            available: client.available(currency).to_string(),
+            held: client.held(currency).to_string(),
+            total: client.total(currency).to_string(),
+            locked: client.locked(),
+        }))
+    }
+
+    /// The summary of every client as CSV, exactly as `transactions process`
+    /// writes it.
+    #[napi]
+    pub fn summary(&self) -> Result<String> {
+        let mut summary = Vec::new();
+        self.clients.write(&mut summary).map_err(failure)?;
+        Ok(String::from_utf8(summary).expect("summaries are UTF-8"))
+    }
+}
+
+fn invalid_arg(error: impl std::fmt::Display) -> Error {
+    Error::new(Status::InvalidArg, error.to_string())
+}
+
+fn failure(error: impl std::fmt::Display) -> Error {
+    Error::new(Status::GenericFailure, error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_engine() {
+        let mut engine = Engine::new();
+        assert!(engine.submit("deposit,1,1,2.5".into()).unwrap());
+        assert!(!engine.submit("withdrawal,1,2,5.0".into()).unwrap());
+        for record in [
+            "deposit,1,x",
+            "",
+            "trailer,,0,0",
+            "deposit,1,3,1\ndeposit,1,4,1",
+        ] {
+            assert_eq!(
+                engine.submit(record.into()).unwrap_err().status,
+                Status::InvalidArg
+            );
+        }
+        assert_eq!(
+            engine.balance(1, None).unwrap(),
+            Some(Balance {
+                available: "2.5000".into(),
+                held: "0.0000".into(),
+                total: "2.5000".into(),
+                locked: false,
+            })
+        );
+        assert_eq!(engine.balance(2, None).unwrap(), None);
+        assert!(engine.balance(1, Some("gbp".into())).is_err());
+        assert_eq!(
+            engine.summary().unwrap(),
+            "client,available,held,total,locked\n\
+             1,2.5000,0.0000,2.5000,false\n"
+        );
+    }
+}
@@ -0,0 +1,230 @@
+//! Counts and volumes of accepted transactions by the day or hour they
+//! happened, from the input's `timestamp` column, for spotting unusual
+//! spikes in a batch before it's signed off.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::amount::{AmountFormat, DisplayAmount};
+use crate::event::Event;
+use crate::snapshot::DepositSnapshot;
+use crate::{Amount, Currency, TransactionId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Period {
+    Day,
+    Hour,
+}
+
+impl Period {
+    fn seconds(self) -> u64 {
+        match self {
+            Period::Day => 24 * 60 * 60,
+            Period::Hour => 60 * 60,
+        }
+    }
+
+    /// The period starting at `start`, e.g. `2024-05-01` for a day or
+    /// `2024-05-01T09:00Z` for an hour, in UTC.
+    fn label(self, start: u64) -> String {
+        let Some(time) = i64::try_from(start)
+            .ok()
+            .and_then(|start| time::OffsetDateTime::from_unix_timestamp(start).ok())
+        else {
+            return start.to_string();
+        };
+        let date = format!(
+            "{:04}-{:02}-{:02}",
+            time.year(),
+            u8::from(time.month()),
+            time.day()
+        );
+        match self {
+            Period::Day => date,
+            Period::Hour => format!("{}T{:02}:00Z", date, time.hour()),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counts {
+    pub deposits: u64,
+    pub deposited: Amount,
+    pub withdrawals: u64,
+    pub withdrawn: Amount,
+    pub disputes: u64,
+    /// The sum of the deposits disputed.
+    pub disputed: Amount,
+}
+
+pub struct Activity {
+    period: Period,
+    // By the start of each period, with `None` for transactions without a
+    // timestamp, then by currency.
+    counts: BTreeMap<(Option<u64>, Option<Currency>), Counts>,
+    // Whether any transaction specified a currency, as for the summary.
+    multi_currency: bool,
+}
+
+impl Activity {
+    pub fn new(period: Period) -> Self {
+        Self {
+            period,
+            counts: BTreeMap::new(),
+            multi_currency: false,
+        }
+    }
+
+    /// Count the events caused by an accepted transaction at `timestamp`.
+    /// `find_deposit` looks up the deposit a dispute refers to, for its
+    /// amount and currency. Sums saturate rather than overflow, as they're
+    /// only a guide.
+    pub fn record<E>(
+        &mut self,
+        timestamp: Option<u64>,
+        events: &[Event],
+        find_deposit: impl Fn(TransactionId) -> Result<Option<DepositSnapshot>, E>,
+    ) -> Result<(), E> {
+        let start = timestamp.map(|timestamp| timestamp - timestamp % self.period.seconds());
+        for event in events {
+            match *event {
+                Event::FundsDeposited {
+                    currency, amount, ..
+                } => {
+                    self.multi_currency |= currency.is_some();
+                    let counts = self.counts.entry((start, currency)).or_default();
+                    counts.deposits += 1;
+                    counts.deposited = counts.deposited.saturating_add(amount);
+                }
+                Event::FundsWithdrawn {
+                    currency, amount, ..
+                } => {
+                    self.multi_currency |= currency.is_some();
+                    let counts = self.counts.entry((start, currency)).or_default();
+                    counts.withdrawals += 1;
+                    counts.withdrawn = counts.withdrawn.saturating_add(amount);
+                }
+                Event::DisputeOpened { transaction_id, .. } => {
+                    // The deposit is there, as the dispute was accepted.
+                    let Some(deposit) = find_deposit(transaction_id)? else {
+                        continue;
+                    };
+                    let counts = self.counts.entry((start, deposit.currency)).or_default();
+                    counts.disputes += 1;
+                    counts.disputed = counts.disputed.saturating_add(deposit.amount);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The counts for each period and currency, in order, with transactions
+    /// without a timestamp last.
+    pub fn counts(&self) -> impl Iterator<Item = (Option<u64>, Option<Currency>, &Counts)> {
+        let (untimed, timed): (Vec<_>, Vec<_>) = self
+            .counts
+            .iter()
+            .partition(|((start, _), _)| start.is_none());
+        timed
+            .into_iter()
+            .chain(untimed)
+            .map(|(&(start, currency), counts)| (start, currency, counts))
+    }
+
+    /// Write a CSV row for each period and currency with any activity.
+    /// Transactions without a timestamp are counted in a row with an empty
+    /// period.
+    pub fn write(
+        &self,
+        writer: impl std::io::Write,
+        format: AmountFormat,
+    ) -> Result<(), csv::Error> {
+        #[derive(Serialize)]
+        struct Row {
+            period: String,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            currency: Option<String>,
+            deposits: u64,
+            deposited: DisplayAmount,
+            withdrawals: u64,
+            withdrawn: DisplayAmount,
+            disputes: u64,
+            disputed: DisplayAmount,
+        }
+
+        let mut writer = csv::Writer::from_writer(writer);
+        for (start, currency, counts) in self.counts() {
+            writer.serialize(Row {
+                period: start
+                    .map(|start| self.period.label(start))
+                    .unwrap_or_default(),
+                currency: self
+                    .multi_currency
+                    .then(|| currency.map(|c| c.to_string()).unwrap_or_default()),
+                deposits: counts.deposits,
+                deposited: counts.deposited.display(format),
+                withdrawals: counts.withdrawals,
+                withdrawn: counts.withdrawn.display(format),
+                disputes: counts.disputes,
+                disputed: counts.disputed.display(format),
+            })?;
+        }
+        Ok(writer.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::Clients;
+    use crate::transaction::{LoadOptions, TransactionReader};
+
+    #[test]
+    fn test_activity() {
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,10.0,2024-05-01T09:15:00Z\n\
+                     deposit,1,2,5.0,2024-05-01T09:45:00Z\n\
+                     withdrawal,1,3,100.0,2024-05-01T09:50:00Z\n\
+                     withdrawal,1,4,1.0,2024-05-01T10:05:00Z\n\
+                     dispute,1,1,,2024-05-02T00:00:00Z\n\
+                     deposit,2,5,2.0,\n";
+        let run = |period| {
+            let mut reader =
+                TransactionReader::new(input.as_bytes(), LoadOptions::default()).unwrap();
+            let mut clients = Clients::new();
+            let mut activity = Activity::new(period);
+            while let Some(transaction) = reader.next() {
+                let transaction = transaction.unwrap();
+                let client = transaction.client_id;
+                // Rejected transactions aren't counted.
+                let Ok(events) = clients.process_transaction(transaction) else {
+                    continue;
+                };
+                activity
+                    .record(reader.timestamp(), &events, |tx| {
+                        clients.find_deposit(client, tx)
+                    })
+                    .unwrap();
+            }
+            let mut buf = Vec::new();
+            activity.write(&mut buf, AmountFormat::Trimmed).unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+        assert_eq!(
+            run(Period::Day),
+            "period,deposits,deposited,withdrawals,withdrawn,disputes,disputed\n\
+             2024-05-01,2,15,1,1,0,0\n\
+             2024-05-02,0,0,0,0,1,10\n\
+             ,1,2,0,0,0,0\n"
+        );
+        assert_eq!(
+            run(Period::Hour),
+            "period,deposits,deposited,withdrawals,withdrawn,disputes,disputed\n\
+             2024-05-01T09:00Z,2,15,0,0,0,0\n\
+             2024-05-01T10:00Z,0,0,1,1,0,0\n\
+             2024-05-02T00:00Z,0,0,0,0,1,10\n\
+             ,1,2,0,0,0,0\n"
+        );
+    }
+}
@@ -0,0 +1,231 @@
+//! A local admin interface over a Unix domain socket, so that operators can
+//! manage a long-running server without restarting it, e.g. with
+//! `socat - UNIX-CONNECT:admin.sock`.
+//!
+//! Each line is a command, and gets one or more lines in reply. Only local
+//! users with access to the socket file can connect, so there's no other
+//! authentication.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+use crate::clients::Clients;
+use crate::engine::Engine;
+use crate::transaction::ClientId;
+use crate::{Amount, Currency, SignedAmount};
+
+const HELP: &str = "\
+lock <client> [reason]
+                   lock a client's account, keeping the reason if given
+unlock <client>    unlock a client's account, however it was locked
+locks              list the locked clients, with the reasons given
+snapshot <path>    save a snapshot of every client to a file on the server
+stats              show the number of clients and the sum of their balances
+help               show this message
+";
+
+/// Listen on a socket at `path`, replacing any socket left there by an earlier
+/// run.
+pub fn bind(path: &Path) -> std::io::Result<UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+/// Accept connections on `listener` until it fails, handling each on a
+/// thread of its own.
+pub fn serve(listener: UnixListener, engine: Engine) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                tracing::warn!("failed to accept admin connection: {}", e);
+                continue;
+            }
+        };
+        let engine = engine.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = connection(stream, &engine) {
+                tracing::warn!("admin connection failed: {}", e);
+            }
+        });
+    }
+}
+
+fn connection(stream: UnixStream, engine: &Engine) -> std::io::Result<()> {
+    let mut output = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        tracing::info!("admin command: {}", line);
+        let reply = engine
+            .call(move |clients| run(clients, &line))
+            .map_err(std::io::Error::other)?;
+        output.write_all(reply.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Run one command, returning the reply.
+fn run(clients: &mut Clients, command: &str) -> String {
+    let words: Vec<&str> = command.split_whitespace().collect();
+    let reply = match words.as_slice() {
+        ["lock", id] => set_locked(clients, id, true),
+        ["lock", id, reason @ ..] => lock(clients, id, &reason.join(" ")),
+        ["unlock", id] => set_locked(clients, id, false),
+        ["snapshot", path] => match clients.save_snapshot(path) {
+            Ok(()) => "ok".to_string(),
+            Err(e) => format!("error: {}", e),
+        },
+        ["locks"] => locks(clients),
+        ["stats"] => stats(clients),
+        ["help"] => return HELP.to_string(),
+        _ => "unrecognised command (try 'help')".to_string(),
+    };
+    reply + "\n"
+}
+
+fn set_locked(clients: &mut Clients, id: &str, locked: bool) -> String {
+    let Ok(id) = id.parse::<u16>() else {
+        return format!("invalid client ID '{}'", id);
+    };
+    match clients.set_locked(ClientId::from(id), locked) {
+        Ok(true) => "ok".to_string(),
+        Ok(false) => format!("no client {}", id),
+        Err(e) => format!("error: {}", e),
+    }
+}
+
+fn lock(clients: &mut Clients, id: &str, reason: &str) -> String {
+    let Ok(id) = id.parse::<u16>() else {
+        return format!("invalid client ID '{}'", id);
+    };
+    match clients.lock(ClientId::from(id), reason) {
+        Ok(true) => "ok".to_string(),
+        Ok(false) => format!("no client {}", id),
+        Err(e) => format!("error: {}", e),
+    }
+}
+
+fn locks(clients: &Clients) -> String {
+    let snapshot = match clients.snapshot() {
+        Ok(snapshot) => snapshot,
+        Err(e) => return format!("error: {}", e),
+    };
+    let lines: Vec<String> = snapshot
+        .clients
+        .iter()
+        .filter(|c| c.locked || c.lock_reason.is_some())
+        .map(|c| match &c.lock_reason {
+            Some(reason) => format!("{}: {}", c.id, reason),
+            None => c.id.to_string(),
+        })
+        .collect();
+    if lines.is_empty() {
+        return "no clients locked".to_string();
+    }
+    lines.join("\n")
+}
+
+fn stats(clients: &Clients) -> String {
+    let snapshot = match clients.snapshot() {
+        Ok(snapshot) => snapshot,
+        Err(e) => return format!("error: {}", e),
+    };
+    let locked = snapshot
+        .clients
+        .iter()
+        .filter(|c| c.locked || c.lock_reason.is_some())
+        .count();
+    let mut lines = vec![
+        format!("clients: {}", snapshot.clients.len()),
+        format!("locked: {}", locked),
+    ];
+    // Sums of the balances in each currency, in a stable order.
+    let mut sums: std::collections::BTreeMap<Option<Currency>, (SignedAmount, Amount)> =
+        Default::default();
+    for balance in snapshot.clients.iter().flat_map(|c| &c.balances) {
+        let (available, held) = sums.entry(balance.currency).or_default();
+        *available = available.saturating_add(balance.available);
+        *held = held.saturating_add(balance.held);
+    }
+    for (currency, (available, held)) in sums {
+        lines.push(format!(
+            "{}available {}, held {}",
+            currency.map(|c| format!("{}: ", c)).unwrap_or_default(),
+            available,
+            held
+        ));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::parse_record;
+    use std::io::Read;
+
+    #[test]
+    fn test_commands() {
+        let mut clients = Clients::new();
+        for record in ["deposit,1,1,10.0", "deposit,2,2,5.0", "dispute,2,2"] {
+            clients
+                .process_transaction(parse_record(record).unwrap())
+                .unwrap();
+        }
+        assert_eq!(run(&mut clients, "lock 1"), "ok\n");
+        assert!(clients.get(ClientId::from(1)).unwrap().unwrap().locked());
+        assert_eq!(
+            run(&mut clients, "stats"),
+            "clients: 2\nlocked: 1\navailable 10.0000, held 5.0000\n"
+        );
+        assert_eq!(run(&mut clients, "lock 2 suspected fraud"), "ok\n");
+        assert_eq!(run(&mut clients, "locks"), "1\n2: suspected fraud\n");
+        assert_eq!(run(&mut clients, "unlock 1"), "ok\n");
+        assert_eq!(run(&mut clients, "unlock 2"), "ok\n");
+        assert!(!clients.get(ClientId::from(1)).unwrap().unwrap().locked());
+        assert_eq!(run(&mut clients, "locks"), "no clients locked\n");
+        assert_eq!(run(&mut clients, "lock 3"), "no client 3\n");
+        assert_eq!(run(&mut clients, "lock x"), "invalid client ID 'x'\n");
+        assert_eq!(
+            run(&mut clients, "frobnicate"),
+            "unrecognised command (try 'help')\n"
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot.json");
+        assert_eq!(
+            run(&mut clients, &format!("snapshot {}", path.display())),
+            "ok\n"
+        );
+        assert_eq!(
+            Clients::load_snapshot(&path).unwrap().snapshot().unwrap(),
+            clients.snapshot().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_serve() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("admin.sock");
+        // A socket left behind by an earlier run is replaced.
+        drop(bind(&path).unwrap());
+        let listener = bind(&path).unwrap();
+        let engine = Engine::start(None).unwrap();
+        std::thread::spawn(move || serve(listener, engine));
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream.write_all(b"stats\n\nlock 1\n").unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut replies = String::new();
+        stream.read_to_string(&mut replies).unwrap();
+        assert_eq!(replies, "clients: 0\nlocked: 0\nno client 1\n");
+    }
+}
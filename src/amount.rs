@@ -6,7 +6,7 @@ use serde::{Deserialize, Serialize};
 ///
 /// Can store values no greater than u64::MAX / 10000, that is,
 /// 1,844,674,407,370,955.1615.
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Amount(u64);
 
 // An alternative approach would be using a "BigInt", a variable size integer
@@ -15,6 +15,24 @@ pub struct Amount(u64);
 // This is more fun, though!
 
 impl Amount {
+    pub const ZERO: Amount = Amount(0);
+    pub const MAX: Amount = Amount(u64::MAX);
+
+    /// Create an amount from an integer number of minor units, i.e.
+    /// ten-thousandths. For example, 15000 is 1.5.
+    pub const fn from_minor_units(minor_units: u64) -> Amount {
+        Amount(minor_units)
+    }
+
+    /// The amount as an integer number of minor units, i.e. ten-thousandths.
+    pub const fn to_minor_units(self) -> u64 {
+        self.0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == Amount::ZERO
+    }
+
     pub fn checked_add(self, other: Amount) -> Option<Amount> {
         self.0.checked_add(other.0).map(Amount)
     }
@@ -22,6 +40,63 @@ impl Amount {
     pub fn checked_sub(self, other: Amount) -> Option<Amount> {
         self.0.checked_sub(other.0).map(Amount)
     }
+
+    /// Multiply by an integer factor, e.g. to scale a limit.
+    pub fn checked_mul(self, factor: u64) -> Option<Amount> {
+        self.0.checked_mul(factor).map(Amount)
+    }
+
+    pub fn saturating_add(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_add(other.0))
+    }
+
+    pub fn saturating_sub(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_sub(other.0))
+    }
+}
+
+// Like the primitive integer types, the arithmetic operators panic on overflow,
+// so they're only appropriate where overflow would be a bug. Use the checked
+// methods where it's a possibility.
+
+impl std::ops::Add for Amount {
+    type Output = Amount;
+
+    fn add(self, other: Amount) -> Amount {
+        self.checked_add(other).expect("amount overflow")
+    }
+}
+
+impl std::ops::AddAssign for Amount {
+    fn add_assign(&mut self, other: Amount) {
+        *self = *self + other;
+    }
+}
+
+impl std::ops::Sub for Amount {
+    type Output = Amount;
+
+    fn sub(self, other: Amount) -> Amount {
+        self.checked_sub(other).expect("amount underflow")
+    }
+}
+
+impl std::ops::SubAssign for Amount {
+    fn sub_assign(&mut self, other: Amount) {
+        *self = *self - other;
+    }
+}
+
+impl std::iter::Sum for Amount {
+    fn sum<I: Iterator<Item = Amount>>(iter: I) -> Amount {
+        iter.fold(Amount::ZERO, |acc, x| acc + x)
+    }
+}
+
+impl<'a> std::iter::Sum<&'a Amount> for Amount {
+    fn sum<I: Iterator<Item = &'a Amount>>(iter: I) -> Amount {
+        iter.copied().sum()
+    }
 }
 
 impl std::fmt::Display for Amount {
@@ -33,6 +108,118 @@ impl std::fmt::Display for Amount {
     }
 }
 
+/// How to write out an amount.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AmountFormat {
+    /// All four decimal digits, e.g. `2.5000`.
+    #[default]
+    Full,
+    /// Without trailing zero decimal digits, e.g. `2.5`, or `2` if there are
+    /// none left.
+    Trimmed,
+    /// Exactly this many decimal digits, at most four, e.g. `2.50`. Rounds
+    /// half up if digits have to be dropped.
+    Fixed(u8),
+    /// An integer number of minor units, i.e. ten-thousandths, e.g. `25000`.
+    MinorUnits,
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum AmountFormatParseError {
+    #[error("expected 'full', 'trimmed', 'minor-units', or 'fixed:N' with N at most 4")]
+    InvalidFormat,
+}
+
+impl std::str::FromStr for AmountFormat {
+    type Err = AmountFormatParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(AmountFormat::Full),
+            "trimmed" => Ok(AmountFormat::Trimmed),
+            "minor-units" => Ok(AmountFormat::MinorUnits),
+            _ => s
+                .strip_prefix("fixed:")
+                .and_then(|n| n.parse::<u8>().ok())
+                .filter(|n| *n <= 4)
+                .map(AmountFormat::Fixed)
+                .ok_or(AmountFormatParseError::InvalidFormat),
+        }
+    }
+}
+
+impl Amount {
+    /// Display the amount in the given format. The plain `Display`
+    /// implementation uses `AmountFormat::Full`.
+    pub fn display(self, format: AmountFormat) -> DisplayAmount {
+        DisplayAmount {
+            negative: false,
+            amount: self,
+            format,
+        }
+    }
+}
+
+/// An amount together with the format to display it in.
+#[derive(Debug, Clone, Copy)]
+pub struct DisplayAmount {
+    // Whether it's the magnitude of a negative balance.
+    negative: bool,
+    amount: Amount,
+    format: AmountFormat,
+}
+
+impl std::fmt::Display for DisplayAmount {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.negative {
+            f.write_str("-")?;
+        }
+        let value = self.amount.0;
+        match self.format {
+            AmountFormat::Full => self.amount.fmt(f),
+            AmountFormat::Trimmed => {
+                let decimal = format!("{:0>4}", value % 10000);
+                let decimal = decimal.trim_end_matches('0');
+                if decimal.is_empty() {
+                    write!(f, "{}", value / 10000)
+                } else {
+                    write!(f, "{}.{}", value / 10000, decimal)
+                }
+            }
+            AmountFormat::Fixed(digits) => {
+                assert!(digits <= 4);
+                // Round in u128 so that rounding Amount::MAX up can't overflow.
+                // Negative balances are rounded by magnitude, so halves are
+                // rounded away from zero.
+                let divisor = 10u128.pow(4 - digits as u32);
+                let rounded = (value as u128 + divisor / 2) / divisor;
+                let scale = 10u128.pow(digits as u32);
+                if digits == 0 {
+                    write!(f, "{}", rounded)
+                } else {
+                    write!(
+                        f,
+                        "{}.{:0>width$}",
+                        rounded / scale,
+                        rounded % scale,
+                        width = digits as usize
+                    )
+                }
+            }
+            AmountFormat::MinorUnits => write!(f, "{}", value),
+        }
+    }
+}
+
+impl Serialize for DisplayAmount {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.collect_str(&self)
+    }
+}
+
 impl Serialize for Amount {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -82,6 +269,83 @@ impl TryFrom<&str> for Amount {
     }
 }
 
+/// How amounts are written in an input file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum AmountLocale {
+    /// Digits with an optional `.` and up to four decimal digits, e.g.
+    /// `1234.5678`.
+    #[default]
+    Strict,
+    /// As `Strict`, but also accepting `,` thousands separators, e.g.
+    /// `1,234.5678`.
+    DotDecimal,
+    /// A `,` decimal separator, optionally with `.` or space thousands
+    /// separators, e.g. `1.234,5678`.
+    CommaDecimal,
+}
+
+/// What the amounts in an input file count.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum AmountUnit {
+    /// Whole units, with up to four decimal digits, written according to the
+    /// `AmountLocale`.
+    #[default]
+    Major,
+    /// An integer number of minor units, i.e. ten-thousandths, e.g. `15000`
+    /// for 1.5, as some systems export them.
+    Minor,
+}
+
+impl Amount {
+    /// Parse an amount written according to `locale`.
+    pub fn parse(s: &str, locale: AmountLocale) -> Result<Amount, AmountParseError> {
+        static DOT_DECIMAL: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r"^(\d{1,3}(?:,\d{3})+)((?:\.\d{1,4})?)$").unwrap());
+        static COMMA_DECIMAL: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(r"^(\d+|\d{1,3}(?:\.\d{3})+|\d{1,3}(?: \d{3})+)(?:,(\d{1,4}))?$").unwrap()
+        });
+
+        // Rewrite the amount in the strict format and parse that, so the
+        // locales only differ in which strings they accept.
+        match locale {
+            AmountLocale::Strict => s.try_into(),
+            AmountLocale::DotDecimal => match DOT_DECIMAL.captures(s) {
+                Some(captures) => {
+                    let integer = captures.get(1).unwrap().as_str().replace(',', "");
+                    format!("{}{}", integer, captures.get(2).unwrap().as_str())
+                        .as_str()
+                        .try_into()
+                }
+                // Amounts without thousands separators are also accepted.
+                None => s.try_into(),
+            },
+            AmountLocale::CommaDecimal => {
+                let captures = COMMA_DECIMAL
+                    .captures(s)
+                    .ok_or(AmountParseError::InvalidFormat)?;
+                let mut strict = captures.get(1).unwrap().as_str().replace(['.', ' '], "");
+                if let Some(decimal) = captures.get(2) {
+                    strict.push('.');
+                    strict.push_str(decimal.as_str());
+                }
+                strict.as_str().try_into()
+            }
+        }
+    }
+
+    /// Parse an integer number of minor units, e.g. `15000` for 1.5. Only
+    /// digits are accepted, without any decimal parsing.
+    pub fn parse_minor_units(s: &str) -> Result<Amount, AmountParseError> {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(AmountParseError::InvalidFormat);
+        }
+        // Only digits, so it can only fail by overflowing.
+        s.parse()
+            .map(Amount)
+            .map_err(|_| AmountParseError::TooLarge)
+    }
+}
+
 /// Parse an up to four digit fractional part into a u64 between 0 and 9999.
 /// For example, parse "1" into 1000, "123" into 1230, and "1234" into 1234.
 fn parse_decimal_part(s: &str) -> u64 {
@@ -96,8 +360,196 @@ impl<'de> Deserialize<'de> for Amount {
     where
         D: serde::de::Deserializer<'de>,
     {
-        let s: &str = Deserialize::deserialize(deserializer)?;
-        s.try_into().map_err(serde::de::Error::custom)
+        // Use a visitor rather than deserializing a `&str` so that owned
+        // strings are accepted too, e.g. when reading from an `io::Read`.
+        struct Visitor;
+
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = Amount;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a decimal amount")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, s: &str) -> Result<Amount, E> {
+                s.try_into().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
+    }
+}
+
+/// A balance, which unlike an `Amount` can be negative, if a `Policy` lets it
+/// go below zero. It can be as large as any `Amount`, or as far below zero.
+///
+/// [`Policy`]: crate::policy::Policy
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SignedAmount(i128);
+
+impl SignedAmount {
+    pub const ZERO: SignedAmount = SignedAmount(0);
+    pub const MAX: SignedAmount = SignedAmount(u64::MAX as i128);
+    pub const MIN: SignedAmount = SignedAmount(-(u64::MAX as i128));
+
+    /// The balance as an integer number of minor units, i.e. ten-thousandths.
+    pub const fn to_minor_units(self) -> i128 {
+        self.0
+    }
+
+    /// Create a balance from an integer number of minor units, unless it's
+    /// further from zero than any `Amount`.
+    pub fn from_minor_units(minor_units: i128) -> Option<SignedAmount> {
+        Some(SignedAmount(minor_units)).filter(|balance| (Self::MIN..=Self::MAX).contains(balance))
+    }
+
+    pub const fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == SignedAmount::ZERO
+    }
+
+    /// How far the balance is from zero, in either direction.
+    pub fn magnitude(self) -> Amount {
+        // Can't fail, as the balance is between MIN and MAX.
+        Amount(self.0.unsigned_abs() as u64)
+    }
+
+    /// The balance as an `Amount`, unless it's negative.
+    pub fn to_amount(self) -> Option<Amount> {
+        (!self.is_negative()).then(|| self.magnitude())
+    }
+
+    pub fn checked_add(self, other: SignedAmount) -> Option<SignedAmount> {
+        SignedAmount::from_minor_units(self.0 + other.0)
+    }
+
+    pub fn saturating_add(self, other: SignedAmount) -> SignedAmount {
+        SignedAmount((self.0 + other.0).clamp(Self::MIN.0, Self::MAX.0))
+    }
+
+    pub fn saturating_sub(self, other: SignedAmount) -> SignedAmount {
+        self.saturating_add(-other)
+    }
+
+    /// Subtract `amount`, unless that would take the balance more than
+    /// `overdraft` below zero.
+    pub fn checked_sub_overdrawn(self, amount: Amount, overdraft: Amount) -> Option<SignedAmount> {
+        Some(SignedAmount(self.0 - amount.0 as i128))
+            .filter(|balance| balance.0 >= -(overdraft.0 as i128))
+    }
+
+    /// Display the balance in the given format, with a `-` first if it's
+    /// negative.
+    pub fn display(self, format: AmountFormat) -> DisplayAmount {
+        DisplayAmount {
+            negative: self.is_negative(),
+            amount: self.magnitude(),
+            format,
+        }
+    }
+}
+
+impl From<Amount> for SignedAmount {
+    fn from(amount: Amount) -> SignedAmount {
+        SignedAmount(amount.0 as i128)
+    }
+}
+
+// As for `Amount`, the operators panic where the checked methods would fail.
+
+impl std::ops::Add for SignedAmount {
+    type Output = SignedAmount;
+
+    fn add(self, other: SignedAmount) -> SignedAmount {
+        SignedAmount::from_minor_units(self.0 + other.0).expect("balance overflow")
+    }
+}
+
+impl std::ops::Sub for SignedAmount {
+    type Output = SignedAmount;
+
+    fn sub(self, other: SignedAmount) -> SignedAmount {
+        self + -other
+    }
+}
+
+impl std::ops::Neg for SignedAmount {
+    type Output = SignedAmount;
+
+    fn neg(self) -> SignedAmount {
+        // Can't overflow, as MIN is -MAX.
+        SignedAmount(-self.0)
+    }
+}
+
+impl std::ops::AddAssign<Amount> for SignedAmount {
+    fn add_assign(&mut self, amount: Amount) {
+        *self = *self + amount.into();
+    }
+}
+
+impl std::ops::SubAssign<Amount> for SignedAmount {
+    fn sub_assign(&mut self, amount: Amount) {
+        *self = *self - amount.into();
+    }
+}
+
+impl std::iter::Sum for SignedAmount {
+    fn sum<I: Iterator<Item = SignedAmount>>(iter: I) -> SignedAmount {
+        iter.fold(SignedAmount::ZERO, |acc, x| acc + x)
+    }
+}
+
+impl std::fmt::Display for SignedAmount {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.display(AmountFormat::Full).fmt(f)
+    }
+}
+
+impl Serialize for SignedAmount {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.collect_str(&self)
+    }
+}
+
+impl TryFrom<&str> for SignedAmount {
+    type Error = AmountParseError;
+
+    /// Parse an amount as for `Amount`, optionally with a leading `-`.
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s.strip_prefix('-') {
+            Some(magnitude) => Ok(-SignedAmount::from(Amount::try_from(magnitude)?)),
+            None => Ok(Amount::try_from(s)?.into()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SignedAmount {
+    fn deserialize<D>(deserializer: D) -> Result<SignedAmount, D::Error>
+    where
+        D: serde::de::Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = SignedAmount;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a decimal balance")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, s: &str) -> Result<SignedAmount, E> {
+                s.try_into().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
     }
 }
 
@@ -147,7 +599,29 @@ mod tests {
     fn test_max_value() {
         let mut s = u64::MAX.to_string();
         s.insert(s.len() - 4, '.');
-        Amount::try_from(s.as_str()).unwrap();
+        assert_eq!(Amount::try_from(s.as_str()).unwrap(), Amount::MAX);
+        assert_eq!(Amount::MAX.to_string(), s);
+    }
+
+    #[test]
+    fn test_minor_units() {
+        assert_eq!(Amount::from_minor_units(15000), amount("1.5"));
+        assert_eq!(amount("1234.5678").to_minor_units(), 12345678);
+        assert_eq!(Amount::from_minor_units(0), Amount::ZERO);
+        assert_eq!(Amount::ZERO, Amount::default());
+
+        assert_eq!(Amount::parse_minor_units("15000"), Ok(amount("1.5")));
+        assert_eq!(Amount::parse_minor_units("007"), Ok(amount("0.0007")));
+        for s in ["", "1.5", "-1", "+1", "1,000", " 1"] {
+            assert_eq!(
+                Amount::parse_minor_units(s),
+                Err(AmountParseError::InvalidFormat)
+            );
+        }
+        assert_eq!(
+            Amount::parse_minor_units(&(u64::MAX as u128 + 1).to_string()),
+            Err(AmountParseError::TooLarge)
+        );
     }
 
     #[test]
@@ -167,4 +641,187 @@ mod tests {
     fn test_parse_decimal_part(s: &str, expected: u64) {
         assert_eq!(parse_decimal_part(s), expected);
     }
+
+    fn amount(s: &str) -> Amount {
+        Amount::try_from(s).unwrap()
+    }
+
+    #[test]
+    fn test_operators() {
+        let mut x = amount("1.5") + amount("2.25");
+        assert_eq!(x, amount("3.75"));
+        x -= amount("0.75");
+        assert_eq!(x, amount("3.0"));
+        x += amount("1.0");
+        assert_eq!(x - amount("4.0"), Amount::ZERO);
+    }
+
+    #[test]
+    #[should_panic(expected = "amount overflow")]
+    fn test_add_overflow() {
+        let _ = Amount::MAX + amount("0.0001");
+    }
+
+    #[test]
+    #[should_panic(expected = "amount underflow")]
+    fn test_sub_underflow() {
+        let _ = amount("1.0") - amount("2.0");
+    }
+
+    #[test]
+    fn test_sum() {
+        let amounts = [amount("1.0"), amount("2.5"), amount("0.0001")];
+        assert_eq!(amounts.iter().sum::<Amount>(), amount("3.5001"));
+        assert_eq!(amounts.into_iter().sum::<Amount>(), amount("3.5001"));
+        assert_eq!(std::iter::empty::<Amount>().sum::<Amount>(), Amount::ZERO);
+    }
+
+    #[test]
+    fn test_checked_mul() {
+        assert_eq!(amount("1.25").checked_mul(3), Some(amount("3.75")));
+        assert_eq!(Amount::MAX.checked_mul(2), None);
+    }
+
+    #[test]
+    fn test_saturating() {
+        assert_eq!(amount("1.0").saturating_sub(amount("2.0")), Amount::ZERO);
+        assert_eq!(Amount::MAX.saturating_add(amount("1.0")), Amount::MAX);
+        assert_eq!(amount("2.0").saturating_sub(amount("0.5")), amount("1.5"));
+    }
+
+    #[test_case("1,234.5678", "1234.5678")]
+    #[test_case("1,234", "1234")]
+    #[test_case("12,345,678.9", "12345678.9")]
+    #[test_case("1234.5", "1234.5")]
+    fn test_parse_dot_decimal(s: &str, expected: &str) {
+        assert_eq!(
+            Amount::parse(s, AmountLocale::DotDecimal),
+            Ok(amount(expected))
+        );
+    }
+
+    #[test_case("1234,5678", "1234.5678")]
+    #[test_case("1.234,56", "1234.56")]
+    #[test_case("1 234 567,8", "1234567.8")]
+    #[test_case("1.234", "1234")]
+    #[test_case("12", "12")]
+    fn test_parse_comma_decimal(s: &str, expected: &str) {
+        assert_eq!(
+            Amount::parse(s, AmountLocale::CommaDecimal),
+            Ok(amount(expected))
+        );
+    }
+
+    #[test_case("1,234.5", AmountLocale::Strict; "strict with separator")]
+    #[test_case("1,23.5", AmountLocale::DotDecimal; "dot misplaced separator")]
+    #[test_case("1234,5", AmountLocale::DotDecimal; "dot with comma decimal")]
+    #[test_case("1.234.5", AmountLocale::CommaDecimal; "comma misplaced separator")]
+    #[test_case("1.234 567", AmountLocale::CommaDecimal; "comma mixed separators")]
+    #[test_case("1,23456", AmountLocale::CommaDecimal; "comma too many decimal digits")]
+    fn test_parse_invalid_format(s: &str, locale: AmountLocale) {
+        assert_eq!(
+            Amount::parse(s, locale),
+            Err(AmountParseError::InvalidFormat)
+        );
+    }
+
+    #[test_case("2.5", AmountFormat::Full, "2.5000")]
+    #[test_case("2.5", AmountFormat::Trimmed, "2.5")]
+    #[test_case("2.0", AmountFormat::Trimmed, "2")]
+    #[test_case("0.0", AmountFormat::Trimmed, "0")]
+    #[test_case("2.0001", AmountFormat::Trimmed, "2.0001")]
+    #[test_case("2.5", AmountFormat::Fixed(2), "2.50")]
+    #[test_case("2.125", AmountFormat::Fixed(2), "2.13")]
+    #[test_case("2.1249", AmountFormat::Fixed(2), "2.12")]
+    #[test_case("9.9999", AmountFormat::Fixed(3), "10.000")]
+    #[test_case("2.5", AmountFormat::Fixed(0), "3")]
+    #[test_case("2.5", AmountFormat::Fixed(4), "2.5000")]
+    #[test_case("2.5", AmountFormat::MinorUnits, "25000")]
+    fn test_display_format(s: &str, format: AmountFormat, expected: &str) {
+        assert_eq!(amount(s).display(format).to_string(), expected);
+    }
+
+    #[test]
+    fn test_display_fixed_max() {
+        // Rounding up the largest amount mustn't overflow.
+        assert_eq!(
+            Amount::MAX.display(AmountFormat::Fixed(0)).to_string(),
+            "1844674407370955"
+        );
+    }
+
+    #[test_case("full", Ok(AmountFormat::Full))]
+    #[test_case("trimmed", Ok(AmountFormat::Trimmed))]
+    #[test_case("minor-units", Ok(AmountFormat::MinorUnits))]
+    #[test_case("fixed:2", Ok(AmountFormat::Fixed(2)))]
+    #[test_case("fixed:5", Err(AmountFormatParseError::InvalidFormat))]
+    #[test_case("fixed", Err(AmountFormatParseError::InvalidFormat))]
+    fn test_parse_amount_format(s: &str, expected: Result<AmountFormat, AmountFormatParseError>) {
+        assert_eq!(s.parse::<AmountFormat>(), expected);
+    }
+
+    fn balance(s: &str) -> SignedAmount {
+        SignedAmount::try_from(s).unwrap()
+    }
+
+    #[test_case("-2.5", AmountFormat::Full, "-2.5000")]
+    #[test_case("-2.5", AmountFormat::Trimmed, "-2.5")]
+    #[test_case("-2.125", AmountFormat::Fixed(2), "-2.13")]
+    #[test_case("-2.5", AmountFormat::MinorUnits, "-25000")]
+    #[test_case("-0.0", AmountFormat::Full, "0.0000")]
+    fn test_display_signed(s: &str, format: AmountFormat, expected: &str) {
+        assert_eq!(balance(s).display(format).to_string(), expected);
+    }
+
+    #[test]
+    fn test_signed_range() {
+        // Balances go as far below zero as any amount goes above it.
+        assert_eq!(SignedAmount::from(Amount::MAX), SignedAmount::MAX);
+        assert_eq!(-SignedAmount::MAX, SignedAmount::MIN);
+        assert_eq!(SignedAmount::MIN.magnitude(), Amount::MAX);
+        assert_eq!(balance("-1844674407370955.1615"), SignedAmount::MIN);
+        assert_eq!(
+            SignedAmount::try_from("-1844674407370955.1616"),
+            Err(AmountParseError::TooLarge)
+        );
+        assert_eq!(SignedAmount::MAX.checked_add(balance("0.0001")), None);
+        assert_eq!(
+            SignedAmount::from_minor_units(i128::from(u64::MAX) + 1),
+            None
+        );
+        assert_eq!(balance("-1").to_amount(), None);
+        assert_eq!(balance("1").to_amount(), Some(amount("1")));
+    }
+
+    #[test]
+    fn test_checked_sub_overdrawn() {
+        let overdraft = amount("5");
+        assert_eq!(
+            balance("2").checked_sub_overdrawn(amount("7"), overdraft),
+            Some(balance("-5"))
+        );
+        assert_eq!(
+            balance("2").checked_sub_overdrawn(amount("7.0001"), overdraft),
+            None
+        );
+        assert_eq!(
+            balance("2").checked_sub_overdrawn(amount("3"), Amount::ZERO),
+            None
+        );
+        assert_eq!(
+            SignedAmount::ZERO.checked_sub_overdrawn(Amount::MAX, Amount::MAX),
+            Some(SignedAmount::MIN)
+        );
+    }
+
+    #[test]
+    fn test_signed_serde() {
+        let json = serde_json::to_string(&balance("-8")).unwrap();
+        assert_eq!(json, "\"-8.0000\"");
+        assert_eq!(
+            serde_json::from_str::<SignedAmount>(&json).unwrap(),
+            balance("-8")
+        );
+        assert!(serde_json::from_str::<Amount>(&json).is_err());
+    }
 }
@@ -0,0 +1,268 @@
+//! An append-only audit log of accepted transactions and the balances they
+//! resulted in.
+//!
+//! Each record includes the hash of the record before it, and its own hash,
+//! so changing, removing, or reordering any record breaks the chain from that
+//! point on. The log is JSON lines, one record per line.
+
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use crate::client::Client;
+use crate::transaction::{ClientId, Transaction};
+use crate::{Amount, Currency, SignedAmount, TransactionId};
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuditError {
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("malformed audit record at line {line}: {error}")]
+    Malformed { line: u64, error: serde_json::Error },
+    // The record doesn't follow on from the one before it, or its hash is
+    // wrong: the log has been modified.
+    #[error("audit log chain broken at line {0}")]
+    Broken(u64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    // Numbered from 1.
+    pub seq: u64,
+    pub client: ClientId,
+    pub tx: TransactionId,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub currency: Option<Currency>,
+    pub amount: Option<Amount>,
+    // The client's balance in `currency` after the transaction.
+    pub available: SignedAmount,
+    pub held: Amount,
+    pub total: SignedAmount,
+    // The transaction's memo, if it had one. Left out otherwise, so that
+    // records from before memos were kept still hash the same.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+    // Hex hash of the previous record, or all zeroes for the first one.
+    pub prev_hash: String,
+}
+
+impl AuditRecord {
+    fn hash(&self) -> blake3::Hash {
+        // Serializing a record always gives the same bytes, so this can be
+        // recomputed from a record read back from the log.
+        blake3::hash(&serde_json::to_vec(self).expect("records always serialize"))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    #[serde(flatten)]
+    record: AuditRecord,
+    hash: String,
+}
+
+pub struct AuditLog {
+    file: std::io::BufWriter<std::fs::File>,
+    seq: u64,
+    last_hash: blake3::Hash,
+}
+
+impl AuditLog {
+    /// Open the log at `path` to append to it, creating it if it doesn't
+    /// exist. An existing log is verified first, so that new records carry on
+    /// its chain.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, AuditError> {
+        let path = path.as_ref();
+        let (seq, last_hash) = match std::fs::File::open(path) {
+            Ok(file) => verify(file)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (0, genesis()),
+            Err(e) => return Err(e.into()),
+        };
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self {
+            file: std::io::BufWriter::new(file),
+            seq,
+            last_hash,
+        })
+    }
+
+    /// Record an accepted transaction and its memo, if any, with `client`'s
+    /// state after it.
+    pub fn append(
+        &mut self,
+        transaction: &Transaction,
+        memo: Option<&str>,
+        client: &Client,
+    ) -> Result<(), AuditError> {
+        let currency = transaction.currency;
+        let record = AuditRecord {
+            seq: self.seq + 1,
+            client: transaction.client_id,
+            tx: transaction.data.transaction_id(),
+            type_: transaction.data.name().to_string(),
+            currency,
+            amount: transaction.data.amount(),
+            available: client.available(currency),
+            held: client.held(currency),
+            total: client.total(currency),
+            memo: memo.map(str::to_string),
+            prev_hash: self.last_hash.to_hex().to_string(),
+        };
+        let hash = record.hash();
+        let entry = Entry {
+            record,
+            hash: hash.to_hex().to_string(),
+        };
+        serde_json::to_writer(&mut self.file, &entry).map_err(std::io::Error::from)?;
+        self.file.write_all(b"\n")?;
+        self.seq += 1;
+        self.last_hash = hash;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), AuditError> {
+        self.file.flush()?;
+        Ok(self.file.get_ref().sync_data()?)
+    }
+}
+
+fn genesis() -> blake3::Hash {
+    blake3::Hash::from_bytes([0; blake3::OUT_LEN])
+}
+
+/// Check the chain of records in a log, returning the number of records and
+/// the hash of the last one.
+pub fn verify(reader: impl std::io::Read) -> Result<(u64, blake3::Hash), AuditError> {
+    let mut seq = 0;
+    let mut last_hash = genesis();
+    for (line, text) in (1..).zip(std::io::BufReader::new(reader).lines()) {
+        let entry: Entry =
+            serde_json::from_str(&text?).map_err(|error| AuditError::Malformed { line, error })?;
+        let hash = entry.record.hash();
+        if entry.record.seq != seq + 1
+            || entry.record.prev_hash != last_hash.to_hex().as_str()
+            || entry.hash != hash.to_hex().as_str()
+        {
+            return Err(AuditError::Broken(line));
+        }
+        seq += 1;
+        last_hash = hash;
+    }
+    Ok((seq, last_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::Clients;
+    use crate::transaction::TransactionReader;
+
+    fn log(path: &Path, input: &str) {
+        let mut clients = Clients::new();
+        let mut audit = AuditLog::open(path).unwrap();
+        let mut reader = TransactionReader::new(input.as_bytes(), Default::default()).unwrap();
+        while let Some(transaction) = reader.next() {
+            let transaction = transaction.unwrap();
+            if clients.process_transaction(transaction.clone()).is_ok() {
+                let client = clients.get(transaction.client_id).unwrap().unwrap();
+                audit.append(&transaction, reader.memo(), &client).unwrap();
+            }
+        }
+        audit.flush().unwrap();
+    }
+
+    #[test]
+    fn test_log_and_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        log(
+            &path,
+            "type, client, tx, amount\ndeposit, 1, 1, 2.0\nwithdrawal, 1, 2, 5.0\ndispute, 1, 1\n",
+        );
+        // The chain carries on across runs.
+        log(&path, "type, client, tx, amount\ndeposit, 2, 3, 1.0\n");
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let records: Vec<AuditRecord> = text
+            .lines()
+            .map(|line| serde_json::from_str::<Entry>(line).unwrap().record)
+            .collect();
+        // The rejected withdrawal isn't recorded.
+        assert_eq!(
+            records
+                .iter()
+                .map(|r| (r.seq, r.type_.as_str(), r.available, r.held))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    1,
+                    "deposit",
+                    SignedAmount::try_from("2").unwrap(),
+                    Amount::ZERO
+                ),
+                (
+                    2,
+                    "dispute",
+                    SignedAmount::ZERO,
+                    Amount::try_from("2").unwrap()
+                ),
+                (
+                    3,
+                    "deposit",
+                    SignedAmount::try_from("1").unwrap(),
+                    Amount::ZERO
+                ),
+            ]
+        );
+        assert_eq!(verify(text.as_bytes()).unwrap().0, 3);
+    }
+
+    #[test]
+    fn test_memo() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        log(&path, "type, client, tx, amount\ndeposit, 1, 1, 2.0\n");
+        log(
+            &path,
+            "type, client, tx, amount, memo\ndeposit, 1, 2, 1.0,\"Invoice 42, paid\"\n",
+        );
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let memos: Vec<_> = text
+            .lines()
+            .map(|line| serde_json::from_str::<Entry>(line).unwrap().record.memo)
+            .collect();
+        assert_eq!(memos, vec![None, Some("Invoice 42, paid".to_string())]);
+        assert!(!text.lines().next().unwrap().contains("memo"));
+        assert_eq!(verify(text.as_bytes()).unwrap().0, 2);
+    }
+
+    #[test]
+    fn test_tampering_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        log(
+            &path,
+            "type, client, tx, amount\ndeposit, 1, 1, 2.0\ndeposit, 1, 2, 3.0\n",
+        );
+        let text = std::fs::read_to_string(&path).unwrap();
+
+        let altered = text.replacen("\"2.0000\"", "\"20.0000\"", 1);
+        assert!(matches!(
+            verify(altered.as_bytes()),
+            Err(AuditError::Broken(1))
+        ));
+        let removed: String = text.lines().skip(1).map(|l| format!("{}\n", l)).collect();
+        assert!(matches!(
+            verify(removed.as_bytes()),
+            Err(AuditError::Broken(1))
+        ));
+        // Appending to a tampered log fails rather than extending the chain.
+        std::fs::write(&path, altered).unwrap();
+        assert!(matches!(AuditLog::open(&path), Err(AuditError::Broken(1))));
+    }
+}
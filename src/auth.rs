@@ -0,0 +1,289 @@
+//! API keys for the servers, each allowed some scopes: `read` to query
+//! clients, `submit` to submit transactions, and `admin` for everything,
+//! including snapshots of every client.
+//!
+//! Keys are configured in a TOML file:
+//!
+//! ```toml
+//! [[key]]
+//! name = "ingest"
+//! key = "<at least 16 characters>"
+//! scopes = ["submit"]
+//! tenants = ["acme"]
+//! ```
+//!
+//! and sent as `Authorization: Bearer <key>` or `X-Api-Key: <key>`. Only
+//! digests of the keys are kept once they're loaded.
+//!
+//! A key with `tenants` only allows requests for those tenants, and not for
+//! the default clients; one without allows requests for any.
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// Keys shorter than this are rejected, as they're too easy to guess.
+const MIN_KEY_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    Read,
+    Submit,
+    Admin,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum KeyFileError {
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid key file: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("key '{0}' is shorter than {MIN_KEY_LEN} characters")]
+    TooShort(String),
+}
+
+/// Why a request wasn't allowed.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum AuthError {
+    #[error("missing API key")]
+    Missing,
+    #[error("unknown API key")]
+    Unknown,
+    #[error("key '{name}' doesn't allow {scope:?} requests")]
+    Forbidden { name: String, scope: Scope },
+    /// `tenant` is `None` for the default clients.
+    #[error("key '{name}' doesn't allow requests for {}", describe(.tenant))]
+    WrongTenant {
+        name: String,
+        tenant: Option<String>,
+    },
+}
+
+fn describe(tenant: &Option<String>) -> String {
+    match tenant {
+        Some(tenant) => format!("tenant '{}'", tenant),
+        None => "the default clients".to_string(),
+    }
+}
+
+#[derive(Deserialize)]
+struct KeyFile {
+    #[serde(rename = "key", default)]
+    keys: Vec<KeyEntry>,
+}
+
+#[derive(Deserialize)]
+struct KeyEntry {
+    name: String,
+    key: String,
+    scopes: Vec<Scope>,
+    tenants: Option<Vec<String>>,
+}
+
+struct Key {
+    name: String,
+    digest: [u8; 32],
+    scopes: Vec<Scope>,
+    // `None` to allow any tenant.
+    tenants: Option<Vec<String>>,
+}
+
+pub struct ApiKeys {
+    keys: Vec<Key>,
+}
+
+impl ApiKeys {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, KeyFileError> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    /// Check that `key` is allowed requests needing `scope`, for `tenant`, or
+    /// the default clients if `None`, returning the name of the key.
+    pub fn authorize(
+        &self,
+        key: Option<&str>,
+        scope: Scope,
+        tenant: Option<&str>,
+    ) -> Result<&str, AuthError> {
+        let digest = digest(key.ok_or(AuthError::Missing)?);
+        // Compare every byte of every digest, so that the time taken doesn't
+        // depend on how close the key is to a real one.
+        let found = self.keys.iter().fold(None, |found, key| {
+            let difference = key
+                .digest
+                .iter()
+                .zip(&digest)
+                .fold(0, |acc, (a, b)| acc | (a ^ b));
+            if difference == 0 {
+                Some(key)
+            } else {
+                found
+            }
+        });
+        let key = found.ok_or(AuthError::Unknown)?;
+        if !key.scopes.contains(&scope) && !key.scopes.contains(&Scope::Admin) {
+            return Err(AuthError::Forbidden {
+                name: key.name.clone(),
+                scope,
+            });
+        }
+        if let Some(tenants) = &key.tenants {
+            if !tenant.is_some_and(|tenant| tenants.iter().any(|t| t == tenant)) {
+                return Err(AuthError::WrongTenant {
+                    name: key.name.clone(),
+                    tenant: tenant.map(str::to_string),
+                });
+            }
+        }
+        Ok(&key.name)
+    }
+}
+
+impl std::str::FromStr for ApiKeys {
+    type Err = KeyFileError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let file: KeyFile = toml::from_str(s)?;
+        let keys = file
+            .keys
+            .into_iter()
+            .map(|entry| {
+                if entry.key.chars().count() < MIN_KEY_LEN {
+                    return Err(KeyFileError::TooShort(entry.name));
+                }
+                Ok(Key {
+                    digest: digest(&entry.key),
+                    name: entry.name,
+                    scopes: entry.scopes,
+                    tenants: entry.tenants,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { keys })
+    }
+}
+
+/// The key from an `Authorization` header, if it's a bearer token.
+pub fn bearer(header: &str) -> Option<&str> {
+    let (scheme, token) = header.trim().split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then_some(token.trim())
+}
+
+fn digest(key: &str) -> [u8; 32] {
+    Sha256::digest(key.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYS: &str = r#"
+[[key]]
+name = "ingest"
+key = "0123456789abcdef"
+scopes = ["submit"]
+
+[[key]]
+name = "support"
+key = "fedcba9876543210"
+scopes = ["read"]
+
+[[key]]
+name = "ops"
+key = "ops-ops-ops-ops-ops"
+scopes = ["admin"]
+
+[[key]]
+name = "acme"
+key = "acme-acme-acme-acme"
+scopes = ["submit", "read"]
+tenants = ["acme"]
+"#;
+
+    #[test]
+    fn test_authorize() {
+        let keys: ApiKeys = KEYS.parse().unwrap();
+        assert_eq!(
+            keys.authorize(Some("0123456789abcdef"), Scope::Submit, None),
+            Ok("ingest")
+        );
+        assert_eq!(
+            keys.authorize(Some("0123456789abcdef"), Scope::Read, None),
+            Err(AuthError::Forbidden {
+                name: "ingest".into(),
+                scope: Scope::Read
+            })
+        );
+        assert_eq!(
+            keys.authorize(Some("fedcba9876543210"), Scope::Read, Some("acme")),
+            Ok("support")
+        );
+        for scope in [Scope::Read, Scope::Submit, Scope::Admin] {
+            assert_eq!(
+                keys.authorize(Some("ops-ops-ops-ops-ops"), scope, None),
+                Ok("ops")
+            );
+        }
+        assert_eq!(
+            keys.authorize(Some("0123456789abcdeF"), Scope::Submit, None),
+            Err(AuthError::Unknown)
+        );
+        assert_eq!(
+            keys.authorize(None, Scope::Read, None),
+            Err(AuthError::Missing)
+        );
+
+        // A key for some tenants allows nothing else.
+        let acme = Some("acme-acme-acme-acme");
+        assert_eq!(
+            keys.authorize(acme, Scope::Submit, Some("acme")),
+            Ok("acme")
+        );
+        assert_eq!(
+            keys.authorize(acme, Scope::Submit, Some("other")),
+            Err(AuthError::WrongTenant {
+                name: "acme".into(),
+                tenant: Some("other".into())
+            })
+        );
+        assert_eq!(
+            keys.authorize(acme, Scope::Read, None),
+            Err(AuthError::WrongTenant {
+                name: "acme".into(),
+                tenant: None
+            })
+        );
+        assert_eq!(
+            keys.authorize(acme, Scope::Admin, Some("acme")),
+            Err(AuthError::Forbidden {
+                name: "acme".into(),
+                scope: Scope::Admin
+            })
+        );
+    }
+
+    #[test]
+    fn test_key_file_errors() {
+        let short = "[[key]]\nname = \"short\"\nkey = \"abc\"\nscopes = [\"read\"]\n";
+        assert!(matches!(
+            short.parse::<ApiKeys>(),
+            Err(KeyFileError::TooShort(name)) if name == "short"
+        ));
+        let unknown_scope = KEYS.replace("\"read\"", "\"write\"");
+        assert!(matches!(
+            unknown_scope.parse::<ApiKeys>(),
+            Err(KeyFileError::Toml(_))
+        ));
+    }
+
+    #[test]
+    fn test_bearer() {
+        assert_eq!(bearer("Bearer abc"), Some("abc"));
+        assert_eq!(bearer("bearer  abc "), Some("abc"));
+        assert_eq!(bearer("Basic abc"), None);
+        assert_eq!(bearer("abc"), None);
+    }
+}
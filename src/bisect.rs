@@ -0,0 +1,179 @@
+//! Finding the earliest record after which processing an input diverges from
+//! an expected summary, instead of bisecting it by hand with `--limit`.
+//!
+//! Only a client's final state is expected, so a client can only be checked
+//! once its last record has been processed: a client's state only changes
+//! through its own records, so from then on it's final. Whether the clients
+//! checked so far match can only go from true to false as more records are
+//! processed, so the earliest record after which they don't can be found by
+//! binary search, processing a prefix of the input each time.
+
+use std::collections::HashMap;
+
+use crate::clients::{Clients, ProcessError};
+use crate::diff::{self, Difference};
+use crate::snapshot::Snapshot;
+use crate::store::StoreError;
+use crate::transaction::{ClientId, Transaction};
+use crate::Amount;
+
+/// Where processing diverges from the expected summary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// How many records had been processed, or 0 if expected clients have no
+    /// records at all.
+    pub records: usize,
+    /// The line of the last record processed, if any.
+    pub line: Option<u64>,
+    /// How the clients checked after that record differ.
+    pub differences: Vec<Difference>,
+    /// The lines of the records of the clients that differ, one of which is
+    /// where they went wrong.
+    pub lines: Vec<u64>,
+}
+
+/// Find the earliest of `records`, each with its line, after which the state
+/// diverges from `expected` by more than `tolerance`, if it does.
+pub fn bisect(
+    records: &[(u64, Transaction)],
+    expected: &Snapshot,
+    tolerance: Amount,
+) -> Result<Option<Divergence>, StoreError> {
+    // How many records have been processed once each client's state is final.
+    let mut settled: HashMap<ClientId, usize> =
+        expected.clients.iter().map(|c| (c.id, 0)).collect();
+    for (i, (_, transaction)) in records.iter().enumerate() {
+        settled.insert(transaction.client_id, i + 1);
+    }
+    let differences = |count: usize| -> Result<Vec<Difference>, StoreError> {
+        let mut clients = Clients::new();
+        for (_, transaction) in &records[..count] {
+            match clients.process_transaction(transaction.clone()) {
+                Ok(_) | Err(ProcessError::Rejected(_) | ProcessError::Pending) => {}
+                Err(ProcessError::Store(e)) => return Err(e),
+            }
+        }
+        let checked = |snapshot: &Snapshot| {
+            let mut snapshot = snapshot.clone();
+            snapshot.clients.retain(|c| settled[&c.id] <= count);
+            snapshot
+        };
+        Ok(diff::diff_within(
+            &checked(expected),
+            &checked(&clients.snapshot()?),
+            tolerance,
+        ))
+    };
+
+    if differences(records.len())?.is_empty() {
+        return Ok(None);
+    }
+    // The clients checked after `low` records match, and those checked after
+    // `high` don't.
+    let (mut low, mut high) = (0, records.len());
+    if !differences(0)?.is_empty() {
+        high = 0;
+    }
+    while high > low + 1 {
+        let mid = low + (high - low) / 2;
+        if differences(mid)?.is_empty() {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    let differences = differences(high)?;
+    let clients: Vec<ClientId> = differences.iter().map(client).collect();
+    Ok(Some(Divergence {
+        records: high,
+        line: high.checked_sub(1).map(|i| records[i].0),
+        lines: records[..high]
+            .iter()
+            .filter(|(_, transaction)| clients.contains(&transaction.client_id))
+            .map(|&(line, _)| line)
+            .collect(),
+        differences,
+    }))
+}
+
+fn client(difference: &Difference) -> ClientId {
+    match *difference {
+        Difference::OnlyInFirst(client)
+        | Difference::OnlyInSecond(client)
+        | Difference::Balance { client, .. }
+        | Difference::Locked(client)
+        | Difference::Unlocked(client) => client,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::parse_record;
+
+    fn records(input: &[&str]) -> Vec<(u64, Transaction)> {
+        input
+            .iter()
+            .enumerate()
+            .map(|(i, record)| (i as u64 + 2, parse_record(record).unwrap()))
+            .collect()
+    }
+
+    fn summary(summary: &str) -> Snapshot {
+        let clients = Clients::seed(
+            Box::new(crate::store::MemoryStore::new()),
+            summary.as_bytes(),
+        )
+        .unwrap();
+        clients.snapshot().unwrap()
+    }
+
+    #[test]
+    fn test_bisect() {
+        let records = records(&[
+            "deposit,1,1,5.0",
+            "deposit,2,2,3.0",
+            "withdrawal,2,3,1.0",
+            "deposit,3,4,1.0",
+            "dispute,1,1",
+            "deposit,3,5,1.0",
+        ]);
+        let expected = "client,available,held,total,locked
+1,0.0000,5.0000,5.0000,false
+2,3.0000,0.0000,3.0000,false
+3,2.0000,0.0000,2.0000,false
+";
+        let divergence = bisect(&records, &summary(expected), Amount::ZERO)
+            .unwrap()
+            .unwrap();
+        // Client 2 is final after its withdrawal, on line 4.
+        assert_eq!(divergence.records, 3);
+        assert_eq!(divergence.line, Some(4));
+        assert!(matches!(
+            divergence.differences[..],
+            [Difference::Balance { client, .. }] if client == ClientId::from(2)
+        ));
+        assert_eq!(divergence.lines, [3, 4]);
+
+        let expected = expected.replace("2,3.0000,0.0000,3.0000", "2,2.0000,0.0000,2.0000");
+        assert_eq!(
+            bisect(&records, &summary(&expected), Amount::ZERO).unwrap(),
+            None
+        );
+
+        // A client with no records at all diverges before the first.
+        let divergence = bisect(
+            &records,
+            &summary(&(expected + "4,1.0,0.0,1.0,false\n")),
+            Amount::ZERO,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(divergence.records, 0);
+        assert_eq!(divergence.line, None);
+        assert_eq!(
+            divergence.differences,
+            [Difference::OnlyInFirst(ClientId::from(4))]
+        );
+    }
+}
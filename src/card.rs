@@ -0,0 +1,237 @@
+//! Reading transactions from card network clearing files, as a CSV dump of a
+//! simplified subset of ISO 8583 messages, so that they can be processed
+//! without a pre-processor of their own.
+//!
+//! Each record has the columns:
+//!
+//! - `mti`: the message type indicator, e.g. `1240`;
+//! - `function_code`: data element 24, which says what the message is for;
+//! - `merchant_id`: data element 42, the card acceptor, which is the client;
+//! - `rrn`: data element 37, the retrieval reference number, which is the
+//!   transaction ID, and is the same for every message about a sale;
+//! - `amount`: data element 4, in the currency's minor units, e.g. cents;
+//! - `currency`: data element 49, the ISO 4217 numeric or alphabetic code,
+//!   which must be one of the currencies in `CURRENCIES`.
+//!
+//! The messages understood are first presentments (`1240`, function code
+//! `200`), which are deposits; retrieval requests (`1644`, `603`), which are
+//! disputes; second presentments (`1240`, `205`), which resolve them; and
+//! first chargebacks (`1442`, `450`).
+
+use serde::Deserialize;
+
+use crate::transaction::{ClientId, RawTransactionId, Transaction, TransactionData};
+use crate::{Amount, Currency, TransactionId};
+
+/// The numeric and alphabetic codes of the currencies whose numeric codes
+/// are understood, with the number of digits after the decimal point in
+/// their minor units.
+const CURRENCIES: &[(&str, &str, u32)] = &[
+    ("036", "AUD", 2),
+    ("124", "CAD", 2),
+    ("156", "CNY", 2),
+    ("344", "HKD", 2),
+    ("392", "JPY", 0),
+    ("554", "NZD", 2),
+    ("578", "NOK", 2),
+    ("702", "SGD", 2),
+    ("752", "SEK", 2),
+    ("756", "CHF", 2),
+    ("826", "GBP", 2),
+    ("840", "USD", 2),
+    ("978", "EUR", 2),
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum CardError {
+    #[error("invalid CSV: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("line {line}: invalid {field} '{value}'")]
+    Invalid {
+        line: u64,
+        field: &'static str,
+        value: String,
+    },
+    #[error("line {line}: unsupported message {mti} with function code {function_code}")]
+    Unsupported {
+        line: u64,
+        mti: String,
+        function_code: String,
+    },
+}
+
+#[derive(Deserialize)]
+struct Record {
+    mti: String,
+    function_code: String,
+    merchant_id: String,
+    rrn: String,
+    #[serde(default)]
+    amount: String,
+    #[serde(default)]
+    currency: String,
+}
+
+/// The transactions in a clearing file, in the order they appear.
+pub fn read_transactions(reader: impl std::io::Read) -> Result<Vec<Transaction>, CardError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let headers = reader.headers()?.clone();
+    let mut transactions = Vec::new();
+    for result in reader.records() {
+        let record = result?;
+        let line = record.position().map_or(0, csv::Position::line);
+        let record: Record = record.deserialize(Some(&headers))?;
+        let invalid = |field, value: &str| CardError::Invalid {
+            line,
+            field,
+            value: value.to_string(),
+        };
+        let client_id = record
+            .merchant_id
+            .parse::<u16>()
+            .map(ClientId::from)
+            .map_err(|_| invalid("merchant_id", &record.merchant_id))?;
+        let transaction_id = record
+            .rrn
+            .parse::<RawTransactionId>()
+            .map(TransactionId::from)
+            .map_err(|_| invalid("rrn", &record.rrn))?;
+        let (currency, exponent) =
+            currency(&record.currency).ok_or_else(|| invalid("currency", &record.currency))?;
+        let amount = || {
+            record
+                .amount
+                .parse::<u64>()
+                .ok()
+                .and_then(|amount| amount.checked_mul(10u64.pow(4 - exponent)))
+                .map(Amount::from_minor_units)
+                .ok_or_else(|| invalid("amount", &record.amount))
+        };
+        let data = match (record.mti.as_str(), record.function_code.as_str()) {
+            ("1240", "200") => TransactionData::Deposit {
+                transaction_id,
+                amount: amount()?,
+            },
+            ("1644", "603") => TransactionData::Dispute { transaction_id },
+            ("1240", "205") => TransactionData::Resolve { transaction_id },
+            ("1442", "450") => TransactionData::Chargeback { transaction_id },
+            _ => {
+                return Err(CardError::Unsupported {
+                    line,
+                    mti: record.mti,
+                    function_code: record.function_code,
+                })
+            }
+        };
+        transactions.push(Transaction {
+            client_id,
+            currency,
+            data,
+        });
+    }
+    Ok(transactions)
+}
+
+/// The currency with a numeric or alphabetic code in `CURRENCIES`, and the
+/// digits in its minor units. Any other code, or none, is rejected rather
+/// than guessed at, as the amount can't be scaled without its exponent.
+fn currency(code: &str) -> Option<(Option<Currency>, u32)> {
+    let &(_, alphabetic, exponent) = CURRENCIES
+        .iter()
+        .find(|(numeric, alphabetic, _)| *numeric == code || *alphabetic == code)?;
+    Some((Currency::try_from(alphabetic).ok(), exponent))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let file = "\
+mti,function_code,merchant_id,rrn,amount,currency
+1240,200,42,1001,1999,978
+1240,200,42,1002,500,JPY
+1644,603,42,1001,,978
+1240,205,42,1001,,978
+1644,603,42,1001,,978
+1442,450,42,1001,1999,978
+";
+        let transactions = read_transactions(file.as_bytes()).unwrap();
+        let eur = Some(Currency::try_from("EUR").unwrap());
+        let data: Vec<_> = transactions.iter().map(|t| t.data).collect();
+        assert_eq!(
+            data,
+            vec![
+                TransactionData::Deposit {
+                    transaction_id: 1001.into(),
+                    amount: Amount::try_from("19.99").unwrap(),
+                },
+                TransactionData::Deposit {
+                    transaction_id: 1002.into(),
+                    amount: Amount::try_from("500").unwrap(),
+                },
+                TransactionData::Dispute {
+                    transaction_id: 1001.into()
+                },
+                TransactionData::Resolve {
+                    transaction_id: 1001.into()
+                },
+                TransactionData::Dispute {
+                    transaction_id: 1001.into()
+                },
+                TransactionData::Chargeback {
+                    transaction_id: 1001.into()
+                },
+            ]
+        );
+        assert!(transactions.iter().all(|t| t.client_id == 42.into()));
+        assert_eq!(transactions[0].currency, eur);
+        assert_eq!(
+            transactions[1].currency,
+            Some(Currency::try_from("JPY").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let header = "mti,function_code,merchant_id,rrn,amount,currency\n";
+        let read = |record: &str| read_transactions(format!("{}{}", header, record).as_bytes());
+        assert!(matches!(
+            read("1100,100,42,1,100,978"),
+            Err(CardError::Unsupported { line: 2, .. })
+        ));
+        assert!(matches!(
+            read("1240,200,70000,1,100,978"),
+            Err(CardError::Invalid {
+                field: "merchant_id",
+                ..
+            })
+        ));
+        assert!(matches!(
+            read("1240,200,42,1,100,999"),
+            Err(CardError::Invalid {
+                field: "currency",
+                ..
+            })
+        ));
+        for currency in ["XYZ", ""] {
+            assert!(matches!(
+                read(&format!("1240,200,42,1,100,{}", currency)),
+                Err(CardError::Invalid {
+                    field: "currency",
+                    ..
+                })
+            ));
+        }
+        assert!(matches!(
+            read("1240,200,42,1,1.00,978"),
+            Err(CardError::Invalid {
+                field: "amount",
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,230 @@
+//! Chargeback rates - how many deposits, and how much of the amount
+//! deposited, were charged back - overall and for each client, a key
+//! measure of fraud.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::amount::{AmountFormat, DisplayAmount};
+use crate::event::Event;
+use crate::metadata::ClientMetadata;
+use crate::snapshot::DepositSnapshot;
+use crate::transaction::ClientId;
+use crate::{Amount, Currency};
+
+/// The rate above which clients are flagged, unless another is given.
+pub const DEFAULT_THRESHOLD: f64 = 0.01;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counts {
+    pub deposits: u64,
+    pub deposited: Amount,
+    pub chargebacks: u64,
+    pub charged_back: Amount,
+}
+
+impl Counts {
+    /// The share of deposits charged back.
+    pub fn count_rate(&self) -> f64 {
+        rate(self.chargebacks as f64, self.deposits as f64)
+    }
+
+    /// The share of the amount deposited that was charged back.
+    pub fn volume_rate(&self) -> f64 {
+        rate(
+            self.charged_back.to_minor_units() as f64,
+            self.deposited.to_minor_units() as f64,
+        )
+    }
+
+    fn add(&mut self, other: &Counts) {
+        self.deposits += other.deposits;
+        self.deposited = self.deposited.saturating_add(other.deposited);
+        self.chargebacks += other.chargebacks;
+        self.charged_back = self.charged_back.saturating_add(other.charged_back);
+    }
+}
+
+fn rate(part: f64, whole: f64) -> f64 {
+    if whole == 0.0 {
+        0.0
+    } else {
+        part / whole
+    }
+}
+
+#[derive(Default)]
+pub struct ChargebackRates {
+    // By client, then currency.
+    counts: BTreeMap<(ClientId, Option<Currency>), Counts>,
+    // Whether any deposit specified a currency, as for the summary.
+    multi_currency: bool,
+}
+
+impl ChargebackRates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count the events caused by an accepted transaction for `client`.
+    /// `charged_back` is the deposit a chargeback refers to, looked up
+    /// before it was charged back. Sums saturate rather than overflow, as
+    /// they're only a guide.
+    pub fn record(
+        &mut self,
+        client: ClientId,
+        events: &[Event],
+        charged_back: Option<&DepositSnapshot>,
+    ) {
+        for event in events {
+            match *event {
+                Event::FundsDeposited {
+                    currency, amount, ..
+                } => {
+                    self.multi_currency |= currency.is_some();
+                    let counts = self.counts.entry((client, currency)).or_default();
+                    counts.deposits += 1;
+                    counts.deposited = counts.deposited.saturating_add(amount);
+                }
+                Event::ChargedBack { .. } => {
+                    // Charged back withdrawals aren't deposits charged back.
+                    let Some(deposit) = charged_back.filter(|deposit| !deposit.withdrawal) else {
+                        continue;
+                    };
+                    let counts = self.counts.entry((client, deposit.currency)).or_default();
+                    counts.chargebacks += 1;
+                    counts.charged_back = counts.charged_back.saturating_add(deposit.amount);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The counts for each client and currency.
+    pub fn clients(&self) -> impl Iterator<Item = (ClientId, Option<Currency>, &Counts)> {
+        self.counts
+            .iter()
+            .map(|(&(client, currency), counts)| (client, currency, counts))
+    }
+
+    /// The counts over every client, for each currency.
+    pub fn overall(&self) -> BTreeMap<Option<Currency>, Counts> {
+        let mut overall = BTreeMap::<_, Counts>::new();
+        for (_, currency, counts) in self.clients() {
+            overall.entry(currency).or_default().add(counts);
+        }
+        overall
+    }
+
+    /// Write a CSV row for each client and currency, followed by a `total`
+    /// row for each currency, with the rates by count and by volume as
+    /// fractions. Rows with either rate above `threshold` are flagged. With
+    /// `metadata`, each client's name, email, and tier are added as the last
+    /// columns.
+    pub fn write(
+        &self,
+        writer: impl std::io::Write,
+        format: AmountFormat,
+        threshold: f64,
+        metadata: Option<&ClientMetadata>,
+    ) -> Result<(), csv::Error> {
+        #[derive(Serialize)]
+        struct Row {
+            client: String,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            currency: Option<String>,
+            deposits: u64,
+            deposited: DisplayAmount,
+            chargebacks: u64,
+            charged_back: DisplayAmount,
+            count_rate: String,
+            volume_rate: String,
+            flagged: bool,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            name: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            email: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            tier: Option<String>,
+        }
+
+        // The totals have empty details.
+        let row = |client: Option<ClientId>, currency: Option<Currency>, counts: &Counts| {
+            let info = metadata.map(|m| client.map(|id| m.get(id)).unwrap_or_default());
+            Row {
+                client: client.map_or("total".to_string(), |id| id.to_string()),
+                currency: self
+                    .multi_currency
+                    .then(|| currency.map(|c| c.to_string()).unwrap_or_default()),
+                deposits: counts.deposits,
+                deposited: counts.deposited.display(format),
+                chargebacks: counts.chargebacks,
+                charged_back: counts.charged_back.display(format),
+                count_rate: format!("{:.4}", counts.count_rate()),
+                volume_rate: format!("{:.4}", counts.volume_rate()),
+                flagged: counts.count_rate() > threshold || counts.volume_rate() > threshold,
+                name: info.as_ref().map(|info| info.name.clone()),
+                email: info.as_ref().map(|info| info.email.clone()),
+                tier: info.map(|info| info.tier),
+            }
+        };
+        let mut writer = csv::Writer::from_writer(writer);
+        for (client, currency, counts) in self.clients() {
+            writer.serialize(row(Some(client), currency, counts))?;
+        }
+        for (currency, counts) in self.overall() {
+            writer.serialize(row(None, currency, &counts))?;
+        }
+        Ok(writer.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::Clients;
+    use crate::transaction::{parse_record, TransactionData};
+
+    #[test]
+    fn test_chargeback_rates() {
+        let mut clients = Clients::new();
+        let mut rates = ChargebackRates::new();
+        for record in [
+            "deposit,1,1,10.0",
+            "deposit,1,2,30.0",
+            "dispute,1,1",
+            "chargeback,1,1",
+            "deposit,2,3,5.0",
+            "deposit,2,4,5.0",
+            "deposit,2,5,5.0",
+            "deposit,2,6,5.0",
+            "dispute,2,3",
+            "resolve,2,3",
+            // Rejected, so not counted.
+            "chargeback,2,3",
+        ] {
+            let transaction = parse_record(record).unwrap();
+            let client = transaction.client_id;
+            let charged_back = match transaction.data {
+                TransactionData::Chargeback { transaction_id } => {
+                    clients.find_deposit(client, transaction_id).unwrap()
+                }
+                _ => None,
+            };
+            if let Ok(events) = clients.process_transaction(transaction) {
+                rates.record(client, &events, charged_back.as_ref());
+            }
+        }
+        let mut buf = Vec::new();
+        rates
+            .write(&mut buf, AmountFormat::Trimmed, 0.1, None)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,deposits,deposited,chargebacks,charged_back,count_rate,volume_rate,flagged\n\
+             1,2,40,1,10,0.5000,0.2500,true\n\
+             2,4,20,0,0,0.0000,0.0000,false\n\
+             total,6,60,1,10,0.1667,0.1667,true\n"
+        );
+    }
+}
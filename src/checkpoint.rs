@@ -0,0 +1,89 @@
+//! Checkpoints of a run in progress - the engine state plus how far through
+//! the input it had got - so that a long run that crashes can resume from the
+//! last checkpoint rather than restarting from the beginning.
+
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+
+use crate::snapshot::{save_atomically, Snapshot, SnapshotError};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    // The snapshot reflects every transaction before this position, and none
+    // after it.
+    pub position: InputPosition,
+    // The length of the write-ahead log when the checkpoint was saved, if
+    // there is one, so that recovery only replays the log after it.
+    #[serde(default)]
+    pub wal_position: Option<u64>,
+    pub snapshot: Snapshot,
+}
+
+/// A position in the input. Mirrors `csv::Position`, which isn't serializable.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputPosition {
+    pub byte: u64,
+    pub line: u64,
+    pub record: u64,
+}
+
+impl From<&csv::Position> for InputPosition {
+    fn from(position: &csv::Position) -> Self {
+        Self {
+            byte: position.byte(),
+            line: position.line(),
+            record: position.record(),
+        }
+    }
+}
+
+impl From<&InputPosition> for csv::Position {
+    fn from(position: &InputPosition) -> Self {
+        let mut result = csv::Position::new();
+        result
+            .set_byte(position.byte)
+            .set_line(position.line)
+            .set_record(position.record);
+        result
+    }
+}
+
+impl Checkpoint {
+    /// Save the checkpoint to `path`, atomically replacing the previous one.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        save_atomically(path.as_ref(), |file| {
+            let mut writer = std::io::BufWriter::new(file);
+            serde_json::to_writer(&mut writer, self)?;
+            Ok(writer.flush()?)
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Checkpoint, SnapshotError> {
+        let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::Clients;
+
+    #[test]
+    fn test_save_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint.json");
+        let mut position = csv::Position::new();
+        position.set_byte(123).set_line(4).set_record(3);
+        let checkpoint = Checkpoint {
+            position: (&position).into(),
+            wal_position: Some(42),
+            snapshot: Clients::new().snapshot().unwrap(),
+        };
+        checkpoint.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap();
+        assert_eq!(loaded, checkpoint);
+        assert_eq!(csv::Position::from(&loaded.position), position);
+    }
+}
@@ -1,41 +1,177 @@
-use crate::{Amount, TransactionId};
-use std::collections::{hash_map::Entry, HashMap};
+use serde::{Deserialize, Serialize};
 
+use crate::event::Event;
+use crate::policy::{DefaultPolicy, Policy};
+use crate::snapshot::{BalanceSnapshot, ClientSnapshot, DepositSnapshot, SnapshotError};
+use crate::transaction::{ClientId, Id, Transaction, TransactionData, TransactionKind};
+use crate::{Amount, Currency, SignedAmount, TransactionId};
+use std::collections::{hash_map::Entry, BTreeMap, HashMap, HashSet, VecDeque};
+
+/// A transaction that can be disputed: a deposit, or a withdrawal if the
+/// policy allows withdrawals to be disputed.
+#[derive(Clone)]
 struct Deposit {
+    currency: Option<Currency>,
     amount: Amount,
     disputed: bool,
+    withdrawal: bool,
 }
 
 impl Deposit {
-    fn new(amount: Amount) -> Self {
+    fn new(currency: Option<Currency>, amount: Amount, withdrawal: bool) -> Self {
         Self {
+            currency,
             amount,
             disputed: false,
+            withdrawal,
+        }
+    }
+
+    fn kind(&self) -> TransactionKind {
+        if self.withdrawal {
+            TransactionKind::Withdrawal
+        } else {
+            TransactionKind::Deposit
         }
     }
-}
 
-#[derive(Default)]
-pub struct Client {
-    // Assumption: Only deposits can be disputed, not withdrawals. This
-    // approach could be extended to allow disputing withdrawals as well, at
-    // the cost of having to keep track of them.
-    // In a real system we'd want to limit the size of this HashMap by limiting
-    // the number of transactions that can be disputed. For example, we might
-    // only keep the last 100 transactions.
-    deposits: HashMap<TransactionId, Deposit>,
+    /// Disputes, resolves, and chargebacks refer to a deposit by transaction
+    /// ID, so they don't need to specify a currency. If they do, it has to
+    /// match the deposit's.
+    fn check_currency(&self, currency: Option<Currency>) -> Result<(), ClientError> {
+        match currency {
+            Some(_) if currency != self.currency => Err(ClientError::CurrencyMismatch),
+            _ => Ok(()),
+        }
+    }
+}
 
-    available: Amount,
+/// Funds held in a single currency.
+#[derive(Debug, Default, Clone, Copy)]
+struct Balance {
+    available: SignedAmount,
 
     // Invariant: total = available + held
-    // where held is the sum of the disputed deposits.
+    // where held is the sum of the disputed deposits and withdrawals in this
+    // currency. Available can be negative if the policy allows it, but held
+    // can't.
     //
     // This is somewhat duplicating state, since we could calculate the total
     // from available and the deposits HashMap. However, this lets us avoid
     // recalculating the total every time we need it.
-    total: Amount,
+    total: SignedAmount,
+}
+
+/// A client's account. Generic over the client and transaction IDs, for
+/// embedders with their own; see `Id`.
+#[derive(Clone)]
+pub struct Client<C = ClientId, T = TransactionId> {
+    // By default only deposits can be disputed, so withdrawals are only kept
+    // here if the policy lets them be disputed; see `Policy::disputable`.
+    // The size of this HashMap can be limited by expiring the oldest deposits
+    // once there are too many; see `expire_deposits`.
+    deposits: HashMap<T, Deposit>,
+    // The IDs of `deposits`, oldest first, to expire them in that order.
+    // Only tracked once deposits are first expired, since removing a deposit
+    // from it means scanning it; see `expire_deposits`.
+    order: Option<VecDeque<T>>,
+    // Deposits that have been expired, so that disputes of them can be told
+    // apart from disputes of deposits that never happened, and their IDs
+    // aren't reused. Only the ID of each is kept.
+    expired: HashSet<T>,
 
+    // Balances are kept separately per currency, and funds are never moved
+    // between currencies. `None` is the currency of transactions that don't
+    // specify one, which is all of them for single-currency input.
+    // A BTreeMap so that balances are always reported in the same order.
+    balances: BTreeMap<Option<Currency>, Balance>,
+
+    // Set by a chargeback, or by `set_locked`.
     locked: bool,
+    // Set while the account is locked by `lock`, e.g. by risk tooling, which
+    // only `unlock` or `set_locked` clears.
+    lock_reason: Option<String>,
+
+    // How many of each kind of transaction have been accepted.
+    activity: ClientActivity,
+
+    // The transactions accepted, oldest first, if they're being kept; see
+    // `Clients::set_keep_history`. Not included in snapshots.
+    history: Vec<HistoryEntry<C, T>>,
+}
+
+/// A transaction accepted for a client, as kept in its history; see
+/// `Client::history`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry<C = ClientId, T = TransactionId> {
+    pub transaction: Transaction<C, T>,
+    // When it was accepted, in seconds since the Unix epoch, as given to
+    // `Clients::set_time`, or `None` if no time had been given.
+    pub time: Option<u64>,
+    // Its memo, as given to `Clients::set_memo`, if it had one.
+    pub memo: Option<String>,
+}
+
+/// How many of each kind of transaction a client has had accepted, and when
+/// the first and last were, e.g. to tell how active an account is.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientActivity {
+    pub deposits: u64,
+    pub withdrawals: u64,
+    pub disputes: u64,
+    pub resolves: u64,
+    pub chargebacks: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first: Option<Seen>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last: Option<Seen>,
+}
+
+/// When a transaction was accepted: the line of the input it was on, as given
+/// to `Clients::set_line`, and its time in seconds since the Unix epoch, as
+/// given to `Clients::set_time`, whichever are known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Seen {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+}
+
+impl Seen {
+    /// Whether this was before `other`, by time if both have one. Otherwise
+    /// by line, which is only meaningful within one input.
+    fn before(&self, other: &Seen) -> bool {
+        match (self.timestamp, other.timestamp, self.line, other.line) {
+            (Some(a), Some(b), _, _) | (_, _, Some(a), Some(b)) => a < b,
+            _ => false,
+        }
+    }
+}
+
+impl ClientActivity {
+    pub fn is_empty(&self) -> bool {
+        *self == ClientActivity::default()
+    }
+
+    /// The sum of each count, saturating rather than overflowing, with the
+    /// earlier first and the later last transaction.
+    pub fn combine(self, other: ClientActivity) -> ClientActivity {
+        let pick = |a: Option<Seen>, b: Option<Seen>, earlier: bool| match (a, b) {
+            (Some(a), Some(b)) if b.before(&a) == earlier => Some(b),
+            (a, b) => a.or(b),
+        };
+        ClientActivity {
+            deposits: self.deposits.saturating_add(other.deposits),
+            withdrawals: self.withdrawals.saturating_add(other.withdrawals),
+            disputes: self.disputes.saturating_add(other.disputes),
+            resolves: self.resolves.saturating_add(other.resolves),
+            chargebacks: self.chargebacks.saturating_add(other.chargebacks),
+            first: pick(self.first, other.first, true),
+            last: pick(self.last, other.last, false),
+        }
+    }
 }
 
 // These are all errors we'd expect to report to the client, _not_ e.g. logic
@@ -57,146 +193,709 @@ pub enum ClientError {
     NotDisputed,
     #[error("account locked")]
     Locked,
+    #[error("currency does not match the original deposit")]
+    CurrencyMismatch,
+    #[error("deposit too old to dispute")]
+    TooOldToDispute,
+    #[error("transaction can't be disputed")]
+    NotDisputable,
+    /// Rejected by a rule of the `Policy`, named by it.
+    #[error("rejected by policy: {0}")]
+    Policy(&'static str),
 }
 
-impl Client {
-    pub fn deposit(
+impl ClientError {
+    /// A short, stable name for the kind of error, e.g. for counting them.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClientError::Overflow => "overflow",
+            ClientError::InsufficientFunds => "insufficient_funds",
+            ClientError::UnknownTransactionId => "unknown_transaction_id",
+            ClientError::DuplicateTransactionId => "duplicate_transaction_id",
+            ClientError::AlreadyDisputed => "already_disputed",
+            ClientError::NotDisputed => "not_disputed",
+            ClientError::Locked => "locked",
+            ClientError::CurrencyMismatch => "currency_mismatch",
+            ClientError::TooOldToDispute => "too_old_to_dispute",
+            ClientError::NotDisputable => "not_disputable",
+            ClientError::Policy(name) => name,
+        }
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Id, T: Id> Client<C, T> {
+    /// A client with no funds or transactions.
+    pub fn new() -> Self {
+        Self {
+            deposits: HashMap::new(),
+            order: None,
+            expired: HashSet::new(),
+            balances: BTreeMap::new(),
+            locked: false,
+            lock_reason: None,
+            activity: ClientActivity::default(),
+            history: Vec::new(),
+        }
+    }
+
+    /// Apply `transaction` under `policy`, returning the events it caused.
+    /// `deposit`, `withdraw`, and so on apply the `DefaultPolicy`.
+    pub fn process(
         &mut self,
-        transaction_id: TransactionId,
-        amount: Amount,
-    ) -> Result<(), ClientError> {
-        if self.locked {
+        transaction: &Transaction<C, T>,
+        policy: &dyn Policy<C, T>,
+    ) -> Result<Vec<Event<T>>, ClientError> {
+        if self.locked() && !policy.allowed_when_locked(self, transaction) {
             return Err(ClientError::Locked);
         }
-        // Don't allow the total funds - available and held - to overflow. This
-        // allows us to freely transfer funds between available and held without
-        // worrying about overflow.
-        // Don't immediately update `self.total` because we might still decide
-        // to return an error and we don't want to leave the client in an
-        // inconsistent state.
-        let total = self
-            .total
-            .checked_add(amount)
-            .ok_or(ClientError::Overflow)?;
-        let entry = match self.deposits.entry(transaction_id) {
-            // We rely on transaction ID uniqueness to match disputes to
-            // deposits.
-            Entry::Occupied(_) => return Err(ClientError::DuplicateTransactionId),
-            Entry::Vacant(entry) => entry,
+        policy.check(self, transaction)?;
+        let currency = transaction.currency;
+        let event = match transaction.data {
+            TransactionData::Deposit {
+                transaction_id,
+                amount,
+            } => Event::FundsDeposited {
+                transaction_id,
+                currency,
+                amount,
+            },
+            TransactionData::Withdrawal {
+                transaction_id,
+                amount,
+            } => Event::FundsWithdrawn {
+                transaction_id,
+                currency,
+                amount,
+                disputable: policy.disputable(TransactionKind::Withdrawal),
+                overdraft: policy.overdraft(self, transaction),
+            },
+            TransactionData::Dispute { transaction_id } => {
+                self.open_dispute(transaction_id, currency, policy)?
+            }
+            TransactionData::Resolve { transaction_id } => {
+                self.check_deposit(transaction_id, currency)?;
+                Event::DisputeResolved { transaction_id }
+            }
+            TransactionData::Chargeback { transaction_id } => {
+                self.check_deposit(transaction_id, currency)?;
+                Event::ChargedBack { transaction_id }
+            }
         };
+        let mut events = self.emit(vec![event])?;
+        if !self.locked && policy.lock_after(self, transaction, &events) {
+            let event = Event::AccountLocked {
+                transaction_id: transaction.data.transaction_id(),
+            };
+            self.apply(&event).expect("an account can always be locked");
+            events.push(event);
+        }
+        Ok(events)
+    }
 
-        // Since available <= total, this isn't going to overflow.
-        self.available = self.available.checked_add(amount).unwrap();
-        self.total = total;
-        entry.insert(Deposit::new(amount));
-        Ok(())
+    pub fn deposit(
+        &mut self,
+        transaction_id: T,
+        currency: Option<Currency>,
+        amount: Amount,
+    ) -> Result<Vec<Event<T>>, ClientError> {
+        self.check_unlocked()?;
+        self.emit(vec![Event::FundsDeposited {
+            transaction_id,
+            currency,
+            amount,
+        }])
     }
 
-    pub fn withdraw(&mut self, amount: Amount) -> Result<(), ClientError> {
-        if self.locked {
-            return Err(ClientError::Locked);
+    pub fn withdraw(
+        &mut self,
+        transaction_id: T,
+        currency: Option<Currency>,
+        amount: Amount,
+    ) -> Result<Vec<Event<T>>, ClientError> {
+        self.check_unlocked()?;
+        self.emit(vec![Event::FundsWithdrawn {
+            transaction_id,
+            currency,
+            amount,
+            disputable: Policy::<C, T>::disputable(&DefaultPolicy, TransactionKind::Withdrawal),
+            overdraft: Amount::ZERO,
+        }])
+    }
+
+    pub fn dispute(
+        &mut self,
+        transaction_id: T,
+        currency: Option<Currency>,
+    ) -> Result<Vec<Event<T>>, ClientError> {
+        self.check_unlocked()?;
+        let event = self.open_dispute(transaction_id, currency, &DefaultPolicy)?;
+        self.emit(vec![event])
+    }
+
+    pub fn resolve(
+        &mut self,
+        transaction_id: T,
+        currency: Option<Currency>,
+    ) -> Result<Vec<Event<T>>, ClientError> {
+        self.check_unlocked()?;
+        self.check_deposit(transaction_id, currency)?;
+        self.emit(vec![Event::DisputeResolved { transaction_id }])
+    }
+
+    pub fn chargeback(
+        &mut self,
+        transaction_id: T,
+        currency: Option<Currency>,
+    ) -> Result<Vec<Event<T>>, ClientError> {
+        self.check_unlocked()?;
+        self.check_deposit(transaction_id, currency)?;
+        // A chargeback should cause the account to be locked, preventing any
+        // further transactions.
+        self.emit(vec![
+            Event::ChargedBack { transaction_id },
+            Event::AccountLocked { transaction_id },
+        ])
+    }
+
+    /// Expire the oldest deposits until at most `max` are kept, so that they
+    /// can no longer be disputed. Disputed deposits are never expired, so
+    /// more than `max` are kept if that many are disputed.
+    ///
+    /// The order deposits are made in is only tracked from the first call
+    /// on; any kept before then are taken to be oldest by lowest ID.
+    pub fn expire_deposits(&mut self, max: usize) -> Vec<Event<T>> {
+        if self.order.is_none() {
+            let mut order: Vec<T> = self.deposits.keys().copied().collect();
+            order.sort();
+            self.order = Some(order.into());
         }
-        self.available = self
-            .available
-            .checked_sub(amount)
-            .ok_or(ClientError::InsufficientFunds)?;
-        // This can't fail because available <= total and we've already
-        // successfully reduced available.
-        self.total = self.total.checked_sub(amount).unwrap();
-        Ok(())
+        let mut events = Vec::new();
+        let mut disputed = 0;
+        while self.deposits.len() > max {
+            // Disputed deposits are skipped over, but stay where they are.
+            let Some(&transaction_id) = self.order.iter().flatten().nth(disputed) else {
+                break;
+            };
+            if self.deposits[&transaction_id].disputed {
+                disputed += 1;
+                continue;
+            }
+            let event = Event::DepositExpired { transaction_id };
+            self.apply(&event)
+                .expect("undisputed deposits can be expired");
+            events.push(event);
+        }
+        events
     }
 
-    pub fn dispute(&mut self, transaction_id: TransactionId) -> Result<(), ClientError> {
-        if self.locked {
-            return Err(ClientError::Locked);
+    /// Remove a deposit from `order`, if it's tracked. Deposits are almost
+    /// always removed from at or near the front, as they expire, so this
+    /// stops as soon as it finds it rather than scanning the whole queue.
+    fn remove_from_order(&mut self, transaction_id: T) {
+        if let Some(order) = &mut self.order {
+            if let Some(index) = order.iter().position(|id| *id == transaction_id) {
+                order.remove(index);
+            }
         }
-        let deposit = self
-            .deposits
-            .get_mut(&transaction_id)
-            .ok_or(ClientError::UnknownTransactionId)?;
-        if deposit.disputed {
-            return Err(ClientError::AlreadyDisputed);
+    }
+
+    fn check_unlocked(&self) -> Result<(), ClientError> {
+        if self.locked() {
+            return Err(ClientError::Locked);
         }
-        // Assumption: A dispute can't be opened for an amount greater than the
-        // available balance.
-        // Assuming the funds are available, a dispute triggers the funds to be
-        // "held" until the dispute is resolved, decreasing the available
-        // balance but not the total.
-        self.available = self
-            .available
-            .checked_sub(deposit.amount)
-            .ok_or(ClientError::InsufficientFunds)?;
-        deposit.disputed = true;
         Ok(())
     }
 
-    pub fn resolve(&mut self, transaction_id: TransactionId) -> Result<(), ClientError> {
-        if self.locked {
-            return Err(ClientError::Locked);
+    /// Check that a dispute, resolve, or chargeback refers to a known deposit
+    /// in the right currency.
+    fn check_deposit(
+        &self,
+        transaction_id: T,
+        currency: Option<Currency>,
+    ) -> Result<(), ClientError> {
+        self.deposits
+            .get(&transaction_id)
+            .ok_or_else(|| self.missing_deposit(transaction_id))?
+            .check_currency(currency)
+    }
+
+    /// The event opening a dispute of a known deposit, or withdrawal, if
+    /// `policy` lets it be disputed.
+    fn open_dispute(
+        &self,
+        transaction_id: T,
+        currency: Option<Currency>,
+        policy: &dyn Policy<C, T>,
+    ) -> Result<Event<T>, ClientError> {
+        self.check_deposit(transaction_id, currency)?;
+        if !policy.disputable(self.deposits[&transaction_id].kind()) {
+            return Err(ClientError::NotDisputable);
         }
-        let deposit = self
-            .deposits
-            .get_mut(&transaction_id)
-            .ok_or(ClientError::UnknownTransactionId)?;
-        if !deposit.disputed {
-            return Err(ClientError::NotDisputed);
+        Ok(Event::DisputeOpened {
+            transaction_id,
+            allow_negative: policy.allow_negative_available(),
+        })
+    }
+
+    /// Why there's no deposit with the given ID.
+    fn missing_deposit(&self, transaction_id: T) -> ClientError {
+        if self.expired.contains(&transaction_id) {
+            ClientError::TooOldToDispute
+        } else {
+            ClientError::UnknownTransactionId
         }
-        // Resolving a dispute releases the held funds back to the available
-        // balance. It does not affect the total.
-        // This can't fail because total = available + held, total doesn't
-        // overflow, and deposit.amount is part of the held balance.
-        self.available = self.available.checked_add(deposit.amount).unwrap();
-        deposit.disputed = false;
-        Ok(())
     }
 
-    pub fn chargeback(&mut self, transaction_id: TransactionId) -> Result<(), ClientError> {
-        if self.locked {
-            return Err(ClientError::Locked);
+    /// Apply the events for a transaction, returning them.
+    ///
+    /// Only the first event can fail - any others, e.g. locking the account
+    /// after a chargeback, follow from it - so a rejected transaction leaves
+    /// the client unchanged.
+    fn emit(&mut self, events: Vec<Event<T>>) -> Result<Vec<Event<T>>, ClientError> {
+        for event in &events {
+            self.apply(event)?;
         }
-        let entry = match self.deposits.entry(transaction_id) {
-            Entry::Occupied(entry) => entry,
-            Entry::Vacant(_) => return Err(ClientError::UnknownTransactionId),
-        };
-        let deposit = entry.get();
-        // Assumption: A dispute must be opened before attempting a chargeback.
-        if !deposit.disputed {
-            return Err(ClientError::NotDisputed);
-        };
+        Ok(events)
+    }
 
-        // A chargeback causes the held funds to be returned to the client,
-        // decreasing the total balance. It does not affect the available
-        // balance.
-        // This can't fail because total >= held, and deposit.amount is part of
-        // the held balance.
-        self.total = self.total.checked_sub(deposit.amount).unwrap();
+    /// Apply an event to the client's state.
+    ///
+    /// This is the only way the state changes, so the state can be rebuilt
+    /// from the events alone. Events that don't fit the current state, e.g.
+    /// withdrawing more than is available, are rejected and leave the client
+    /// unchanged.
+    pub fn apply(&mut self, event: &Event<T>) -> Result<(), ClientError> {
+        match *event {
+            Event::FundsDeposited {
+                transaction_id,
+                currency,
+                amount,
+            } => {
+                // Work on a copy of the balance and only write it back once
+                // we know the deposit will succeed, so we don't leave the
+                // client in an inconsistent state (or with an empty balance in
+                // a new currency) if we return an error.
+                let mut balance = self.balance(currency);
+                // Don't allow the total funds - available and held - to
+                // overflow. This allows us to freely transfer funds between
+                // available and held without worrying about overflow.
+                balance.total = balance
+                    .total
+                    .checked_add(amount.into())
+                    .ok_or(ClientError::Overflow)?;
+                if self.expired.contains(&transaction_id) {
+                    return Err(ClientError::DuplicateTransactionId);
+                }
+                let entry = match self.deposits.entry(transaction_id) {
+                    // We rely on transaction ID uniqueness to match disputes
+                    // to deposits.
+                    Entry::Occupied(_) => return Err(ClientError::DuplicateTransactionId),
+                    Entry::Vacant(entry) => entry,
+                };
 
-        // We could mark the transaction as "charged back", but it's easier to
-        // just remove it - we don't currently have any requirement to keep
-        // track of the transaction after it's been charged back.
-        entry.remove();
+                // Since available <= total, this isn't going to overflow.
+                balance.available += amount;
+                self.balances.insert(currency, balance);
+                entry.insert(Deposit::new(currency, amount, false));
+                if let Some(order) = &mut self.order {
+                    order.push_back(transaction_id);
+                }
+                self.activity.deposits += 1;
+            }
 
-        // A chargeback should cause the account to be locked, preventing any
-        // further transactions.
-        self.locked = true;
+            Event::FundsWithdrawn {
+                transaction_id,
+                currency,
+                amount,
+                disputable,
+                overdraft,
+            } => {
+                let mut balance = self.balance(currency);
+                balance.available = balance
+                    .available
+                    .checked_sub_overdrawn(amount, overdraft)
+                    .ok_or(ClientError::InsufficientFunds)?;
+                // This can't fail because available <= total and we've already
+                // successfully reduced available.
+                balance.total -= amount;
+                if disputable {
+                    if self.expired.contains(&transaction_id) {
+                        return Err(ClientError::DuplicateTransactionId);
+                    }
+                    match self.deposits.entry(transaction_id) {
+                        Entry::Occupied(_) => return Err(ClientError::DuplicateTransactionId),
+                        Entry::Vacant(entry) => {
+                            entry.insert(Deposit::new(currency, amount, true));
+                        }
+                    }
+                    if let Some(order) = &mut self.order {
+                        order.push_back(transaction_id);
+                    }
+                }
+                self.balances.insert(currency, balance);
+                self.activity.withdrawals += 1;
+            }
+
+            Event::DisputeOpened {
+                transaction_id,
+                allow_negative,
+            } => {
+                let missing = self.missing_deposit(transaction_id);
+                let deposit = self.deposits.get_mut(&transaction_id).ok_or(missing)?;
+                if deposit.disputed {
+                    return Err(ClientError::AlreadyDisputed);
+                }
+                // This can't fail because the deposit created the balance.
+                let balance = self.balances.get_mut(&deposit.currency).unwrap();
+                // Held funds can't go past the largest amount, which they
+                // could if available funds were let go below zero.
+                balance
+                    .total
+                    .checked_add(-balance.available)
+                    .and_then(|held| held.checked_add(deposit.amount.into()))
+                    .ok_or(ClientError::Overflow)?;
+                if deposit.withdrawal {
+                    // Disputing a withdrawal holds the funds withdrawn until
+                    // the dispute is resolved, increasing the total but not
+                    // the available balance.
+                    balance.total = balance
+                        .total
+                        .checked_add(deposit.amount.into())
+                        .ok_or(ClientError::Overflow)?;
+                } else {
+                    // Disputing a deposit triggers the funds to be "held"
+                    // until the dispute is resolved, decreasing the available
+                    // balance but not the total. Unless the policy allows it,
+                    // the funds have to be available.
+                    let overdraft = if allow_negative {
+                        Amount::MAX
+                    } else {
+                        Amount::ZERO
+                    };
+                    balance.available = balance
+                        .available
+                        .checked_sub_overdrawn(deposit.amount, overdraft)
+                        .ok_or(ClientError::InsufficientFunds)?;
+                }
+                deposit.disputed = true;
+                self.activity.disputes += 1;
+            }
+
+            Event::DisputeResolved { transaction_id } => {
+                let deposit = self
+                    .deposits
+                    .get_mut(&transaction_id)
+                    .ok_or(ClientError::UnknownTransactionId)?;
+                if !deposit.disputed {
+                    return Err(ClientError::NotDisputed);
+                }
+                let balance = self.balances.get_mut(&deposit.currency).unwrap();
+                if deposit.withdrawal {
+                    // The withdrawal stands, so the held funds are released
+                    // from the total.
+                    balance.total -= deposit.amount;
+                } else {
+                    // Resolving a dispute releases the held funds back to the
+                    // available balance. It does not affect the total.
+                    // This can't fail because total = available + held, total
+                    // doesn't overflow, and deposit.amount is part of the held
+                    // balance.
+                    balance.available += deposit.amount;
+                }
+                deposit.disputed = false;
+                self.activity.resolves += 1;
+            }
+
+            Event::ChargedBack { transaction_id } => {
+                let entry = match self.deposits.entry(transaction_id) {
+                    Entry::Occupied(entry) => entry,
+                    Entry::Vacant(_) => return Err(ClientError::UnknownTransactionId),
+                };
+                let deposit = entry.get();
+                // Assumption: A dispute must be opened before attempting a
+                // chargeback.
+                if !deposit.disputed {
+                    return Err(ClientError::NotDisputed);
+                };
+
+                let balance = self.balances.get_mut(&deposit.currency).unwrap();
+                if deposit.withdrawal {
+                    // Charging back a withdrawal reverses it, making the held
+                    // funds available again. It does not affect the total.
+                    balance.available += deposit.amount;
+                } else {
+                    // A chargeback causes the held funds to be returned to the
+                    // client, decreasing the total balance. It does not affect
+                    // the available balance.
+                    // This can't fail because total = available + held, and
+                    // deposit.amount is part of the held balance.
+                    balance.total -= deposit.amount;
+                }
+
+                // We could mark the transaction as "charged back", but it's
+                // easier to just remove it - we don't currently have any
+                // requirement to keep track of the transaction after it's been
+                // charged back.
+                entry.remove();
+                self.remove_from_order(transaction_id);
+                self.activity.chargebacks += 1;
+            }
+
+            Event::DepositExpired { transaction_id } => {
+                let deposit = self
+                    .deposits
+                    .get(&transaction_id)
+                    .ok_or(ClientError::UnknownTransactionId)?;
+                if deposit.disputed {
+                    return Err(ClientError::AlreadyDisputed);
+                }
+                self.deposits.remove(&transaction_id);
+                self.remove_from_order(transaction_id);
+                self.expired.insert(transaction_id);
+            }
+
+            Event::AccountLocked { .. } => self.locked = true,
+        }
         Ok(())
     }
 
-    pub fn available(&self) -> Amount {
-        self.available
+    /// The currencies this client holds a balance in, in a stable order.
+    pub fn currencies(&self) -> impl Iterator<Item = Option<Currency>> + '_ {
+        self.balances.keys().copied()
+    }
+
+    fn balance(&self, currency: Option<Currency>) -> Balance {
+        self.balances.get(&currency).copied().unwrap_or_default()
+    }
+
+    /// The funds available, which are negative if the client is overdrawn.
+    pub fn available(&self, currency: Option<Currency>) -> SignedAmount {
+        self.balance(currency).available
     }
 
-    pub fn held(&self) -> Amount {
-        // This can't fail because total >= available.
-        self.total.checked_sub(self.available).unwrap()
+    pub fn held(&self, currency: Option<Currency>) -> Amount {
+        let balance = self.balance(currency);
+        // Held funds never go past the largest amount, or below zero, as
+        // total >= available.
+        balance
+            .total
+            .saturating_sub(balance.available)
+            .to_amount()
+            .unwrap_or_default()
     }
 
-    pub fn total(&self) -> Amount {
-        self.total
+    /// The available and held funds, which are negative if the client is
+    /// overdrawn by more than is held.
+    pub fn total(&self, currency: Option<Currency>) -> SignedAmount {
+        self.balance(currency).total
     }
 
+    /// Whether the account is locked, by a chargeback or by hand.
     pub fn locked(&self) -> bool {
-        self.locked
+        self.locked || self.lock_reason.is_some()
+    }
+
+    /// Why the account was locked by `lock`, if it was.
+    pub fn lock_reason(&self) -> Option<&str> {
+        self.lock_reason.as_deref()
+    }
+
+    /// How many of each kind of transaction have been accepted.
+    pub fn activity(&self) -> ClientActivity {
+        self.activity
+    }
+
+    /// A deposit that's still retained, i.e. that could be disputed or is
+    /// disputed now.
+    pub fn find_deposit(&self, transaction_id: T) -> Option<DepositSnapshot<T>> {
+        self.deposits
+            .get(&transaction_id)
+            .map(|deposit| DepositSnapshot {
+                transaction_id,
+                currency: deposit.currency,
+                amount: deposit.amount,
+                disputed: deposit.disputed,
+                withdrawal: deposit.withdrawal,
+            })
+    }
+
+    /// The transactions accepted for this client, oldest first. Empty unless
+    /// they're being kept; see `Clients::set_keep_history`.
+    pub fn history(&self) -> &[HistoryEntry<C, T>] {
+        &self.history
+    }
+
+    /// Note that a transaction was accepted when given by `seen`, if it says
+    /// anything.
+    pub fn mark_active(&mut self, seen: Seen) {
+        if seen.line.is_none() && seen.timestamp.is_none() {
+            return;
+        }
+        self.activity.first.get_or_insert(seen);
+        self.activity.last = Some(seen);
+    }
+
+    /// Add an accepted transaction to the history.
+    pub fn record(&mut self, entry: HistoryEntry<C, T>) {
+        self.history.push(entry);
+    }
+
+    /// Lock or unlock the account by hand, e.g. by an operator, regardless of
+    /// how it came to be locked.
+    pub fn set_locked(&mut self, locked: bool) {
+        self.locked = locked;
+        if !locked {
+            self.lock_reason = None;
+        }
+    }
+
+    /// Freeze the account pre-emptively, e.g. on suspicion of fraud, until
+    /// `unlock`. Locking it again replaces the reason.
+    pub fn lock(&mut self, reason: impl Into<String>) {
+        self.lock_reason = Some(reason.into());
+    }
+
+    /// Undo `lock`. An account locked by a chargeback stays locked.
+    pub fn unlock(&mut self) {
+        self.lock_reason = None;
+    }
+}
+
+impl Client {
+    pub fn snapshot(&self, id: ClientId) -> ClientSnapshot {
+        let mut deposits: Vec<_> = self
+            .deposits
+            .iter()
+            .map(|(transaction_id, deposit)| DepositSnapshot {
+                transaction_id: *transaction_id,
+                currency: deposit.currency,
+                amount: deposit.amount,
+                disputed: deposit.disputed,
+                withdrawal: deposit.withdrawal,
+            })
+            .collect();
+        deposits.sort_by_key(|d| d.transaction_id);
+        let mut expired: Vec<_> = self.expired.iter().copied().collect();
+        expired.sort();
+        ClientSnapshot {
+            id,
+            locked: self.locked,
+            lock_reason: self.lock_reason.clone(),
+            activity: self.activity,
+            balances: self
+                .currencies()
+                .map(|currency| BalanceSnapshot {
+                    currency,
+                    available: self.available(currency),
+                    held: self.held(currency),
+                })
+                .collect(),
+            deposits,
+            order: self.order.iter().flatten().copied().collect(),
+            expired,
+        }
+    }
+
+    /// Rebuild a client from a snapshot, checking that it satisfies the same
+    /// invariants as a client built up from transactions.
+    pub fn restore(snapshot: ClientSnapshot) -> Result<Client, SnapshotError> {
+        Client::restore_impl(snapshot, true)
+    }
+
+    /// Rebuild a client from a snapshot containing only some of its deposits,
+    /// for stores that load deposits on demand. Since the disputed deposits
+    /// may not all be present, this can't check them against the held funds.
+    pub fn restore_partial(snapshot: ClientSnapshot) -> Result<Client, SnapshotError> {
+        Client::restore_impl(snapshot, false)
+    }
+
+    fn restore_impl(snapshot: ClientSnapshot, complete: bool) -> Result<Client, SnapshotError> {
+        let id = snapshot.id;
+        let inconsistent =
+            |message: &str| SnapshotError::Inconsistent(format!("client {}: {}", id, message));
+
+        let mut client = Client {
+            locked: snapshot.locked,
+            lock_reason: snapshot.lock_reason,
+            activity: snapshot.activity,
+            ..Default::default()
+        };
+        for balance in snapshot.balances {
+            let total = balance
+                .available
+                .checked_add(balance.held.into())
+                .ok_or_else(|| inconsistent("balance would overflow"))?;
+            let currency = balance.currency;
+            let balance = Balance {
+                available: balance.available,
+                total,
+            };
+            if client.balances.insert(currency, balance).is_some() {
+                return Err(inconsistent("duplicate currency"));
+            }
+        }
+
+        let mut held = BTreeMap::new();
+        for deposit in snapshot.deposits {
+            if !client.balances.contains_key(&deposit.currency) {
+                return Err(inconsistent("deposit in a currency with no balance"));
+            }
+            if deposit.disputed {
+                let held = held.entry(deposit.currency).or_insert(Amount::ZERO);
+                *held = held
+                    .checked_add(deposit.amount)
+                    .ok_or_else(|| inconsistent("held funds would overflow"))?;
+            }
+            let previous = client.deposits.insert(
+                deposit.transaction_id,
+                Deposit {
+                    currency: deposit.currency,
+                    amount: deposit.amount,
+                    disputed: deposit.disputed,
+                    withdrawal: deposit.withdrawal,
+                },
+            );
+            if previous.is_some() {
+                return Err(inconsistent("duplicate transaction ID"));
+            }
+        }
+        // A partial snapshot may not have every deposit in the order, but
+        // the stores that take them don't expire deposits anyway.
+        if complete && !snapshot.order.is_empty() {
+            let mut order = VecDeque::new();
+            let mut ordered = HashSet::new();
+            for transaction_id in snapshot.order {
+                if !client.deposits.contains_key(&transaction_id) || !ordered.insert(transaction_id)
+                {
+                    return Err(inconsistent("deposit order doesn't match deposits"));
+                }
+                order.push_back(transaction_id);
+            }
+            if order.len() != client.deposits.len() {
+                return Err(inconsistent("deposit order doesn't match deposits"));
+            }
+            client.order = Some(order);
+        }
+        for transaction_id in snapshot.expired {
+            if client.deposits.contains_key(&transaction_id) {
+                return Err(inconsistent("expired deposit is still kept"));
+            }
+            if !client.expired.insert(transaction_id) {
+                return Err(inconsistent("duplicate expired deposit"));
+            }
+        }
+        for currency in client.currencies().filter(|_| complete) {
+            if client.held(currency) != held.get(&currency).copied().unwrap_or(Amount::ZERO) {
+                return Err(inconsistent("held funds don't match disputed deposits"));
+            }
+        }
+        Ok(client)
     }
 }
 
@@ -205,22 +904,38 @@ mod tests {
     use super::*;
 
     fn check_client(client: &Client, available: &str, held: &str, total: &str, locked: bool) {
-        assert_eq!(client.available(), Amount::try_from(available).unwrap());
-        assert_eq!(client.held(), Amount::try_from(held).unwrap());
-        assert_eq!(client.total(), Amount::try_from(total).unwrap());
+        check_balance(client, None, available, held, total);
         assert_eq!(client.locked(), locked);
+    }
+
+    fn check_balance(
+        client: &Client,
+        currency: Option<Currency>,
+        available: &str,
+        held: &str,
+        total: &str,
+    ) {
+        assert_eq!(
+            client.available(currency),
+            SignedAmount::try_from(available).unwrap()
+        );
+        assert_eq!(client.held(currency), Amount::try_from(held).unwrap());
+        assert_eq!(
+            client.total(currency),
+            SignedAmount::try_from(total).unwrap()
+        );
 
         // Check the Client invariant.
         let actual_held = client
             .deposits
             .values()
-            .filter(|d| d.disputed)
+            .filter(|d| d.disputed && d.currency == currency)
             .map(|d| d.amount)
-            .fold(Amount::default(), |acc, x| acc.checked_add(x).unwrap());
-        assert_eq!(client.held(), actual_held);
+            .sum::<Amount>();
+        assert_eq!(client.held(currency), actual_held);
         assert_eq!(
-            client.total(),
-            client.available().checked_add(client.held()).unwrap()
+            client.total(currency),
+            client.available(currency) + client.held(currency).into()
         );
     }
 
@@ -229,7 +944,11 @@ mod tests {
         // A deposit should increase the available and total funds.
         let mut client = Client::default();
         client
-            .deposit(TransactionId::new(1), Amount::try_from("1.0").unwrap())
+            .deposit(
+                TransactionId::new(1),
+                None,
+                Amount::try_from("1.0").unwrap(),
+            )
             .unwrap();
         check_client(&client, "1.0", "0.0", "1.0", false);
     }
@@ -239,10 +958,18 @@ mod tests {
         // A deposit with a duplicate transaction ID should fail.
         let mut client = Client::default();
         client
-            .deposit(TransactionId::new(1), Amount::try_from("1.0").unwrap())
+            .deposit(
+                TransactionId::new(1),
+                None,
+                Amount::try_from("1.0").unwrap(),
+            )
             .unwrap();
         assert_eq!(
-            client.deposit(TransactionId::new(1), Amount::try_from("2.0").unwrap()),
+            client.deposit(
+                TransactionId::new(1),
+                None,
+                Amount::try_from("2.0").unwrap()
+            ),
             Err(ClientError::DuplicateTransactionId)
         );
     }
@@ -252,11 +979,21 @@ mod tests {
         // A withdrawal should decrease the available and total funds.
         let mut client = Client::default();
         client
-            .deposit(TransactionId::new(1), Amount::try_from("2.0").unwrap())
+            .deposit(
+                TransactionId::new(1),
+                None,
+                Amount::try_from("2.0").unwrap(),
+            )
             .unwrap();
         check_client(&client, "2.0", "0.0", "2.0", false);
 
-        client.withdraw(Amount::try_from("1.0").unwrap()).unwrap();
+        client
+            .withdraw(
+                TransactionId::new(100),
+                None,
+                Amount::try_from("1.0").unwrap(),
+            )
+            .unwrap();
         check_client(&client, "1.0", "0.0", "1.0", false);
     }
 
@@ -266,12 +1003,20 @@ mod tests {
         // total amount of funds should not change.
         let mut client = Client::default();
         client
-            .deposit(TransactionId::new(1), Amount::try_from("1.0").unwrap())
+            .deposit(
+                TransactionId::new(1),
+                None,
+                Amount::try_from("1.0").unwrap(),
+            )
             .unwrap();
         check_client(&client, "1.0", "0.0", "1.0", false);
 
         assert_eq!(
-            client.withdraw(Amount::try_from("2.0").unwrap()),
+            client.withdraw(
+                TransactionId::new(100),
+                None,
+                Amount::try_from("2.0").unwrap()
+            ),
             Err(ClientError::InsufficientFunds)
         );
         check_client(&client, "1.0", "0.0", "1.0", false);
@@ -284,14 +1029,22 @@ mod tests {
         // change.
         let mut client = Client::default();
         client
-            .deposit(TransactionId::new(1), Amount::try_from("1.0").unwrap())
+            .deposit(
+                TransactionId::new(1),
+                None,
+                Amount::try_from("1.0").unwrap(),
+            )
             .unwrap();
         client
-            .deposit(TransactionId::new(2), Amount::try_from("2.0").unwrap())
+            .deposit(
+                TransactionId::new(2),
+                None,
+                Amount::try_from("2.0").unwrap(),
+            )
             .unwrap();
         check_client(&client, "3.0", "0.0", "3.0", false);
 
-        client.dispute(TransactionId::new(1)).unwrap();
+        client.dispute(TransactionId::new(1), None).unwrap();
         check_client(&client, "2.0", "1.0", "3.0", false);
     }
 
@@ -302,7 +1055,7 @@ mod tests {
         let mut client = Client::default();
         check_client(&client, "0.0", "0.0", "0.0", false);
         assert_eq!(
-            client.dispute(TransactionId::new(1)),
+            client.dispute(TransactionId::new(1), None),
             Err(ClientError::UnknownTransactionId)
         );
         // The client should be unchanged.
@@ -315,13 +1068,17 @@ mod tests {
         // the caller.
         let mut client = Client::default();
         client
-            .deposit(TransactionId::new(1), Amount::try_from("1.0").unwrap())
+            .deposit(
+                TransactionId::new(1),
+                None,
+                Amount::try_from("1.0").unwrap(),
+            )
             .unwrap();
         check_client(&client, "1.0", "0.0", "1.0", false);
-        client.dispute(TransactionId::new(1)).unwrap();
+        client.dispute(TransactionId::new(1), None).unwrap();
         check_client(&client, "0.0", "1.0", "1.0", false);
         assert_eq!(
-            client.dispute(TransactionId::new(1)),
+            client.dispute(TransactionId::new(1), None),
             Err(ClientError::AlreadyDisputed)
         );
         // The client should be unchanged.
@@ -333,15 +1090,29 @@ mod tests {
         // A deposit can't be disputed if the funds aren't available to hold.
         let mut client = Client::default();
         client
-            .deposit(TransactionId::new(1), Amount::try_from("2.0").unwrap())
+            .deposit(
+                TransactionId::new(1),
+                None,
+                Amount::try_from("2.0").unwrap(),
+            )
+            .unwrap();
+        client
+            .deposit(
+                TransactionId::new(2),
+                None,
+                Amount::try_from("3.0").unwrap(),
+            )
             .unwrap();
         client
-            .deposit(TransactionId::new(2), Amount::try_from("3.0").unwrap())
+            .withdraw(
+                TransactionId::new(100),
+                None,
+                Amount::try_from("4.0").unwrap(),
+            )
             .unwrap();
-        client.withdraw(Amount::try_from("4.0").unwrap()).unwrap();
         check_client(&client, "1.0", "0.0", "1.0", false);
         assert_eq!(
-            client.dispute(TransactionId::new(1)),
+            client.dispute(TransactionId::new(1), None),
             Err(ClientError::InsufficientFunds)
         );
         // The client should be unchanged.
@@ -355,17 +1126,25 @@ mod tests {
         // should not change.
         let mut client = Client::default();
         client
-            .deposit(TransactionId::new(1), Amount::try_from("1.0").unwrap())
+            .deposit(
+                TransactionId::new(1),
+                None,
+                Amount::try_from("1.0").unwrap(),
+            )
             .unwrap();
         client
-            .deposit(TransactionId::new(2), Amount::try_from("2.0").unwrap())
+            .deposit(
+                TransactionId::new(2),
+                None,
+                Amount::try_from("2.0").unwrap(),
+            )
             .unwrap();
         check_client(&client, "3.0", "0.0", "3.0", false);
 
-        client.dispute(TransactionId::new(1)).unwrap();
+        client.dispute(TransactionId::new(1), None).unwrap();
         check_client(&client, "2.0", "1.0", "3.0", false);
 
-        client.resolve(TransactionId::new(1)).unwrap();
+        client.resolve(TransactionId::new(1), None).unwrap();
         check_client(&client, "3.0", "0.0", "3.0", false);
     }
 
@@ -373,7 +1152,7 @@ mod tests {
     fn test_resolve_unknown_transaction_id() {
         let mut client = Client::default();
         assert_eq!(
-            client.resolve(TransactionId::new(1)),
+            client.resolve(TransactionId::new(1), None),
             Err(ClientError::UnknownTransactionId)
         );
     }
@@ -382,11 +1161,15 @@ mod tests {
     fn test_resolve_not_disputed() {
         let mut client = Client::default();
         client
-            .deposit(TransactionId::new(1), Amount::try_from("1.0").unwrap())
+            .deposit(
+                TransactionId::new(1),
+                None,
+                Amount::try_from("1.0").unwrap(),
+            )
             .unwrap();
         check_client(&client, "1.0", "0.0", "1.0", false);
         assert_eq!(
-            client.resolve(TransactionId::new(1)),
+            client.resolve(TransactionId::new(1), None),
             Err(ClientError::NotDisputed)
         );
         // The client should be unchanged.
@@ -400,58 +1183,139 @@ mod tests {
         // further transactions.
         let mut client = Client::default();
         client
-            .deposit(TransactionId::new(1), Amount::try_from("1.0").unwrap())
+            .deposit(
+                TransactionId::new(1),
+                None,
+                Amount::try_from("1.0").unwrap(),
+            )
             .unwrap();
         client
-            .deposit(TransactionId::new(2), Amount::try_from("2.0").unwrap())
+            .deposit(
+                TransactionId::new(2),
+                None,
+                Amount::try_from("2.0").unwrap(),
+            )
             .unwrap();
         check_client(&client, "3.0", "0.0", "3.0", false);
 
-        client.dispute(TransactionId::new(1)).unwrap();
+        client.dispute(TransactionId::new(1), None).unwrap();
         check_client(&client, "2.0", "1.0", "3.0", false);
 
-        client.chargeback(TransactionId::new(1)).unwrap();
+        client.chargeback(TransactionId::new(1), None).unwrap();
         check_client(&client, "2.0", "0.0", "2.0", true);
 
         // The account is frozen; any further transactions should fail without
         // changing client state.
 
         assert_eq!(
-            client.deposit(TransactionId::new(3), Amount::try_from("1.0").unwrap()),
+            client.deposit(
+                TransactionId::new(3),
+                None,
+                Amount::try_from("1.0").unwrap()
+            ),
             Err(ClientError::Locked)
         );
         check_client(&client, "2.0", "0.0", "2.0", true);
 
         assert_eq!(
-            client.withdraw(Amount::try_from("1.0").unwrap()),
+            client.withdraw(
+                TransactionId::new(100),
+                None,
+                Amount::try_from("1.0").unwrap()
+            ),
             Err(ClientError::Locked)
         );
         check_client(&client, "2.0", "0.0", "2.0", true);
 
         assert_eq!(
-            client.dispute(TransactionId::new(2)),
+            client.dispute(TransactionId::new(2), None),
             Err(ClientError::Locked)
         );
         check_client(&client, "2.0", "0.0", "2.0", true);
 
         assert_eq!(
-            client.resolve(TransactionId::new(2)),
+            client.resolve(TransactionId::new(2), None),
             Err(ClientError::Locked)
         );
         check_client(&client, "2.0", "0.0", "2.0", true);
 
         assert_eq!(
-            client.chargeback(TransactionId::new(2)),
+            client.chargeback(TransactionId::new(2), None),
             Err(ClientError::Locked)
         );
         check_client(&client, "2.0", "0.0", "2.0", true);
     }
 
+    #[test]
+    fn test_lock() {
+        let mut client = Client::default();
+        client
+            .deposit(
+                TransactionId::new(1),
+                None,
+                Amount::try_from("1.0").unwrap(),
+            )
+            .unwrap();
+        client.lock("suspected fraud");
+        check_client(&client, "1.0", "0.0", "1.0", true);
+        assert_eq!(client.lock_reason(), Some("suspected fraud"));
+        assert_eq!(
+            client.withdraw(
+                TransactionId::new(2),
+                None,
+                Amount::try_from("1.0").unwrap()
+            ),
+            Err(ClientError::Locked)
+        );
+        let restored = Client::restore(client.snapshot(ClientId::from(1))).unwrap();
+        assert_eq!(restored.lock_reason(), Some("suspected fraud"));
+
+        client.unlock();
+        check_client(&client, "1.0", "0.0", "1.0", false);
+        assert_eq!(client.lock_reason(), None);
+
+        // Unlocking doesn't undo a chargeback's lock.
+        client.dispute(TransactionId::new(1), None).unwrap();
+        client.chargeback(TransactionId::new(1), None).unwrap();
+        client.lock("closed");
+        client.unlock();
+        check_client(&client, "0.0", "0.0", "0.0", true);
+    }
+
+    #[test]
+    fn test_activity() {
+        let mut client = Client::default();
+        let amount = Amount::try_from("1.0").unwrap();
+        client.deposit(TransactionId::new(1), None, amount).unwrap();
+        client.deposit(TransactionId::new(2), None, amount).unwrap();
+        client
+            .withdraw(TransactionId::new(3), None, amount)
+            .unwrap();
+        client.dispute(TransactionId::new(1), None).unwrap();
+        client.resolve(TransactionId::new(1), None).unwrap();
+        client.dispute(TransactionId::new(1), None).unwrap();
+        client.chargeback(TransactionId::new(1), None).unwrap();
+        // Rejected transactions aren't counted.
+        assert!(client.deposit(TransactionId::new(4), None, amount).is_err());
+        let expected = ClientActivity {
+            deposits: 2,
+            withdrawals: 1,
+            disputes: 2,
+            resolves: 1,
+            chargebacks: 1,
+            ..Default::default()
+        };
+        assert_eq!(client.activity(), expected);
+
+        let restored = Client::restore(client.snapshot(ClientId::from(1))).unwrap();
+        assert_eq!(restored.activity(), expected);
+    }
+
     #[test]
     fn test_chargeback_unknown_transaction_id() {
         let mut client = Client::default();
         assert_eq!(
-            client.chargeback(TransactionId::new(1)),
+            client.chargeback(TransactionId::new(1), None),
             Err(ClientError::UnknownTransactionId)
         );
     }
@@ -460,11 +1324,15 @@ mod tests {
     fn test_chargeback_not_disputed() {
         let mut client = Client::default();
         client
-            .deposit(TransactionId::new(1), Amount::try_from("1.0").unwrap())
+            .deposit(
+                TransactionId::new(1),
+                None,
+                Amount::try_from("1.0").unwrap(),
+            )
             .unwrap();
         check_client(&client, "1.0", "0.0", "1.0", false);
         assert_eq!(
-            client.chargeback(TransactionId::new(1)),
+            client.chargeback(TransactionId::new(1), None),
             Err(ClientError::NotDisputed)
         );
         // The client should be unchanged.
@@ -477,16 +1345,234 @@ mod tests {
         // even if the available funds wouldn't overflow.
         let mut client = Client::default();
 
-        let amount = {
-            let mut s = u64::MAX.to_string();
-            s.insert(s.len() - 4, '.');
-            Amount::try_from(s.as_str()).unwrap()
-        };
-        client.deposit(TransactionId::new(1), amount).unwrap();
-        client.dispute(TransactionId::new(1)).unwrap();
+        client
+            .deposit(TransactionId::new(1), None, Amount::MAX)
+            .unwrap();
+        client.dispute(TransactionId::new(1), None).unwrap();
         assert_eq!(
-            client.deposit(TransactionId::new(2), Amount::try_from("1.0").unwrap()),
+            client.deposit(
+                TransactionId::new(2),
+                None,
+                Amount::try_from("1.0").unwrap()
+            ),
             Err(ClientError::Overflow)
         );
     }
+
+    fn currency(s: &str) -> Option<Currency> {
+        Some(Currency::try_from(s).unwrap())
+    }
+
+    #[test]
+    fn test_currencies_are_independent() {
+        // Deposits in different currencies go into separate balances, and
+        // funds in one currency can't be withdrawn in another.
+        let mut client = Client::default();
+        client
+            .deposit(
+                TransactionId::new(1),
+                currency("USD"),
+                Amount::try_from("1.0").unwrap(),
+            )
+            .unwrap();
+        client
+            .deposit(
+                TransactionId::new(2),
+                currency("EUR"),
+                Amount::try_from("2.0").unwrap(),
+            )
+            .unwrap();
+        check_balance(&client, currency("USD"), "1.0", "0.0", "1.0");
+        check_balance(&client, currency("EUR"), "2.0", "0.0", "2.0");
+        check_client(&client, "0.0", "0.0", "0.0", false);
+        assert_eq!(
+            client.currencies().collect::<Vec<_>>(),
+            vec![currency("EUR"), currency("USD")]
+        );
+
+        assert_eq!(
+            client.withdraw(
+                TransactionId::new(100),
+                currency("USD"),
+                Amount::try_from("2.0").unwrap()
+            ),
+            Err(ClientError::InsufficientFunds)
+        );
+        assert_eq!(
+            client.withdraw(
+                TransactionId::new(100),
+                currency("GBP"),
+                Amount::try_from("1.0").unwrap()
+            ),
+            Err(ClientError::InsufficientFunds)
+        );
+        // A failed withdrawal shouldn't leave behind an empty balance.
+        assert_eq!(client.currencies().count(), 2);
+
+        client
+            .withdraw(
+                TransactionId::new(100),
+                currency("EUR"),
+                Amount::try_from("2.0").unwrap(),
+            )
+            .unwrap();
+        check_balance(&client, currency("USD"), "1.0", "0.0", "1.0");
+        check_balance(&client, currency("EUR"), "0.0", "0.0", "0.0");
+    }
+
+    #[test]
+    fn test_dispute_currency() {
+        // A dispute holds funds in the currency of the original deposit. It
+        // may omit the currency, but if it gives one it must match.
+        let mut client = Client::default();
+        client
+            .deposit(
+                TransactionId::new(1),
+                currency("USD"),
+                Amount::try_from("1.0").unwrap(),
+            )
+            .unwrap();
+        client
+            .deposit(
+                TransactionId::new(2),
+                currency("EUR"),
+                Amount::try_from("2.0").unwrap(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            client.dispute(TransactionId::new(1), currency("EUR")),
+            Err(ClientError::CurrencyMismatch)
+        );
+        check_balance(&client, currency("USD"), "1.0", "0.0", "1.0");
+
+        client
+            .dispute(TransactionId::new(1), currency("USD"))
+            .unwrap();
+        check_balance(&client, currency("USD"), "0.0", "1.0", "1.0");
+        check_balance(&client, currency("EUR"), "2.0", "0.0", "2.0");
+
+        assert_eq!(
+            client.resolve(TransactionId::new(1), currency("EUR")),
+            Err(ClientError::CurrencyMismatch)
+        );
+        client.dispute(TransactionId::new(2), None).unwrap();
+        client.chargeback(TransactionId::new(2), None).unwrap();
+        check_balance(&client, currency("USD"), "0.0", "1.0", "1.0");
+        check_balance(&client, currency("EUR"), "0.0", "0.0", "0.0");
+        assert!(client.locked());
+    }
+
+    #[test]
+    fn test_chargeback_events() {
+        // A chargeback emits an event for the chargeback itself and another
+        // for locking the account, and replaying the events rebuilds the same
+        // client.
+        let mut client = Client::default();
+        let mut events = Vec::new();
+        let amount = Amount::try_from("1.0").unwrap();
+        let tx = TransactionId::new(1);
+        events.extend(client.deposit(tx, None, amount).unwrap());
+        events.extend(client.dispute(tx, None).unwrap());
+        let chargeback = client.chargeback(tx, None).unwrap();
+        assert_eq!(
+            chargeback,
+            vec![
+                Event::ChargedBack { transaction_id: tx },
+                Event::AccountLocked { transaction_id: tx },
+            ]
+        );
+        events.extend(chargeback);
+
+        let mut replayed = Client::default();
+        for event in &events {
+            replayed.apply(event).unwrap();
+        }
+        assert_eq!(
+            replayed.snapshot(ClientId::from(1)),
+            client.snapshot(ClientId::from(1))
+        );
+    }
+
+    #[test]
+    fn test_expire_deposits() {
+        // Only the most recent deposits can be disputed, apart from any that
+        // are already disputed, which are kept.
+        let mut client = Client::default();
+        let one = Amount::try_from("1.0").unwrap();
+        // The order deposits are made in is tracked from the first expiry.
+        assert_eq!(client.expire_deposits(3), []);
+        for id in [5, 4, 3] {
+            client.deposit(TransactionId::new(id), None, one).unwrap();
+        }
+        client.dispute(TransactionId::new(5), None).unwrap();
+        assert_eq!(client.expire_deposits(3), []);
+        client.deposit(TransactionId::new(1), None, one).unwrap();
+        assert_eq!(
+            client.expire_deposits(2),
+            [
+                Event::DepositExpired {
+                    transaction_id: TransactionId::new(4)
+                },
+                Event::DepositExpired {
+                    transaction_id: TransactionId::new(3)
+                },
+            ]
+        );
+        // Expiring doesn't change the balances.
+        check_client(&client, "3.0", "1.0", "4.0", false);
+
+        assert_eq!(
+            client.dispute(TransactionId::new(3), None),
+            Err(ClientError::TooOldToDispute)
+        );
+        assert_eq!(
+            client.dispute(TransactionId::new(9), None),
+            Err(ClientError::UnknownTransactionId)
+        );
+        assert_eq!(
+            client.deposit(TransactionId::new(4), None, one),
+            Err(ClientError::DuplicateTransactionId)
+        );
+        client.resolve(TransactionId::new(5), None).unwrap();
+        client.dispute(TransactionId::new(1), None).unwrap();
+        // The disputed deposit is kept even though it's the newest.
+        assert_eq!(
+            client.expire_deposits(1),
+            [Event::DepositExpired {
+                transaction_id: TransactionId::new(5)
+            }]
+        );
+        check_client(&client, "3.0", "1.0", "4.0", false);
+
+        // Every expired deposit is remembered, and the order of those kept
+        // survives a snapshot. IDs that were never used are still unknown,
+        // however low.
+        client.deposit(TransactionId::new(2), None, one).unwrap();
+        let snapshot = client.snapshot(ClientId::from(1));
+        assert_eq!(snapshot.expired, [3, 4, 5].map(TransactionId::new));
+        assert_eq!(snapshot.order, [1, 2].map(TransactionId::new));
+        let mut client = Client::restore(snapshot).unwrap();
+        for id in [3, 4, 5] {
+            assert_eq!(
+                client.dispute(TransactionId::new(id), None),
+                Err(ClientError::TooOldToDispute)
+            );
+            assert_eq!(
+                client.deposit(TransactionId::new(id), None, one),
+                Err(ClientError::DuplicateTransactionId)
+            );
+        }
+        assert_eq!(
+            client.dispute(TransactionId::new(0), None),
+            Err(ClientError::UnknownTransactionId)
+        );
+        client.resolve(TransactionId::new(1), None).unwrap();
+        assert_eq!(
+            client.expire_deposits(1),
+            [Event::DepositExpired {
+                transaction_id: TransactionId::new(1)
+            }]
+        );
+    }
 }
@@ -1,61 +1,2460 @@
-use serde::Serialize;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
 
-use crate::client::{Client, ClientError};
-use crate::transaction::{ClientId, Transaction, TransactionData};
-use crate::Amount;
+use crate::amount::{AmountFormat, DisplayAmount};
+use crate::client::{Client, ClientActivity, ClientError, HistoryEntry, Seen};
+use crate::event::{ClientEvent, Event};
+use crate::keys::ClientKeys;
+use crate::metadata::ClientMetadata;
+use crate::policy::{DefaultPolicy, Policy};
+use crate::risk::{Risk, RiskAlert, RiskLimits};
+use crate::snapshot::{
+    BalanceSnapshot, ClientSnapshot, DepositSnapshot, ProcessedTransaction, Snapshot,
+    SnapshotError, SnapshotFormat,
+};
+use crate::store::{MemoryStore, Store, StoreError};
+use crate::transaction::{
+    ClientFilter, ClientId, Id, Transaction, TransactionData, TransactionKind,
+};
+use crate::{Amount, Currency, SignedAmount, TransactionId};
 
-pub struct Clients {
-    clients: HashMap<ClientId, Client>,
+/// Options controlling how the summary is written.
+#[derive(Debug, Default, Clone)]
+pub struct WriteOptions {
+    pub amount_format: AmountFormat,
+    /// Only write these clients.
+    pub clients: ClientFilter,
+    /// Follow the clients with a row of totals for each currency; see
+    /// `Clients::write_totals`.
+    pub totals: bool,
+    /// Add each client's name, email, and tier from this as the last columns
+    /// of the summary and held funds breakdown.
+    pub metadata: Option<Arc<ClientMetadata>>,
+    /// Add each client's counts of accepted deposits, withdrawals, disputes,
+    /// resolves, and chargebacks, and when it was first and last active, as
+    /// the last columns of the summary.
+    pub extended: bool,
+    /// Write each client as the key it was given as in the input, rather
+    /// than its ID; see `LoadOptions::client_keys`.
+    pub client_keys: Option<Arc<ClientKeys>>,
+}
+
+impl WriteOptions {
+    /// The client column for `client`.
+    fn client(&self, client: ClientId) -> String {
+        self.client_keys
+            .as_ref()
+            .and_then(|keys| keys.key(client))
+            .map_or_else(|| client.to_string(), |key| key.to_string())
+    }
+
+    /// The name, email, and tier columns for `client`, if there are any.
+    fn info(&self, client: Option<ClientId>) -> [Option<String>; 3] {
+        match &self.metadata {
+            Some(metadata) => {
+                let info = client.map(|id| metadata.get(id)).unwrap_or_default();
+                [Some(info.name), Some(info.email), Some(info.tier)]
+            }
+            None => [None, None, None],
+        }
+    }
+
+    /// The activity columns for `activity`, if they're being written.
+    fn counts(&self, activity: ClientActivity) -> [Option<u64>; 5] {
+        if !self.extended {
+            return [None; 5];
+        }
+        [
+            Some(activity.deposits),
+            Some(activity.withdrawals),
+            Some(activity.disputes),
+            Some(activity.resolves),
+            Some(activity.chargebacks),
+        ]
+    }
+
+    /// The first and last activity columns for `activity`, if they're being
+    /// written: the time of the transaction if it had one, otherwise its line
+    /// in the input, or empty if neither is known.
+    fn seen(&self, activity: ClientActivity) -> [Option<String>; 2] {
+        if !self.extended {
+            return [None, None];
+        }
+        let format = |seen: Option<Seen>| match seen {
+            Some(Seen {
+                timestamp: Some(timestamp),
+                ..
+            }) => format_time(timestamp),
+            Some(Seen {
+                line: Some(line), ..
+            }) => line.to_string(),
+            _ => String::new(),
+        };
+        [Some(format(activity.first)), Some(format(activity.last))]
+    }
+}
+
+/// `timestamp`, in seconds since the Unix epoch, in RFC 3339 in UTC, e.g.
+/// `2024-05-01T09:30:00Z`.
+fn format_time(timestamp: u64) -> String {
+    let Some(time) = i64::try_from(timestamp)
+        .ok()
+        .and_then(|timestamp| time::OffsetDateTime::from_unix_timestamp(timestamp).ok())
+    else {
+        return timestamp.to_string();
+    };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        time.year(),
+        u8::from(time.month()),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
+
+/// The sums of the summary rows in one currency, e.g. to reconcile against a
+/// general ledger.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Totals {
+    pub currency: Option<Currency>,
+    pub available: SignedAmount,
+    pub held: Amount,
+    pub total: SignedAmount,
+    /// The number of rows summed, i.e. of clients with a balance in the
+    /// currency, and how many of them are locked.
+    pub clients: u64,
+    pub locked: u64,
+    /// The sum of the activity of the clients counted.
+    pub activity: ClientActivity,
+}
+
+/// A row of the summary: a client's balances in one currency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SummaryRow {
+    pub client: ClientId,
+    pub currency: Option<Currency>,
+    pub available: SignedAmount,
+    pub held: Amount,
+    pub total: SignedAmount,
+    pub locked: bool,
+}
+
+/// Called with each event caused by an accepted transaction, along with the
+/// transaction's client and currency and the client's state afterwards.
+pub type Listener<C = ClientId, T = TransactionId> =
+    Box<dyn FnMut(C, Option<Currency>, &Event<T>, &Client<C, T>) + Send>;
+
+/// Every client's account. Generic over the client and transaction IDs, for
+/// embedders with their own, e.g. `u64` or a composite key; see `Id`. Only
+/// processing is generic: snapshots and the summary are for the default
+/// IDs.
+pub struct Clients<C: Id = ClientId, T: Id = TransactionId> {
+    store: Box<dyn Store<C, T>>,
+    // Whether any transaction has specified a currency. If not, the summary
+    // is written without a currency column, exactly as for single-currency
+    // input.
+    multi_currency: bool,
+    listeners: Vec<Listener<C, T>>,
+    // The rules transactions are accepted by; see `set_policy`.
+    policy: Arc<dyn Policy<C, T>>,
+    // The client, ID, and type of every transaction processed, accepted or
+    // rejected, if processing is idempotent; see `set_idempotent`.
+    processed: Option<HashSet<(C, T, TransactionKind)>>,
+    // The reference of every accepted transaction given one by
+    // `set_reference`, with its client.
+    references: HashSet<(C, String)>,
+    // How many transactions have been skipped as already processed.
+    duplicates: u64,
+    pending: Option<Pending<C, T>>,
+    // The most deposits to keep for each client; see `set_max_deposits`.
+    max_deposits: Option<usize>,
+    // Each client's recent disputes, and the limits on them, if any; see
+    // `set_risk_limits`.
+    risk: Risk<C, T>,
+    // Whether each client keeps a history; see `set_keep_history`.
+    keep_history: bool,
+    // How many transactions have been processed, for ageing disputes.
+    clock: u64,
+    // The latest time given to `set_time`, if any.
+    time: Option<u64>,
+    // The line given to `set_line`, if any.
+    line: Option<u64>,
+    // The memo given to `set_memo`, until the transaction it's for.
+    memo: Option<String>,
+    // The reference given to `set_reference`, until the transaction it's for.
+    reference: Option<String>,
+    // The key given to `set_record_key`, until the transaction it's for.
+    record_key: Option<String>,
+    // When each open dispute was opened, if disputes are tracked; see
+    // `set_track_disputes`.
+    disputes: Option<HashMap<(C, T), Opened>>,
+}
+
+/// When a dispute was opened.
+#[derive(Debug, Clone, Copy)]
+struct Opened {
+    // The number of transactions processed before it.
+    transaction: u64,
+    timestamp: Option<u64>,
+}
+
+/// The buckets of the dispute ageing report, by the most days or transactions
+/// ago a dispute in each was opened, exclusive.
+const DAY_BUCKETS: [(u64, &str); 5] = [
+    (1, "under 1 day"),
+    (7, "1-7 days"),
+    (30, "7-30 days"),
+    (90, "30-90 days"),
+    (u64::MAX, "over 90 days"),
+];
+const TRANSACTION_BUCKETS: [(u64, &str); 5] = [
+    (1_000, "under 1000 transactions"),
+    (10_000, "1000-10000 transactions"),
+    (100_000, "10000-100000 transactions"),
+    (1_000_000, "100000-1000000 transactions"),
+    (u64::MAX, "over 1000000 transactions"),
+];
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A dispute, resolve, or chargeback held for the deposit it refers to; see
+/// `Clients::set_pending_limit`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Parked<C = ClientId, T = TransactionId> {
+    pub transaction: Transaction<C, T>,
+    /// The line it was on, if given by `Clients::set_line`.
+    pub line: Option<u64>,
+}
+
+/// Disputes, resolves, and chargebacks waiting for the deposit they refer
+/// to; see `Clients::set_pending_limit`.
+struct Pending<C, T> {
+    limit: usize,
+    // The number of transactions in `waiting`.
+    len: usize,
+    // By client and deposit, in the order they arrived.
+    waiting: HashMap<(C, T), Vec<Parked<C, T>>>,
+    // Applied when they were retried, until taken by `take_released`.
+    released: Vec<Parked<C, T>>,
+    // Rejected when they were retried.
+    failed: Vec<(Parked<C, T>, ClientError)>,
+}
+
+impl<C: Id, T: Id> Pending<C, T> {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            len: 0,
+            waiting: HashMap::new(),
+            released: Vec::new(),
+            failed: Vec::new(),
+        }
+    }
+
+    /// Hold `transaction`, from `line`, until its deposit arrives, returning
+    /// false if it can't be held.
+    fn park(&mut self, transaction: &Transaction<C, T>, line: Option<u64>) -> bool {
+        if self.len >= self.limit || transaction.data.amount().is_some() {
+            return false;
+        }
+        self.len += 1;
+        self.waiting
+            .entry((transaction.client_id, transaction.data.transaction_id()))
+            .or_default()
+            .push(Parked {
+                transaction: transaction.clone(),
+                line,
+            });
+        true
+    }
+
+    fn take(&mut self, client: C, deposit: T) -> Vec<Parked<C, T>> {
+        let waiting = self.waiting.remove(&(client, deposit)).unwrap_or_default();
+        self.len -= waiting.len();
+        waiting
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProcessError {
+    // The transaction was rejected, e.g. for insufficient funds. This is
+    // expected to happen in normal operation.
+    #[error(transparent)]
+    Rejected(#[from] ClientError),
+    #[error("store error: {0}")]
+    Store(#[from] StoreError),
+    // The deposit the transaction refers to hasn't been seen, so it's held
+    // until it is, and is neither accepted nor rejected yet; see
+    // `Clients::set_pending_limit`.
+    #[error("waiting for the deposit it refers to")]
+    Pending,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SeedError {
+    #[error("csv error: {0}")]
+    Csv(#[from] csv::Error),
+    #[error(transparent)]
+    Snapshot(#[from] SnapshotError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WriteError {
+    #[error("csv error: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("store error: {0}")]
+    Store(#[from] StoreError),
+    #[error("totals would overflow")]
+    TotalsOverflow,
+}
+
+impl Default for Clients {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Clients {
+    /// Create an empty set of clients, kept in memory.
     pub fn new() -> Self {
+        Self::with_store(Box::new(MemoryStore::new()), false)
+    }
+}
+
+impl<C: Id, T: Id> Clients<C, T> {
+    /// Use the clients in `store`, which may already contain some.
+    pub fn open(store: Box<dyn Store<C, T>>) -> Result<Self, StoreError> {
+        let mut multi_currency = false;
+        for id in store.client_ids()? {
+            if let Some(client) = store.get(id, false)? {
+                multi_currency |= client.currencies().any(|c| c.is_some());
+            }
+        }
+        Ok(Self::with_store(store, multi_currency))
+    }
+
+    /// Clients in `store`, with every setting at its default.
+    fn with_store(store: Box<dyn Store<C, T>>, multi_currency: bool) -> Self {
         Self {
-            clients: HashMap::new(),
+            store,
+            multi_currency,
+            listeners: Vec::new(),
+            policy: Arc::new(DefaultPolicy),
+            processed: None,
+            references: HashSet::new(),
+            duplicates: 0,
+            pending: None,
+            max_deposits: None,
+            risk: Risk::default(),
+            keep_history: false,
+            clock: 0,
+            time: None,
+            line: None,
+            memo: None,
+            reference: None,
+            record_key: None,
+            disputes: None,
         }
     }
 
-    pub fn process_transaction(&mut self, transaction: Transaction) -> Result<(), ClientError> {
-        let client = self.clients.entry(transaction.client_id).or_default();
-        match transaction.data {
-            TransactionData::Deposit {
-                transaction_id,
-                amount,
-            } => client.deposit(transaction_id, amount),
+    /// Process a transaction, returning the events it caused.
+    pub fn process_transaction(
+        &mut self,
+        transaction: Transaction<C, T>,
+    ) -> Result<Vec<Event<T>>, ProcessError> {
+        let reference = self
+            .reference
+            .take()
+            .map(|reference| (transaction.client_id, reference));
+        let record_key = self.record_key.take();
+        let key = (
+            transaction.client_id,
+            transaction.data.transaction_id(),
+            transaction.data.kind(),
+        );
+        if self
+            .processed
+            .as_ref()
+            .is_some_and(|processed| processed.contains(&key))
+            || reference
+                .as_ref()
+                .is_some_and(|reference| self.references.contains(reference))
+            || match &record_key {
+                Some(key) => self.store.is_consumed(key)?,
+                None => false,
+            }
+        {
+            tracing::debug!(
+                client = %transaction.client_id,
+                tx = %transaction.data.transaction_id(),
+                "skipping transaction already processed"
+            );
+            self.duplicates += 1;
+            self.memo = None;
+            return Ok(Vec::new());
+        }
+        self.clock += 1;
+        // Every transaction updates the store, even if it's rejected, so the
+        // key is stored along with it.
+        if let Some(key) = record_key {
+            self.store.mark_consumed(key);
+        }
+        let result = self.apply_transaction(&transaction);
+        if let Some(processed) = self
+            .processed
+            .as_mut()
+            .filter(|_| !matches!(result, Err(ProcessError::Store(_))))
+        {
+            processed.insert(key);
+            // A deposit can be disputed again once its dispute is resolved, so
+            // a resolve forgets the dispute, and itself, and a dispute the
+            // resolve before it.
+            let client_id = transaction.client_id;
+            for event in result.iter().flatten() {
+                match *event {
+                    Event::DisputeOpened { transaction_id, .. } => {
+                        processed.remove(&(client_id, transaction_id, TransactionKind::Resolve));
+                    }
+                    Event::DisputeResolved { transaction_id } => {
+                        processed.remove(&(client_id, transaction_id, TransactionKind::Dispute));
+                        processed.remove(&(client_id, transaction_id, TransactionKind::Resolve));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        // Only accepted transactions, and those held for their deposit, so
+        // that a retry of one that was rejected, e.g. for insufficient funds,
+        // is applied.
+        if let Some(reference) =
+            reference.filter(|_| matches!(result, Ok(_) | Err(ProcessError::Pending)))
+        {
+            self.references.insert(reference);
+        }
+        result
+    }
 
-            TransactionData::Withdrawal { amount, .. } => client.withdraw(amount),
-            TransactionData::Dispute { transaction_id } => client.dispute(transaction_id),
-            TransactionData::Resolve { transaction_id } => client.resolve(transaction_id),
-            TransactionData::Chargeback { transaction_id } => client.chargeback(transaction_id),
+    fn apply_transaction(
+        &mut self,
+        transaction: &Transaction<C, T>,
+    ) -> Result<Vec<Event<T>>, ProcessError> {
+        let currency = transaction.currency;
+        self.multi_currency |= currency.is_some();
+        let client_id = transaction.client_id;
+        let data = transaction.data;
+        let max_deposits = self.max_deposits;
+        let keep_history = self.keep_history;
+        let policy = self.policy.clone();
+        let risk = &mut self.risk;
+        let time = self.time;
+        // Only the transaction itself has its memo, not any it lets through
+        // from the pending queue.
+        let mut memo = self.memo.take();
+        let seen = Seen {
+            line: self.line,
+            timestamp: time,
+        };
+        let mut events = Vec::new();
+        let result = self.store.update(
+            transaction.client_id,
+            Some(data.transaction_id()),
+            &mut |client| {
+                events = client.process(transaction, &*policy)?;
+                if let (TransactionData::Deposit { .. }, Some(max)) = (data, max_deposits) {
+                    events.extend(client.expire_deposits(max));
+                }
+                let alert = risk.record(client_id, client, &events);
+                if let Some(alert) = alert {
+                    tracing::warn!(client = %client_id, tx = %alert.transaction_id, rule = alert.rule, "risk limit exceeded");
+                    if !client.locked() {
+                        let event = Event::AccountLocked {
+                            transaction_id: alert.transaction_id,
+                        };
+                        client.apply(&event).expect("an account can always be locked");
+                        events.push(event);
+                    }
+                }
+                client.mark_active(seen);
+                if keep_history {
+                    client.record(HistoryEntry {
+                        transaction: transaction.clone(),
+                        time,
+                        memo: memo.take(),
+                    });
+                }
+                Ok(())
+            },
+        )?;
+        if result == Err(ClientError::UnknownTransactionId) {
+            if let Some(pending) = &mut self.pending {
+                if pending.park(transaction, self.line) {
+                    tracing::debug!(
+                        client = %client_id,
+                        tx = %data.transaction_id(),
+                        "waiting for deposit"
+                    );
+                    return Err(ProcessError::Pending);
+                }
+            }
+        }
+        #[cfg(feature = "otel")]
+        crate::telemetry::record_processed(result.as_ref().map(|_| ()));
+        if let Err(error) = result {
+            tracing::debug!(
+                client = %client_id,
+                tx = %data.transaction_id(),
+                %error,
+                "transaction rejected"
+            );
+            return Err(error.into());
+        }
+        for event in &events {
+            if let Event::AccountLocked { transaction_id } = event {
+                tracing::warn!(client = %client_id, tx = %transaction_id, "account locked");
+            }
         }
+        if let Some(disputes) = &mut self.disputes {
+            for event in &events {
+                match *event {
+                    Event::DisputeOpened { transaction_id, .. } => {
+                        let opened = Opened {
+                            transaction: self.clock,
+                            timestamp: self.time,
+                        };
+                        disputes.insert((client_id, transaction_id), opened);
+                    }
+                    Event::DisputeResolved { transaction_id }
+                    | Event::ChargedBack { transaction_id }
+                    | Event::DepositExpired { transaction_id } => {
+                        disputes.remove(&(client_id, transaction_id));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if !self.listeners.is_empty() {
+            let client = self
+                .store
+                .get(client_id, false)?
+                .expect("client exists after an accepted transaction");
+            for listener in &mut self.listeners {
+                for event in &events {
+                    listener(client_id, currency, event, &client);
+                }
+            }
+        }
+        if let TransactionData::Deposit { transaction_id, .. } = data {
+            let waiting = self
+                .pending
+                .as_mut()
+                .map(|pending| pending.take(client_id, transaction_id))
+                .unwrap_or_default();
+            for waiting in waiting {
+                match self.apply_transaction(&waiting.transaction) {
+                    Ok(more) => {
+                        events.extend(more);
+                        if let Some(pending) = &mut self.pending {
+                            pending.released.push(waiting);
+                        }
+                    }
+                    Err(ProcessError::Rejected(error)) => {
+                        if let Some(pending) = &mut self.pending {
+                            pending.failed.push((waiting, error));
+                        }
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(events)
     }
 
-    pub fn write(&self, writer: impl std::io::Write) -> Result<(), csv::Error> {
-        #[derive(Serialize)]
+    /// Keep at most `max` deposits for each client, expiring the oldest once
+    /// there are more, so that memory use doesn't grow with the number of
+    /// deposits. Disputes of expired deposits are rejected with
+    /// `TooOldToDispute`.
+    ///
+    /// Expiring a deposit touches a deposit other than the one being made,
+    /// which stores that don't keep every deposit in memory can't do, so
+    /// this is only for the in-memory store.
+    pub fn set_max_deposits(&mut self, max: usize) {
+        self.max_deposits = Some(max);
+    }
+
+    pub fn max_deposits(&self) -> Option<usize> {
+        self.max_deposits
+    }
+
+    /// Lock an account as soon as a dispute takes it over `limits`, if given,
+    /// raising a `RiskAlert` to be collected with `take_alerts`. Each client's
+    /// window of recent transactions is kept if the limits change, including
+    /// those restored from a snapshot.
+    pub fn set_risk_limits(&mut self, limits: Option<RiskLimits>) {
+        self.risk.set_limits(limits);
+    }
+
+    pub fn risk_limits(&self) -> Option<RiskLimits> {
+        self.risk.limits()
+    }
+
+    /// The alerts raised by `set_risk_limits` since they were last taken,
+    /// oldest first.
+    pub fn take_alerts(&mut self) -> Vec<RiskAlert<C, T>> {
+        self.risk.take_alerts()
+    }
+
+    /// Keep each client's accepted transactions, so that `Client::history`
+    /// can say what happened on an account. The history isn't kept in
+    /// snapshots, and only the in-memory store keeps it between
+    /// transactions, as the others only store what's in a snapshot.
+    pub fn set_keep_history(&mut self) {
+        self.keep_history = true;
+    }
+
+    pub fn keeps_history(&self) -> bool {
+        self.keep_history
+    }
+
+    /// Note when each dispute is opened, so that `write_dispute_ageing` can
+    /// say how long open disputes have been waiting. Disputes opened before
+    /// this, e.g. restored from a snapshot, are of unknown age.
+    pub fn set_track_disputes(&mut self) {
+        self.disputes.get_or_insert_with(HashMap::new);
+    }
+
+    pub fn tracks_disputes(&self) -> bool {
+        self.disputes.is_some()
+    }
+
+    /// Accept transactions by the rules of `policy` rather than the
+    /// `DefaultPolicy`.
+    pub fn set_policy(&mut self, policy: Arc<dyn Policy<C, T>>) {
+        self.policy = policy;
+    }
+
+    pub fn policy(&self) -> &Arc<dyn Policy<C, T>> {
+        &self.policy
+    }
+
+    /// Set the time of the transactions about to be processed, in seconds
+    /// since the Unix epoch, e.g. from the input's `timestamp` column. Times
+    /// earlier than one already given are ignored, so that out of order
+    /// input doesn't make disputes younger.
+    pub fn set_time(&mut self, timestamp: u64) {
+        self.time = Some(self.time.map_or(timestamp, |time| time.max(timestamp)));
+    }
+
+    /// Set the line of the input the transaction about to be processed is on,
+    /// for when clients were first and last active; see `ClientActivity`.
+    pub fn set_line(&mut self, line: u64) {
+        self.line = Some(line);
+    }
+
+    /// Set the memo of the transaction about to be processed, e.g. from the
+    /// input's `memo` column, to keep in its client's history. Only that
+    /// transaction has it.
+    pub fn set_memo(&mut self, memo: impl Into<String>) {
+        self.memo = Some(memo.into());
+    }
+
+    /// Set the reference of the transaction about to be processed, the
+    /// upstream system's own key for it, e.g. from the input's `reference`
+    /// column. If its client already has an accepted transaction with the
+    /// same reference, it's skipped as already processed, whatever its
+    /// transaction ID, so that a partner can safely replay records under its
+    /// own keys, and retry those that were rejected. References are kept in
+    /// snapshots, and in the write-ahead log.
+    pub fn set_reference(&mut self, reference: impl Into<String>) {
+        self.reference = Some(reference.into());
+    }
+
+    /// Set the key of the record about to be processed, e.g. a message
+    /// broker's ID for the message it came in and its position in it. If the
+    /// store already has a record with the same key, accepted or rejected,
+    /// it's skipped as already processed. Otherwise the key is stored in the
+    /// same commit as the transaction's effects, so that a record delivered
+    /// again after a crash is applied exactly once, however the crash fell.
+    pub fn set_record_key(&mut self, key: impl Into<String>) {
+        self.record_key = Some(key.into());
+    }
+
+    /// Hold up to `limit` disputes, resolves, and chargebacks of deposits that
+    /// haven't been seen, rather than rejecting them, and retry them once the
+    /// deposit is accepted. Processing one that's held returns
+    /// `ProcessError::Pending`. Once retried, those applied are returned by
+    /// `take_released`, with their events along with the deposit's, and
+    /// those rejected by `take_unmatched`. Pending transactions aren't kept
+    /// in snapshots.
+    pub fn set_pending_limit(&mut self, limit: usize) {
+        self.pending = Some(Pending::new(limit));
+    }
+
+    pub fn has_pending_queue(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    pub fn pending_limit(&self) -> Option<usize> {
+        self.pending.as_ref().map(|pending| pending.limit)
+    }
+
+    /// The transactions that were held for a deposit and applied once it
+    /// arrived, since they were last taken, in the order they were applied.
+    pub fn take_released(&mut self) -> Vec<Parked<C, T>> {
+        self.pending
+            .as_mut()
+            .map(|pending| std::mem::take(&mut pending.released))
+            .unwrap_or_default()
+    }
+
+    /// The transactions that were held for a deposit but can't be applied:
+    /// those whose deposit never arrived, rejected with
+    /// `UnknownTransactionId`, and those rejected when they were retried.
+    /// They're removed, so that this is the final word on them.
+    pub fn take_unmatched(&mut self) -> Vec<(Parked<C, T>, ClientError)> {
+        let Some(pending) = &mut self.pending else {
+            return Vec::new();
+        };
+        let mut waiting: Vec<_> = std::mem::take(&mut pending.waiting).into_iter().collect();
+        waiting.sort_by_key(|(key, _)| *key);
+        pending.len = 0;
+        let mut unmatched: Vec<_> = waiting
+            .into_iter()
+            .flat_map(|(_, parked)| parked)
+            .map(|parked| (parked, ClientError::UnknownTransactionId))
+            .collect();
+        unmatched.append(&mut pending.failed);
+        unmatched
+    }
+
+    /// Skip any transaction with the same client, ID, and type as one already
+    /// processed, whether it was accepted or rejected, so that the same input
+    /// can be processed again safely, e.g. after a partial failure. Resolving
+    /// a dispute forgets the dispute and the resolve, so that the deposit can
+    /// be disputed again; processing the same input again then disputes and
+    /// resolves it again, ending in the same state. The transactions
+    /// processed are kept in snapshots, and restoring a snapshot that has
+    /// them makes processing idempotent again.
+    pub fn set_idempotent(&mut self) {
+        self.processed.get_or_insert_with(HashSet::new);
+    }
+
+    pub fn is_idempotent(&self) -> bool {
+        self.processed.is_some()
+    }
+
+    /// How many transactions have been skipped as already processed, by
+    /// `set_idempotent`, `set_reference`, or `set_record_key`.
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    /// Call `listener` for every event caused by transactions processed from
+    /// now on. Events applied directly, e.g. when replaying, aren't included.
+    pub fn add_listener(&mut self, listener: Listener<C, T>) {
+        self.listeners.push(listener);
+    }
+
+    /// The IDs of every client, in order.
+    pub fn client_ids(&self) -> Result<Vec<C>, StoreError> {
+        self.store.client_ids()
+    }
+
+    /// The client's current state, without its deposits.
+    #[allow(clippy::type_complexity)]
+    pub fn get(&self, id: C) -> Result<Option<Cow<'_, Client<C, T>>>, StoreError> {
+        self.store.get(id, false)
+    }
+
+    /// A deposit of client `id` that's still retained; see
+    /// `Client::find_deposit`.
+    pub fn find_deposit(
+        &self,
+        id: C,
+        transaction_id: T,
+    ) -> Result<Option<DepositSnapshot<T>>, StoreError> {
+        Ok(self
+            .store
+            .get(id, true)?
+            .and_then(|client| client.find_deposit(transaction_id)))
+    }
+
+    /// Apply an event directly, e.g. when replaying events.
+    pub fn apply_event(&mut self, event: &ClientEvent<C, T>) -> Result<(), ProcessError> {
+        if let Event::FundsDeposited { currency, .. } = event.event {
+            self.multi_currency |= currency.is_some();
+        }
+        Ok(self.store.update(
+            event.client,
+            Some(event.event.transaction_id()),
+            &mut |client| client.apply(&event.event),
+        )??)
+    }
+
+    /// Lock or unlock a client's account by hand, e.g. by an operator.
+    /// Returns false if there's no such client.
+    pub fn set_locked(&mut self, id: C, locked: bool) -> Result<bool, StoreError> {
+        self.update_lock(id, |client| client.set_locked(locked))
+    }
+
+    /// Freeze a client's account, as `Client::lock`. Returns false if there's
+    /// no such client.
+    pub fn lock(&mut self, id: C, reason: &str) -> Result<bool, StoreError> {
+        self.update_lock(id, |client| client.lock(reason))
+    }
+
+    /// Undo `lock`, as `Client::unlock`. Returns false if there's no such
+    /// client.
+    pub fn unlock(&mut self, id: C) -> Result<bool, StoreError> {
+        self.update_lock(id, Client::unlock)
+    }
+
+    fn update_lock(
+        &mut self,
+        id: C,
+        mut f: impl FnMut(&mut Client<C, T>),
+    ) -> Result<bool, StoreError> {
+        if self.store.get(id, false)?.is_none() {
+            return Ok(false);
+        }
+        self.store
+            .update(id, None, &mut |client| {
+                f(client);
+                Ok(())
+            })?
+            .expect("locking can't fail");
+        Ok(true)
+    }
+
+    /// Remove a client, e.g. once its account is closed, returning it, or
+    /// `None` if there's no such client. Its transactions are forgotten too,
+    /// including any held waiting for a deposit, so a duplicate of one is no
+    /// longer skipped.
+    pub fn remove(&mut self, id: C) -> Result<Option<Client<C, T>>, StoreError> {
+        let Some(client) = self.store.get(id, true)?.map(Cow::into_owned) else {
+            return Ok(None);
+        };
+        self.store.remove(id)?;
+        if let Some(processed) = &mut self.processed {
+            processed.retain(|&(client, _, _)| client != id);
+        }
+        self.references.retain(|(client, _)| *client != id);
+        if let Some(pending) = &mut self.pending {
+            pending.waiting.retain(|&(client, _), waiting| {
+                if client == id {
+                    pending.len -= waiting.len();
+                }
+                client != id
+            });
+        }
+        if let Some(disputes) = &mut self.disputes {
+            disputes.retain(|&(client, _), _| client != id);
+        }
+        self.risk.remove(id);
+        Ok(Some(client))
+    }
+
+    /// Remove the clients for which `keep` returns false, as `remove`.
+    /// Returns how many were removed.
+    pub fn retain(
+        &mut self,
+        mut keep: impl FnMut(C, &Client<C, T>) -> bool,
+    ) -> Result<usize, StoreError> {
+        let mut removed = 0;
+        for id in self.store.client_ids()? {
+            let Some(client) = self.store.get(id, true)? else {
+                continue;
+            };
+            if !keep(id, &client) {
+                drop(client);
+                self.remove(id)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Remove every client, and everything else processing has recorded, as
+    /// if no transaction had been processed, e.g. between test cases.
+    /// Settings such as `set_idempotent` and any listeners are kept.
+    pub fn clear(&mut self) -> Result<(), StoreError> {
+        for id in self.store.client_ids()? {
+            self.store.remove(id)?;
+        }
+        self.multi_currency = false;
+        if let Some(processed) = &mut self.processed {
+            processed.clear();
+        }
+        self.references.clear();
+        self.duplicates = 0;
+        if let Some(pending) = &mut self.pending {
+            *pending = Pending::new(pending.limit);
+        }
+        self.clock = 0;
+        if let Some(disputes) = &mut self.disputes {
+            disputes.clear();
+        }
+        self.risk.clear();
+        Ok(())
+    }
+
+    /// Make sure the store has durably stored all transactions processed so
+    /// far.
+    pub fn flush(&mut self) -> Result<(), StoreError> {
+        self.store.flush()
+    }
+}
+
+impl Clients {
+    pub fn snapshot(&self) -> Result<Snapshot, StoreError> {
+        let mut clients = Vec::new();
+        for id in self.store.client_ids()? {
+            if let Some(client) = self.store.get(id, true)? {
+                clients.push(client.snapshot(id));
+            }
+        }
+        let processed = self.processed.as_ref().map(|processed| {
+            let mut processed: Vec<_> = processed
+                .iter()
+                .map(|&(client_id, transaction_id, kind)| ProcessedTransaction {
+                    client_id,
+                    transaction_id,
+                    kind,
+                })
+                .collect();
+            processed.sort();
+            processed
+        });
+        let mut references: Vec<_> = self.references.iter().cloned().collect();
+        references.sort();
+        Ok(Snapshot {
+            multi_currency: self.multi_currency,
+            clients,
+            processed,
+            references,
+            risk_windows: self.risk.windows(),
+        })
+    }
+
+    /// A hash of the full state - balances, locks, and deposits - that's the
+    /// same for the same state regardless of how it was reached or stored,
+    /// for checking whether two runs have diverged.
+    pub fn state_hash(&self) -> Result<blake3::Hash, StoreError> {
+        let mut hasher = blake3::Hasher::new();
+        for id in self.store.client_ids()? {
+            if let Some(client) = self.store.get(id, true)? {
+                client.snapshot(id).hash_into(&mut hasher);
+            }
+        }
+        Ok(hasher.finalize())
+    }
+
+    /// Check that every client satisfies the invariants it would have if built
+    /// up from transactions, e.g. after recovering from a crash.
+    pub fn verify(&self) -> Result<(), SnapshotError> {
+        for id in self.store.client_ids()? {
+            if let Some(client) = self.store.get(id, true)? {
+                Client::restore(client.snapshot(id))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn restore(snapshot: Snapshot) -> Result<Clients, SnapshotError> {
+        Clients::restore_into(Box::new(MemoryStore::new()), snapshot)
+    }
+
+    /// Restore a snapshot that may not include the deposits backing held
+    /// funds, e.g. one built from summaries. As for `seed`, those funds stay
+    /// held.
+    pub fn restore_partial(snapshot: Snapshot) -> Result<Clients, SnapshotError> {
+        Clients::restore_impl(Box::new(MemoryStore::new()), snapshot, false)
+    }
+
+    /// Restore a snapshot into `store`, replacing any clients in it with the
+    /// same IDs.
+    pub fn restore_into(
+        store: Box<dyn Store>,
+        snapshot: Snapshot,
+    ) -> Result<Clients, SnapshotError> {
+        Clients::restore_impl(store, snapshot, true)
+    }
+
+    fn restore_impl(
+        mut store: Box<dyn Store>,
+        snapshot: Snapshot,
+        complete: bool,
+    ) -> Result<Clients, SnapshotError> {
+        let mut ids = HashSet::new();
+        let mut risk = Risk::default();
+        for (id, window) in snapshot.risk_windows {
+            risk.set_window(id, window);
+        }
+        for client in snapshot.clients {
+            let id = client.id;
+            if !ids.insert(id) {
+                return Err(SnapshotError::Inconsistent(format!(
+                    "duplicate client {}",
+                    id
+                )));
+            }
+            let client = if complete {
+                Client::restore(client)?
+            } else {
+                Client::restore_partial(client)?
+            };
+            store.put(id, &client)?;
+        }
+        let mut clients = Clients::with_store(store, snapshot.multi_currency);
+        clients.processed = snapshot.processed.map(|processed| {
+            processed
+                .into_iter()
+                .map(|t| (t.client_id, t.transaction_id, t.kind))
+                .collect()
+        });
+        clients.references = snapshot.references.into_iter().collect();
+        clients.risk = risk;
+        Ok(clients)
+    }
+
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        self.snapshot()?.save(path)
+    }
+
+    pub fn save_snapshot_as(
+        &self,
+        path: impl AsRef<Path>,
+        format: SnapshotFormat,
+    ) -> Result<(), SnapshotError> {
+        self.snapshot()?.save_as(path, format)
+    }
+
+    pub fn load_snapshot(path: impl AsRef<Path>) -> Result<Clients, SnapshotError> {
+        Clients::restore(Snapshot::load(path)?)
+    }
+
+    /// Start from the balances in a summary written by a previous run, e.g. to
+    /// process daily files incrementally without the full history.
+    ///
+    /// The summary doesn't include deposits, so deposits from before it can't
+    /// be disputed, and funds held in it stay held: there's no deposit to
+    /// resolve or charge back.
+    pub fn seed(
+        mut store: Box<dyn Store>,
+        reader: impl std::io::Read,
+    ) -> Result<Clients, SeedError> {
+        #[derive(Deserialize)]
         struct Row {
             client: ClientId,
-            available: Amount,
+            #[serde(default)]
+            currency: Option<Currency>,
+            available: SignedAmount,
             held: Amount,
-            total: Amount,
+            total: SignedAmount,
             locked: bool,
         }
 
-        // HashMaps aren't ordered. Print the clients in a stable order to make
-        // testing easier.
-        let mut client_ids: Vec<_> = self.clients.iter().collect();
-        client_ids.sort_by_key(|(id, _)| **id);
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        // Keep writing the currency column if the previous run did.
+        let mut multi_currency = reader.headers()?.iter().any(|h| h == "currency");
+        let mut clients = BTreeMap::new();
+        for row in reader.deserialize() {
+            let row: Row = row?;
+            if row.available.checked_add(row.held.into()) != Some(row.total) {
+                return Err(SnapshotError::Inconsistent(format!(
+                    "client {}: total isn't available + held",
+                    row.client
+                ))
+                .into());
+            }
+            multi_currency |= row.currency.is_some();
+            let client = clients.entry(row.client).or_insert_with(|| ClientSnapshot {
+                id: row.client,
+                locked: row.locked,
+                lock_reason: None,
+                activity: Default::default(),
+                balances: Vec::new(),
+                deposits: Vec::new(),
+                order: Vec::new(),
+                expired: Vec::new(),
+            });
+            if client.locked != row.locked {
+                return Err(SnapshotError::Inconsistent(format!(
+                    "client {}: locked differs between currencies",
+                    row.client
+                ))
+                .into());
+            }
+            client.balances.push(BalanceSnapshot {
+                currency: row.currency,
+                available: row.available,
+                held: row.held,
+            });
+        }
+        for (id, client) in clients {
+            store
+                .put(id, &Client::restore_partial(client)?)
+                .map_err(SnapshotError::from)?;
+        }
+        Ok(Clients::with_store(store, multi_currency))
+    }
+
+    pub fn write(&self, writer: impl std::io::Write) -> Result<(), WriteError> {
+        self.write_with(writer, &WriteOptions::default())
+    }
+
+    pub fn write_with(
+        &self,
+        writer: impl std::io::Write,
+        options: &WriteOptions,
+    ) -> Result<(), WriteError> {
+        #[derive(Serialize)]
+        struct Row {
+            client: String,
+            // Only present for multi-currency input. An empty value is the
+            // balance of transactions that didn't specify a currency.
+            #[serde(skip_serializing_if = "Option::is_none")]
+            currency: Option<String>,
+            available: DisplayAmount,
+            held: DisplayAmount,
+            total: DisplayAmount,
+            locked: bool,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            name: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            email: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            tier: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            deposits: Option<u64>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            withdrawals: Option<u64>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            disputes: Option<u64>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            resolves: Option<u64>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            chargebacks: Option<u64>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            first_active: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            last_active: Option<String>,
+        }
 
+        let format = options.amount_format;
         let mut writer = csv::Writer::from_writer(writer);
-        for (id, client) in client_ids {
-            writer.serialize(Row {
-                client: *id,
-                available: client.available(),
-                held: client.held(),
-                total: client.total(),
+        for id in self.store.client_ids()? {
+            if !options.clients.contains(id) {
+                continue;
+            }
+            let Some(client) = self.store.get(id, false)? else {
+                continue;
+            };
+            // Every client gets at least one row, even if they've never
+            // successfully deposited anything.
+            let mut currencies: Vec<_> = client.currencies().collect();
+            if currencies.is_empty() {
+                currencies.push(None);
+            }
+            for currency in currencies {
+                let [name, email, tier] = options.info(Some(id));
+                let [deposits, withdrawals, disputes, resolves, chargebacks] =
+                    options.counts(client.activity());
+                let [first_active, last_active] = options.seen(client.activity());
+                writer.serialize(Row {
+                    client: options.client(id),
+                    currency: self
+                        .multi_currency
+                        .then(|| currency.map(|c| c.to_string()).unwrap_or_default()),
+                    available: client.available(currency).display(format),
+                    held: client.held(currency).display(format),
+                    total: client.total(currency).display(format),
+                    locked: client.locked(),
+                    name,
+                    email,
+                    tier,
+                    deposits,
+                    withdrawals,
+                    disputes,
+                    resolves,
+                    chargebacks,
+                    first_active,
+                    last_active,
+                })?
+            }
+        }
+        if options.totals {
+            #[derive(Serialize)]
+            struct TotalsRow {
+                client: &'static str,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                currency: Option<String>,
+                available: DisplayAmount,
+                held: DisplayAmount,
+                total: DisplayAmount,
+                // The number of accounts locked.
+                locked: u64,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                name: Option<String>,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                email: Option<String>,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                tier: Option<String>,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                deposits: Option<u64>,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                withdrawals: Option<u64>,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                disputes: Option<u64>,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                resolves: Option<u64>,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                chargebacks: Option<u64>,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                first_active: Option<String>,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                last_active: Option<String>,
+            }
+
+            for totals in self.totals(&options.clients)? {
+                let [name, email, tier] = options.info(None);
+                let [deposits, withdrawals, disputes, resolves, chargebacks] =
+                    options.counts(totals.activity);
+                let [first_active, last_active] = options.seen(totals.activity);
+                writer.serialize(TotalsRow {
+                    client: "total",
+                    currency: self
+                        .multi_currency
+                        .then(|| totals.currency.map(|c| c.to_string()).unwrap_or_default()),
+                    available: totals.available.display(format),
+                    held: totals.held.display(format),
+                    total: totals.total.display(format),
+                    locked: totals.locked,
+                    name,
+                    email,
+                    tier,
+                    deposits,
+                    withdrawals,
+                    disputes,
+                    resolves,
+                    chargebacks,
+                    first_active,
+                    last_active,
+                })?
+            }
+        }
+        Ok(writer.flush().map_err(csv::Error::from)?)
+    }
+
+    /// The rows of the summary for `clients`, in the order they're written.
+    pub fn summary_rows(&self, clients: &ClientFilter) -> Result<Vec<SummaryRow>, StoreError> {
+        let mut rows = Vec::new();
+        for id in self.store.client_ids()? {
+            if !clients.contains(id) {
+                continue;
+            }
+            let Some(client) = self.store.get(id, false)? else {
+                continue;
+            };
+            let mut currencies: Vec<_> = client.currencies().collect();
+            if currencies.is_empty() {
+                currencies.push(None);
+            }
+            rows.extend(currencies.into_iter().map(|currency| SummaryRow {
+                client: id,
+                currency,
+                available: client.available(currency),
+                held: client.held(currency),
+                total: client.total(currency),
                 locked: client.locked(),
+            }));
+        }
+        Ok(rows)
+    }
+
+    /// Sum the rows of the summary for `clients` in each currency, in the
+    /// same order as they're written.
+    pub fn totals(&self, clients: &ClientFilter) -> Result<Vec<Totals>, WriteError> {
+        let mut totals = BTreeMap::<Option<Currency>, Totals>::new();
+        for id in self.store.client_ids()? {
+            if !clients.contains(id) {
+                continue;
+            }
+            let Some(client) = self.store.get(id, false)? else {
+                continue;
+            };
+            let mut currencies: Vec<_> = client.currencies().collect();
+            if currencies.is_empty() {
+                currencies.push(None);
+            }
+            for currency in currencies {
+                let sums = totals.entry(currency).or_insert(Totals {
+                    currency,
+                    ..Default::default()
+                });
+                let add = |sum: SignedAmount, amount: SignedAmount| {
+                    sum.checked_add(amount).ok_or(WriteError::TotalsOverflow)
+                };
+                sums.available = add(sums.available, client.available(currency))?;
+                sums.held = sums
+                    .held
+                    .checked_add(client.held(currency))
+                    .ok_or(WriteError::TotalsOverflow)?;
+                sums.total = add(sums.total, client.total(currency))?;
+                sums.clients += 1;
+                sums.locked += u64::from(client.locked());
+                sums.activity = sums.activity.combine(client.activity());
+            }
+        }
+        Ok(totals.into_values().collect())
+    }
+
+    /// Write the totals for the clients in `options` as CSV, with the number
+    /// of clients and of locked accounts in each currency.
+    pub fn write_totals(
+        &self,
+        writer: impl std::io::Write,
+        options: &WriteOptions,
+    ) -> Result<(), WriteError> {
+        #[derive(Serialize)]
+        struct Row {
+            #[serde(skip_serializing_if = "Option::is_none")]
+            currency: Option<String>,
+            clients: u64,
+            locked: u64,
+            available: DisplayAmount,
+            held: DisplayAmount,
+            total: DisplayAmount,
+        }
+
+        let format = options.amount_format;
+        let mut writer = csv::Writer::from_writer(writer);
+        for totals in self.totals(&options.clients)? {
+            writer.serialize(Row {
+                currency: self
+                    .multi_currency
+                    .then(|| totals.currency.map(|c| c.to_string()).unwrap_or_default()),
+                clients: totals.clients,
+                locked: totals.locked,
+                available: totals.available.display(format),
+                held: totals.held.display(format),
+                total: totals.total.display(format),
             })?
         }
-        Ok(writer.flush()?)
+        Ok(writer.flush().map_err(csv::Error::from)?)
+    }
+
+    /// Write the open disputes of the clients in `options` as CSV, counted
+    /// and summed by how long ago they were opened: in days, relative to the
+    /// latest time given to `set_time`, if there is one, otherwise in
+    /// transactions processed since. Disputes not tracked by
+    /// `set_track_disputes` are counted as of unknown age.
+    pub fn write_dispute_ageing(
+        &self,
+        writer: impl std::io::Write,
+        options: &WriteOptions,
+    ) -> Result<(), WriteError> {
+        #[derive(Serialize)]
+        struct Row {
+            age: &'static str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            currency: Option<String>,
+            disputes: u64,
+            held: DisplayAmount,
+        }
+
+        let buckets = match self.time {
+            Some(_) => &DAY_BUCKETS,
+            None => &TRANSACTION_BUCKETS,
+        };
+        // By currency, then bucket, with the last for unknown ages.
+        let mut counts = BTreeMap::<Option<Currency>, Vec<(u64, Amount)>>::new();
+        if !self.multi_currency {
+            counts.insert(None, vec![(0, Amount::ZERO); buckets.len() + 1]);
+        }
+        for id in self.store.client_ids()? {
+            if !options.clients.contains(id) {
+                continue;
+            }
+            let Some(client) = self.store.get(id, true)? else {
+                continue;
+            };
+            for deposit in client.snapshot(id).deposits {
+                if !deposit.disputed {
+                    continue;
+                }
+                let opened = self
+                    .disputes
+                    .as_ref()
+                    .and_then(|disputes| disputes.get(&(id, deposit.transaction_id)));
+                let age = match (self.time, opened) {
+                    (
+                        Some(now),
+                        Some(Opened {
+                            timestamp: Some(timestamp),
+                            ..
+                        }),
+                    ) => Some(now.saturating_sub(*timestamp) / SECONDS_PER_DAY),
+                    (None, Some(opened)) => Some(self.clock - opened.transaction),
+                    _ => None,
+                };
+                let bucket = match age {
+                    Some(age) => buckets
+                        .iter()
+                        .position(|&(limit, _)| age < limit)
+                        .unwrap_or(buckets.len() - 1),
+                    None => buckets.len(),
+                };
+                let counts = counts
+                    .entry(deposit.currency)
+                    .or_insert_with(|| vec![(0, Amount::ZERO); buckets.len() + 1]);
+                let (disputes, held) = &mut counts[bucket];
+                *disputes += 1;
+                *held = held
+                    .checked_add(deposit.amount)
+                    .ok_or(WriteError::TotalsOverflow)?;
+            }
+        }
+
+        let format = options.amount_format;
+        let mut writer = csv::Writer::from_writer(writer);
+        for (currency, counts) in counts {
+            let labels = buckets.iter().map(|&(_, label)| label).chain(["unknown"]);
+            for (age, (disputes, held)) in labels.zip(counts) {
+                // Only mention disputes of unknown age if there are some.
+                if age == "unknown" && disputes == 0 {
+                    continue;
+                }
+                writer.serialize(Row {
+                    age,
+                    currency: self
+                        .multi_currency
+                        .then(|| currency.map(|c| c.to_string()).unwrap_or_default()),
+                    disputes,
+                    held: held.display(format),
+                })?
+            }
+        }
+        Ok(writer.flush().map_err(csv::Error::from)?)
+    }
+
+    /// Write the disputed deposits making up the held funds of each client in
+    /// `options` with any, so that the held column of the summary can be
+    /// broken down. Held funds not backed by a disputed deposit, e.g. from a
+    /// seed summary, are written with an empty `tx`.
+    pub fn write_held(
+        &self,
+        writer: impl std::io::Write,
+        options: &WriteOptions,
+    ) -> Result<(), WriteError> {
+        #[derive(Serialize)]
+        struct Row {
+            client: String,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            currency: Option<String>,
+            tx: Option<TransactionId>,
+            amount: DisplayAmount,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            name: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            email: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            tier: Option<String>,
+        }
+
+        let format = options.amount_format;
+        let mut writer = csv::Writer::from_writer(writer);
+        for id in self.store.client_ids()? {
+            if !options.clients.contains(id) {
+                continue;
+            }
+            let Some(client) = self.store.get(id, true)? else {
+                continue;
+            };
+            let deposits = client.snapshot(id).deposits;
+            for currency in client.currencies() {
+                let held = client.held(currency);
+                if held == Amount::ZERO {
+                    continue;
+                }
+                let row = |tx, amount: Amount| {
+                    let [name, email, tier] = options.info(Some(id));
+                    Row {
+                        client: options.client(id),
+                        currency: self
+                            .multi_currency
+                            .then(|| currency.map(|c| c.to_string()).unwrap_or_default()),
+                        tx,
+                        amount: amount.display(format),
+                        name,
+                        email,
+                        tier,
+                    }
+                };
+                let mut unexplained = held;
+                for deposit in deposits
+                    .iter()
+                    .filter(|d| d.disputed && d.currency == currency)
+                {
+                    writer.serialize(row(Some(deposit.transaction_id), deposit.amount))?;
+                    unexplained = unexplained.saturating_sub(deposit.amount);
+                }
+                if unexplained != Amount::ZERO {
+                    writer.serialize(row(None, unexplained))?;
+                }
+            }
+        }
+        Ok(writer.flush().map_err(csv::Error::from)?)
+    }
+
+    /// Write every deposit that's still retained, i.e. that could be disputed
+    /// or is disputed now, so that the held funds in the summary can be
+    /// checked against the deposits backing them.
+    pub fn write_ledger(
+        &self,
+        writer: impl std::io::Write,
+        options: &WriteOptions,
+    ) -> Result<(), WriteError> {
+        #[derive(Serialize)]
+        struct Row {
+            client: String,
+            tx: TransactionId,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            currency: Option<String>,
+            amount: DisplayAmount,
+            disputed: bool,
+        }
+
+        let format = options.amount_format;
+        let mut writer = csv::Writer::from_writer(writer);
+        for id in self.store.client_ids()? {
+            if !options.clients.contains(id) {
+                continue;
+            }
+            let Some(client) = self.store.get(id, true)? else {
+                continue;
+            };
+            for deposit in client.snapshot(id).deposits {
+                writer.serialize(Row {
+                    client: options.client(id),
+                    tx: deposit.transaction_id,
+                    currency: self
+                        .multi_currency
+                        .then(|| deposit.currency.map(|c| c.to_string()).unwrap_or_default()),
+                    amount: deposit.amount.display(format),
+                    disputed: deposit.disputed,
+                })?
+            }
+        }
+        Ok(writer.flush().map_err(csv::Error::from)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{
+        load_transactions, load_transactions_with, parse_record, LoadOptions,
+    };
+
+    fn summary(clients: &Clients) -> String {
+        let mut buf = Vec::new();
+        clients.write(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_seed() {
+        // Carry on from a previous summary. Funds held in it stay held, and
+        // deposits from before it can't be disputed.
+        let previous = "client,currency,available,held,total,locked
+1,,1.5000,1.0000,2.5000,false
+1,USD,2.0000,0.0000,2.0000,false
+2,,0.0000,0.0000,0.0000,true
+";
+        let mut clients = Clients::seed(Box::new(MemoryStore::new()), previous.as_bytes()).unwrap();
+        assert_eq!(summary(&clients), previous);
+
+        let input = "type, client, tx, amount, currency
+deposit, 1, 10, 1.0, USD
+dispute, 1, 1
+deposit, 2, 11, 1.0,
+";
+        let results: Vec<_> = load_transactions(input.as_bytes())
+            .map(|t| clients.process_transaction(t.unwrap()).is_ok())
+            .collect();
+        assert_eq!(results, vec![true, false, false]);
+        assert_eq!(
+            summary(&clients),
+            "client,currency,available,held,total,locked
+1,,1.5000,1.0000,2.5000,false
+1,USD,3.0000,0.0000,3.0000,false
+2,,0.0000,0.0000,0.0000,true
+"
+        );
+    }
+
+    #[test]
+    fn test_write_ledger() {
+        let mut clients = Clients::new();
+        let input = "type, client, tx, amount
+deposit, 1, 1, 1.0
+deposit, 1, 2, 2.0
+dispute, 1, 2
+deposit, 2, 3, 3.0
+dispute, 2, 3
+chargeback, 2, 3
+";
+        for transaction in load_transactions(input.as_bytes()) {
+            clients.process_transaction(transaction.unwrap()).unwrap();
+        }
+        let mut buf = Vec::new();
+        clients
+            .write_ledger(&mut buf, &WriteOptions::default())
+            .unwrap();
+        // Charged back deposits aren't retained.
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,tx,amount,disputed\n1,1,1.0000,false\n1,2,2.0000,true\n"
+        );
+    }
+
+    #[test]
+    fn test_state_hash() {
+        let input = "type, client, tx, amount
+deposit, 1, 1, 1.0
+deposit, 2, 2, 2.0
+dispute, 1, 1
+";
+        let mut clients = Clients::new();
+        for transaction in load_transactions(input.as_bytes()) {
+            clients.process_transaction(transaction.unwrap()).unwrap();
+        }
+        // The same state reached another way has the same hash, but any
+        // difference changes it.
+        let restored = Clients::restore(clients.snapshot().unwrap()).unwrap();
+        assert_eq!(
+            restored.state_hash().unwrap(),
+            clients.state_hash().unwrap()
+        );
+        let mut other = Clients::restore(clients.snapshot().unwrap()).unwrap();
+        for transaction in load_transactions("type, client, tx\nresolve, 1, 1\n".as_bytes()) {
+            other.process_transaction(transaction.unwrap()).unwrap();
+        }
+        assert_ne!(other.state_hash().unwrap(), clients.state_hash().unwrap());
+        assert_ne!(
+            Clients::new().state_hash().unwrap(),
+            clients.state_hash().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_seed_inconsistent() {
+        let previous = "client,available,held,total,locked\n1,1.0,1.0,1.0,false\n";
+        assert!(matches!(
+            Clients::seed(Box::new(MemoryStore::new()), previous.as_bytes()),
+            Err(SeedError::Snapshot(SnapshotError::Inconsistent(_)))
+        ));
+    }
+
+    #[test]
+    fn test_idempotent() {
+        let process = |clients: &mut Clients, records: &[&str]| {
+            for record in records {
+                let _ = clients.process_transaction(parse_record(record).unwrap());
+            }
+        };
+        let records = [
+            "deposit,1,1,10.0",
+            "withdrawal,1,2,20.0",
+            "deposit,1,3,5.0",
+            "dispute,1,1",
+        ];
+        let mut clients = Clients::new();
+        clients.set_idempotent();
+        process(&mut clients, &records);
+        let snapshot = clients.snapshot().unwrap();
+        assert_eq!(snapshot.processed.as_ref().unwrap().len(), 4);
+
+        // Everything is skipped, including the withdrawal that was rejected,
+        // which would now be accepted.
+        let mut restored = Clients::restore(snapshot.clone()).unwrap();
+        assert!(restored.is_idempotent());
+        process(&mut restored, &records);
+        assert_eq!(restored.duplicates(), 4);
+        assert_eq!(restored.snapshot().unwrap(), snapshot);
+        // Transactions are known by their client, ID, and type.
+        process(&mut restored, &["deposit,1,1,10.5"]);
+        assert_eq!(restored.duplicates(), 5);
+        process(&mut restored, &["deposit,2,1,10.5"]);
+        assert_eq!(restored.duplicates(), 5);
+
+        let mut clients = Clients::new();
+        process(&mut clients, &records);
+        process(&mut clients, &records);
+        assert!(clients.snapshot().unwrap().processed.is_none());
+        assert_eq!(clients.duplicates(), 0);
+    }
+
+    #[test]
+    fn test_idempotent_disputes() {
+        let process = |clients: &mut Clients, records: &[&str]| {
+            for record in records {
+                let _ = clients.process_transaction(parse_record(record).unwrap());
+            }
+        };
+        let held = |clients: &Clients, id: u16| clients.get(id.into()).unwrap().unwrap().held(None);
+        let records = [
+            "deposit,1,1,10.0",
+            "dispute,1,1",
+            "resolve,1,1",
+            // Disputed again once resolved.
+            "dispute,1,1",
+            "deposit,2,2,5.0",
+            "dispute,2,2",
+            "chargeback,2,2",
+        ];
+        let mut clients = Clients::new();
+        clients.set_idempotent();
+        process(&mut clients, &records);
+        assert_eq!(clients.duplicates(), 0);
+        assert_eq!(held(&clients, 1), Amount::try_from("10").unwrap());
+
+        // A partial run, and then the whole input again, ends in the same
+        // state as processing it once.
+        let mut partial = Clients::new();
+        partial.set_idempotent();
+        process(&mut partial, &records[..2]);
+        process(&mut partial, &records);
+        process(&mut partial, &records);
+        assert_eq!(held(&partial, 1), Amount::try_from("10").unwrap());
+        assert_eq!(summary(&partial), summary(&clients));
+
+        // A dispute that's still open, and a chargeback, are skipped.
+        assert_eq!(
+            clients
+                .process_transaction(parse_record("dispute,1,1").unwrap())
+                .unwrap(),
+            []
+        );
+        process(&mut clients, &["dispute,2,2", "chargeback,2,2"]);
+        assert_eq!(clients.duplicates(), 3);
+        // Resolving it lets it be disputed again.
+        process(&mut clients, &["resolve,1,1", "dispute,1,1"]);
+        assert_eq!(clients.duplicates(), 3);
+        assert_eq!(held(&clients, 1), Amount::try_from("10").unwrap());
+    }
+
+    #[test]
+    fn test_references() {
+        let records = [
+            ("deposit,1,1,10.0", "a"),
+            // The same reference under a new ID, e.g. a partner's retry.
+            ("deposit,1,2,10.0", "a"),
+            // References are per client.
+            ("deposit,2,3,10.0", "a"),
+            ("withdrawal,1,4,20.0", "b"),
+        ];
+        let process = |clients: &mut Clients| {
+            for (record, reference) in records {
+                clients.set_reference(reference);
+                let _ = clients.process_transaction(parse_record(record).unwrap());
+            }
+        };
+        let mut clients = Clients::new();
+        process(&mut clients);
+        assert_eq!(clients.duplicates(), 1);
+        assert_eq!(
+            clients.get(1.into()).unwrap().unwrap().total(None),
+            SignedAmount::try_from("10").unwrap()
+        );
+        // Only accepted transactions' references are kept.
+        let snapshot = clients.snapshot().unwrap();
+        assert_eq!(snapshot.references.len(), 2);
+
+        // Replaying the same records changes nothing.
+        let mut restored = Clients::restore(snapshot.clone()).unwrap();
+        process(&mut restored);
+        assert_eq!(restored.duplicates(), 3);
+        assert_eq!(restored.snapshot().unwrap(), snapshot);
+        // Transactions without a reference aren't checked.
+        restored
+            .process_transaction(parse_record("deposit,1,5,10.0").unwrap())
+            .unwrap();
+        // The rejected withdrawal is applied when it's retried.
+        restored.set_reference("b");
+        restored
+            .process_transaction(parse_record("withdrawal,1,4,20.0").unwrap())
+            .unwrap();
+        assert_eq!(restored.snapshot().unwrap().references.len(), 3);
+
+        restored.remove(1.into()).unwrap();
+        assert_eq!(restored.snapshot().unwrap().references.len(), 1);
+    }
+
+    #[test]
+    fn test_record_keys() {
+        let mut clients = Clients::new();
+        for (record, key) in [
+            ("deposit,1,1,10.0", "m1#0"),
+            ("dispute,1,1", "m1#1"),
+            ("resolve,1,1", "m2#0"),
+            // Delivered again.
+            ("resolve,1,1", "m2#0"),
+            // The same transaction under a new key isn't a duplicate.
+            ("dispute,1,1", "m3#0"),
+            ("withdrawal,1,2,20.0", "m4#0"),
+        ] {
+            clients.set_record_key(key);
+            let _ = clients.process_transaction(parse_record(record).unwrap());
+        }
+        assert_eq!(clients.duplicates(), 1);
+        assert_eq!(
+            clients.get(1.into()).unwrap().unwrap().held(None),
+            Amount::try_from("10").unwrap()
+        );
+
+        // Rejected transactions are recorded too.
+        clients.set_record_key("m4#0");
+        clients
+            .process_transaction(parse_record("withdrawal,1,2,20.0").unwrap())
+            .unwrap();
+        assert_eq!(clients.duplicates(), 2);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut clients = Clients::new();
+        clients.set_idempotent();
+        for record in [
+            "deposit,1,1,10.0",
+            "deposit,2,2,5.0,GBP",
+            "deposit,3,3,1.0",
+            "dispute,3,3",
+            "chargeback,3,3",
+        ] {
+            clients
+                .process_transaction(parse_record(record).unwrap())
+                .unwrap();
+        }
+
+        let removed = clients.remove(1.into()).unwrap().unwrap();
+        assert_eq!(removed.total(None), SignedAmount::try_from("10").unwrap());
+        assert!(clients.remove(1.into()).unwrap().is_none());
+        assert!(clients.get(1.into()).unwrap().is_none());
+        // Its transactions are forgotten, so they'd be processed again.
+        assert_eq!(clients.snapshot().unwrap().processed.unwrap().len(), 4);
+
+        assert_eq!(clients.retain(|_, client| !client.locked()).unwrap(), 1);
+        assert_eq!(clients.client_ids().unwrap(), vec![2.into()]);
+
+        clients.clear().unwrap();
+        let mut empty = Clients::new();
+        empty.set_idempotent();
+        assert_eq!(summary(&clients), summary(&empty));
+        assert_eq!(clients.snapshot().unwrap(), empty.snapshot().unwrap());
+    }
+
+    #[test]
+    fn test_pending() {
+        let mut clients = Clients::new();
+        clients.set_pending_limit(3);
+        let mut line = 1;
+        let mut process = |record| {
+            line += 1;
+            clients.set_line(line);
+            clients.process_transaction(parse_record(record).unwrap())
+        };
+        let pending = |result| matches!(result, Err(ProcessError::Pending));
+        assert!(pending(process("dispute,1,1")));
+        assert!(pending(process("chargeback,1,1")));
+        assert!(pending(process("dispute,2,5")));
+        // Full.
+        assert!(matches!(
+            process("dispute,2,6"),
+            Err(ProcessError::Rejected(ClientError::UnknownTransactionId))
+        ));
+        let tx = TransactionId::from(1);
+        assert_eq!(
+            process("deposit,1,1,3.0").unwrap(),
+            [
+                Event::FundsDeposited {
+                    transaction_id: tx,
+                    currency: None,
+                    amount: Amount::from_minor_units(30_000),
+                },
+                Event::DisputeOpened {
+                    transaction_id: tx,
+                    allow_negative: false,
+                },
+                Event::ChargedBack { transaction_id: tx },
+                Event::AccountLocked { transaction_id: tx },
+            ]
+        );
+        assert!(pending(process("dispute,2,7")));
+        process("deposit,2,7,1.0,USD").unwrap();
+        // Retried, but for the wrong currency.
+        assert!(pending(process("dispute,2,8,,EUR")));
+        process("deposit,2,8,1.0,USD").unwrap();
+        // Those applied, with the lines they were on.
+        let released: Vec<_> = clients
+            .take_released()
+            .into_iter()
+            .map(|p| (p.transaction.data.name(), p.line))
+            .collect();
+        assert_eq!(
+            released,
+            [
+                ("dispute", Some(2)),
+                ("chargeback", Some(3)),
+                ("dispute", Some(7))
+            ]
+        );
+        assert_eq!(clients.take_released(), []);
+        let unmatched: Vec<_> = clients
+            .take_unmatched()
+            .into_iter()
+            .map(|(p, e)| {
+                (
+                    p.transaction.client_id,
+                    p.transaction.data.transaction_id(),
+                    e,
+                )
+            })
+            .collect();
+        assert_eq!(
+            unmatched,
+            [
+                (
+                    ClientId::from(2),
+                    TransactionId::from(5),
+                    ClientError::UnknownTransactionId
+                ),
+                (
+                    ClientId::from(2),
+                    TransactionId::from(8),
+                    ClientError::CurrencyMismatch
+                ),
+            ]
+        );
+        assert!(clients.get(ClientId::from(1)).unwrap().unwrap().locked());
+        assert_eq!(
+            clients
+                .get(ClientId::from(2))
+                .unwrap()
+                .unwrap()
+                .held(Some(Currency::try_from("USD").unwrap())),
+            Amount::from_minor_units(10_000)
+        );
+    }
+
+    /// A record for one of a few clients, referring to one of a few
+    /// transaction IDs, so that disputes often find their deposit.
+    fn arbitrary_record() -> impl proptest::strategy::Strategy<Value = String> {
+        use proptest::strategy::Strategy;
+        (0..5u8, 1..4u16, 1..12u32, 0..100_000u64).prop_map(|(kind, client, tx, amount)| {
+            let amount = format!("{}.{:04}", amount / 10_000, amount % 10_000);
+            match kind {
+                0 => format!("deposit,{},{},{}", client, tx, amount),
+                1 => format!("withdrawal,{},{},{}", client, tx, amount),
+                2 => format!("dispute,{},{}", client, tx),
+                3 => format!("resolve,{},{}", client, tx),
+                _ => format!("chargeback,{},{}", client, tx),
+            }
+        })
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_invariants(records in proptest::collection::vec(arbitrary_record(), 0..200)) {
+            let mut clients = Clients::new();
+            // What each client's total should be, from the transactions
+            // accepted.
+            let mut totals = HashMap::<ClientId, u64>::new();
+            let mut deposits = HashMap::<(ClientId, TransactionId), u64>::new();
+            for record in &records {
+                let transaction = parse_record(record).unwrap();
+                let (id, data) = (transaction.client_id, transaction.data);
+                let before = clients.get(id).unwrap().map(|client| client.snapshot(id));
+                let result = clients.process_transaction(transaction);
+                let after = clients.get(id).unwrap().unwrap();
+
+                if let Some(before) = before.filter(|client| client.locked) {
+                    // Locked accounts never change.
+                    proptest::prop_assert!(result.is_err());
+                    proptest::prop_assert_eq!(before, after.snapshot(id));
+                }
+                if result.is_ok() {
+                    let total = totals.entry(id).or_default();
+                    match data {
+                        TransactionData::Deposit { transaction_id, amount } => {
+                            deposits.insert((id, transaction_id), amount.to_minor_units());
+                            *total += amount.to_minor_units();
+                        }
+                        TransactionData::Withdrawal { amount, .. } => {
+                            *total -= amount.to_minor_units();
+                        }
+                        TransactionData::Chargeback { transaction_id } => {
+                            *total -= deposits[&(id, transaction_id)];
+                        }
+                        _ => {}
+                    }
+                }
+
+                // Nothing held is available, and the total is always what's
+                // available plus what's held.
+                proptest::prop_assert!(after.available(None) <= after.total(None));
+                proptest::prop_assert_eq!(
+                    after.available(None) + after.held(None).into(),
+                    after.total(None)
+                );
+                proptest::prop_assert_eq!(
+                    after.total(None).to_minor_units(),
+                    i128::from(totals.get(&id).copied().unwrap_or_default())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_history() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 10.0\n\
+                     deposit, 2, 2, 5.0\n\
+                     withdrawal, 1, 3, 20.0\n\
+                     dispute, 1, 1\n\
+                     resolve, 1, 1\n\
+                     withdrawal, 1, 4, 1.5\n";
+        let history = |clients: &Clients, id: u16| -> Vec<Transaction> {
+            let client = clients.get(id.into()).unwrap().unwrap();
+            client
+                .history()
+                .iter()
+                .map(|entry| entry.transaction.clone())
+                .collect()
+        };
+        let memos = |clients: &Clients, id: u16| -> Vec<Option<String>> {
+            let client = clients.get(id.into()).unwrap().unwrap();
+            client
+                .history()
+                .iter()
+                .map(|entry| entry.memo.clone())
+                .collect()
+        };
+
+        let mut clients = Clients::new();
+        for transaction in load_transactions(input.as_bytes()) {
+            let _ = clients.process_transaction(transaction.unwrap());
+        }
+        assert!(history(&clients, 1).is_empty());
+
+        let mut clients = Clients::new();
+        clients.set_keep_history();
+        for transaction in load_transactions(input.as_bytes()) {
+            let _ = clients.process_transaction(transaction.unwrap());
+        }
+        // The rejected withdrawal isn't included.
+        let expected: Vec<_> = [
+            "deposit,1,1,10.0",
+            "dispute,1,1",
+            "resolve,1,1",
+            "withdrawal,1,4,1.5",
+        ]
+        .into_iter()
+        .map(|record| parse_record(record).unwrap())
+        .collect();
+        assert_eq!(history(&clients, 1), expected);
+        assert_eq!(
+            history(&clients, 2),
+            [parse_record("deposit,2,2,5.0").unwrap()]
+        );
+        assert_eq!(memos(&clients, 1), [None, None, None, None]);
+
+        // A memo is only kept for the transaction it was set for.
+        clients.set_memo("Invoice 42");
+        clients
+            .process_transaction(parse_record("deposit,2,5,1.0").unwrap())
+            .unwrap();
+        clients
+            .process_transaction(parse_record("deposit,2,6,1.0").unwrap())
+            .unwrap();
+        assert_eq!(
+            memos(&clients, 2),
+            [None, Some("Invoice 42".to_string()), None]
+        );
+    }
+
+    #[test]
+    fn test_totals() {
+        let input = "type, client, tx, amount, currency\n\
+                     deposit, 1, 1, 10.0, GBP\n\
+                     deposit, 1, 2, 2.5, EUR\n\
+                     deposit, 2, 3, 5.0, GBP\n\
+                     dispute, 2, 3\n\
+                     deposit, 3, 4, 1.0, GBP\n\
+                     dispute, 3, 4\n\
+                     chargeback, 3, 4\n\
+                     withdrawal, 4, 5, 1.0\n";
+        let mut clients = Clients::new();
+        for transaction in load_transactions(input.as_bytes()) {
+            let _ = clients.process_transaction(transaction.unwrap());
+        }
+        let options = WriteOptions {
+            totals: true,
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        clients.write_with(&mut buf, &options).unwrap();
+        let summary = String::from_utf8(buf).unwrap();
+        assert!(summary.ends_with(
+            "total,,0.0000,0.0000,0.0000,0\n\
+             total,EUR,2.5000,0.0000,2.5000,0\n\
+             total,GBP,10.0000,5.0000,15.0000,1\n"
+        ));
+
+        let mut buf = Vec::new();
+        let options = WriteOptions {
+            clients: ClientFilter {
+                ranges: vec!["2..3".parse().unwrap()],
+            },
+            ..Default::default()
+        };
+        clients.write_totals(&mut buf, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "currency,clients,locked,available,held,total\n\
+             GBP,2,1,0.0000,5.0000,5.0000\n"
+        );
+    }
+
+    #[test]
+    fn test_dispute_ageing() {
+        let ageing = |clients: &Clients| {
+            let mut buf = Vec::new();
+            clients
+                .write_dispute_ageing(&mut buf, &WriteOptions::default())
+                .unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+        let mut clients = Clients::new();
+        clients.set_track_disputes();
+        for record in ["deposit,1,1,10.0", "deposit,1,2,5.0", "dispute,1,1"] {
+            clients
+                .process_transaction(parse_record(record).unwrap())
+                .unwrap();
+        }
+        for tx in 3..1003 {
+            let record = format!("deposit,2,{},1.0", tx);
+            clients
+                .process_transaction(parse_record(&record).unwrap())
+                .unwrap();
+        }
+        for record in ["dispute,1,2", "dispute,2,3", "resolve,2,3"] {
+            clients
+                .process_transaction(parse_record(record).unwrap())
+                .unwrap();
+        }
+        assert_eq!(
+            ageing(&clients),
+            "age,disputes,held\n\
+             under 1000 transactions,1,5.0000\n\
+             1000-10000 transactions,1,10.0000\n\
+             10000-100000 transactions,0,0.0000\n\
+             100000-1000000 transactions,0,0.0000\n\
+             over 1000000 transactions,0,0.0000\n"
+        );
+
+        // In days once there are timestamps, and of unknown age if opened
+        // before they were tracked.
+        let mut clients = Clients::restore(clients.snapshot().unwrap()).unwrap();
+        clients.set_track_disputes();
+        clients.set_time(0);
+        for record in ["deposit,3,2000,1.0", "dispute,3,2000"] {
+            clients
+                .process_transaction(parse_record(record).unwrap())
+                .unwrap();
+        }
+        clients.set_time(8 * SECONDS_PER_DAY);
+        // Earlier times are ignored.
+        clients.set_time(SECONDS_PER_DAY);
+        assert_eq!(
+            ageing(&clients),
+            "age,disputes,held\n\
+             under 1 day,0,0.0000\n\
+             1-7 days,0,0.0000\n\
+             7-30 days,1,1.0000\n\
+             30-90 days,0,0.0000\n\
+             over 90 days,0,0.0000\n\
+             unknown,2,15.0000\n"
+        );
+    }
+
+    #[test]
+    fn test_held_breakdown() {
+        let previous = "client,available,held,total,locked\n\
+                        1,1.0,2.0,3.0,false\n\
+                        2,1.0,0.0,1.0,false\n";
+        let mut clients = Clients::seed(Box::new(MemoryStore::new()), previous.as_bytes()).unwrap();
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     deposit,1,2,5.0\n\
+                     deposit,1,3,2.5\n\
+                     dispute,1,1\n\
+                     dispute,1,3\n\
+                     deposit,2,4,4.0\n\
+                     dispute,2,4\n\
+                     resolve,2,4\n\
+                     deposit,3,5,1.0\n\
+                     dispute,3,5\n";
+        for transaction in load_transactions(input.as_bytes()) {
+            clients.process_transaction(transaction.unwrap()).unwrap();
+        }
+        let mut buf = Vec::new();
+        clients
+            .write_held(&mut buf, &WriteOptions::default())
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,tx,amount\n\
+             1,1,10.0000\n\
+             1,3,2.5000\n\
+             1,,2.0000\n\
+             3,5,1.0000\n"
+        );
+    }
+
+    #[test]
+    fn test_metadata_columns() {
+        let mut clients = Clients::new();
+        for record in ["deposit,1,1,2.0", "deposit,2,2,3.0", "dispute,2,2"] {
+            clients
+                .process_transaction(parse_record(record).unwrap())
+                .unwrap();
+        }
+        let metadata =
+            ClientMetadata::read("client,name,email\n2,Bob,bob@example.com\n".as_bytes());
+        let options = WriteOptions {
+            totals: true,
+            metadata: Some(Arc::new(metadata.unwrap())),
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        clients.write_with(&mut buf, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,available,held,total,locked,name,email,tier\n\
+             1,2.0000,0.0000,2.0000,false,,,\n\
+             2,0.0000,3.0000,3.0000,false,Bob,bob@example.com,\n\
+             total,2.0000,3.0000,5.0000,0,,,\n"
+        );
+        let mut buf = Vec::new();
+        clients.write_held(&mut buf, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,tx,amount,name,email,tier\n2,2,3.0000,Bob,bob@example.com,\n"
+        );
+    }
+
+    #[test]
+    fn test_client_keys() {
+        let input = "type,client,tx,amount\n\
+                     deposit,f81d4fae-7dec-11d0-a765-00a0c91e6bf6,1,2.0\n\
+                     deposit,acme,2,3.0\n\
+                     dispute,acme,2\n";
+        let keys = Arc::new(ClientKeys::new());
+        let load = LoadOptions {
+            client_keys: Some(keys.clone()),
+            ..Default::default()
+        };
+        let mut clients = Clients::new();
+        for transaction in load_transactions_with(input.as_bytes(), load) {
+            clients.process_transaction(transaction.unwrap()).unwrap();
+        }
+        let options = WriteOptions {
+            client_keys: Some(keys),
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        clients.write_with(&mut buf, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,available,held,total,locked\n\
+             f81d4fae-7dec-11d0-a765-00a0c91e6bf6,2.0000,0.0000,2.0000,false\n\
+             acme,0.0000,3.0000,3.0000,false\n"
+        );
+        let mut buf = Vec::new();
+        clients.write_held(&mut buf, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,tx,amount\nacme,2,3.0000\n"
+        );
+    }
+
+    #[test]
+    fn test_extended_output() {
+        let mut clients = Clients::new();
+        for (i, record) in [
+            "deposit,1,1,2.0",
+            "withdrawal,1,2,1.0",
+            "deposit,2,3,3.0",
+            "dispute,2,3",
+            "chargeback,2,3",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            clients.set_line(i as u64 + 2);
+            if record.starts_with("chargeback") {
+                clients.set_time(1_714_521_600);
+            }
+            clients
+                .process_transaction(parse_record(record).unwrap())
+                .unwrap();
+        }
+        // Without a line or time, it's not known when.
+        clients.line = None;
+        clients.time = None;
+        clients
+            .process_transaction(parse_record("deposit,3,6,1.0").unwrap())
+            .unwrap();
+        let options = WriteOptions {
+            totals: true,
+            extended: true,
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        clients.write_with(&mut buf, &options).unwrap();
+        // Activity is by line until there's a timestamp.
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,available,held,total,locked,deposits,withdrawals,disputes,resolves,chargebacks,\
+             first_active,last_active\n\
+             1,1.0000,0.0000,1.0000,false,1,1,0,0,0,2,3\n\
+             2,0.0000,0.0000,0.0000,true,1,0,1,0,1,4,2024-05-01T00:00:00Z\n\
+             3,1.0000,0.0000,1.0000,false,1,0,0,0,0,,\n\
+             total,2.0000,0.0000,2.0000,1,3,1,1,0,1,2,2024-05-01T00:00:00Z\n"
+        );
+    }
+
+    #[test]
+    fn test_listener() {
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut clients = Clients::new();
+        clients.add_listener(Box::new({
+            let seen = seen.clone();
+            move |id, _, event, client| {
+                seen.lock().unwrap().push((id, *event, client.locked()));
+            }
+        }));
+        let input = "type, client, tx, amount
+deposit, 1, 1, 1.0
+withdrawal, 1, 2, 5.0
+dispute, 1, 1,
+chargeback, 1, 1,
+";
+        for transaction in load_transactions(input.as_bytes()) {
+            let _ = clients.process_transaction(transaction.unwrap());
+        }
+        let seen = seen.lock().unwrap();
+        let events: Vec<_> = seen.iter().map(|(_, event, _)| *event).collect();
+        let tx = TransactionId::from(1);
+        assert_eq!(
+            events,
+            [
+                Event::FundsDeposited {
+                    transaction_id: tx,
+                    currency: None,
+                    amount: Amount::from_minor_units(10_000),
+                },
+                Event::DisputeOpened {
+                    transaction_id: tx,
+                    allow_negative: false,
+                },
+                Event::ChargedBack { transaction_id: tx },
+                Event::AccountLocked { transaction_id: tx },
+            ]
+        );
+        // Listeners see the state after the whole transaction.
+        assert!(seen[3].2 && seen[2].2);
+    }
+
+    #[test]
+    fn test_generic_ids() {
+        // An embedder's own keys: 64-bit clients, and transactions numbered
+        // within batches.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        struct Key(u16, u64);
+
+        impl std::fmt::Display for Key {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "{}/{}", self.0, self.1)
+            }
+        }
+
+        let mut clients: Clients<u64, Key> =
+            Clients::open(Box::new(MemoryStore::default())).unwrap();
+        clients.set_pending_limit(1);
+        let client = u64::MAX;
+        let transaction = |data| Transaction {
+            client_id: client,
+            currency: None,
+            data,
+        };
+        let amount = Amount::from_minor_units(10_000);
+        let (first, second) = (Key(1, 1), Key(2, 1));
+        clients
+            .process_transaction(transaction(TransactionData::Deposit {
+                transaction_id: first,
+                amount,
+            }))
+            .unwrap();
+        assert!(matches!(
+            clients.process_transaction(transaction(TransactionData::Dispute {
+                transaction_id: second,
+            })),
+            Err(ProcessError::Pending)
+        ));
+        clients
+            .process_transaction(transaction(TransactionData::Deposit {
+                transaction_id: second,
+                amount,
+            }))
+            .unwrap();
+        // The same number in another batch is another transaction.
+        assert!(matches!(
+            clients.process_transaction(transaction(TransactionData::Deposit {
+                transaction_id: Key(1, 1),
+                amount,
+            })),
+            Err(ProcessError::Rejected(ClientError::DuplicateTransactionId))
+        ));
+        let events = clients
+            .process_transaction(transaction(TransactionData::Chargeback {
+                transaction_id: second,
+            }))
+            .unwrap();
+        assert_eq!(
+            events,
+            [
+                Event::ChargedBack {
+                    transaction_id: second
+                },
+                Event::AccountLocked {
+                    transaction_id: second
+                },
+            ]
+        );
+        let state = clients.get(client).unwrap().unwrap();
+        assert_eq!(state.total(None), amount.into());
+        assert!(state.locked());
+        assert_eq!(clients.client_ids().unwrap(), [client]);
+
+        clients.set_locked(client, false).unwrap();
+        assert!(!clients.get(client).unwrap().unwrap().locked());
     }
 }
@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+
+/// ISO 4217 style currency code, e.g. "USD".
+///
+/// Stored inline as three ASCII bytes so that it's cheap to copy and to keep
+/// alongside every retained deposit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Currency([u8; 3]);
+
+impl std::fmt::Display for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // The bytes are guaranteed to be ASCII uppercase letters by
+        // construction, so this can't fail.
+        f.write_str(std::str::from_utf8(&self.0).unwrap())
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.collect_str(&self)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum CurrencyParseError {
+    #[error("invalid currency code")]
+    InvalidFormat,
+}
+
+impl TryFrom<&str> for Currency {
+    type Error = CurrencyParseError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        // Be strict, as with amounts: exactly three uppercase ASCII letters.
+        let bytes: [u8; 3] = s
+            .as_bytes()
+            .try_into()
+            .map_err(|_| CurrencyParseError::InvalidFormat)?;
+        if !bytes.iter().all(u8::is_ascii_uppercase) {
+            return Err(CurrencyParseError::InvalidFormat);
+        }
+        Ok(Currency(bytes))
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D>(deserializer: D) -> Result<Currency, D::Error>
+    where
+        D: serde::de::Deserializer<'de>,
+    {
+        // Use a visitor rather than deserializing a `&str` so that owned
+        // strings are accepted too, e.g. when reading from an `io::Read`.
+        struct Visitor;
+
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = Currency;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a currency code")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, s: &str) -> Result<Currency, E> {
+                s.try_into().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(Currency::try_from("USD").unwrap().to_string(), "USD");
+    }
+
+    #[test_case(""; "empty")]
+    #[test_case("US"; "too short")]
+    #[test_case("USDT"; "too long")]
+    #[test_case("usd"; "lowercase")]
+    #[test_case("U5D"; "digit")]
+    fn test_invalid_format(s: &str) {
+        assert_eq!(
+            Currency::try_from(s),
+            Err(CurrencyParseError::InvalidFormat)
+        );
+    }
+}
@@ -0,0 +1,160 @@
+//! The summary and client histories as Polars `DataFrame`s, for analysis.
+//!
+//! Amounts are decimals with four decimal places, so they're exact.
+
+use polars::prelude::*;
+
+use crate::client::HistoryEntry;
+use crate::clients::Clients;
+use crate::store::StoreError;
+use crate::transaction::{ClientFilter, RawTransactionId};
+use crate::{Amount, SignedAmount};
+
+const AMOUNT_PRECISION: usize = 20;
+const AMOUNT_SCALE: usize = 4;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FrameError {
+    #[error("polars error: {0}")]
+    Polars(#[from] PolarsError),
+    #[error("store error: {0}")]
+    Store(#[from] StoreError),
+}
+
+fn amounts(name: &str, amounts: impl IntoIterator<Item = Option<Amount>>) -> Column {
+    let values = amounts
+        .into_iter()
+        .map(|amount| amount.map(|amount| i128::from(amount.to_minor_units())));
+    decimals(name, values)
+}
+
+fn balances(name: &str, balances: impl IntoIterator<Item = SignedAmount>) -> Column {
+    decimals(
+        name,
+        balances
+            .into_iter()
+            .map(|balance| Some(balance.to_minor_units())),
+    )
+}
+
+fn decimals(name: &str, minor_units: impl IntoIterator<Item = Option<i128>>) -> Column {
+    let values: Vec<_> = minor_units.into_iter().collect();
+    Int128Chunked::from_slice_options(name.into(), &values)
+        .into_decimal_unchecked(Some(AMOUNT_PRECISION), AMOUNT_SCALE)
+        .into_series()
+        .into_column()
+}
+
+/// The rows of the summary for `filter`, with columns `client`, `currency`
+/// (null for transactions without one), `available`, `held`, `total`, and
+/// `locked`.
+pub fn summary_frame(clients: &Clients, filter: &ClientFilter) -> Result<DataFrame, FrameError> {
+    let rows = clients.summary_rows(filter)?;
+    Ok(DataFrame::new(vec![
+        Column::new(
+            "client".into(),
+            rows.iter()
+                .map(|row| u16::from(row.client))
+                .collect::<Vec<_>>(),
+        ),
+        Column::new(
+            "currency".into(),
+            rows.iter()
+                .map(|row| row.currency.map(|c| c.to_string()))
+                .collect::<Vec<_>>(),
+        ),
+        balances("available", rows.iter().map(|row| row.available)),
+        amounts("held", rows.iter().map(|row| Some(row.held))),
+        balances("total", rows.iter().map(|row| row.total)),
+        Column::new(
+            "locked".into(),
+            rows.iter().map(|row| row.locked).collect::<Vec<_>>(),
+        ),
+    ])?)
+}
+
+/// The accepted transactions of the clients in `filter`, by client and then
+/// oldest first, with the same columns as the input: `type`, `client`,
+/// `tx`, `amount`, and `currency`. Empty unless the clients keep their
+/// history; see `Clients::set_keep_history`.
+pub fn history_frame(clients: &Clients, filter: &ClientFilter) -> Result<DataFrame, FrameError> {
+    let (mut types, mut ids, mut txs, mut values, mut currencies) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for id in clients.client_ids()? {
+        if !filter.contains(id) {
+            continue;
+        }
+        let Some(client) = clients.get(id)? else {
+            continue;
+        };
+        for HistoryEntry { transaction, .. } in client.history() {
+            types.push(transaction.data.name());
+            ids.push(u16::from(id));
+            txs.push(RawTransactionId::from(transaction.data.transaction_id()));
+            values.push(transaction.data.amount());
+            currencies.push(transaction.currency.map(|c| c.to_string()));
+        }
+    }
+    Ok(DataFrame::new(vec![
+        Column::new("type".into(), types),
+        Column::new("client".into(), ids),
+        Column::new("tx".into(), txs),
+        amounts("amount", values),
+        Column::new("currency".into(), currencies),
+    ])?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::parse_record;
+
+    #[test]
+    fn test_frames() {
+        let mut clients = Clients::new();
+        clients.set_keep_history();
+        for record in [
+            "deposit,1,1,1.5",
+            "deposit,2,2,2.0,GBP",
+            "withdrawal,1,3,5.0",
+            "dispute,2,2",
+        ] {
+            let _ = clients.process_transaction(parse_record(record).unwrap());
+        }
+
+        let summary = summary_frame(&clients, &ClientFilter::default()).unwrap();
+        assert_eq!(summary.shape(), (2, 6));
+        assert_eq!(
+            summary.column("currency").unwrap().str().unwrap().get(1),
+            Some("GBP")
+        );
+        let held = summary.column("held").unwrap();
+        assert_eq!(
+            held.dtype(),
+            &DataType::Decimal(Some(AMOUNT_PRECISION), Some(AMOUNT_SCALE))
+        );
+        assert_eq!(held.decimal().unwrap().physical().get(1), Some(20000));
+
+        let history = history_frame(&clients, &ClientFilter::default()).unwrap();
+        // The rejected withdrawal isn't in the history.
+        assert_eq!(history.height(), 3);
+        let types: Vec<_> = history
+            .column("type")
+            .unwrap()
+            .str()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert_eq!(types, vec!["deposit", "deposit", "dispute"]);
+        assert_eq!(
+            history
+                .column("amount")
+                .unwrap()
+                .decimal()
+                .unwrap()
+                .physical()
+                .get(2),
+            None
+        );
+    }
+}
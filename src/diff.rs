@@ -0,0 +1,365 @@
+//! Comparing two states - summaries written by previous runs, or snapshots -
+//! for reconciliation: which clients' balances moved, which accounts were
+//! locked, and which clients only appear on one side.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::clients::{Clients, SeedError};
+use crate::snapshot::{binary, ClientSnapshot, Snapshot, SnapshotError};
+use crate::store::{MemoryStore, StoreError};
+use crate::transaction::ClientId;
+use crate::{Amount, Currency, SignedAmount};
+
+#[derive(Debug, thiserror::Error)]
+pub enum DiffError {
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid summary: {0}")]
+    Summary(#[from] SeedError),
+    #[error("invalid snapshot: {0}")]
+    Snapshot(#[from] SnapshotError),
+    #[error("store error: {0}")]
+    Store(#[from] StoreError),
+    #[error("csv error: {0}")]
+    Csv(#[from] csv::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difference {
+    OnlyInFirst(ClientId),
+    OnlyInSecond(ClientId),
+    /// The change in the client's balance in `currency`, from the first state
+    /// to the second.
+    Balance {
+        client: ClientId,
+        currency: Option<Currency>,
+        available: Delta,
+        held: Delta,
+    },
+    Locked(ClientId),
+    Unlocked(ClientId),
+}
+
+/// A signed change in an amount.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Delta {
+    pub from: SignedAmount,
+    pub to: SignedAmount,
+}
+
+impl Delta {
+    /// Whether the change is no more than `tolerance` either way.
+    fn within(&self, tolerance: Amount) -> bool {
+        self.to.saturating_sub(self.from).magnitude() <= tolerance
+    }
+}
+
+impl std::fmt::Display for Delta {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // Changes from overdrawn to in credit can be larger than any amount,
+        // so are shown as the largest.
+        let change = self.to.saturating_sub(self.from);
+        if change.is_negative() {
+            write!(f, "{}", change)
+        } else {
+            write!(f, "+{}", change)
+        }
+    }
+}
+
+/// Load the state saved at `path`, either as a summary or as a snapshot in
+/// either format.
+pub fn load(path: impl AsRef<Path>) -> Result<Snapshot, DiffError> {
+    let data = std::fs::read(path)?;
+    if data.starts_with(b"{") || data.starts_with(binary::MAGIC) {
+        Ok(Snapshot::read(data.as_slice())?)
+    } else {
+        let clients = Clients::seed(Box::new(MemoryStore::new()), data.as_slice())?;
+        Ok(clients.snapshot()?)
+    }
+}
+
+/// Compare two states, ignoring deposits: summaries don't include them.
+///
+/// Differences are ordered by client ID. A balance missing from one side is
+/// treated as zero.
+pub fn diff(first: &Snapshot, second: &Snapshot) -> Vec<Difference> {
+    diff_within(first, second, Amount::ZERO)
+}
+
+/// Compare two states as for `diff`, but ignoring changes in balances of no
+/// more than `tolerance`, e.g. from rounding.
+pub fn diff_within(first: &Snapshot, second: &Snapshot, tolerance: Amount) -> Vec<Difference> {
+    fn by_id(snapshot: &Snapshot) -> BTreeMap<ClientId, &ClientSnapshot> {
+        snapshot.clients.iter().map(|c| (c.id, c)).collect()
+    }
+    fn balances(
+        client: &ClientSnapshot,
+    ) -> BTreeMap<Option<Currency>, (SignedAmount, SignedAmount)> {
+        client
+            .balances
+            .iter()
+            .map(|b| (b.currency, (b.available, b.held.into())))
+            .collect()
+    }
+
+    let first = by_id(first);
+    let second = by_id(second);
+    let mut ids: Vec<_> = first.keys().chain(second.keys()).copied().collect();
+    ids.sort();
+    ids.dedup();
+
+    let mut differences = Vec::new();
+    for id in ids {
+        let (a, b) = match (first.get(&id), second.get(&id)) {
+            (Some(a), Some(b)) => (a, b),
+            (Some(_), None) => {
+                differences.push(Difference::OnlyInFirst(id));
+                continue;
+            }
+            (None, _) => {
+                differences.push(Difference::OnlyInSecond(id));
+                continue;
+            }
+        };
+
+        let a_balances = balances(a);
+        let b_balances = balances(b);
+        let mut currencies: Vec<_> = a_balances.keys().chain(b_balances.keys()).collect();
+        currencies.sort();
+        currencies.dedup();
+        for &currency in currencies {
+            let zero = (SignedAmount::ZERO, SignedAmount::ZERO);
+            let from = a_balances.get(&currency).unwrap_or(&zero);
+            let to = b_balances.get(&currency).unwrap_or(&zero);
+            let available = Delta {
+                from: from.0,
+                to: to.0,
+            };
+            let held = Delta {
+                from: from.1,
+                to: to.1,
+            };
+            if !available.within(tolerance) || !held.within(tolerance) {
+                differences.push(Difference::Balance {
+                    client: id,
+                    currency,
+                    available,
+                    held,
+                });
+            }
+        }
+
+        match (a.locked, b.locked) {
+            (false, true) => differences.push(Difference::Locked(id)),
+            (true, false) => differences.push(Difference::Unlocked(id)),
+            _ => {}
+        }
+    }
+    differences
+}
+
+/// Write the differences as CSV, one row per difference. Amounts are signed
+/// changes, and are empty for rows that aren't about a balance. The currency
+/// column is only written if `multi_currency`.
+pub fn write(
+    writer: impl std::io::Write,
+    differences: &[Difference],
+    multi_currency: bool,
+) -> Result<(), DiffError> {
+    #[derive(Serialize)]
+    struct Row {
+        client: ClientId,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        currency: Option<String>,
+        change: &'static str,
+        available: Option<String>,
+        held: Option<String>,
+        total: Option<String>,
+    }
+
+    let mut writer = csv::Writer::from_writer(writer);
+    for difference in differences {
+        let (client, change) = match *difference {
+            Difference::OnlyInFirst(client) => (client, "only_in_first"),
+            Difference::OnlyInSecond(client) => (client, "only_in_second"),
+            Difference::Balance { client, .. } => (client, "balance"),
+            Difference::Locked(client) => (client, "locked"),
+            Difference::Unlocked(client) => (client, "unlocked"),
+        };
+        let mut row = Row {
+            client,
+            currency: multi_currency.then(String::new),
+            change,
+            available: None,
+            held: None,
+            total: None,
+        };
+        if let Difference::Balance {
+            currency,
+            available,
+            held,
+            ..
+        } = *difference
+        {
+            let total = Delta {
+                from: available.from + held.from,
+                to: available.to + held.to,
+            };
+            if multi_currency {
+                row.currency = currency.map(|c| c.to_string()).or(row.currency);
+            }
+            row.available = Some(available.to_string());
+            row.held = Some(held.to_string());
+            row.total = Some(total.to_string());
+        }
+        writer.serialize(row)?;
+    }
+    writer.flush().map_err(csv::Error::from)?;
+    Ok(())
+}
+
+/// Write a line describing each difference, taking the first state as the
+/// expected one and the second as the actual one.
+pub fn write_report(
+    mut writer: impl std::io::Write,
+    differences: &[Difference],
+) -> std::io::Result<()> {
+    for difference in differences {
+        match *difference {
+            Difference::OnlyInFirst(client) => writeln!(writer, "client {}: missing", client)?,
+            Difference::OnlyInSecond(client) => writeln!(writer, "client {}: unexpected", client)?,
+            Difference::Balance {
+                client,
+                currency,
+                available,
+                held,
+            } => writeln!(
+                writer,
+                "client {}{}: expected available {}, held {}; got available {}, held {}",
+                client,
+                currency.map(|c| format!(" {}", c)).unwrap_or_default(),
+                available.from,
+                held.from,
+                available.to,
+                held.to
+            )?,
+            Difference::Locked(client) => {
+                writeln!(writer, "client {}: expected unlocked; got locked", client)?
+            }
+            Difference::Unlocked(client) => {
+                writeln!(writer, "client {}: expected locked; got unlocked", client)?
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::load_transactions;
+
+    fn state(input: &str) -> Snapshot {
+        let mut clients = Clients::new();
+        for transaction in load_transactions(input.as_bytes()) {
+            let _ = clients.process_transaction(transaction.unwrap());
+        }
+        clients.snapshot().unwrap()
+    }
+
+    fn written(differences: &[Difference], multi_currency: bool) -> String {
+        let mut buf = Vec::new();
+        write(&mut buf, differences, multi_currency).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_diff() {
+        let first = state(
+            "type, client, tx, amount
+deposit, 1, 1, 1.0
+deposit, 2, 2, 2.0
+deposit, 3, 3, 3.0
+",
+        );
+        let second = state(
+            "type, client, tx, amount
+deposit, 1, 1, 1.5
+deposit, 2, 2, 2.0
+dispute, 2, 2
+chargeback, 2, 2
+deposit, 4, 4, 4.0
+",
+        );
+        assert_eq!(
+            written(&diff(&first, &second), false),
+            "client,change,available,held,total
+1,balance,+0.5000,+0.0000,+0.5000
+2,balance,-2.0000,+0.0000,-2.0000
+2,locked,,,
+3,only_in_first,,,
+4,only_in_second,,,
+"
+        );
+    }
+
+    #[test]
+    fn test_tolerance() {
+        let first = state("type, client, tx, amount\ndeposit, 1, 1, 1.0\ndeposit, 2, 2, 2.0\n");
+        let second =
+            state("type, client, tx, amount\ndeposit, 1, 1, 1.0001\ndeposit, 2, 2, 2.01\n");
+        let differences = diff_within(&first, &second, Amount::try_from("0.0001").unwrap());
+        let mut buf = Vec::new();
+        write_report(&mut buf, &differences).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client 2: expected available 2.0000, held 0.0000; got available 2.0100, held 0.0000\n"
+        );
+        assert_eq!(diff(&first, &second).len(), 2);
+    }
+
+    #[test]
+    fn test_same() {
+        let input = "type, client, tx, amount\ndeposit, 1, 1, 1.0\ndispute, 1, 1\n";
+        assert_eq!(diff(&state(input), &state(input)), vec![]);
+    }
+
+    #[test]
+    fn test_multi_currency() {
+        let first = state("type, client, tx, amount, currency\ndeposit, 1, 1, 1.0, USD\n");
+        let second = state("type, client, tx, amount, currency\ndeposit, 1, 1, 1.0, EUR\n");
+        assert_eq!(
+            written(&diff(&first, &second), true),
+            "client,currency,change,available,held,total
+1,EUR,balance,+1.0000,+0.0000,+1.0000
+1,USD,balance,-1.0000,+0.0000,-1.0000
+"
+        );
+    }
+
+    #[test]
+    fn test_load_summary_and_snapshot() {
+        // A summary and a snapshot of the same state don't differ.
+        let dir = tempfile::tempdir().unwrap();
+        let mut clients = Clients::new();
+        for transaction in load_transactions(
+            "type, client, tx, amount\ndeposit, 1, 1, 1.0\ndeposit, 2, 2, 2.0\ndispute, 2, 2\n"
+                .as_bytes(),
+        ) {
+            clients.process_transaction(transaction.unwrap()).unwrap();
+        }
+        let summary_path = dir.path().join("summary.csv");
+        clients
+            .write(std::fs::File::create(&summary_path).unwrap())
+            .unwrap();
+        let snapshot_path = dir.path().join("snapshot.json");
+        clients.save_snapshot(&snapshot_path).unwrap();
+
+        let summary = load(&summary_path).unwrap();
+        let snapshot = load(&snapshot_path).unwrap();
+        assert_eq!(diff(&summary, &snapshot), vec![]);
+    }
+}
@@ -0,0 +1,173 @@
+//! Writing the summary periodically while transactions stream in, e.g. from a
+//! message broker or a server's clients, so that downstream consumers always
+//! have a recent summary rather than waiting for the program to stop.
+//!
+//! Each summary replaces the last atomically, so a reader never sees one half
+//! written.
+
+use std::num::NonZeroU64;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::clients::{Clients, WriteError, WriteOptions};
+use crate::snapshot::{save_atomically, SnapshotError};
+
+/// How often to write the summary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmitEvery {
+    /// Once this long has passed since the last one, at the next transaction.
+    Interval(Duration),
+    /// After every N transactions.
+    Transactions(NonZeroU64),
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum EmitEveryParseError {
+    #[error("expected a number of transactions, or a duration such as '30s', '5m', or '1h'")]
+    InvalidFormat,
+}
+
+impl std::str::FromStr for EmitEvery {
+    type Err = EmitEveryParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unit = match s.as_bytes().last() {
+            Some(b's') => 1,
+            Some(b'm') => 60,
+            Some(b'h') => 60 * 60,
+            _ => {
+                return s
+                    .parse()
+                    .map(EmitEvery::Transactions)
+                    .map_err(|_| EmitEveryParseError::InvalidFormat)
+            }
+        };
+        match s[..s.len() - 1].parse::<u64>() {
+            Ok(n) if n > 0 => n
+                .checked_mul(unit)
+                .map(|secs| EmitEvery::Interval(Duration::from_secs(secs)))
+                .ok_or(EmitEveryParseError::InvalidFormat),
+            _ => Err(EmitEveryParseError::InvalidFormat),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EmitError {
+    #[error(transparent)]
+    Write(#[from] WriteError),
+    #[error(transparent)]
+    Save(#[from] SnapshotError),
+}
+
+pub struct Emitter {
+    path: PathBuf,
+    // `None` to only write the summary when asked.
+    every: Option<EmitEvery>,
+    options: WriteOptions,
+    // When the summary was last written, or the emitter created.
+    last: Instant,
+    // Transactions processed since the summary was last written.
+    count: u64,
+}
+
+impl Emitter {
+    /// Write the summary to `path` `every` so often, if given, as well as when
+    /// asked.
+    pub fn new(path: impl Into<PathBuf>, every: Option<EmitEvery>, options: WriteOptions) -> Self {
+        Self {
+            path: path.into(),
+            every,
+            options,
+            last: Instant::now(),
+            count: 0,
+        }
+    }
+
+    /// Note that a transaction has been processed, writing the summary of
+    /// `clients` if one is due. Returns whether it was written.
+    pub fn processed(&mut self, clients: &Clients) -> Result<bool, EmitError> {
+        self.count += 1;
+        let due = match self.every {
+            Some(EmitEvery::Interval(interval)) => self.last.elapsed() >= interval,
+            Some(EmitEvery::Transactions(n)) => self.count >= n.get(),
+            None => false,
+        };
+        if due {
+            self.write(clients)?;
+        }
+        Ok(due)
+    }
+
+    /// Whether the summary is written while processing, not just when asked.
+    pub fn is_periodic(&self) -> bool {
+        self.every.is_some()
+    }
+
+    /// Write the summary of `clients` now, e.g. at the end.
+    pub fn write(&mut self, clients: &Clients) -> Result<(), EmitError> {
+        // Written in full first, so that a failure leaves the last summary in
+        // place.
+        let mut summary = Vec::new();
+        clients.write_with(&mut summary, &self.options)?;
+        save_atomically(&self.path, |mut file| {
+            std::io::Write::write_all(&mut file, &summary)?;
+            Ok(())
+        })?;
+        self.last = Instant::now();
+        self.count = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::parse_record;
+
+    #[test]
+    fn test_parse_emit_every() {
+        assert_eq!(
+            "60s".parse(),
+            Ok(EmitEvery::Interval(Duration::from_secs(60)))
+        );
+        assert_eq!(
+            "5m".parse(),
+            Ok(EmitEvery::Interval(Duration::from_secs(300)))
+        );
+        assert_eq!(
+            "1000".parse(),
+            Ok(EmitEvery::Transactions(NonZeroU64::new(1000).unwrap()))
+        );
+        for s in ["", "0", "0s", "s", "-1m", "1d", "1.5h"] {
+            assert_eq!(
+                s.parse::<EmitEvery>(),
+                Err(EmitEveryParseError::InvalidFormat)
+            );
+        }
+    }
+
+    #[test]
+    fn test_emit_every_transactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("summary.csv");
+        let every = Some(EmitEvery::Transactions(NonZeroU64::new(2).unwrap()));
+        let mut emitter = Emitter::new(&path, every, WriteOptions::default());
+        let mut clients = Clients::new();
+        let mut written = Vec::new();
+        for record in ["deposit,1,1,1.0", "deposit,1,2,2.0", "deposit,2,3,4.0"] {
+            let _ = clients.process_transaction(parse_record(record).unwrap());
+            written.push(emitter.processed(&clients).unwrap());
+        }
+        assert_eq!(written, [false, true, false]);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "client,available,held,total,locked\n1,3.0000,0.0000,3.0000,false\n"
+        );
+
+        emitter.write(&clients).unwrap();
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .ends_with("2,4.0000,0.0000,4.0000,false\n"));
+    }
+}
@@ -0,0 +1,140 @@
+//! Sharing one set of clients between threads, e.g. for a server handling
+//! several connections at once.
+//!
+//! `Clients` can't be sent between threads, as a store may not allow it, so
+//! the clients stay on a thread of their own, which runs each job it's sent
+//! in turn. Jobs from one thread run in the order they're sent.
+
+use std::sync::mpsc;
+
+use crate::clients::Clients;
+use crate::snapshot::{Snapshot, SnapshotError};
+
+type Job = Box<dyn FnOnce(&mut Clients) + Send>;
+
+/// A handle to the thread that owns the clients. It stops once every clone of
+/// the handle is dropped.
+#[derive(Clone)]
+pub struct Engine {
+    jobs: mpsc::Sender<Job>,
+}
+
+/// The thread that owns the clients has stopped, because a job panicked.
+#[derive(Debug, thiserror::Error)]
+#[error("the engine has stopped")]
+pub struct Stopped;
+
+impl Engine {
+    /// Start the thread that owns the clients, starting from `snapshot` or
+    /// from scratch.
+    pub fn start(snapshot: Option<Snapshot>) -> Result<Self, SnapshotError> {
+        Self::spawn(None, snapshot)
+    }
+
+    /// As `start`, but for one tenant of several, labelling its logs and
+    /// metrics with `tenant`.
+    pub fn start_tenant(tenant: &str, snapshot: Option<Snapshot>) -> Result<Self, SnapshotError> {
+        Self::spawn(Some(tenant.to_string()), snapshot)
+    }
+
+    fn spawn(tenant: Option<String>, snapshot: Option<Snapshot>) -> Result<Self, SnapshotError> {
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let (ready, started) = mpsc::sync_channel(1);
+        std::thread::spawn(move || {
+            let span = match &tenant {
+                Some(tenant) => tracing::info_span!("tenant", tenant = %tenant),
+                None => tracing::Span::none(),
+            };
+            let _entered = span.enter();
+            #[cfg(feature = "otel")]
+            if let Some(tenant) = tenant {
+                crate::telemetry::set_tenant(tenant);
+            }
+            let mut clients = match snapshot.map(Clients::restore).transpose() {
+                Ok(clients) => clients.unwrap_or_default(),
+                Err(e) => {
+                    let _ = ready.send(Err(e));
+                    return;
+                }
+            };
+            let _ = ready.send(Ok(()));
+            for job in receiver {
+                job(&mut clients);
+            }
+        });
+        started.recv().expect("thread reports whether it started")?;
+        Ok(Self { jobs })
+    }
+
+    /// Run `job` on the clients without waiting for it, e.g. when it sends
+    /// its result some other way.
+    pub fn send(&self, job: impl FnOnce(&mut Clients) + Send + 'static) -> Result<(), Stopped> {
+        self.jobs.send(Box::new(job)).map_err(|_| Stopped)
+    }
+
+    /// Run `job` on the clients, waiting for its result.
+    pub fn call<T: Send + 'static>(
+        &self,
+        job: impl FnOnce(&mut Clients) -> T + Send + 'static,
+    ) -> Result<T, Stopped> {
+        let (sender, result) = mpsc::sync_channel(1);
+        self.send(move |clients| {
+            let _ = sender.send(job(clients));
+        })?;
+        result.recv().map_err(|_| Stopped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{ClientId, RawTransactionId, Transaction, TransactionData};
+    use crate::{Amount, TransactionId};
+
+    fn deposit(client: u16, tx: RawTransactionId) -> Transaction {
+        Transaction {
+            client_id: ClientId::from(client),
+            currency: None,
+            data: TransactionData::Deposit {
+                transaction_id: TransactionId::from(tx),
+                amount: Amount::from_minor_units(10_000),
+            },
+        }
+    }
+
+    #[test]
+    fn test_shared_between_threads() {
+        let engine = Engine::start(None).unwrap();
+        std::thread::scope(|scope| {
+            for client in 1..=4 {
+                let engine = engine.clone();
+                scope.spawn(move || {
+                    for tx in 0..100 {
+                        let tx = RawTransactionId::from(client) * 1000 + tx;
+                        engine
+                            .call(move |clients| clients.process_transaction(deposit(client, tx)))
+                            .unwrap()
+                            .unwrap();
+                    }
+                });
+            }
+        });
+        let snapshot = engine.call(|clients| clients.snapshot().unwrap()).unwrap();
+        assert_eq!(snapshot.clients.len(), 4);
+
+        let restarted = Engine::start(Some(snapshot.clone())).unwrap();
+        assert_eq!(
+            restarted
+                .call(|clients| clients.snapshot().unwrap())
+                .unwrap(),
+            snapshot
+        );
+    }
+
+    #[test]
+    fn test_stopped() {
+        let engine = Engine::start(None).unwrap();
+        assert!(engine.call(|_| panic!("job failed")).is_err());
+        assert!(engine.call(|_| ()).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! A machine-readable stream of problems found while processing - invalid
+//! records and rejected transactions - as JSON lines, one per problem.
+//!
+//! Invalid records can also be quarantined: copied exactly as they were in
+//! the input, so they can be fixed and processed again.
+
+use serde::Serialize;
+use std::io::Write;
+
+use crate::client::ClientError;
+use crate::transaction::TransactionError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    /// The record couldn't be parsed as a transaction.
+    Invalid,
+    /// The transaction was rejected, e.g. for insufficient funds.
+    Rejected,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Problem<'a> {
+    pub line: u64,
+    // The record's fields, after trimming.
+    pub record: Vec<&'a str>,
+    pub category: Category,
+    // A stable name for the error, e.g. `insufficient_funds`.
+    pub kind: &'static str,
+    pub message: String,
+}
+
+impl<'a> Problem<'a> {
+    pub fn invalid(line: u64, record: &'a csv::StringRecord, error: &TransactionError) -> Self {
+        Self {
+            line,
+            record: record.iter().collect(),
+            category: Category::Invalid,
+            kind: error.name(),
+            message: error.to_string(),
+        }
+    }
+
+    pub fn rejected(line: u64, record: &'a csv::StringRecord, error: &ClientError) -> Self {
+        Self {
+            line,
+            record: record.iter().collect(),
+            category: Category::Rejected,
+            kind: error.name(),
+            message: error.to_string(),
+        }
+    }
+}
+
+pub struct ErrorWriter<W: std::io::Write> {
+    writer: std::io::BufWriter<W>,
+}
+
+impl<W: std::io::Write> ErrorWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: std::io::BufWriter::new(writer),
+        }
+    }
+
+    pub fn write(&mut self, problem: &Problem) -> std::io::Result<()> {
+        serde_json::to_writer(&mut self.writer, problem)?;
+        self.writer.write_all(b"\n")
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Writes quarantined records as CSV, with the line number, the error's
+/// `kind` and message, and the raw line.
+pub struct Quarantine<W: std::io::Write> {
+    writer: csv::Writer<W>,
+    count: u64,
+}
+
+impl<W: std::io::Write> Quarantine<W> {
+    pub fn new(writer: W) -> std::io::Result<Self> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(["line", "kind", "error", "record"])?;
+        Ok(Self { writer, count: 0 })
+    }
+
+    /// Quarantine the record read from `raw`, which may span several lines
+    /// if it has quoted fields, or not be valid UTF-8.
+    pub fn write(
+        &mut self,
+        line: u64,
+        error: &TransactionError,
+        raw: &[u8],
+    ) -> std::io::Result<()> {
+        let raw = trim_line_breaks(raw);
+        self.writer.write_record([
+            line.to_string().as_bytes(),
+            error.name().as_bytes(),
+            error.to_string().as_bytes(),
+            raw,
+        ])?;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// How many records have been quarantined.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// `raw` without the line break ending it, or any blank lines around it.
+fn trim_line_breaks(raw: &[u8]) -> &[u8] {
+    let is_break = |b: &u8| *b == b'\n' || *b == b'\r';
+    let start = raw.iter().position(|b| !is_break(b)).unwrap_or(raw.len());
+    let end = raw
+        .iter()
+        .rposition(|b| !is_break(b))
+        .map_or(start, |i| i + 1);
+    &raw[start..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let record = csv::StringRecord::from(vec!["withdrawal", "1", "2", "5.0"]);
+        let mut writer = ErrorWriter::new(Vec::new());
+        writer
+            .write(&Problem::rejected(
+                3,
+                &record,
+                &ClientError::InsufficientFunds,
+            ))
+            .unwrap();
+        writer
+            .write(&Problem::invalid(
+                4,
+                &csv::StringRecord::new(),
+                &TransactionError::MissingAmount,
+            ))
+            .unwrap();
+        writer.flush().unwrap();
+        assert_eq!(
+            String::from_utf8(writer.writer.into_inner().unwrap()).unwrap(),
+            r#"{"line":3,"record":["withdrawal","1","2","5.0"],"category":"rejected","kind":"insufficient_funds","message":"insufficient funds"}
+{"line":4,"record":[],"category":"invalid","kind":"missing_amount","message":"missing amount"}
+"#
+        );
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let mut quarantine = Quarantine::new(Vec::new()).unwrap();
+        quarantine
+            .write(2, &TransactionError::MissingAmount, b"deposit,1,1,\r\n")
+            .unwrap();
+        quarantine
+            .write(
+                5,
+                &TransactionError::MissingAmount,
+                b"\n\"deposit\",1,2,\xff\n",
+            )
+            .unwrap();
+        assert_eq!(quarantine.count(), 2);
+        quarantine.flush().unwrap();
+        assert_eq!(
+            quarantine.writer.into_inner().unwrap(),
+            b"line,kind,error,record
+2,missing_amount,missing amount,\"deposit,1,1,\"
+5,missing_amount,missing amount,\"\"\"deposit\"\",1,2,\xff\"
+"
+        );
+    }
+}
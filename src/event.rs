@@ -0,0 +1,220 @@
+//! Domain events. Every change to a client's state is made by applying an
+//! event, so the events emitted while processing can be written out and later
+//! replayed to rebuild exactly the same state, without the original
+//! transactions.
+//!
+//! Events are written as JSON lines, one per event.
+
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+use crate::client::ClientError;
+use crate::clients::{Clients, ProcessError};
+use crate::store::StoreError;
+use crate::transaction::{ClientId, Id};
+use crate::{Amount, Currency, TransactionId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<T = TransactionId> {
+    FundsDeposited {
+        transaction_id: T,
+        currency: Option<Currency>,
+        amount: Amount,
+    },
+    FundsWithdrawn {
+        transaction_id: T,
+        currency: Option<Currency>,
+        amount: Amount,
+        // Whether the withdrawal is kept so that it can be disputed later.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        disputable: bool,
+        // How far below zero the withdrawal may take the available funds.
+        #[serde(default, skip_serializing_if = "Amount::is_zero")]
+        overdraft: Amount,
+    },
+    DisputeOpened {
+        transaction_id: T,
+        // Whether a disputed deposit may be held even if that takes the
+        // available funds below zero.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        allow_negative: bool,
+    },
+    DisputeResolved {
+        transaction_id: T,
+    },
+    ChargedBack {
+        transaction_id: T,
+    },
+    // `transaction_id` is the transaction that caused the lock.
+    AccountLocked {
+        transaction_id: T,
+    },
+    // The deposit is too old to be disputed any more.
+    DepositExpired {
+        transaction_id: T,
+    },
+}
+
+impl<T: Id> Event<T> {
+    pub fn transaction_id(&self) -> T {
+        match self {
+            Event::FundsDeposited { transaction_id, .. }
+            | Event::FundsWithdrawn { transaction_id, .. }
+            | Event::DisputeOpened { transaction_id, .. }
+            | Event::DisputeResolved { transaction_id }
+            | Event::ChargedBack { transaction_id }
+            | Event::AccountLocked { transaction_id }
+            | Event::DepositExpired { transaction_id } => *transaction_id,
+        }
+    }
+}
+
+/// An event along with the client it happened to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientEvent<C = ClientId, T = TransactionId> {
+    pub client: C,
+    #[serde(flatten)]
+    pub event: Event<T>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EventError {
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("malformed event: {0}")]
+    Malformed(#[from] serde_json::Error),
+    // The event doesn't fit the state built up from the events before it, so
+    // the log can't have been produced by processing transactions.
+    #[error("inconsistent event for client {client}: {error}")]
+    Inconsistent {
+        client: ClientId,
+        error: ClientError,
+    },
+    #[error("store error: {0}")]
+    Store(#[from] StoreError),
+}
+
+pub struct EventWriter<W: std::io::Write> {
+    writer: std::io::BufWriter<W>,
+}
+
+impl<W: std::io::Write> EventWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: std::io::BufWriter::new(writer),
+        }
+    }
+
+    pub fn write(&mut self, event: &ClientEvent) -> Result<(), EventError> {
+        serde_json::to_writer(&mut self.writer, event)?;
+        Ok(self.writer.write_all(b"\n")?)
+    }
+
+    pub fn flush(&mut self) -> Result<(), EventError> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+pub fn read_events<R: std::io::Read>(
+    reader: R,
+) -> impl Iterator<Item = Result<ClientEvent, EventError>> {
+    serde_json::Deserializer::from_reader(std::io::BufReader::new(reader))
+        .into_iter()
+        .map(|event| Ok(event?))
+}
+
+/// Rebuild state purely from events, applying those read from `reader` to
+/// `clients`.
+pub fn replay(reader: impl std::io::Read, clients: &mut Clients) -> Result<(), EventError> {
+    for event in read_events(reader) {
+        let event = event?;
+        match clients.apply_event(&event) {
+            Ok(()) => {}
+            Err(ProcessError::Rejected(error)) => {
+                return Err(EventError::Inconsistent {
+                    client: event.client,
+                    error,
+                })
+            }
+            Err(ProcessError::Store(e)) => return Err(e.into()),
+            Err(ProcessError::Pending) => unreachable!("events are never held"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::load_transactions;
+
+    fn summary(clients: &Clients) -> String {
+        let mut buf = Vec::new();
+        clients.write(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_replay() {
+        let input = "type, client, tx, amount, currency
+deposit, 1, 1, 1.0, USD
+deposit, 1, 2, 2.0,
+withdrawal, 1, 3, 5.0,
+dispute, 1, 2
+deposit, 2, 4, 3.0,
+dispute, 2, 4
+chargeback, 2, 4
+deposit, 2, 5, 1.0,
+";
+        let mut clients = Clients::new();
+        let mut writer = EventWriter::new(Vec::new());
+        for transaction in load_transactions(input.as_bytes()) {
+            let client = transaction.as_ref().unwrap().client_id;
+            // Rejected transactions don't emit any events.
+            for event in clients
+                .process_transaction(transaction.unwrap())
+                .unwrap_or_default()
+            {
+                writer.write(&ClientEvent { client, event }).unwrap();
+            }
+        }
+        writer.flush().unwrap();
+        let events = writer.writer.into_inner().unwrap();
+
+        let mut replayed = Clients::new();
+        replay(events.as_slice(), &mut replayed).unwrap();
+        assert_eq!(replayed.snapshot().unwrap(), clients.snapshot().unwrap());
+        assert_eq!(summary(&replayed), summary(&clients));
+    }
+
+    #[test]
+    fn test_format() {
+        let event = ClientEvent {
+            client: ClientId::from(1),
+            event: Event::FundsDeposited {
+                transaction_id: TransactionId::from(2),
+                currency: None,
+                amount: Amount::try_from("3.5").unwrap(),
+            },
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            json,
+            r#"{"client":1,"event":"funds_deposited","transaction_id":2,"currency":null,"amount":"3.5000"}"#
+        );
+        assert_eq!(serde_json::from_str::<ClientEvent>(&json).unwrap(), event);
+    }
+
+    #[test]
+    fn test_replay_inconsistent() {
+        let events = r#"{"client":1,"event":"dispute_opened","transaction_id":1}"#;
+        assert!(matches!(
+            replay(events.as_bytes(), &mut Clients::new()),
+            Err(EventError::Inconsistent {
+                error: ClientError::UnknownTransactionId,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,510 @@
+//! Working out what kind of file the input is - CSV, JSON lines, or Parquet,
+//! and whether it's compressed with gzip or zstd - from its extension and its
+//! first few bytes, so that operators needn't say for each source.
+//!
+//! Anything but CSV is converted to CSV as it's read, one line for each line
+//! of JSON or row of Parquet, so that it's processed exactly as CSV would be
+//! and line numbers in reports count lines or rows, as for CSV ignoring blank
+//! lines.
+
+use serde_json::value::RawValue;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+use crate::transaction::{LoadOptions, COLUMNS};
+
+/// The format of an input file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum InputFormat {
+    /// Detect the format from the file's extension and first bytes.
+    #[default]
+    Auto,
+    Csv,
+    /// One JSON object per line, with the CSV columns as its fields.
+    Jsonl,
+    /// Columns named as in CSV. Only with the `parquet` feature.
+    Parquet,
+}
+
+/// How an input file is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum FormatError {
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("built without support for {0}")]
+    Unsupported(&'static str),
+    #[error("compressed Parquet isn't supported: Parquet compresses its own columns")]
+    CompressedParquet,
+    #[cfg(feature = "parquet")]
+    #[error("invalid Parquet: {0}")]
+    Parquet(#[from] polars::prelude::PolarsError),
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const PARQUET_MAGIC: &[u8] = b"PAR1";
+
+/// How a file starting with `start` is compressed.
+pub fn detect_compression(start: &[u8]) -> Compression {
+    if start.starts_with(GZIP_MAGIC) {
+        Compression::Gzip
+    } else if start.starts_with(ZSTD_MAGIC) {
+        Compression::Zstd
+    } else {
+        Compression::None
+    }
+}
+
+/// The format of the file at `path`, whose contents, once decompressed,
+/// start with `start`. Parquet is recognised by its magic number, anything
+/// else by its extension, ignoring any `.gz` or `.zst`, or failing that by
+/// whether it starts with a JSON object.
+pub fn detect_format(path: &Path, start: &[u8]) -> InputFormat {
+    if start.starts_with(PARQUET_MAGIC) {
+        return InputFormat::Parquet;
+    }
+    let mut path = path.to_path_buf();
+    let extension = |path: &Path| {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase)
+    };
+    if matches!(extension(&path).as_deref(), Some("gz" | "zst" | "zstd")) {
+        path.set_extension("");
+    }
+    match extension(&path).as_deref() {
+        Some("jsonl" | "ndjson" | "json") => InputFormat::Jsonl,
+        Some("parquet") => InputFormat::Parquet,
+        Some("csv" | "tsv" | "txt") => InputFormat::Csv,
+        _ => {
+            let text = start.strip_prefix(b"\xef\xbb\xbf").unwrap_or(start);
+            match text.iter().find(|b| !b.is_ascii_whitespace()) {
+                Some(b'{') => InputFormat::Jsonl,
+                _ => InputFormat::Csv,
+            }
+        }
+    }
+}
+
+/// An input file, decompressed and converted to CSV as it's read.
+pub struct InputFile {
+    inner: Inner,
+    format: InputFormat,
+    compression: Compression,
+}
+
+enum Inner {
+    // Only this can seek.
+    Plain(BufReader<std::fs::File>),
+    Decompressed(Box<dyn BufRead + Send>),
+    Converted(Converted),
+}
+
+impl InputFile {
+    /// Open the file at `path` in `format`, detecting it if it's `Auto`, and
+    /// adjust `options` to read it: anything converted is comma-delimited
+    /// UTF-8, and has its columns given in `options.headers`.
+    pub fn open(
+        path: impl AsRef<Path>,
+        format: InputFormat,
+        options: &mut LoadOptions,
+    ) -> Result<Self, FormatError> {
+        let path = path.as_ref();
+        let mut file = BufReader::new(std::fs::File::open(path)?);
+        // Not consumed, so that e.g. a named pipe can still be read from the
+        // start.
+        let compression = detect_compression(file.fill_buf()?);
+        let mut reader: Box<dyn BufRead + Send> = match compression {
+            Compression::None => {
+                let format = match format {
+                    InputFormat::Auto => detect_format(path, file.fill_buf()?),
+                    format => format,
+                };
+                if format == InputFormat::Csv {
+                    return Ok(Self {
+                        inner: Inner::Plain(file),
+                        format,
+                        compression,
+                    });
+                }
+                if format == InputFormat::Parquet {
+                    return Self::open_parquet(file.into_inner(), options);
+                }
+                Box::new(file)
+            }
+            Compression::Gzip => {
+                Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(file)))
+            }
+            Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::with_buffer(file)?)),
+        };
+        let format = match format {
+            InputFormat::Auto => detect_format(path, reader.fill_buf()?),
+            format => format,
+        };
+        let inner = match format {
+            InputFormat::Auto | InputFormat::Csv => Inner::Decompressed(reader),
+            InputFormat::Jsonl => {
+                converted(options, COLUMNS.to_vec());
+                Inner::Converted(Converted::new(JsonLines {
+                    reader,
+                    max_len: options.max_record_len,
+                    line: Vec::new(),
+                }))
+            }
+            InputFormat::Parquet => return Err(FormatError::CompressedParquet),
+        };
+        Ok(Self {
+            inner,
+            format,
+            compression,
+        })
+    }
+
+    #[cfg(feature = "parquet")]
+    fn open_parquet(file: std::fs::File, options: &mut LoadOptions) -> Result<Self, FormatError> {
+        use polars::prelude::*;
+
+        let frame = ParquetReader::new(file).finish()?;
+        converted(
+            options,
+            frame
+                .get_column_names()
+                .iter()
+                .map(|c| c.as_str())
+                .collect(),
+        );
+        let rows = (0..frame.height()).map(move |i| {
+            let fields = frame
+                .get_columns()
+                .iter()
+                .map(|column| match column.get(i) {
+                    Ok(AnyValue::Null) => Ok(String::new()),
+                    Ok(AnyValue::String(s)) => Ok(s.to_string()),
+                    Ok(AnyValue::StringOwned(s)) => Ok(s.to_string()),
+                    Ok(value) => Ok(value.to_string()),
+                    Err(e) => Err(std::io::Error::other(e)),
+                })
+                .collect::<std::io::Result<Vec<_>>>()?;
+            Ok(csv_line(fields))
+        });
+        Ok(Self {
+            inner: Inner::Converted(Converted::new(rows)),
+            format: InputFormat::Parquet,
+            compression: Compression::None,
+        })
+    }
+
+    #[cfg(not(feature = "parquet"))]
+    fn open_parquet(_: std::fs::File, _: &mut LoadOptions) -> Result<Self, FormatError> {
+        Err(FormatError::Unsupported("parquet"))
+    }
+
+    /// The format the file was read in, as detected if it was `Auto`.
+    pub fn format(&self) -> InputFormat {
+        self.format
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Whether the file is read as it is, so that positions in the input are
+    /// positions in the file, e.g. to seek to or to copy records from.
+    pub fn is_plain(&self) -> bool {
+        matches!(self.inner, Inner::Plain(_))
+    }
+}
+
+impl Read for InputFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match &mut self.inner {
+            Inner::Plain(reader) => reader.read(buf),
+            Inner::Decompressed(reader) => reader.read(buf),
+            Inner::Converted(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl std::io::Seek for InputFile {
+    fn seek(&mut self, position: std::io::SeekFrom) -> std::io::Result<u64> {
+        match &mut self.inner {
+            Inner::Plain(reader) => reader.seek(position),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "can't seek in compressed or converted input",
+            )),
+        }
+    }
+}
+
+/// Read converted input as CSV with `headers`.
+fn converted(options: &mut LoadOptions, headers: Vec<&str>) {
+    options.headers = Some(csv::StringRecord::from(headers));
+    options.delimiter = b',';
+    options.encoding = Default::default();
+}
+
+/// `fields` as a line of CSV. Line breaks within them are replaced, so that
+/// each row is still one line.
+fn csv_line(fields: impl IntoIterator<Item = String>) -> Vec<u8> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record(fields.into_iter().map(|f| f.replace(['\r', '\n'], " ")))
+        .expect("writing to a Vec can't fail");
+    writer.into_inner().expect("writing to a Vec can't fail")
+}
+
+/// Lines of CSV, one at a time, read as a whole.
+struct Converted {
+    lines: Box<dyn Iterator<Item = std::io::Result<Vec<u8>>> + Send>,
+    line: Vec<u8>,
+    // How much of `line` has been read.
+    pos: usize,
+}
+
+impl Converted {
+    fn new(lines: impl Iterator<Item = std::io::Result<Vec<u8>>> + Send + 'static) -> Self {
+        Self {
+            lines: Box::new(lines),
+            line: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for Converted {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.line.len() {
+            match self.lines.next() {
+                Some(line) => self.line = line?,
+                None => return Ok(0),
+            }
+            self.pos = 0;
+        }
+        let len = buf.len().min(self.line.len() - self.pos);
+        buf[..len].copy_from_slice(&self.line[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// JSON lines converted to lines of CSV with `COLUMNS` as the headers,
+/// followed by the values of any other fields, which are only an error with
+/// `LoadOptions::strict_schema`.
+struct JsonLines<R> {
+    reader: R,
+    // Longer lines are replaced by one that's too long for the CSV reader,
+    // so that they aren't read into memory either.
+    max_len: usize,
+    line: Vec<u8>,
+}
+
+impl<R: BufRead> Iterator for JsonLines<R> {
+    type Item = std::io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line.clear();
+            let limit = self.max_len as u64 + 1;
+            match (&mut self.reader)
+                .take(limit)
+                .read_until(b'\n', &mut self.line)
+            {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => return Some(Err(e)),
+            }
+            if self.line.len() > self.max_len && !self.line.ends_with(b"\n") {
+                if let Err(e) = self.reader.skip_until(b'\n') {
+                    return Some(Err(e));
+                }
+                let mut line = vec![b' '; self.max_len + 1];
+                line.push(b'\n');
+                return Some(Ok(line));
+            }
+            // Blank lines are skipped, as they are in CSV.
+            if let Some(line) = json_to_csv(&self.line) {
+                return Some(Ok(line));
+            }
+        }
+    }
+}
+
+/// A line of JSON as a line of CSV, if it isn't blank. Anything that isn't a
+/// JSON object is written as the type, so that it's reported as invalid
+/// along with why.
+///
+/// Numbers are written exactly as they appear, rather than going through a
+/// float, so amounts keep every digit, and exponents, e.g. `1e2`, are
+/// rejected as they would be in CSV.
+fn json_to_csv(line: &[u8]) -> Option<Vec<u8>> {
+    let line = String::from_utf8_lossy(line);
+    let line = line.trim().trim_start_matches('\u{feff}');
+    if line.is_empty() {
+        return None;
+    }
+    let object = match serde_json::from_str::<BTreeMap<String, &RawValue>>(line) {
+        Ok(object) => object,
+        Err(e) => return Some(csv_line([format!("invalid JSON: {}", e)])),
+    };
+    let field = |value: &&RawValue| match value.get() {
+        "null" => String::new(),
+        text if text.starts_with('"') => serde_json::from_str(text).unwrap_or_default(),
+        text => text.to_string(),
+    };
+    let known = COLUMNS
+        .iter()
+        .map(|column| object.get(*column).map(field).unwrap_or_default());
+    let other = object
+        .iter()
+        .filter(|(key, _)| !COLUMNS.contains(&key.as_str()))
+        .map(|(_, value)| field(value));
+    Some(csv_line(known.chain(other).collect::<Vec<_>>()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{TransactionError, TransactionReader};
+    use std::io::Write;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect_compression(b"\x1f\x8b\x08"), Compression::Gzip);
+        assert_eq!(detect_compression(b"\x28\xb5\x2f\xfd"), Compression::Zstd);
+        assert_eq!(detect_compression(b"type,client"), Compression::None);
+
+        let detect = |path: &str, start: &[u8]| detect_format(Path::new(path), start);
+        assert_eq!(detect("in.csv", b"{"), InputFormat::Csv);
+        assert_eq!(detect("in.CSV.gz", b""), InputFormat::Csv);
+        assert_eq!(detect("in.ndjson.zst", b""), InputFormat::Jsonl);
+        assert_eq!(detect("in.parquet", b""), InputFormat::Parquet);
+        assert_eq!(detect("in.csv", b"PAR1\x15"), InputFormat::Parquet);
+        assert_eq!(detect("in", b"\xef\xbb\xbf {\"type\""), InputFormat::Jsonl);
+        assert_eq!(detect("in.dat", b"type,client"), InputFormat::Csv);
+        assert_eq!(detect("in", b""), InputFormat::Csv);
+    }
+
+    /// How `contents`, written to a file named `name`, is read, and the line
+    /// and transaction or error for each record.
+    fn read(
+        name: &str,
+        contents: &[u8],
+        format: InputFormat,
+    ) -> ((InputFormat, Compression, bool), Vec<String>) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        std::fs::write(&path, contents).unwrap();
+        let mut options = LoadOptions {
+            max_record_len: 100,
+            ..Default::default()
+        };
+        let file = InputFile::open(&path, format, &mut options).unwrap();
+        let read_as = (file.format(), file.compression(), file.is_plain());
+        let mut reader = TransactionReader::new(file, options).unwrap();
+        let mut results = Vec::new();
+        while let Some(result) = reader.next() {
+            let line = reader.record().position().map_or(0, |p| p.line());
+            results.push(match result {
+                Ok(t) => format!(
+                    "{}: {} {} {}",
+                    line,
+                    t.data.name(),
+                    t.client_id,
+                    t.data.transaction_id()
+                ),
+                Err(TransactionError::RecordTooLong(_)) => format!("{}: too long", line),
+                Err(e) => format!("{}: {}", line, e.name()),
+            });
+        }
+        (read_as, results)
+    }
+
+    #[test]
+    fn test_jsonl() {
+        let input = format!(
+            "{{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": 1.5}}\n\
+             \n\
+             {{\"type\": \"withdrawal\", \"client\": 1, \"tx\": 2, \"amount\": \"0.5\", \"memo\": \"a\\nb\"}}\n\
+             [1, 2]\n\
+             {{\"type\": \"deposit\", \"client\": 2, \"tx\": 3, \"amount\": \"{}\"}}\n\
+             {{\"type\": \"dispute\", \"client\": 1, \"tx\": 1, \"extra\": true}}",
+            "9".repeat(100)
+        );
+        let expected = [
+            "1: deposit 1 1",
+            "2: withdrawal 1 2",
+            "3: csv",
+            "4: too long",
+            "5: dispute 1 1",
+        ];
+        let (read_as, results) = read("in.jsonl", input.as_bytes(), InputFormat::Auto);
+        assert_eq!(read_as, (InputFormat::Jsonl, Compression::None, false));
+        assert_eq!(results, expected);
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        gzip.write_all(input.as_bytes()).unwrap();
+        let (read_as, results) = read("in", &gzip.finish().unwrap(), InputFormat::Auto);
+        assert_eq!(read_as, (InputFormat::Jsonl, Compression::Gzip, false));
+        assert_eq!(results, expected);
+    }
+
+    #[test]
+    fn test_json_numbers() {
+        let csv = |line: &str| String::from_utf8(json_to_csv(line.as_bytes()).unwrap()).unwrap();
+        assert!(csv(r#"{"type": "deposit", "amount": 1234567890123.4567}"#)
+            .starts_with("deposit,,,1234567890123.4567,"));
+        assert!(csv(r#"{"type": "deposit", "amount": 1e2}"#).starts_with("deposit,,,1e2,"));
+
+        let input = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 1e2}"#;
+        let (_, results) = read("in.jsonl", input.as_bytes(), InputFormat::Auto);
+        assert_eq!(results, ["1: invalid_amount"]);
+    }
+
+    #[test]
+    fn test_compressed_csv() {
+        let input = b"type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,2.0\n";
+        let expected = ["2: deposit 1 1", "3: deposit 2 2"];
+        let (read_as, results) = read("in.csv", input, InputFormat::Auto);
+        assert_eq!(read_as, (InputFormat::Csv, Compression::None, true));
+        assert_eq!(results, expected);
+
+        let zstd = zstd::encode_all(&input[..], 0).unwrap();
+        let (read_as, results) = read("in.csv.zst", &zstd, InputFormat::Csv);
+        assert_eq!(read_as, (InputFormat::Csv, Compression::Zstd, false));
+        assert_eq!(results, expected);
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_parquet() {
+        use polars::prelude::*;
+
+        let mut frame = df!(
+            "type" => ["deposit", "withdrawal", "deposit"],
+            "client" => [1u16, 1, 2],
+            "tx" => [1u32, 2, 3],
+            "amount" => [Some("1.5"), Some("0.5"), None],
+        )
+        .unwrap();
+        let mut contents = Vec::new();
+        ParquetWriter::new(&mut contents)
+            .with_compression(ParquetCompression::Uncompressed)
+            .finish(&mut frame)
+            .unwrap();
+        let (read_as, results) = read("in.dat", &contents, InputFormat::Auto);
+        assert_eq!(read_as, (InputFormat::Parquet, Compression::None, false));
+        assert_eq!(
+            results,
+            ["1: deposit 1 1", "2: withdrawal 1 2", "3: missing_amount"]
+        );
+    }
+}
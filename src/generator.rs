@@ -0,0 +1,334 @@
+//! Generating synthetic input files for benchmarking and testing.
+//!
+//! The same options and seed always give the same file, so a workload can be
+//! shared by its command line rather than by copying the file around.
+
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg64Mcg;
+use std::collections::VecDeque;
+
+use crate::transaction::{RawTransactionId, Transaction, TransactionData};
+use crate::Amount;
+
+/// How many of the most recent deposits can be disputed. Real disputes are
+/// mostly of recent deposits, and this keeps memory bounded for large files.
+const DISPUTABLE_DEPOSITS: usize = 100_000;
+
+#[derive(Debug, Clone)]
+pub struct GenerateOptions {
+    /// Number of clients, with IDs from 1.
+    pub clients: u16,
+    /// Number of records to write.
+    pub rows: u64,
+    /// Proportion of records that dispute an earlier deposit. About as many
+    /// again resolve or charge back a dispute.
+    pub dispute_rate: f64,
+    /// Proportion of records that are malformed, e.g. missing an amount.
+    pub invalid_rate: f64,
+    pub seed: u64,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        Self {
+            clients: 1000,
+            rows: 100_000,
+            dispute_rate: 0.01,
+            invalid_rate: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+/// A reproducible stream of synthetic transactions, for benchmarks and
+/// property tests: mostly deposits and withdrawals of random amounts for
+/// random clients, some of which will be rejected for insufficient funds,
+/// with disputes of earlier deposits, and their resolutions and chargebacks,
+/// mixed in.
+///
+/// ```
+/// use transactions::generator::Workload;
+///
+/// let transactions: Vec<_> = Workload::new(42).clients(10).rows(100).collect();
+/// assert_eq!(transactions.len(), 100);
+/// ```
+///
+/// The options are meant to be set before iterating; changing them part way
+/// through affects only the transactions that follow.
+#[derive(Debug, Clone)]
+pub struct Workload {
+    clients: u16,
+    rows: u64,
+    dispute_rate: f64,
+    invalid_rate: f64,
+    rng: Pcg64Mcg,
+    next_tx: RawTransactionId,
+    deposits: VecDeque<(u16, RawTransactionId)>,
+    disputes: Vec<(u16, RawTransactionId)>,
+}
+
+/// A generated record, which is only written if it's malformed.
+enum Row {
+    Transaction(Transaction),
+    Invalid([String; 4]),
+}
+
+impl Workload {
+    /// A workload with the default options, which are those of
+    /// `GenerateOptions`.
+    pub fn new(seed: u64) -> Self {
+        Self::from_options(&GenerateOptions {
+            seed,
+            ..Default::default()
+        })
+    }
+
+    fn from_options(options: &GenerateOptions) -> Self {
+        Self {
+            clients: options.clients,
+            rows: options.rows,
+            dispute_rate: options.dispute_rate,
+            invalid_rate: options.invalid_rate,
+            rng: Pcg64Mcg::seed_from_u64(options.seed),
+            next_tx: 1,
+            deposits: VecDeque::new(),
+            disputes: Vec::new(),
+        }
+    }
+
+    /// Number of clients, with IDs from 1.
+    pub fn clients(mut self, clients: u16) -> Self {
+        self.clients = clients;
+        self
+    }
+
+    /// Number of transactions to generate.
+    pub fn rows(mut self, rows: u64) -> Self {
+        self.rows = rows;
+        self
+    }
+
+    /// Proportion of transactions that dispute an earlier deposit. About as
+    /// many again resolve or charge back a dispute.
+    pub fn dispute_rate(mut self, dispute_rate: f64) -> Self {
+        self.dispute_rate = dispute_rate;
+        self
+    }
+
+    fn next_row(&mut self) -> Row {
+        let rng = &mut self.rng;
+        let client = rng.random_range(1..=self.clients.max(1));
+        let roll: f64 = rng.random();
+        if roll < self.invalid_rate {
+            let tx = self.next_tx.to_string();
+            self.next_tx += 1;
+            let (type_, amount) = match rng.random_range(0..3) {
+                0 => ("deposit", ""),
+                1 => ("withdrawal", "1.0.0"),
+                _ => ("deposit", "0.00001"),
+            };
+            return Row::Invalid([
+                type_.to_string(),
+                client.to_string(),
+                tx,
+                amount.to_string(),
+            ]);
+        }
+        let (client, data) = if roll < self.invalid_rate + self.dispute_rate
+            && !self.deposits.is_empty()
+        {
+            let index = rng.random_range(0..self.deposits.len());
+            let (client, tx) = self.deposits.remove(index).expect("index is in range");
+            self.disputes.push((client, tx));
+            let data = TransactionData::Dispute {
+                transaction_id: tx.into(),
+            };
+            (client, data)
+        } else if roll < self.invalid_rate + 2.0 * self.dispute_rate && !self.disputes.is_empty() {
+            let index = rng.random_range(0..self.disputes.len());
+            let (client, tx) = self.disputes.swap_remove(index);
+            let transaction_id = tx.into();
+            let data = if rng.random_bool(0.9) {
+                // A resolved deposit can be disputed again.
+                self.deposits.push_back((client, tx));
+                TransactionData::Resolve { transaction_id }
+            } else {
+                TransactionData::Chargeback { transaction_id }
+            };
+            (client, data)
+        } else {
+            let tx = self.next_tx;
+            self.next_tx += 1;
+            // Up to 1000, with four decimal places.
+            let amount = Amount::from_minor_units(rng.random_range(1..=10_000_000));
+            let transaction_id = tx.into();
+            let data = if rng.random_bool(0.6) {
+                self.deposits.push_back((client, tx));
+                if self.deposits.len() > DISPUTABLE_DEPOSITS {
+                    self.deposits.pop_front();
+                }
+                TransactionData::Deposit {
+                    transaction_id,
+                    amount,
+                }
+            } else {
+                TransactionData::Withdrawal {
+                    transaction_id,
+                    amount,
+                }
+            };
+            (client, data)
+        };
+        Row::Transaction(Transaction {
+            client_id: client.into(),
+            currency: None,
+            data,
+        })
+    }
+}
+
+impl Iterator for Workload {
+    type Item = Transaction;
+
+    fn next(&mut self) -> Option<Transaction> {
+        while self.rows > 0 {
+            self.rows -= 1;
+            if let Row::Transaction(transaction) = self.next_row() {
+                return Some(transaction);
+            }
+        }
+        None
+    }
+}
+
+/// Write a CSV file of a `Workload`'s transactions, with malformed records
+/// mixed in at `options.invalid_rate`.
+pub fn generate(writer: impl std::io::Write, options: &GenerateOptions) -> csv::Result<()> {
+    let mut workload = Workload::from_options(options);
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["type", "client", "tx", "amount"])?;
+    for _ in 0..options.rows {
+        match workload.next_row() {
+            Row::Invalid(record) => writer.write_record(&record)?,
+            Row::Transaction(transaction) => {
+                let (type_, transaction_id, amount) = match transaction.data {
+                    TransactionData::Deposit {
+                        transaction_id,
+                        amount,
+                    } => ("deposit", transaction_id, amount.to_string()),
+                    TransactionData::Withdrawal {
+                        transaction_id,
+                        amount,
+                    } => ("withdrawal", transaction_id, amount.to_string()),
+                    TransactionData::Dispute { transaction_id } => {
+                        ("dispute", transaction_id, String::new())
+                    }
+                    TransactionData::Resolve { transaction_id } => {
+                        ("resolve", transaction_id, String::new())
+                    }
+                    TransactionData::Chargeback { transaction_id } => {
+                        ("chargeback", transaction_id, String::new())
+                    }
+                };
+                writer.write_record([
+                    type_,
+                    &transaction.client_id.to_string(),
+                    &transaction_id.to_string(),
+                    &amount,
+                ])?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::{Clients, ProcessError};
+    use crate::stats::Stats;
+    use crate::transaction::load_transactions;
+
+    fn generated(options: &GenerateOptions) -> String {
+        let mut buf = Vec::new();
+        generate(&mut buf, options).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_reproducible() {
+        let options = GenerateOptions {
+            rows: 1000,
+            ..Default::default()
+        };
+        assert_eq!(generated(&options), generated(&options));
+        assert_ne!(
+            generated(&options),
+            generated(&GenerateOptions {
+                seed: 1,
+                ..options.clone()
+            })
+        );
+        assert_eq!(generated(&options).lines().count(), 1001);
+    }
+
+    #[test]
+    fn test_workload() {
+        let workload = Workload::new(42)
+            .clients(100)
+            .rows(10_000)
+            .dispute_rate(0.01);
+        let transactions: Vec<_> = workload.clone().collect();
+        assert_eq!(transactions, workload.collect::<Vec<_>>());
+        assert_eq!(transactions.len(), 10_000);
+        assert!(transactions
+            .iter()
+            .all(|t| (1..=100).contains(&u16::from(t.client_id))));
+        let disputes = transactions
+            .iter()
+            .filter(|t| matches!(t.data, TransactionData::Dispute { .. }))
+            .count();
+        assert!((50..150).contains(&disputes), "{} disputes", disputes);
+
+        // The same as the generated file, without the invalid records.
+        let options = GenerateOptions {
+            clients: 100,
+            rows: 1000,
+            seed: 42,
+            ..Default::default()
+        };
+        let loaded: Vec<_> = load_transactions(generated(&options).as_bytes())
+            .map(|t| t.unwrap())
+            .collect();
+        assert_eq!(loaded, transactions[..1000]);
+    }
+
+    #[test]
+    fn test_processes() {
+        let options = GenerateOptions {
+            clients: 100,
+            rows: 10_000,
+            dispute_rate: 0.01,
+            invalid_rate: 0.01,
+            seed: 42,
+        };
+        let mut clients = Clients::new();
+        let mut stats = Stats::default();
+        for transaction in load_transactions(generated(&options).as_bytes()) {
+            match transaction {
+                Ok(transaction) => match clients.process_transaction(transaction) {
+                    Ok(_) => stats.record_processed(Ok(())),
+                    Err(ProcessError::Rejected(e)) => stats.record_processed(Err(&e)),
+                    Err(e) => panic!("unexpected error: {}", e),
+                },
+                Err(e) => stats.record_invalid(&e),
+            }
+        }
+        let invalid: u64 = stats.invalid.values().sum();
+        assert!((50..150).contains(&invalid), "{} invalid", invalid);
+        // Withdrawals are sometimes rejected, and chargebacks lock accounts.
+        assert!(stats.accepted() > 7000, "{}", stats);
+        assert!(clients.snapshot().unwrap().clients.iter().any(|c| c.locked));
+    }
+}
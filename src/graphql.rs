@@ -0,0 +1,223 @@
+//! A GraphQL endpoint, so that support tooling can ask questions like "which
+//! locked clients have a total over 1000?" or "which of client 7's deposits
+//! are disputed?" without a REST route for each.
+//!
+//! Each query runs against a snapshot of every client taken when it arrives,
+//! so it sees a consistent state, but is slower the more clients there are.
+
+use async_graphql::{Context, EmptyMutation, EmptySubscription, Object, Schema, SimpleObject};
+use once_cell::sync::Lazy;
+
+use crate::snapshot::{ClientSnapshot, Snapshot};
+use crate::transaction::{ClientId, RawTransactionId};
+use crate::{Currency, SignedAmount};
+
+static SCHEMA: Lazy<Schema<Query, EmptyMutation, EmptySubscription>> =
+    Lazy::new(|| Schema::new(Query, EmptyMutation, EmptySubscription));
+
+/// The schema, in GraphQL's schema definition language.
+pub fn sdl() -> String {
+    SCHEMA.sdl()
+}
+
+/// Run a GraphQL request, given as JSON, e.g. `{"query": "{ client(id: 7) {
+/// locked } }"}`, against `snapshot`. Returns the response as JSON, or `None`
+/// if the request isn't valid JSON.
+pub fn execute(snapshot: Snapshot, request: &[u8]) -> Option<Vec<u8>> {
+    let request: async_graphql::Request = serde_json::from_slice(request).ok()?;
+    // None of the resolvers wait for anything, so the future is ready at once.
+    let response = pollster::block_on(SCHEMA.execute(request.data(snapshot)));
+    Some(serde_json::to_vec(&response).expect("responses serialize"))
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// Clients matching every filter given, in ID order. Totals are compared
+    /// in `currency`, or for transactions without a currency if it's not
+    /// given.
+    async fn clients(
+        &self,
+        ctx: &Context<'_>,
+        locked: Option<bool>,
+        total_above: Option<String>,
+        total_below: Option<String>,
+        currency: Option<String>,
+    ) -> async_graphql::Result<Vec<Client>> {
+        let parse_amount = |amount: Option<String>| -> async_graphql::Result<Option<SignedAmount>> {
+            amount
+                .map(|amount| SignedAmount::try_from(amount.as_str()))
+                .transpose()
+                .map_err(|e| format!("invalid amount: {}", e).into())
+        };
+        let total_above = parse_amount(total_above)?;
+        let total_below = parse_amount(total_below)?;
+        let currency = currency
+            .map(|code| Currency::try_from(code.as_str()))
+            .transpose()
+            .map_err(|e| format!("invalid currency: {}", e))?;
+
+        let snapshot = ctx.data::<Snapshot>()?;
+        Ok(snapshot
+            .clients
+            .iter()
+            .filter(|client| locked.is_none_or(|locked| client.locked == locked))
+            .filter(|client| {
+                let total = total(client, currency);
+                total_above.is_none_or(|above| total > above)
+                    && total_below.is_none_or(|below| total < below)
+            })
+            .map(|client| Client(client.clone()))
+            .collect())
+    }
+
+    async fn client(&self, ctx: &Context<'_>, id: u16) -> async_graphql::Result<Option<Client>> {
+        let snapshot = ctx.data::<Snapshot>()?;
+        // Snapshots are sorted by client ID.
+        let id = ClientId::from(id);
+        Ok(snapshot
+            .clients
+            .binary_search_by_key(&id, |client| client.id)
+            .ok()
+            .map(|index| Client(snapshot.clients[index].clone())))
+    }
+}
+
+fn total(client: &ClientSnapshot, currency: Option<Currency>) -> SignedAmount {
+    client
+        .balances
+        .iter()
+        .find(|balance| balance.currency == currency)
+        .map(|balance| balance.available.saturating_add(balance.held.into()))
+        .unwrap_or_default()
+}
+
+pub struct Client(ClientSnapshot);
+
+#[Object]
+impl Client {
+    async fn id(&self) -> u16 {
+        self.0.id.into()
+    }
+
+    async fn locked(&self) -> bool {
+        self.0.locked
+    }
+
+    /// One per currency, or a single balance without a currency for
+    /// single-currency input.
+    async fn balances(&self) -> Vec<Balance> {
+        self.0
+            .balances
+            .iter()
+            .map(|balance| Balance {
+                currency: balance.currency.map(|c| c.to_string()),
+                available: balance.available.to_string(),
+                held: balance.held.to_string(),
+                total: balance
+                    .available
+                    .saturating_add(balance.held.into())
+                    .to_string(),
+            })
+            .collect()
+    }
+
+    /// The deposits that are still retained, i.e. that could be disputed or
+    /// are disputed now, optionally only those that are or aren't disputed.
+    async fn deposits(&self, disputed: Option<bool>) -> Vec<Deposit> {
+        self.0
+            .deposits
+            .iter()
+            .filter(|deposit| disputed.is_none_or(|disputed| deposit.disputed == disputed))
+            .map(|deposit| Deposit {
+                tx: deposit.transaction_id.into(),
+                currency: deposit.currency.map(|c| c.to_string()),
+                amount: deposit.amount.to_string(),
+                disputed: deposit.disputed,
+            })
+            .collect()
+    }
+}
+
+/// Amounts are decimal strings, so that they're never rounded through a
+/// floating point type.
+#[derive(SimpleObject)]
+pub struct Balance {
+    currency: Option<String>,
+    available: String,
+    held: String,
+    total: String,
+}
+
+#[derive(SimpleObject)]
+pub struct Deposit {
+    tx: RawTransactionId,
+    currency: Option<String>,
+    amount: String,
+    disputed: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::Clients;
+    use crate::transaction::parse_record;
+
+    fn query(snapshot: &Snapshot, query: &str) -> serde_json::Value {
+        let request = serde_json::json!({ "query": query }).to_string();
+        let response = execute(snapshot.clone(), request.as_bytes()).unwrap();
+        serde_json::from_slice(&response).unwrap()
+    }
+
+    #[test]
+    fn test_queries() {
+        let mut clients = Clients::new();
+        for record in [
+            "deposit,1,1,10.0",
+            "deposit,1,2,5.0",
+            "dispute,1,1",
+            "deposit,2,3,2000.0",
+            "deposit,2,4,1500.0",
+            "dispute,2,3",
+            "chargeback,2,3",
+            "deposit,3,5,3000.0",
+        ] {
+            let _ = clients.process_transaction(parse_record(record).unwrap());
+        }
+        let snapshot = clients.snapshot().unwrap();
+
+        assert_eq!(
+            query(
+                &snapshot,
+                r#"{ clients(locked: true, totalAbove: "1000") { id balances { total } } }"#
+            ),
+            serde_json::json!({
+                "data": { "clients": [{ "id": 2, "balances": [{ "total": "1500.0000" }] }] }
+            })
+        );
+        assert_eq!(
+            query(
+                &snapshot,
+                "{ client(id: 1) { deposits(disputed: true) { tx amount } } }"
+            ),
+            serde_json::json!({
+                "data": { "client": { "deposits": [{ "tx": 1, "amount": "10.0000" }] } }
+            })
+        );
+        assert_eq!(
+            query(&snapshot, "{ client(id: 9) { id } }"),
+            serde_json::json!({ "data": { "client": null } })
+        );
+        let response = query(&snapshot, r#"{ clients(totalAbove: "x") { id } }"#);
+        assert_eq!(
+            response["errors"][0]["message"],
+            "invalid amount: invalid format"
+        );
+    }
+
+    #[test]
+    fn test_invalid_request() {
+        assert!(execute(Clients::new().snapshot().unwrap(), b"{").is_none());
+    }
+}
@@ -0,0 +1,444 @@
+//! A gRPC service over the clients, as defined in `proto/transactions.proto`.
+//!
+//! Requests for a client are handled in the order they arrive, by the shard of
+//! `SharedClients` with that client. Results of
+//! `SubmitTransactions` are returned in the order the transactions were sent.
+//!
+//! With API keys configured, each call needs a key in `authorization` metadata
+//! (`Bearer <key>`) or `x-api-key` metadata, allowing `submit` to submit
+//! transactions or `read` to get a client.
+
+// `tonic::Status` is large, but it's what every RPC returns.
+#![allow(clippy::result_large_err)]
+
+use std::sync::Arc;
+use tokio::sync::oneshot;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Status, Streaming};
+
+use crate::auth::{self, ApiKeys, AuthError, Scope};
+use crate::client::Client;
+use crate::clients::{Clients, ProcessError};
+use crate::engine::Stopped;
+use crate::shared::SharedClients;
+use crate::transaction::{ClientId, Transaction, TransactionData};
+use crate::{Amount, Currency, TransactionId};
+
+pub mod proto {
+    tonic::include_proto!("transactions");
+}
+
+use proto::submit_result::Result as SubmitOutcome;
+use proto::transactions_server::{Transactions, TransactionsServer};
+use proto::TransactionType;
+
+/// Why a submitted transaction couldn't be read.
+#[derive(Debug, thiserror::Error)]
+enum InvalidTransaction {
+    #[error("missing transaction type")]
+    MissingType,
+    #[error("invalid client ID {0}")]
+    ClientId(u32),
+    #[error("invalid transaction ID {0}")]
+    TransactionId(u64),
+    #[error("missing amount")]
+    MissingAmount,
+    #[error("invalid amount: {0}")]
+    Amount(#[from] crate::amount::AmountParseError),
+    #[error("invalid currency: {0}")]
+    Currency(#[from] crate::currency::CurrencyParseError),
+}
+
+#[derive(Clone)]
+pub struct Service {
+    clients: SharedClients,
+    /// If `None`, every call is allowed.
+    keys: Option<Arc<ApiKeys>>,
+    #[cfg(feature = "tls")]
+    identity: Option<tonic::transport::Identity>,
+}
+
+impl Service {
+    pub fn new(clients: impl Into<SharedClients>) -> Self {
+        Self {
+            clients: clients.into(),
+            keys: None,
+            #[cfg(feature = "tls")]
+            identity: None,
+        }
+    }
+
+    /// Only allow calls with one of `keys`.
+    pub fn with_api_keys(mut self, keys: ApiKeys) -> Self {
+        self.keys = Some(Arc::new(keys));
+        self
+    }
+
+    /// Serve over TLS with the PEM-encoded certificate chain and private key.
+    #[cfg(feature = "tls")]
+    pub fn with_tls(mut self, certificate: Vec<u8>, private_key: Vec<u8>) -> Self {
+        self.identity = Some(tonic::transport::Identity::from_pem(
+            certificate,
+            private_key,
+        ));
+        self
+    }
+
+    /// Serve gRPC on `listener` until the connection is closed.
+    pub async fn serve(
+        self,
+        listener: tokio::net::TcpListener,
+    ) -> Result<(), tonic::transport::Error> {
+        #[allow(unused_mut)]
+        let mut builder = tonic::transport::Server::builder();
+        #[cfg(feature = "tls")]
+        if let Some(identity) = self.identity.clone() {
+            builder =
+                builder.tls_config(tonic::transport::ServerTlsConfig::new().identity(identity))?;
+        }
+        builder
+            .add_service(TransactionsServer::new(self))
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await
+    }
+
+    /// Check the call's key allows `scope`.
+    fn authorize<T>(&self, request: &tonic::Request<T>, scope: Scope) -> Result<(), Status> {
+        let Some(keys) = &self.keys else {
+            return Ok(());
+        };
+        let metadata = |name| request.metadata().get(name).and_then(|v| v.to_str().ok());
+        let key = metadata("authorization")
+            .and_then(auth::bearer)
+            .or_else(|| metadata("x-api-key"));
+        // gRPC only serves the default clients.
+        match keys.authorize(key, scope, None) {
+            Ok(name) => {
+                tracing::debug!(key = name, "authorized call");
+                Ok(())
+            }
+            Err(e @ (AuthError::Forbidden { .. } | AuthError::WrongTenant { .. })) => {
+                Err(Status::permission_denied(e.to_string()))
+            }
+            Err(e) => Err(Status::unauthenticated(e.to_string())),
+        }
+    }
+
+    /// Run `job` on the shard with client `id`, without blocking the runtime.
+    async fn call<T: Send + 'static>(
+        &self,
+        id: ClientId,
+        job: impl FnOnce(&mut Clients) -> Result<T, Status> + Send + 'static,
+    ) -> Result<T, Status> {
+        let (reply, result) = oneshot::channel();
+        let stopped = |e: Stopped| Status::unavailable(e.to_string());
+        self.clients
+            .send(id, move |clients| {
+                // If the caller has gone away there's no one to tell.
+                let _ = reply.send(job(clients));
+            })
+            .map_err(stopped)?;
+        result.await.map_err(|_| stopped(Stopped))?
+    }
+
+    async fn submit(&self, transaction: proto::Transaction) -> Result<proto::SubmitResult, Status> {
+        let transaction = match convert(transaction) {
+            Ok(transaction) => transaction,
+            Err(e) => return Ok(submit_result(SubmitOutcome::Invalid, e.to_string())),
+        };
+        self.call(transaction.client_id, move |clients| {
+            submit(clients, transaction)
+        })
+        .await
+    }
+}
+
+#[tonic::async_trait]
+impl Transactions for Service {
+    async fn submit_transaction(
+        &self,
+        request: tonic::Request<proto::Transaction>,
+    ) -> Result<tonic::Response<proto::SubmitResult>, Status> {
+        self.authorize(&request, Scope::Submit)?;
+        let transaction = request.into_inner();
+        let result = self.submit(transaction).await?;
+        Ok(tonic::Response::new(result))
+    }
+
+    type SubmitTransactionsStream = ReceiverStream<Result<proto::SubmitResult, Status>>;
+
+    async fn submit_transactions(
+        &self,
+        request: tonic::Request<Streaming<proto::Transaction>>,
+    ) -> Result<tonic::Response<Self::SubmitTransactionsStream>, Status> {
+        self.authorize(&request, Scope::Submit)?;
+        let mut transactions = request.into_inner();
+        let (results, stream) = tokio::sync::mpsc::channel(16);
+        let service = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let result = match transactions.message().await {
+                    Ok(Some(transaction)) => service.submit(transaction).await,
+                    Ok(None) => break,
+                    Err(status) => Err(status),
+                };
+                let failed = result.is_err();
+                // Stop if the caller has gone away, or after reporting an
+                // error, which ends the stream.
+                if results.send(result).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Ok(tonic::Response::new(ReceiverStream::new(stream)))
+    }
+
+    async fn get_client(
+        &self,
+        request: tonic::Request<proto::GetClientRequest>,
+    ) -> Result<tonic::Response<proto::Client>, Status> {
+        self.authorize(&request, Scope::Read)?;
+        let id = request.into_inner().client;
+        // An invalid ID is rejected by whichever shard it's sent to.
+        let shard = ClientId::from(u16::try_from(id).unwrap_or_default());
+        let client = self
+            .call(shard, move |clients| get_client(clients, id))
+            .await?;
+        Ok(tonic::Response::new(client))
+    }
+}
+
+fn submit(clients: &mut Clients, transaction: Transaction) -> Result<proto::SubmitResult, Status> {
+    match clients.process_transaction(transaction) {
+        Ok(_) => Ok(submit_result(SubmitOutcome::Accepted, String::new())),
+        Err(ProcessError::Rejected(e)) => Ok(submit_result(SubmitOutcome::Rejected, e.to_string())),
+        Err(ProcessError::Pending) => Ok(submit_result(SubmitOutcome::Pending, String::new())),
+        Err(ProcessError::Store(e)) => Err(Status::internal(e.to_string())),
+    }
+}
+
+fn submit_result(outcome: SubmitOutcome, error: String) -> proto::SubmitResult {
+    proto::SubmitResult {
+        result: outcome.into(),
+        error,
+    }
+}
+
+fn convert(transaction: proto::Transaction) -> Result<Transaction, InvalidTransaction> {
+    let client_id = u16::try_from(transaction.client)
+        .map_err(|_| InvalidTransaction::ClientId(transaction.client))?;
+    let transaction_id = TransactionId::from_u64(transaction.tx)
+        .ok_or(InvalidTransaction::TransactionId(transaction.tx))?;
+    let amount = || -> Result<Amount, InvalidTransaction> {
+        if transaction.amount.is_empty() {
+            return Err(InvalidTransaction::MissingAmount);
+        }
+        Ok(Amount::try_from(transaction.amount.as_str())?)
+    };
+    let data = match transaction.r#type() {
+        TransactionType::Unspecified => return Err(InvalidTransaction::MissingType),
+        TransactionType::Deposit => TransactionData::Deposit {
+            transaction_id,
+            amount: amount()?,
+        },
+        TransactionType::Withdrawal => TransactionData::Withdrawal {
+            transaction_id,
+            amount: amount()?,
+        },
+        TransactionType::Dispute => TransactionData::Dispute { transaction_id },
+        TransactionType::Resolve => TransactionData::Resolve { transaction_id },
+        TransactionType::Chargeback => TransactionData::Chargeback { transaction_id },
+    };
+    let currency = match transaction.currency.as_str() {
+        "" => None,
+        code => Some(Currency::try_from(code)?),
+    };
+    Ok(Transaction {
+        client_id: ClientId::from(client_id),
+        currency,
+        data,
+    })
+}
+
+fn get_client(clients: &Clients, id: u32) -> Result<proto::Client, Status> {
+    let Ok(client_id) = u16::try_from(id) else {
+        return Err(Status::invalid_argument(format!(
+            "invalid client ID {}",
+            id
+        )));
+    };
+    let client = clients
+        .get(ClientId::from(client_id))
+        .map_err(|e| Status::internal(e.to_string()))?
+        .ok_or_else(|| Status::not_found(format!("no client {}", id)))?;
+    Ok(proto::Client {
+        client: id,
+        balances: balances(&client),
+        locked: client.locked(),
+    })
+}
+
+fn balances(client: &Client) -> Vec<proto::Balance> {
+    let mut currencies: Vec<_> = client.currencies().collect();
+    if currencies.is_empty() {
+        currencies.push(None);
+    }
+    currencies
+        .into_iter()
+        .map(|currency| proto::Balance {
+            currency: currency.map(|c| c.to_string()).unwrap_or_default(),
+            available: client.available(currency).to_string(),
+            held: client.held(currency).to_string(),
+            total: client.total(currency).to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use proto::transactions_client::TransactionsClient;
+
+    fn transaction(
+        type_: TransactionType,
+        client: u32,
+        tx: u64,
+        amount: &str,
+    ) -> proto::Transaction {
+        proto::Transaction {
+            r#type: type_.into(),
+            client,
+            tx,
+            amount: amount.to_string(),
+            currency: String::new(),
+        }
+    }
+
+    async fn connect() -> TransactionsClient<tonic::transport::Channel> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service = Service::new(Engine::start(None).unwrap());
+        tokio::spawn(service.serve(listener));
+        TransactionsClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_service() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let mut client = connect().await;
+
+            let result = client
+                .submit_transaction(transaction(TransactionType::Deposit, 7, 1, "10.5"))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(result.result(), SubmitOutcome::Accepted);
+
+            let transactions = vec![
+                transaction(TransactionType::Withdrawal, 7, 2, "20"),
+                transaction(TransactionType::Deposit, 7, 3, ""),
+                transaction(TransactionType::Deposit, 70_000, 4, "1"),
+                transaction(TransactionType::Dispute, 7, 1, ""),
+            ];
+            let mut results = client
+                .submit_transactions(tokio_stream::iter(transactions))
+                .await
+                .unwrap()
+                .into_inner();
+            let mut outcomes = Vec::new();
+            while let Some(result) = results.message().await.unwrap() {
+                outcomes.push((result.result(), result.error));
+            }
+            assert_eq!(
+                outcomes,
+                [
+                    (SubmitOutcome::Rejected, "insufficient funds".to_string()),
+                    (SubmitOutcome::Invalid, "missing amount".to_string()),
+                    (
+                        SubmitOutcome::Invalid,
+                        "invalid client ID 70000".to_string()
+                    ),
+                    (SubmitOutcome::Accepted, String::new()),
+                ]
+            );
+
+            let client_7 = client
+                .get_client(proto::GetClientRequest { client: 7 })
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(
+                client_7,
+                proto::Client {
+                    client: 7,
+                    balances: vec![proto::Balance {
+                        currency: String::new(),
+                        available: "0.0000".to_string(),
+                        held: "10.5000".to_string(),
+                        total: "10.5000".to_string(),
+                    }],
+                    locked: false,
+                }
+            );
+
+            let status = client
+                .get_client(proto::GetClientRequest { client: 8 })
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::NotFound);
+        });
+    }
+
+    #[test]
+    fn test_start_from_snapshot() {
+        let mut clients = Clients::new();
+        clients
+            .process_transaction(Transaction {
+                client_id: ClientId::from(1),
+                currency: None,
+                data: TransactionData::Deposit {
+                    transaction_id: TransactionId::from(1),
+                    amount: Amount::from_minor_units(10_000),
+                },
+            })
+            .unwrap();
+        let service = Service::new(Engine::start(Some(clients.snapshot().unwrap())).unwrap());
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let client = service
+                .call(1.into(), |clients| get_client(clients, 1))
+                .await
+                .unwrap();
+            assert_eq!(client.balances[0].available, "1.0000");
+        });
+    }
+
+    #[test]
+    fn test_authorize() {
+        let keys =
+            "[[key]]\nname = \"support\"\nkey = \"read-read-read-read\"\nscopes = [\"read\"]\n";
+        let service =
+            Service::new(Engine::start(None).unwrap()).with_api_keys(keys.parse().unwrap());
+        let request = |name: &'static str, value: &'static str| {
+            let mut request = tonic::Request::new(());
+            request.metadata_mut().insert(name, value.parse().unwrap());
+            request
+        };
+        assert!(service
+            .authorize(&request("x-api-key", "read-read-read-read"), Scope::Read)
+            .is_ok());
+        let denied = service
+            .authorize(
+                &request("authorization", "Bearer read-read-read-read"),
+                Scope::Submit,
+            )
+            .unwrap_err();
+        assert_eq!(denied.code(), tonic::Code::PermissionDenied);
+        let missing = service
+            .authorize(&tonic::Request::new(()), Scope::Read)
+            .unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::Unauthenticated);
+    }
+}
@@ -0,0 +1,342 @@
+//! Reading transactions from ISO 20022 XML messages, as some banking partners
+//! deliver them:
+//!
+//! - bank to customer statements, reports, and notifications (camt.053,
+//!   camt.052, and camt.054), where each booked credit entry is a deposit and
+//!   each booked debit entry a withdrawal;
+//! - credit transfer initiations (pain.001), where each transfer is a
+//!   withdrawal from the debtor's account;
+//! - direct debit initiations (pain.008), where each collection is a deposit
+//!   to the creditor's account.
+//!
+//! The client is the account's proprietary identification (`Othr/Id`), and
+//! the transaction ID is the entry reference (`NtryRef`, or else
+//! `AcctSvcrRef`) or the instruction ID (`InstrId`, or else `EndToEndId`), all
+//! of which must be numbers. Entries that aren't booked yet are skipped, and
+//! reversals are rejected, as they don't correspond to any transaction.
+
+use roxmltree::Node;
+
+use crate::transaction::{ClientId, RawTransactionId, Transaction, TransactionData};
+use crate::{Amount, Currency, TransactionId};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Iso20022Error {
+    #[error("invalid XML: {0}")]
+    Xml(#[from] roxmltree::Error),
+    #[error("unsupported message '{0}'")]
+    UnsupportedMessage(String),
+    #[error("missing {0}")]
+    Missing(&'static str),
+    #[error("invalid {element} '{value}'")]
+    Invalid {
+        element: &'static str,
+        value: String,
+    },
+    #[error("entry {0} is a reversal")]
+    Reversal(String),
+}
+
+/// The transactions in an ISO 20022 message, in the order they appear.
+pub fn read_transactions(xml: &str) -> Result<Vec<Transaction>, Iso20022Error> {
+    let document = roxmltree::Document::parse(xml)?;
+    // The message is the root, or its only child if the root is `Document`.
+    let root = document.root_element();
+    let message = if root.tag_name().name() == "Document" {
+        root.children()
+            .find(Node::is_element)
+            .ok_or(Iso20022Error::Missing("message"))?
+    } else {
+        root
+    };
+    let mut transactions = Vec::new();
+    match message.tag_name().name() {
+        "BkToCstmrStmt" | "BkToCstmrAcctRpt" | "BkToCstmrDbtCdtNtfctn" => {
+            for statement in elements(message, &["Stmt", "Rpt", "Ntfctn"]) {
+                let client = account(child(statement, "Acct")?)?;
+                for entry in elements(statement, &["Ntry"]) {
+                    if let Some(transaction) = read_entry(client, entry)? {
+                        transactions.push(transaction);
+                    }
+                }
+            }
+        }
+        "CstmrCdtTrfInitn" => {
+            for payment in elements(message, &["PmtInf"]) {
+                let client = account(child(payment, "DbtrAcct")?)?;
+                for transfer in elements(payment, &["CdtTrfTxInf"]) {
+                    let (transaction_id, currency, amount) = read_instruction(transfer)?;
+                    transactions.push(Transaction {
+                        client_id: client,
+                        currency: Some(currency),
+                        data: TransactionData::Withdrawal {
+                            transaction_id,
+                            amount,
+                        },
+                    });
+                }
+            }
+        }
+        "CstmrDrctDbtInitn" => {
+            for payment in elements(message, &["PmtInf"]) {
+                let client = account(child(payment, "CdtrAcct")?)?;
+                for debit in elements(payment, &["DrctDbtTxInf"]) {
+                    let (transaction_id, currency, amount) = read_instruction(debit)?;
+                    transactions.push(Transaction {
+                        client_id: client,
+                        currency: Some(currency),
+                        data: TransactionData::Deposit {
+                            transaction_id,
+                            amount,
+                        },
+                    });
+                }
+            }
+        }
+        other => return Err(Iso20022Error::UnsupportedMessage(other.to_string())),
+    }
+    Ok(transactions)
+}
+
+/// A statement entry, or `None` if it isn't booked yet.
+fn read_entry(client: ClientId, entry: Node) -> Result<Option<Transaction>, Iso20022Error> {
+    // The status is either the code itself, or from camt.053.001.08 on, in
+    // a `Cd` element.
+    if let Some(status) = entry
+        .children()
+        .find(|node| node.tag_name().name() == "Sts")
+    {
+        let code = status
+            .children()
+            .find(|node| node.tag_name().name() == "Cd")
+            .unwrap_or(status);
+        if text(code) != "BOOK" {
+            return Ok(None);
+        }
+    }
+    let reference = optional(entry, "NtryRef")
+        .or_else(|| optional(entry, "AcctSvcrRef"))
+        .ok_or(Iso20022Error::Missing("NtryRef"))?;
+    if optional(entry, "RvslInd").is_some_and(|reversal| reversal == "true") {
+        return Err(Iso20022Error::Reversal(reference.to_string()));
+    }
+    let transaction_id = parse_id(reference, "NtryRef")?;
+    let (currency, amount) = read_amount(child(entry, "Amt")?)?;
+    let data = match text(child(entry, "CdtDbtInd")?) {
+        "CRDT" => TransactionData::Deposit {
+            transaction_id,
+            amount,
+        },
+        "DBIT" => TransactionData::Withdrawal {
+            transaction_id,
+            amount,
+        },
+        other => {
+            return Err(Iso20022Error::Invalid {
+                element: "CdtDbtInd",
+                value: other.to_string(),
+            })
+        }
+    };
+    Ok(Some(Transaction {
+        client_id: client,
+        currency: Some(currency),
+        data,
+    }))
+}
+
+/// The ID and amount of a credit transfer or direct debit.
+fn read_instruction(instruction: Node) -> Result<(TransactionId, Currency, Amount), Iso20022Error> {
+    let id = child(instruction, "PmtId")?;
+    let reference = optional(id, "InstrId")
+        .or_else(|| optional(id, "EndToEndId"))
+        .ok_or(Iso20022Error::Missing("InstrId"))?;
+    let transaction_id = parse_id(reference, "InstrId")?;
+    let amount = instruction
+        .descendants()
+        .find(|node| node.tag_name().name() == "InstdAmt")
+        .ok_or(Iso20022Error::Missing("InstdAmt"))?;
+    let (currency, amount) = read_amount(amount)?;
+    Ok((transaction_id, currency, amount))
+}
+
+/// The client whose account this is.
+fn account(account: Node) -> Result<ClientId, Iso20022Error> {
+    let id = account
+        .descendants()
+        .find(|node| node.tag_name().name() == "Othr")
+        .ok_or(Iso20022Error::Missing("Othr"))?;
+    let id = text(child(id, "Id")?);
+    id.parse::<u16>()
+        .map(ClientId::from)
+        .map_err(|_| Iso20022Error::Invalid {
+            element: "Othr/Id",
+            value: id.to_string(),
+        })
+}
+
+/// An amount, with its currency in the `Ccy` attribute.
+fn read_amount(amount: Node) -> Result<(Currency, Amount), Iso20022Error> {
+    let code = amount
+        .attribute("Ccy")
+        .ok_or(Iso20022Error::Missing("Ccy"))?;
+    let currency = Currency::try_from(code).map_err(|_| Iso20022Error::Invalid {
+        element: "Ccy",
+        value: code.to_string(),
+    })?;
+    let value = text(amount);
+    let amount = Amount::try_from(value).map_err(|_| Iso20022Error::Invalid {
+        element: "Amt",
+        value: value.to_string(),
+    })?;
+    Ok((currency, amount))
+}
+
+fn parse_id(id: &str, element: &'static str) -> Result<TransactionId, Iso20022Error> {
+    id.parse::<RawTransactionId>()
+        .map(TransactionId::from)
+        .map_err(|_| Iso20022Error::Invalid {
+            element,
+            value: id.to_string(),
+        })
+}
+
+/// The child elements of `node` with any of `names`.
+fn elements<'a, 'input>(
+    node: Node<'a, 'input>,
+    names: &'static [&'static str],
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children()
+        .filter(move |node| names.contains(&node.tag_name().name()))
+}
+
+fn child<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> Result<Node<'a, 'input>, Iso20022Error> {
+    node.children()
+        .find(|node| node.tag_name().name() == name)
+        .ok_or(Iso20022Error::Missing(name))
+}
+
+fn optional<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|node| node.tag_name().name() == name)
+        .map(text)
+}
+
+fn text<'a>(node: Node<'a, '_>) -> &'a str {
+    node.text().unwrap_or_default().trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATEMENT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.08">
+  <BkToCstmrStmt>
+    <GrpHdr><MsgId>1</MsgId></GrpHdr>
+    <Stmt>
+      <Acct><Id><Othr><Id>7</Id></Othr></Id></Acct>
+      <Ntry>
+        <NtryRef>100</NtryRef>
+        <Amt Ccy="EUR">25.50</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Sts><Cd>BOOK</Cd></Sts>
+      </Ntry>
+      <Ntry>
+        <AcctSvcrRef>101</AcctSvcrRef>
+        <Amt Ccy="EUR">5</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts><Cd>BOOK</Cd></Sts>
+      </Ntry>
+      <Ntry>
+        <NtryRef>102</NtryRef>
+        <Amt Ccy="EUR">1.00</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Sts><Cd>PDNG</Cd></Sts>
+      </Ntry>
+    </Stmt>
+  </BkToCstmrStmt>
+</Document>"#;
+
+    #[test]
+    fn test_statement() {
+        let transactions = read_transactions(STATEMENT).unwrap();
+        let eur = Some(Currency::try_from("EUR").unwrap());
+        assert_eq!(
+            transactions,
+            vec![
+                Transaction {
+                    client_id: 7.into(),
+                    currency: eur,
+                    data: TransactionData::Deposit {
+                        transaction_id: 100.into(),
+                        amount: Amount::try_from("25.5").unwrap(),
+                    },
+                },
+                Transaction {
+                    client_id: 7.into(),
+                    currency: eur,
+                    data: TransactionData::Withdrawal {
+                        transaction_id: 101.into(),
+                        amount: Amount::try_from("5").unwrap(),
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_credit_transfers() {
+        let xml = r#"<Document xmlns="urn:iso:std:iso:20022:tech:xsd:pain.001.001.09">
+  <CstmrCdtTrfInitn>
+    <PmtInf>
+      <DbtrAcct><Id><Othr><Id>3</Id></Othr></Id></DbtrAcct>
+      <CdtTrfTxInf>
+        <PmtId><InstrId>9</InstrId><EndToEndId>NOTPROVIDED</EndToEndId></PmtId>
+        <Amt><InstdAmt Ccy="GBP">12.34</InstdAmt></Amt>
+      </CdtTrfTxInf>
+    </PmtInf>
+  </CstmrCdtTrfInitn>
+</Document>"#;
+        assert_eq!(
+            read_transactions(xml).unwrap(),
+            vec![Transaction {
+                client_id: 3.into(),
+                currency: Some(Currency::try_from("GBP").unwrap()),
+                data: TransactionData::Withdrawal {
+                    transaction_id: 9.into(),
+                    amount: Amount::try_from("12.34").unwrap(),
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            read_transactions("<Document><Stmt></Document>"),
+            Err(Iso20022Error::Xml(_))
+        ));
+        assert!(matches!(
+            read_transactions("<Document><FIToFICstmrCdtTrf/></Document>"),
+            Err(Iso20022Error::UnsupportedMessage(name)) if name == "FIToFICstmrCdtTrf"
+        ));
+        assert!(matches!(
+            read_transactions(&STATEMENT.replace("<Id>7</Id>", "<Id>DE89</Id>")),
+            Err(Iso20022Error::Invalid {
+                element: "Othr/Id",
+                ..
+            })
+        ));
+        assert!(matches!(
+            read_transactions(&STATEMENT.replace(
+                "<CdtDbtInd>DBIT</CdtDbtInd>",
+                "<CdtDbtInd>DBIT</CdtDbtInd><RvslInd>true</RvslInd>"
+            )),
+            Err(Iso20022Error::Reversal(reference)) if reference == "101"
+        ));
+    }
+}
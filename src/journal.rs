@@ -0,0 +1,310 @@
+//! The accepted transactions as a double-entry journal, in the plain text
+//! formats of [Ledger](https://ledger-cli.org) or
+//! [Beancount](https://beancount.github.io), for pulling into bookkeeping
+//! tools.
+//!
+//! Each client's funds are a liability, split between what's available and
+//! what's held, and the funds they've deposited are an asset:
+//!
+//! - a deposit debits `Assets:Bank` and credits the client's available funds,
+//!   and a withdrawal does the reverse;
+//! - a dispute moves the deposit from the client's available funds to their
+//!   held funds, and a resolve moves it back;
+//! - a chargeback debits the held funds and credits `Assets:Bank`.
+//!
+//! Locks are written as comments, as they don't move any funds.
+
+use std::collections::HashSet;
+use std::io::Write;
+
+use crate::event::Event;
+use crate::snapshot::DepositSnapshot;
+use crate::transaction::ClientId;
+use crate::{Amount, Currency, TransactionId};
+
+/// The account holding every client's deposits.
+const BANK: &str = "Assets:Bank";
+
+/// The commodity for amounts without a currency in Beancount, which needs
+/// one for every amount. It's the ISO 4217 code for "no currency".
+const NO_CURRENCY: &str = "XXX";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum JournalFormat {
+    #[default]
+    Ledger,
+    Beancount,
+}
+
+pub struct Journal<W: std::io::Write> {
+    writer: std::io::BufWriter<W>,
+    format: JournalFormat,
+    // The accounts opened so far, as Beancount needs each opened before it's
+    // used.
+    opened: HashSet<String>,
+}
+
+impl<W: std::io::Write> Journal<W> {
+    pub fn new(writer: W, format: JournalFormat) -> Self {
+        Self {
+            writer: std::io::BufWriter::new(writer),
+            format,
+            opened: HashSet::new(),
+        }
+    }
+
+    /// Write an entry for each event caused by an accepted transaction for
+    /// `client` at `timestamp`, dated 1970-01-01 if it's not known. `deposit`
+    /// is the deposit a dispute, resolve, or chargeback refers to, looked up
+    /// before the transaction was processed, for its amount and currency.
+    pub fn record(
+        &mut self,
+        client: ClientId,
+        timestamp: Option<u64>,
+        events: &[Event],
+        deposit: Option<&DepositSnapshot>,
+    ) -> std::io::Result<()> {
+        let date = date(timestamp.unwrap_or(0));
+        let available = format!("Liabilities:Clients:{}:Available", client);
+        let held = format!("Liabilities:Clients:{}:Held", client);
+        // A deposit's held disputes are applied along with it, so look for it
+        // among the events as well. Also whether it's a disputed withdrawal,
+        // whose funds are held from the bank rather than the client.
+        let find = |transaction_id: TransactionId| {
+            events
+                .iter()
+                .find_map(|event| match *event {
+                    Event::FundsDeposited {
+                        transaction_id: id,
+                        currency,
+                        amount,
+                    } if id == transaction_id => Some((currency, amount, false)),
+                    Event::FundsWithdrawn {
+                        transaction_id: id,
+                        currency,
+                        amount,
+                        ..
+                    } if id == transaction_id => Some((currency, amount, true)),
+                    _ => None,
+                })
+                .or_else(|| {
+                    deposit
+                        .filter(|deposit| deposit.transaction_id == transaction_id)
+                        .map(|deposit| (deposit.currency, deposit.amount, deposit.withdrawal))
+                })
+        };
+        for event in events {
+            let (name, debit, credit, currency, amount) = match *event {
+                Event::FundsDeposited {
+                    currency, amount, ..
+                } => ("deposit", BANK, available.as_str(), currency, amount),
+                Event::FundsWithdrawn {
+                    currency, amount, ..
+                } => ("withdrawal", available.as_str(), BANK, currency, amount),
+                Event::DisputeOpened { transaction_id, .. } => {
+                    let Some((currency, amount, withdrawal)) = find(transaction_id) else {
+                        continue;
+                    };
+                    let from = if withdrawal { BANK } else { available.as_str() };
+                    ("dispute", from, held.as_str(), currency, amount)
+                }
+                Event::DisputeResolved { transaction_id } => {
+                    let Some((currency, amount, withdrawal)) = find(transaction_id) else {
+                        continue;
+                    };
+                    let to = if withdrawal { BANK } else { available.as_str() };
+                    ("resolve", held.as_str(), to, currency, amount)
+                }
+                Event::ChargedBack { transaction_id } => {
+                    let Some((currency, amount, withdrawal)) = find(transaction_id) else {
+                        continue;
+                    };
+                    let to = if withdrawal { available.as_str() } else { BANK };
+                    ("chargeback", held.as_str(), to, currency, amount)
+                }
+                Event::AccountLocked { transaction_id } => {
+                    writeln!(
+                        self.writer,
+                        "; {} client {} locked by tx {}\n",
+                        date, client, transaction_id
+                    )?;
+                    continue;
+                }
+                Event::DepositExpired { .. } => continue,
+            };
+            self.entry(
+                &date,
+                &format!("{} client {} tx {}", name, client, event.transaction_id()),
+                [debit, credit],
+                currency,
+                amount,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write an entry debiting `amount` from the first account and crediting
+    /// it to the second.
+    fn entry(
+        &mut self,
+        date: &str,
+        description: &str,
+        [debit, credit]: [&str; 2],
+        currency: Option<Currency>,
+        amount: Amount,
+    ) -> std::io::Result<()> {
+        let commodity = match (self.format, currency) {
+            (_, Some(currency)) => format!(" {}", currency),
+            (JournalFormat::Ledger, None) => String::new(),
+            (JournalFormat::Beancount, None) => format!(" {}", NO_CURRENCY),
+        };
+        match self.format {
+            JournalFormat::Ledger => writeln!(self.writer, "{} * {}", date, description)?,
+            JournalFormat::Beancount => {
+                for account in [debit, credit] {
+                    if self.opened.insert(account.to_string()) {
+                        writeln!(self.writer, "{} open {}", date, account)?;
+                    }
+                }
+                writeln!(self.writer, "{} * \"{}\"", date, description)?
+            }
+        }
+        writeln!(self.writer, "    {}  {}{}", debit, amount, commodity)?;
+        writeln!(self.writer, "    {}  -{}{}\n", credit, amount, commodity)
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// The date, in UTC, of a time in seconds since the Unix epoch.
+fn date(timestamp: u64) -> String {
+    let time = i64::try_from(timestamp)
+        .ok()
+        .and_then(|timestamp| time::OffsetDateTime::from_unix_timestamp(timestamp).ok())
+        .unwrap_or(time::OffsetDateTime::UNIX_EPOCH);
+    format!(
+        "{:04}-{:02}-{:02}",
+        time.year(),
+        u8::from(time.month()),
+        time.day()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::Clients;
+    use crate::transaction::{parse_record, TransactionData};
+
+    fn journal(format: JournalFormat, records: &[&str]) -> String {
+        let mut clients = Clients::new();
+        let mut output = Vec::new();
+        let mut journal = Journal::new(&mut output, format);
+        for (i, record) in records.iter().enumerate() {
+            let transaction = parse_record(record).unwrap();
+            let client = transaction.client_id;
+            let deposit = match transaction.data {
+                TransactionData::Dispute { transaction_id }
+                | TransactionData::Resolve { transaction_id }
+                | TransactionData::Chargeback { transaction_id } => {
+                    clients.find_deposit(client, transaction_id).unwrap()
+                }
+                _ => None,
+            };
+            if let Ok(events) = clients.process_transaction(transaction) {
+                let timestamp = 1_714_521_600 + 86_400 * i as u64;
+                journal
+                    .record(client, Some(timestamp), &events, deposit.as_ref())
+                    .unwrap();
+            }
+        }
+        journal.flush().unwrap();
+        drop(journal);
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_ledger() {
+        let output = journal(
+            JournalFormat::Ledger,
+            &[
+                "deposit,1,1,10.0",
+                "deposit,1,2,5.0",
+                "withdrawal,1,3,2.5",
+                "dispute,1,1",
+                "resolve,1,1",
+                "dispute,1,1",
+                "chargeback,1,1",
+            ],
+        );
+        assert_eq!(
+            output,
+            "\
+2024-05-01 * deposit client 1 tx 1
+    Assets:Bank  10.0000
+    Liabilities:Clients:1:Available  -10.0000
+
+2024-05-02 * deposit client 1 tx 2
+    Assets:Bank  5.0000
+    Liabilities:Clients:1:Available  -5.0000
+
+2024-05-03 * withdrawal client 1 tx 3
+    Liabilities:Clients:1:Available  2.5000
+    Assets:Bank  -2.5000
+
+2024-05-04 * dispute client 1 tx 1
+    Liabilities:Clients:1:Available  10.0000
+    Liabilities:Clients:1:Held  -10.0000
+
+2024-05-05 * resolve client 1 tx 1
+    Liabilities:Clients:1:Held  10.0000
+    Liabilities:Clients:1:Available  -10.0000
+
+2024-05-06 * dispute client 1 tx 1
+    Liabilities:Clients:1:Available  10.0000
+    Liabilities:Clients:1:Held  -10.0000
+
+2024-05-07 * chargeback client 1 tx 1
+    Liabilities:Clients:1:Held  10.0000
+    Assets:Bank  -10.0000
+
+; 2024-05-07 client 1 locked by tx 1
+
+"
+        );
+    }
+
+    #[test]
+    fn test_beancount() {
+        let output = journal(
+            JournalFormat::Beancount,
+            &[
+                "deposit,2,1,1.5,GBP",
+                "deposit,2,2,3.0,GBP",
+                "deposit,3,3,1.0",
+            ],
+        );
+        assert_eq!(
+            output,
+            "\
+2024-05-01 open Assets:Bank
+2024-05-01 open Liabilities:Clients:2:Available
+2024-05-01 * \"deposit client 2 tx 1\"
+    Assets:Bank  1.5000 GBP
+    Liabilities:Clients:2:Available  -1.5000 GBP
+
+2024-05-02 * \"deposit client 2 tx 2\"
+    Assets:Bank  3.0000 GBP
+    Liabilities:Clients:2:Available  -3.0000 GBP
+
+2024-05-03 open Liabilities:Clients:3:Available
+2024-05-03 * \"deposit client 3 tx 3\"
+    Assets:Bank  1.0000 XXX
+    Liabilities:Clients:3:Available  -1.0000 XXX
+
+"
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! Clients identified in the input by strings, e.g. UUIDs, rather than small
+//! integers, as some partner feeds do. Each is interned to a compact
+//! `ClientId` the first time it's seen, so the engine is unchanged, and
+//! written back as it was given in the summary.
+//!
+//! Keys are compared exactly, so e.g. the same UUID in upper and lower case
+//! is two clients. The mapping isn't saved in snapshots or stores, so it only
+//! lasts for a single run.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::transaction::ClientId;
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum KeyError {
+    #[error("missing client")]
+    Missing,
+    #[error("client '{0}' is one more than the {max} distinct clients supported", max = ClientKeys::MAX)]
+    TooMany(String),
+}
+
+#[derive(Debug, Default)]
+struct Interned {
+    ids: HashMap<Arc<str>, ClientId>,
+    // Indexed by `ClientId`.
+    keys: Vec<Arc<str>>,
+}
+
+/// The clients' keys, shared between the reader interning them and the
+/// writer of the summary.
+#[derive(Debug, Default)]
+pub struct ClientKeys {
+    interned: RwLock<Interned>,
+}
+
+impl ClientKeys {
+    /// How many distinct keys there can be, one for each `ClientId`.
+    pub const MAX: usize = u16::MAX as usize + 1;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The `ClientId` for `key`, assigning the next one if it's new.
+    pub fn intern(&self, key: &str) -> Result<ClientId, KeyError> {
+        if key.is_empty() {
+            return Err(KeyError::Missing);
+        }
+        if let Some(&id) = self.interned.read().unwrap().ids.get(key) {
+            return Ok(id);
+        }
+        let mut interned = self.interned.write().unwrap();
+        // Another reader may have added it in the meantime.
+        if let Some(&id) = interned.ids.get(key) {
+            return Ok(id);
+        }
+        let id = u16::try_from(interned.keys.len())
+            .map_err(|_| KeyError::TooMany(key.to_string()))?
+            .into();
+        let key: Arc<str> = key.into();
+        interned.ids.insert(key.clone(), id);
+        interned.keys.push(key);
+        Ok(id)
+    }
+
+    /// The key `id` was interned from, if any.
+    pub fn key(&self, id: ClientId) -> Option<Arc<str>> {
+        let index = usize::from(u16::from(id));
+        self.interned.read().unwrap().keys.get(index).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.interned.read().unwrap().keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let keys = ClientKeys::new();
+        let uuid = "0b6a3e0c-6f4e-4f0e-9d55-3f1c2b7a8e11";
+        assert_eq!(keys.intern(uuid), Ok(ClientId::from(0)));
+        assert_eq!(keys.intern("acme"), Ok(ClientId::from(1)));
+        assert_eq!(keys.intern(uuid), Ok(ClientId::from(0)));
+        assert_eq!(keys.intern(""), Err(KeyError::Missing));
+        assert_eq!(keys.key(1.into()).as_deref(), Some("acme"));
+        assert_eq!(keys.key(2.into()), None);
+        assert_eq!(keys.len(), 2);
+
+        let keys = ClientKeys::new();
+        for i in 0..ClientKeys::MAX {
+            keys.intern(&i.to_string()).unwrap();
+        }
+        assert_eq!(
+            keys.intern("one too many"),
+            Err(KeyError::TooMany("one too many".to_string()))
+        );
+        assert_eq!(keys.intern("65535"), Ok(ClientId::from(65535)));
+    }
+}
@@ -0,0 +1,66 @@
+//! Processes CSV "transactions" - deposits, withdrawals, disputes, resolves,
+//! and chargebacks - into client accounts.
+
+pub mod activity;
+#[cfg(unix)]
+pub mod admin;
+pub mod amount;
+pub mod audit;
+pub mod auth;
+pub mod bisect;
+pub mod card;
+pub mod chargebacks;
+pub mod checkpoint;
+pub mod client;
+pub mod clients;
+pub mod currency;
+#[cfg(feature = "polars")]
+pub mod data_frame;
+pub mod diff;
+pub mod emit;
+pub mod engine;
+pub mod errors;
+pub mod event;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod format;
+pub mod generator;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "iso20022")]
+pub mod iso20022;
+pub mod journal;
+pub mod keys;
+pub mod merge;
+pub mod metadata;
+pub mod ofx;
+pub mod parallel;
+pub mod policy;
+#[cfg(feature = "arrow")]
+pub mod record_batch;
+pub mod repl;
+pub mod risk;
+pub mod rules;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod server;
+pub mod shared;
+pub mod snapshot;
+pub mod source;
+pub mod stats;
+pub mod store;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod tcp;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+pub mod tenants;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+pub mod transaction;
+pub mod wal;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod webhook;
+
+pub use amount::{Amount, SignedAmount};
+pub use currency::Currency;
+pub use transaction::TransactionId;
@@ -1,43 +1,2804 @@
-use clap::Parser;
-use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use std::collections::HashMap;
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
-mod amount;
-mod client;
-mod clients;
-mod transaction;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+use signal_hook::iterator::Signals;
+use transactions::activity::{Activity, Period};
+#[cfg(unix)]
+use transactions::admin;
+use transactions::amount::{Amount, AmountFormat, AmountLocale, AmountUnit};
+use transactions::audit::{self, AuditError, AuditLog};
+use transactions::auth::ApiKeys;
+use transactions::bisect;
+use transactions::card;
+use transactions::chargebacks::{self, ChargebackRates};
+use transactions::checkpoint::Checkpoint;
+use transactions::client::ClientError;
+use transactions::clients::{Clients, ProcessError, WriteOptions};
+use transactions::diff;
+use transactions::emit::{EmitError, EmitEvery, Emitter};
+use transactions::engine::Engine;
+use transactions::errors::{ErrorWriter, Problem, Quarantine};
+use transactions::event::{self, ClientEvent, EventWriter};
+use transactions::format::{InputFile, InputFormat};
+use transactions::generator::{self, GenerateOptions};
+use transactions::journal::{Journal, JournalFormat};
+use transactions::keys::ClientKeys;
+use transactions::merge;
+use transactions::metadata::ClientMetadata;
+use transactions::ofx;
+use transactions::parallel;
+use transactions::repl;
+use transactions::risk;
+use transactions::rules::Rules;
+use transactions::server::Server;
+use transactions::shared::{SharedClients, SharedError};
+use transactions::snapshot::{Snapshot, SnapshotFormat};
+use transactions::source::{self, SourceSpec};
+use transactions::stats::Stats;
+use transactions::store::{Store, StoreSpec};
+use transactions::tcp;
+use transactions::tenants::{TenantError, Tenants};
+use transactions::transaction::{
+    ClientFilter, ClientId, ClientRange, InputEncoding, LoadOptions, RawTransactionId,
+    TrailerError, Transaction, TransactionData, TransactionError, TransactionId, TransactionReader,
+    TransactionWriter,
+};
+use transactions::wal::{self, SyncPolicy, Wal};
+use transactions::webhook::{Webhook, WebhookConfig};
 
-use amount::Amount;
-use transaction::{load_transactions, TransactionId};
+#[derive(Parser)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    /// Read options from this TOML file. Keys are the long option names, e.g.
+    /// `amount-format = "trimmed"` or `stats = true`, optionally grouped into
+    /// tables of any name. Options given on the command line take precedence.
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Most verbose level of log messages to write to stderr, e.g. 'debug' to
+    /// log every rejected transaction. Overrides RUST_LOG, which accepts
+    /// per-module directives too.
+    #[arg(long, global = true)]
+    log_level: Option<tracing::Level>,
+
+    /// Format of log messages.
+    #[arg(long, value_enum, default_value_t, global = true)]
+    log_format: LogFormat,
+
+    /// Export traces and metrics to the OTLP/gRPC collector at this URL, e.g.
+    /// 'http://localhost:4317'.
+    #[cfg(feature = "otel")]
+    #[arg(long, global = true)]
+    otlp_endpoint: Option<String>,
+
+    /// The config file's `[rules]`, if it has them.
+    #[arg(skip)]
+    rules: Option<Rules>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Process transactions, writing a summary of the clients' accounts to
+    /// stdout. `transactions <FILE>` is short for `transactions process
+    /// <FILE>`.
+    Process(Box<ProcessArgs>),
+    /// Process transactions, and print counts of processed and rejected
+    /// transactions, by reason, instead of the summary.
+    Stats {
+        #[command(flatten)]
+        input: InputArgs,
+        #[command(flatten)]
+        format: FormatArgs,
+        /// Print the counts as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check every record parses, and count the transactions that would be
+    /// rejected, without writing any state. Lists the invalid records, then
+    /// the counts as for `stats`. Exits with status 2 if any record is
+    /// invalid.
+    Validate {
+        #[command(flatten)]
+        input: InputArgs,
+        #[command(flatten)]
+        format: FormatArgs,
+    },
+    /// Process transactions, and write the ones accepted for a client, in the
+    /// order they were applied, in the same format as the input. Shows what
+    /// happened on an account without searching the input for it.
+    History {
+        #[command(flatten)]
+        input: InputArgs,
+        /// The client whose transactions to write.
+        #[arg(long)]
+        client: u16,
+        /// 'csv' for the same format as the input, or 'ofx' for an OFX bank
+        /// statement to import into accounting software.
+        #[arg(long, default_value = "csv")]
+        format: HistoryFormat,
+    },
+    /// Convert a file in another format to transactions in the input format,
+    /// written to stdout, e.g. to process files from banking partners.
+    Convert {
+        file_path: PathBuf,
+        /// The format of the file: 'iso20022' for an ISO 20022 camt.052,
+        /// camt.053, camt.054, pain.001, or pain.008 message (with --features
+        /// iso20022), or 'card' for a CSV dump of ISO 8583 card network
+        /// clearing messages.
+        #[arg(long)]
+        from: ConvertFormat,
+    },
+    /// Compare two summaries or snapshots, writing the differences as CSV:
+    /// balance changes, newly locked or unlocked accounts, and clients only in
+    /// one of them. Exits with status 1 if they differ.
+    Diff { first: PathBuf, second: PathBuf },
+    /// Check that two summaries or snapshots match, allowing balances to
+    /// differ by up to the tolerance, and list the clients that don't. Exits
+    /// with status 1 if any don't match.
+    Compare {
+        expected: PathBuf,
+        actual: PathBuf,
+        /// The most a balance may differ by and still match, e.g. '0.0001'.
+        #[arg(long, default_value = "0", value_parser = |s: &str| Amount::try_from(s))]
+        tolerance: Amount,
+    },
+    /// Find the earliest record after which processing the input diverges
+    /// from an expected summary, and list the records of the clients that
+    /// differ there. Exits with status 1 if it diverges.
+    Bisect {
+        #[command(flatten)]
+        input: InputArgs,
+        #[command(flatten)]
+        format: FormatArgs,
+        /// The expected summary or snapshot after processing the whole input.
+        expected: PathBuf,
+        /// The most a balance may differ by and still match, as for `compare`.
+        #[arg(long, default_value = "0", value_parser = |s: &str| Amount::try_from(s))]
+        tolerance: Amount,
+    },
+    /// Merge summaries or snapshots from runs over separate shards of the
+    /// input into one summary, summing the balances of clients in more than
+    /// one of them. A client is locked if it's locked in any of them.
+    Merge {
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+        /// Write to this file rather than stdout.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// How amounts are written, as for `process`.
+        #[arg(long, default_value = "full")]
+        amount_format: AmountFormat,
+    },
+    /// Check that an audit log written with --audit-log hasn't been modified.
+    VerifyAudit { path: PathBuf },
+    /// Write a completion script for the given shell to stdout, e.g.
+    /// `transactions completions bash > /etc/bash_completion.d/transactions`.
+    Completions { shell: clap_complete::Shell },
+    /// Write a synthetic input file, e.g. for benchmarking. The same options
+    /// always give the same file.
+    Generate(GenerateArgs),
+    /// Type transactions one at a time, e.g. 'deposit 7 1 10.0', and inspect
+    /// the result. Type 'help' for the other commands.
+    Repl {
+        /// Start from the state saved in this snapshot rather than from
+        /// scratch.
+        #[arg(long)]
+        snapshot_in: Option<PathBuf>,
+    },
+    /// Process transactions from a message broker until it stops delivering
+    /// them, then write the summary. Each message holds one or more CSV
+    /// records without a header, and is acknowledged once they've been
+    /// processed and durably stored, along with its key, so that it's skipped
+    /// if it's delivered again.
+    Consume {
+        /// Where to consume from: 'nats:URL#STREAM' (with --features nats),
+        /// 'amqp:URI#QUEUE' (with --features amqp), or 'kafka:BROKERS#TOPIC'
+        /// (with --features kafka).
+        source: SourceSpec,
+        /// Where to keep client state, as for `process`, other than in
+        /// memory, since messages are acknowledged once they're stored.
+        #[arg(long)]
+        store: StoreSpec,
+        #[command(flatten)]
+        emit: EmitArgs,
+        /// Write an alert for each account locked by the risk limits in the
+        /// config's `[rules]` to this file, as JSON lines.
+        #[arg(long)]
+        alerts_out: Option<PathBuf>,
+    },
+    /// Serve the clients over the network: by default over HTTP, where POST
+    /// /transactions takes CSV records, as in an input file, and GET
+    /// /clients/{id} and GET /summary return the summary. Runs until killed,
+    /// or with --snapshot-out or --tenant-snapshots, until SIGINT or SIGTERM,
+    /// saving the clients first.
+    Serve {
+        /// The address to listen on.
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+        #[arg(long, default_value = "http")]
+        protocol: Protocol,
+        /// Also listen for admin commands, e.g. 'lock 7' or 'snapshot PATH',
+        /// on a Unix domain socket at this path.
+        #[cfg(unix)]
+        #[arg(long, value_name = "PATH")]
+        admin_socket: Option<PathBuf>,
+        /// Start from the state saved in this snapshot rather than from
+        /// scratch.
+        #[arg(long)]
+        snapshot_in: Option<PathBuf>,
+        /// Save the clients to this snapshot on SIGINT or SIGTERM, and every
+        /// --save-every.
+        #[arg(long, value_name = "PATH")]
+        snapshot_out: Option<PathBuf>,
+        /// Host independent sets of clients for tenants, under
+        /// /tenants/{tenant}/ or with an X-Tenant header: those given with
+        /// --tenant, and those with a snapshot in --tenant-snapshots. HTTP
+        /// only.
+        #[arg(long)]
+        multi_tenant: bool,
+        /// Host this tenant, even without a snapshot. May be repeated.
+        #[arg(long = "tenant", value_name = "NAME", requires = "multi_tenant")]
+        tenants: Vec<String>,
+        /// Start each tenant from DIR/{tenant}.json, if it exists, and save
+        /// it there on SIGINT or SIGTERM, and every --save-every.
+        #[arg(long, value_name = "DIR", requires = "multi_tenant")]
+        tenant_snapshots: Option<PathBuf>,
+        /// Also save the clients while serving, once DURATION, e.g. '5m', has
+        /// passed, so that less is lost if the server is killed.
+        #[arg(long, value_name = "DURATION")]
+        save_every: Option<EmitEvery>,
+        /// Split the clients between N engines by client ID, so that
+        /// transactions for clients in different shards are processed at the
+        /// same time. Each tenant gets as many shards. Not with
+        /// --admin-socket.
+        #[arg(long, value_name = "N", default_value = "1")]
+        shards: NonZeroUsize,
+        #[command(flatten)]
+        webhook: WebhookArgs,
+        #[command(flatten)]
+        access: AccessArgs,
+        #[command(flatten)]
+        emit: EmitArgs,
+    },
+}
+
+/// Who may use the server, and how they connect. HTTP and gRPC only.
+#[derive(clap::Args)]
+struct AccessArgs {
+    /// Only allow requests with one of the API keys in this TOML file, each
+    /// with a name, the key, its scopes: 'read', 'submit', or 'admin', and
+    /// optionally the only tenants it's for.
+    #[arg(long, value_name = "PATH")]
+    api_keys: Option<PathBuf>,
+
+    /// Serve over TLS with the PEM-encoded certificate chain in this file.
+    #[cfg(feature = "tls")]
+    #[arg(long, value_name = "PATH", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// The PEM-encoded private key for --tls-cert.
+    #[cfg(feature = "tls")]
+    #[arg(long, value_name = "PATH", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+}
+
+impl AccessArgs {
+    fn configured(&self) -> bool {
+        #[cfg(feature = "tls")]
+        if self.tls_cert.is_some() {
+            return true;
+        }
+        self.api_keys.is_some()
+    }
+
+    fn keys(&self) -> Option<ApiKeys> {
+        let path = self.api_keys.as_ref()?;
+        Some(ApiKeys::load(path).or_exit(format_args!("failed to load {}", path.display())))
+    }
+
+    /// The certificate chain and private key to serve TLS with, if any.
+    #[cfg(feature = "tls")]
+    fn tls(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let (cert, key) = self.tls_cert.as_ref().zip(self.tls_key.as_ref())?;
+        let read = |path: &PathBuf| {
+            std::fs::read(path).or_exit(format_args!("failed to read {}", path.display()))
+        };
+        Some((read(cert), read(key)))
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum Protocol {
+    Http,
+    /// gRPC, as defined in proto/transactions.proto.
+    #[cfg(feature = "grpc")]
+    Grpc,
+    /// CSV records without a header, one per line, each acknowledged with
+    /// 'ack' or 'nack <reason>'.
+    Lines,
+}
+
+#[derive(clap::Args)]
+struct GenerateArgs {
+    /// Number of clients.
+    #[arg(long, default_value_t = 1000)]
+    clients: u16,
+
+    /// Number of records.
+    #[arg(long, default_value_t = 100_000)]
+    rows: u64,
+
+    /// Proportion of records that dispute an earlier deposit. About as many
+    /// again resolve or charge back a dispute.
+    #[arg(long, default_value_t = 0.01)]
+    dispute_rate: f64,
+
+    /// Proportion of records that are malformed, to exercise error handling.
+    #[arg(long, default_value_t = 0.0)]
+    invalid_rate: f64,
+
+    /// Seed for the random number generator.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Write to this file rather than stdout.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+/// The input file, and how to read it.
+#[derive(clap::Args)]
+struct InputArgs {
+    file_path: PathBuf,
+
+    /// How amounts are written in the input file.
+    #[arg(long, value_enum, default_value_t)]
+    amount_locale: AmountLocale,
+
+    /// What the amounts in the input file count: 'major', whole units with
+    /// up to four decimal digits, or 'minor', an integer number of
+    /// ten-thousandths, e.g. 15000 for 1.5.
+    #[arg(long, value_enum, default_value_t, conflicts_with = "amount_locale")]
+    amount_unit: AmountUnit,
+
+    /// Field delimiter used in the input file, e.g. ';' for files using a
+    /// comma decimal separator.
+    #[arg(long, default_value_t = ',')]
+    delimiter: char,
+
+    /// Reject a header with missing, duplicate, or unknown columns, and
+    /// treat as invalid any row with a different number of fields to the
+    /// header, or with an amount on a dispute, resolve, or chargeback.
+    #[arg(long)]
+    strict_schema: bool,
+
+    /// Fail if the input doesn't end with a trailer giving the number of
+    /// records and the total of the deposits. A trailer is always checked if
+    /// there is one.
+    #[arg(long)]
+    require_trailer: bool,
+
+    /// Drop rows identical to the one before, or to any of the N before,
+    /// e.g. where a batch was written twice upstream.
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "1")]
+    dedupe: Option<NonZeroUsize>,
+
+    /// How the input file is encoded. It's transcoded to UTF-8 before it's
+    /// read.
+    #[arg(long, value_enum, default_value_t)]
+    encoding: InputEncoding,
+
+    /// Treat as invalid any record with a line longer than this, in bytes,
+    /// without reading the rest of the line into memory.
+    #[arg(long, value_name = "BYTES", default_value_t = LoadOptions::default().max_record_len)]
+    max_record_len: usize,
+
+    /// Treat as invalid any record with more fields than this.
+    #[arg(long, value_name = "N", default_value_t = LoadOptions::default().max_fields)]
+    max_fields: usize,
+}
+
+impl InputArgs {
+    fn open(&self, format: InputFormat) -> (InputFile, LoadOptions) {
+        let mut options = LoadOptions {
+            delimiter: u8::try_from(self.delimiter)
+                .unwrap_or_else(|_| Exit::Usage.fail("delimiter must be a single byte")),
+            amount_locale: self.amount_locale,
+            amount_unit: self.amount_unit,
+            strict_schema: self.strict_schema,
+            require_trailer: self.require_trailer,
+            dedupe_window: self.dedupe.map_or(0, NonZeroUsize::get),
+            encoding: self.encoding,
+            max_record_len: self.max_record_len,
+            max_fields: self.max_fields,
+            client_keys: None,
+            headers: None,
+        };
+        let file =
+            InputFile::open(&self.file_path, format, &mut options).or_exit("failed to open file");
+        (file, options)
+    }
+}
+
+/// What kind of file the input is.
+#[derive(clap::Args)]
+struct FormatArgs {
+    /// The format of the input file: 'csv', 'jsonl' (a JSON object per line,
+    /// with the columns as fields), or 'parquet' (with --features parquet),
+    /// any but Parquet compressed with gzip or zstd if need be. 'auto'
+    /// detects the format and compression from the extension and first
+    /// bytes of the file.
+    #[arg(long, value_enum, default_value_t)]
+    format: InputFormat,
+}
+
+#[derive(clap::Args)]
+struct ProcessArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    #[command(flatten)]
+    format: FormatArgs,
+
+    /// How amounts are written in the output: 'full' (four decimal digits),
+    /// 'trimmed' (no trailing zeroes), 'fixed:N' (N decimal digits), or
+    /// 'minor-units' (integer ten-thousandths).
+    #[arg(long, default_value = "full")]
+    amount_format: AmountFormat,
+
+    /// Only process and write these clients, e.g. '7' or '8..15' (inclusive).
+    /// Repeat to include more. Every transaction names its client, so
+    /// disputes of other clients' deposits are skipped along with them.
+    #[arg(long = "client", value_name = "ID|FIRST..LAST")]
+    clients: Vec<ClientRange>,
+
+    /// Read the client column as any string, e.g. a UUID, rather than as a
+    /// number, and write each client back as it was given in the summary,
+    /// held breakdown, ledger, and messages. Up to 65536 distinct clients,
+    /// numbered in order of appearance only for the run, so only with the
+    /// in-memory store, and not with outputs that give clients by number.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "clients", "dump_client", "snapshot_in", "resume", "recover", "seed",
+            "events_in", "client_metadata", "snapshot_out", "checkpoint", "wal",
+            "events_out", "alerts_out", "audit_log", "webhook_url", "journal",
+            "dispute_ageing", "chargeback_report", "dry_run", "shadow_out",
+        ]
+    )]
+    string_client_ids: bool,
+
+    /// Skip the first N records of the input, valid or not.
+    #[arg(long, value_name = "N", conflicts_with_all = ["resume", "recover"])]
+    skip: Option<u64>,
+
+    /// Stop after M records, valid or not, e.g. to bisect the input for the
+    /// transaction that broke a balance.
+    #[arg(long, value_name = "M")]
+    limit: Option<u64>,
+
+    /// Stop after the first record with this transaction ID, usually the
+    /// deposit or withdrawal, to see the state as of it.
+    #[arg(long, value_name = "ID", conflicts_with = "stop_after_line")]
+    stop_after_tx: Option<RawTransactionId>,
+
+    /// Stop after the record on line N of the input.
+    #[arg(long, value_name = "N")]
+    stop_after_line: Option<u64>,
+
+    /// Write this client's balances and disputed deposits to stderr once
+    /// processing stops, e.g. with --stop-after-tx.
+    #[arg(long, value_name = "ID")]
+    dump_client: Option<u16>,
+
+    /// Start from the state saved in this snapshot rather than from scratch.
+    #[arg(long)]
+    snapshot_in: Option<PathBuf>,
+
+    /// Save the state after processing to this snapshot.
+    #[arg(long)]
+    snapshot_out: Option<PathBuf>,
+
+    /// Format to save the snapshot in. Binary snapshots are smaller, and
+    /// checksummed. Either format can be read back with --snapshot-in.
+    #[arg(long, value_enum, default_value_t, requires = "snapshot_out")]
+    snapshot_format: SnapshotFormat,
+
+    /// Periodically save the state and position in the input to this file, so
+    /// that the run can be resumed with --resume if it's interrupted.
+    #[arg(long, requires = "checkpoint_every")]
+    checkpoint: Option<PathBuf>,
+
+    /// Save a checkpoint after every N transactions, as well as at the end.
+    #[arg(long, value_name = "N", requires = "checkpoint")]
+    checkpoint_every: Option<NonZeroU64>,
+
+    /// Resume from the checkpoint file, if it exists, rather than starting
+    /// from the beginning of the input.
+    #[arg(long, requires = "checkpoint", conflicts_with = "snapshot_in")]
+    resume: bool,
+
+    /// Where to keep client state: 'memory', or 'sqlite:PATH' to keep it in
+    /// an SQLite database, which can hold more clients than fit in memory and
+    /// carries over between runs.
+    #[arg(long, default_value = "memory")]
+    store: StoreSpec,
+
+    /// Process clients on N threads at once, by default as many as there are
+    /// cores. The output is the same as on one thread. Only used with the
+    /// in-memory store, and without the options that need transactions
+    /// handled in input order: --checkpoint, --wal, --audit-log,
+    /// --events-out, --webhook-url, --errors-json, --progress, --strict, -v,
+    /// and --extended-output.
+    #[arg(long, value_name = "N")]
+    threads: Option<NonZeroUsize>,
+
+    /// Append every transaction to this write-ahead log before applying it.
+    #[arg(long)]
+    wal: Option<PathBuf>,
+
+    /// How often to sync the write-ahead log to disk: 'always', 'never', or
+    /// 'every:N' (every N transactions).
+    #[arg(long, default_value = "always", requires = "wal")]
+    wal_sync: SyncPolicy,
+
+    /// Recover an interrupted run by replaying the write-ahead log, then
+    /// carrying on with the rest of the input. The input must be the same as
+    /// for the interrupted run. With --checkpoint, start from the checkpoint
+    /// and replay only the log after it.
+    #[arg(long, requires = "wal", conflicts_with = "resume")]
+    recover: bool,
+
+    /// Write the events caused by each transaction to this file, as JSON
+    /// lines.
+    #[arg(long)]
+    events_out: Option<PathBuf>,
+
+    /// Write an alert for each account locked by the risk limits in the
+    /// config's `[rules]` to this file, as JSON lines.
+    #[arg(long)]
+    alerts_out: Option<PathBuf>,
+
+    /// Start from the state rebuilt by replaying the events in this file,
+    /// as written by --events-out.
+    #[arg(long, conflicts_with_all = ["snapshot_in", "resume", "recover"])]
+    events_in: Option<PathBuf>,
+
+    /// Start from the balances in a summary written by a previous run. Funds
+    /// held in it stay held, and deposits from before it can't be disputed.
+    #[arg(
+        long,
+        conflicts_with_all = ["snapshot_in", "events_in", "resume", "recover"]
+    )]
+    seed: Option<PathBuf>,
+
+    /// Append every accepted transaction, with the balance it resulted in, to
+    /// this tamper-evident log. Check it with the verify-audit subcommand.
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
+
+    #[command(flatten)]
+    webhook: WebhookArgs,
+
+    #[command(flatten)]
+    emit: EmitArgs,
+
+    /// Write every deposit that could still be disputed, or is disputed now,
+    /// to this file as CSV, to back up the held funds in the summary.
+    #[arg(long)]
+    export_ledger: Option<PathBuf>,
+
+    /// Write each accepted transaction to this file as a double-entry journal
+    /// entry, dated from the input's timestamp column, for bookkeeping tools.
+    #[arg(long, value_name = "PATH")]
+    journal: Option<PathBuf>,
+
+    /// The journal's format: 'ledger' for ledger-cli, or 'beancount'.
+    #[arg(long, default_value = "ledger", requires = "journal")]
+    journal_format: JournalFormat,
+
+    /// Write the disputed deposits making up each client's held funds to this
+    /// file as CSV, one row per deposit.
+    #[arg(long, value_name = "PATH")]
+    held_breakdown: Option<PathBuf>,
+
+    /// Follow the summary with a row of totals, for each currency: the sums
+    /// of the balances, with the number of locked accounts in the locked
+    /// column.
+    #[arg(long)]
+    totals: bool,
+
+    /// Write the totals to this file as CSV, with the number of clients and
+    /// of locked accounts, rather than after the summary.
+    #[arg(long, value_name = "PATH")]
+    totals_out: Option<PathBuf>,
+
+    /// Write the disputes still open at the end to this file as CSV, counted
+    /// by how long ago they were opened: in days if the input has a
+    /// timestamp column, otherwise in transactions.
+    #[arg(long, value_name = "PATH")]
+    dispute_ageing: Option<PathBuf>,
+
+    /// Write the number and amount of deposits charged back, and their share
+    /// of the deposits, for each client and overall to this file as CSV.
+    #[arg(long, value_name = "PATH")]
+    chargeback_report: Option<PathBuf>,
+
+    /// Flag clients in the chargeback report whose share of deposits charged
+    /// back, by number or by amount, is above this proportion.
+    #[arg(
+        long,
+        value_name = "RATE",
+        default_value_t = chargebacks::DEFAULT_THRESHOLD,
+        requires = "chargeback_report"
+    )]
+    chargeback_threshold: f64,
+
+    /// Write the number and volume of deposits, withdrawals, and disputes
+    /// accepted in each day or hour, from the input's timestamp column,
+    /// instead of the summary.
+    #[arg(long, value_name = "PERIOD", conflicts_with_all = ["totals", "summary_out"])]
+    summary_by: Option<Period>,
+
+    /// Add the name, email, and tier of each client from this CSV file, with
+    /// columns client, name, email, and tier, to the summary, the held
+    /// breakdown, the chargeback report, and webhook notifications.
+    #[arg(long, value_name = "PATH")]
+    client_metadata: Option<PathBuf>,
+
+    /// Add each client's counts of accepted deposits, withdrawals, disputes,
+    /// resolves, and chargebacks as the last columns of the summary.
+    #[arg(long)]
+    extended_output: bool,
+
+    /// Write each invalid record and rejected transaction to this file, as
+    /// JSON lines with the line number, the record, and the error.
+    #[arg(long)]
+    errors_json: Option<PathBuf>,
+
+    /// Copy each record that can't be parsed, exactly as it was in the input,
+    /// to this file as CSV with its line number and the error, and report how
+    /// many there were on stderr.
+    #[arg(long)]
+    quarantine: Option<PathBuf>,
+
+    /// Process the input against a copy of the starting state, and report
+    /// what would change instead of writing a summary: balance changes and
+    /// locks as for the diff subcommand on stdout, and each transaction that
+    /// would be rejected on stderr, as for -v. Nothing is saved.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "snapshot_out", "checkpoint", "wal", "events_out", "audit_log",
+            "export_ledger", "held_breakdown", "totals", "totals_out", "dispute_ageing", "state_hash",
+            "webhook_url", "summary_by", "chargeback_report", "journal", "summary_out", "shadow",
+            "alerts_out",
+        ]
+    )]
+    dry_run: bool,
+
+    /// Also process the input against a copy of the starting state under the
+    /// rules in the `[rules]` table of this TOML file, or the default rules if
+    /// it has none, e.g. to try out a change to them. Nothing else is
+    /// affected.
+    #[arg(long, value_name = "FILE", requires = "shadow_out")]
+    shadow: Option<PathBuf>,
+
+    /// Write how each client's final state under the --shadow rules differs
+    /// from the real one to this file, in the same format as `transactions
+    /// diff`.
+    #[arg(long, requires = "shadow")]
+    shadow_out: Option<PathBuf>,
+
+    /// Print why each transaction was rejected to stderr. Repeat (-vv) to also
+    /// print how each accepted transaction changed the account.
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Stop at the first invalid record or rejected transaction, exiting with
+    /// status 2 or 5 respectively without writing the summary.
+    #[arg(long, overrides_with = "lenient")]
+    strict: bool,
+
+    /// Skip invalid records and rejected transactions, counting them in
+    /// --stats and listing them in --errors-json. This is the default.
+    #[arg(long, overrides_with = "strict")]
+    lenient: bool,
+
+    /// Print counts of processed and rejected transactions, by reason, to
+    /// stderr at the end.
+    #[arg(long)]
+    stats: bool,
+
+    /// Write the same counts as --stats to this file, as JSON.
+    #[arg(long)]
+    stats_out: Option<PathBuf>,
+
+    /// Show a progress bar on stderr, with the rate and estimated time
+    /// remaining.
+    #[arg(long)]
+    progress: bool,
+
+    /// Print a hash of the final state to stderr, for checking whether two
+    /// runs have diverged without comparing their full output.
+    #[arg(long)]
+    state_hash: bool,
+
+    /// Skip transactions with the same client, ID, and type as ones already
+    /// processed, so that reprocessing a file, e.g. after a partial failure,
+    /// is safe. The transactions processed are saved in snapshots and
+    /// checkpoints, and restoring one that has them implies this.
+    #[arg(long)]
+    idempotent: bool,
+
+    /// Reject deposits and withdrawals of zero, as the `reject_zero_amounts`
+    /// rule does, rather than accepting them.
+    #[arg(long)]
+    reject_zero_amounts: bool,
+
+    /// Hold up to N disputes, resolves, and chargebacks that arrive before
+    /// their deposit, and apply them once it does, rather than rejecting
+    /// them. Any whose deposit never arrives are listed on stderr at the end.
+    #[arg(long, value_name = "N")]
+    pending_disputes: Option<usize>,
+
+    /// Keep at most N deposits for each client that could be disputed,
+    /// expiring the oldest once there are more. Disputes of expired deposits
+    /// are rejected as too old. Only for the in-memory store.
+    #[arg(long, value_name = "N")]
+    max_deposits: Option<usize>,
+}
+
+/// Notifying another service of chargebacks and locked accounts.
+#[derive(clap::Args)]
+struct WebhookArgs {
+    /// POST a JSON notification to this URL whenever a chargeback is applied
+    /// or an account is locked.
+    #[arg(long, value_name = "URL")]
+    webhook_url: Option<String>,
+
+    /// Sign each notification with the key in this file, in an
+    /// X-Signature-256 header: 'sha256=' and the hex HMAC-SHA256 of the body.
+    #[arg(long, value_name = "PATH", requires = "webhook_url")]
+    webhook_secret_file: Option<PathBuf>,
+
+    /// How many times to retry a notification that fails, waiting 1s, 2s,
+    /// 4s, ... in between.
+    #[arg(long, value_name = "N", default_value_t = 3, requires = "webhook_url")]
+    webhook_retries: u32,
+}
+
+impl WebhookArgs {
+    fn start(&self) -> Option<Webhook> {
+        let url = self.webhook_url.clone()?;
+        let secret = self.webhook_secret_file.as_ref().map(|path| {
+            let secret = std::fs::read(path).or_exit("failed to read webhook secret");
+            // Allow for a trailing newline, as most editors add one.
+            secret.trim_ascii_end().to_vec()
+        });
+        Some(Webhook::start(WebhookConfig {
+            url,
+            secret,
+            retries: self.webhook_retries,
+            backoff: Duration::from_secs(1),
+        }))
+    }
+}
+
+/// Writing the summary to a file, and keeping it up to date while
+/// transactions stream in.
+#[derive(clap::Args)]
+struct EmitArgs {
+    /// Write the summary to this file, atomically replacing it, rather than
+    /// to stdout.
+    #[arg(long, value_name = "PATH")]
+    summary_out: Option<PathBuf>,
+
+    /// Also write the summary to --summary-out while processing: every N
+    /// transactions, or once DURATION, e.g. '60s', '5m', or '1h', has passed.
+    #[arg(long, value_name = "N|DURATION", requires = "summary_out")]
+    emit_every: Option<EmitEvery>,
+}
+
+impl EmitArgs {
+    fn emitter(&self, options: WriteOptions) -> Option<Emitter> {
+        let path = self.summary_out.clone()?;
+        Some(Emitter::new(path, self.emit_every, options))
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum HistoryFormat {
+    #[default]
+    Csv,
+    Ofx,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum ConvertFormat {
+    Iso20022,
+    Card,
+}
+
+#[derive(Debug, Default, Clone, Copy, clap::ValueEnum)]
+enum LogFormat {
+    /// Human-readable, over multiple lines.
+    #[default]
+    Pretty,
+    /// One JSON object per line.
+    Json,
+}
+
+/// Everything written while processing, besides the client state itself.
+#[derive(Default)]
+struct Outputs {
+    checkpointing: Option<Checkpointing>,
+    wal: Option<Wal>,
+    events: Option<EventWriter<std::fs::File>>,
+    alerts: Option<std::io::BufWriter<std::fs::File>>,
+    progress: Option<Progress>,
+    stats: Option<StatsReporting>,
+    audit: Option<AuditLog>,
+    webhook: Option<Webhook>,
+    errors: Option<ErrorWriter<std::fs::File>>,
+    quarantine: Option<Quarantining>,
+    activity: Option<Activity>,
+    chargebacks: Option<ChargebackRates>,
+    journal: Option<Journal<std::fs::File>>,
+    emitter: Option<Emitter>,
+    // The same transactions under other rules; see --shadow.
+    shadow: Option<Clients>,
+    // The keys clients were given as, with --string-client-ids.
+    client_keys: Option<Arc<ClientKeys>>,
+    // 1 to print each invalid record and rejected transaction to stderr, 2 to
+    // also print each accepted transaction's effect.
+    verbose: u8,
+    // Stop at the first invalid record or rejected transaction.
+    strict: bool,
+    // Transactions for other clients are skipped.
+    filter: ClientFilter,
+    // Stop after reading this many records.
+    limit: Option<u64>,
+    stop_after: Option<StopAfter>,
+    // The line of the record processing stopped after, once it has.
+    stopped: Option<u64>,
+    // Set by SIGINT or SIGTERM, to stop before the next record.
+    interrupt: Arc<AtomicBool>,
+    // The records held for the deposit they refer to, by line, until they're
+    // applied or rejected; see --pending-disputes.
+    parked: HashMap<u64, csv::StringRecord>,
+}
+
+/// Where to stop processing, to see the state as of a given record.
+#[derive(Debug, Clone, Copy)]
+enum StopAfter {
+    Transaction(TransactionId),
+    Line(u64),
+}
+
+/// The process's exit status, so that scripts can tell outcomes apart without
+/// parsing messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Exit {
+    Success = 0,
+    /// `diff` or `compare` found differences, `bisect` found a divergence, or
+    /// `verify-audit` found the log modified.
+    Mismatch = 1,
+    /// Some records couldn't be parsed.
+    InvalidRecords = 2,
+    /// Reading or writing a file failed.
+    Io = 3,
+    /// The command line or config file is invalid.
+    Usage = 4,
+    /// --strict stopped at a rejected transaction.
+    Rejected = 5,
+    /// Anything else, e.g. a corrupt snapshot or an inconsistent store.
+    Failure = 6,
+    /// Stopped early by SIGINT or SIGTERM, after writing the output for the
+    /// transactions processed so far.
+    Interrupted = 7,
+}
+
+impl Exit {
+    /// Report `message` on stderr and exit.
+    fn fail(self, message: impl std::fmt::Display) -> ! {
+        eprintln!("error: {}", message);
+        // `exit` doesn't run destructors, so `LoggingGuard` never would.
+        flush_telemetry();
+        std::process::exit(self as i32)
+    }
+
+    /// The outcome of processing records, by whether they were all valid.
+    fn from_stats(stats: &Stats) -> Self {
+        if stats.invalid.is_empty() {
+            Exit::Success
+        } else {
+            Exit::InvalidRecords
+        }
+    }
+}
+
+impl From<Exit> for std::process::ExitCode {
+    fn from(exit: Exit) -> Self {
+        (exit as u8).into()
+    }
+}
+
+/// Exiting with an error message for failures that can't be recovered from,
+/// with `Exit::Io` if they were caused by an I/O error, `Exit::Failure`
+/// otherwise.
+trait OrExit<T> {
+    fn or_exit(self, context: impl std::fmt::Display) -> T;
+}
+
+impl<T, E: std::error::Error + 'static> OrExit<T> for Result<T, E> {
+    fn or_exit(self, context: impl std::fmt::Display) -> T {
+        self.unwrap_or_else(|error| {
+            let mut source: Option<&(dyn std::error::Error + 'static)> = Some(&error);
+            let mut exit = Exit::Failure;
+            while let Some(e) = source {
+                if e.is::<std::io::Error>() {
+                    exit = Exit::Io;
+                    break;
+                }
+                source = e.source();
+            }
+            exit.fail(format_args!("{}: {}", context, error))
+        })
+    }
+}
+
+/// Why processing stopped early in strict mode.
+#[derive(Debug, thiserror::Error)]
+enum Abort {
+    #[error("invalid transaction at line {line}: {error}")]
+    Invalid { line: u64, error: TransactionError },
+    #[error("transaction rejected at line {line}: {error}")]
+    Rejected { line: u64, error: ClientError },
+    #[error("sequence numbers {} to {} missing before line {line}", .missing.start(), .missing.end())]
+    Gap {
+        line: u64,
+        missing: std::ops::RangeInclusive<u64>,
+    },
+}
+
+impl Abort {
+    fn exit(&self) -> Exit {
+        match self {
+            Abort::Invalid { .. } | Abort::Gap { .. } => Exit::InvalidRecords,
+            Abort::Rejected { .. } => Exit::Rejected,
+        }
+    }
+}
+
+#[derive(Default)]
+struct StatsReporting {
+    stats: Stats,
+    print: bool,
+    path: Option<PathBuf>,
+}
+
+impl Outputs {
+    /// `client` as it was given in the input, for messages.
+    fn client(&self, client: ClientId) -> String {
+        self.client_keys
+            .as_ref()
+            .and_then(|keys| keys.key(client))
+            .map_or_else(|| client.to_string(), |key| key.to_string())
+    }
+
+    /// Whether any of the outputs need transactions to be handled one at a
+    /// time, in input order.
+    fn need_input_order(&self) -> bool {
+        self.checkpointing.is_some()
+            || self.wal.is_some()
+            || self.events.is_some()
+            || self.alerts.is_some()
+            || self.progress.is_some()
+            || self.audit.is_some()
+            || self.webhook.is_some()
+            || self.errors.is_some()
+            || self.quarantine.is_some()
+            || self.activity.is_some()
+            || self.chargebacks.is_some()
+            || self.journal.is_some()
+            || self.emitter.as_ref().is_some_and(Emitter::is_periodic)
+            || self.stop_after.is_some()
+            || self.shadow.is_some()
+            || self.verbose > 0
+            || self.strict
+    }
+}
+
+impl StatsReporting {
+    fn report(&self) {
+        if self.print {
+            eprint!("{}", self.stats);
+        }
+        if let Some(path) = &self.path {
+            let file = std::fs::File::create(path).or_exit("failed to create stats file");
+            self.stats.write(file).or_exit("failed to write stats");
+        }
+    }
+}
+
+/// A progress bar through the input, by bytes read.
+struct Progress {
+    bar: indicatif::ProgressBar,
+    start: std::time::Instant,
+}
+
+impl Progress {
+    // How often to update the rate, in transactions.
+    const RATE_EVERY: u64 = 1000;
+
+    fn new(len: u64) -> Self {
+        let bar = indicatif::ProgressBar::new(len).with_style(
+            indicatif::ProgressStyle::with_template(
+                "{wide_bar} {bytes}/{total_bytes} {msg} ETA {eta}",
+            )
+            .unwrap(),
+        );
+        Self {
+            bar,
+            start: std::time::Instant::now(),
+        }
+    }
+
+    fn update(&self, position: u64, count: u64) {
+        self.bar.set_position(position);
+        if count.is_multiple_of(Self::RATE_EVERY) {
+            let rate = count as f64 / self.start.elapsed().as_secs_f64();
+            self.bar.set_message(format!("{:.0} rows/s", rate));
+        }
+    }
+}
+
+struct Checkpointing {
+    path: PathBuf,
+    every: NonZeroU64,
+}
+
+struct Quarantining {
+    quarantine: Quarantine<std::fs::File>,
+    // Another handle on the input, to copy invalid records from.
+    input: std::fs::File,
+    path: PathBuf,
+}
+
+impl Quarantining {
+    /// Quarantine the record at `bytes` of the input.
+    fn write(
+        &mut self,
+        line: u64,
+        error: &TransactionError,
+        bytes: std::ops::Range<u64>,
+    ) -> std::io::Result<()> {
+        use std::io::{Read, Seek};
+        let mut raw = Vec::new();
+        self.input.seek(std::io::SeekFrom::Start(bytes.start))?;
+        (&mut self.input)
+            .take(bytes.end - bytes.start)
+            .read_to_end(&mut raw)?;
+        self.quarantine.write(line, error, &raw)
+    }
+}
+
+impl Checkpointing {
+    fn save<R: std::io::Read>(
+        &self,
+        clients: &Clients,
+        reader: &TransactionReader<R>,
+        wal: Option<&mut Wal>,
+    ) {
+        // Sync the log first, so that it never ends before the position the
+        // checkpoint records.
+        let wal_position = wal.map(|wal| {
+            wal.sync().or_exit("failed to sync write-ahead log");
+            wal.position()
+                .or_exit("failed to read write-ahead log position")
+        });
+        let checkpoint = Checkpoint {
+            position: (&reader.input_position()).into(),
+            wal_position,
+            snapshot: clients.snapshot().or_exit("failed to read clients"),
+        };
+        checkpoint
+            .save(&self.path)
+            .or_exit("failed to save checkpoint");
+    }
+}
+
+/// The OTLP exporters, if enabled. Not owned by `LoggingGuard`, so that
+/// `Exit::fail` can flush them too.
+#[cfg(feature = "otel")]
+static TELEMETRY: std::sync::OnceLock<transactions::telemetry::Telemetry> =
+    std::sync::OnceLock::new();
+
+/// Export whatever hasn't been exported to OTLP yet, if enabled.
+fn flush_telemetry() {
+    #[cfg(feature = "otel")]
+    if let Some(telemetry) = TELEMETRY.get() {
+        telemetry.shutdown();
+    }
+}
+
+/// Keeps exporting to OTLP, if enabled, until dropped at the end of the run.
+struct LoggingGuard;
+
+impl Drop for LoggingGuard {
+    fn drop(&mut self) {
+        flush_telemetry();
+    }
+}
+
+fn init_logging(args: &Cli) -> LoggingGuard {
+    use tracing_subscriber::prelude::*;
+    use tracing_subscriber::EnvFilter;
+
+    let filter = match args.log_level {
+        Some(level) => EnvFilter::new(level.to_string()),
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn")),
+    };
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::IsTerminal::is_terminal(&std::io::stderr()));
+    let layer = match args.log_format {
+        LogFormat::Pretty => layer.pretty().boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    };
+    let registry = tracing_subscriber::registry().with(layer.with_filter(filter));
+
+    #[cfg(feature = "otel")]
+    if let Some(endpoint) = &args.otlp_endpoint {
+        let (telemetry, layer) = transactions::telemetry::Telemetry::init(endpoint)
+            .or_exit("failed to set up OTLP export");
+        // Export spans for whole runs, not for every transaction.
+        registry
+            .with(layer.with_filter(tracing_subscriber::filter::LevelFilter::INFO))
+            .init();
+        let _ = TELEMETRY.set(telemetry);
+        return LoggingGuard;
+    }
+    registry.init();
+    LoggingGuard
+}
+
+/// Parse the command line, treating it as a `process` command if it doesn't
+/// start with a subcommand.
+fn parse_args() -> Cli {
+    use clap::{CommandFactory, FromArgMatches};
+
+    let mut args: Vec<_> = std::env::args_os().collect();
+    let mut command = Cli::command();
+    // Propagate the global options to the subcommands.
+    command.build();
+    // Find the first argument that isn't a global option or its value.
+    let mut first = None;
+    let mut i = 1;
+    while let Some(arg) = args.get(i).map(|arg| arg.to_string_lossy()) {
+        let Some(option) = arg.strip_prefix('-') else {
+            first = Some(arg);
+            break;
+        };
+        let takes_value = command
+            .get_arguments()
+            .find(|a| a.get_long() == option.strip_prefix('-'))
+            .is_some_and(|a| a.get_action().takes_values());
+        i += if takes_value { 2 } else { 1 };
+    }
+    let is_help = args[1..]
+        .iter()
+        .any(|arg| ["-h", "--help", "-V", "--version"].contains(&arg.to_string_lossy().as_ref()));
+    match first {
+        Some(arg) if arg == "help" || command.find_subcommand(arg.as_ref()).is_some() => {}
+        None if is_help || args.len() == 1 => {}
+        _ => args.insert(1, "process".into()),
+    }
+
+    let matches = command
+        .clone()
+        .try_get_matches_from(&args)
+        .unwrap_or_else(|e| usage_error(e));
+    let Some(path) = matches.get_one::<PathBuf>("config") else {
+        return Cli::from_arg_matches(&matches).unwrap_or_else(|e| usage_error(e));
+    };
+    let (name, sub_matches) = matches.subcommand().expect("subcommand is required");
+    let config = std::fs::read_to_string(path)
+        .or_exit(format_args!("failed to read config {}", path.display()));
+    let invalid = |e: String| -> ! {
+        Exit::Usage.fail(format_args!("invalid config {}: {}", path.display(), e))
+    };
+    let extra = config_args(&config, command.find_subcommand(name).unwrap(), sub_matches)
+        .unwrap_or_else(|e| invalid(e));
+    let rules = config_rules(&config).unwrap_or_else(|e| invalid(e));
+    // Options are the same wherever they are after the subcommand.
+    let index = args.iter().position(|arg| arg == name).unwrap();
+    args.splice(index + 1..index + 1, extra);
+    let mut cli = Cli::try_parse_from(args).unwrap_or_else(|e| usage_error(e));
+    cli.rules = rules;
+    cli
+}
+
+/// Print a command line error, or the help or version if that's what was
+/// asked for, and exit.
+fn usage_error(error: clap::Error) -> ! {
+    let _ = error.print();
+    std::process::exit(if error.use_stderr() {
+        Exit::Usage as i32
+    } else {
+        Exit::Success as i32
+    })
+}
+
+/// Turn the options in a TOML config file into command line arguments for
+/// `command`, skipping any already given on the command line.
+fn config_args(
+    config: &str,
+    command: &clap::Command,
+    matches: &clap::ArgMatches,
+) -> Result<Vec<std::ffi::OsString>, String> {
+    fn flatten(table: toml::Table, out: &mut Vec<(String, toml::Value)>) {
+        for (key, value) in table {
+            match value {
+                toml::Value::Table(table) => flatten(table, out),
+                value => out.push((key.replace('_', "-"), value)),
+            }
+        }
+    }
+
+    let mut table: toml::Table = config.parse().map_err(|e: toml::de::Error| e.to_string())?;
+    // The rules aren't options; see `config_rules`.
+    table.remove("rules");
+    let mut options = Vec::new();
+    flatten(table, &mut options);
+    let mut args = Vec::new();
+    for (key, value) in options {
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(key.as_str()))
+            .ok_or_else(|| format!("unknown option '{}'", key))?;
+        if matches.value_source(arg.get_id().as_str())
+            == Some(clap::parser::ValueSource::CommandLine)
+        {
+            continue;
+        }
+        let flag = format!("--{}", key);
+        let values = match value {
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            match (arg.get_action(), value) {
+                (clap::ArgAction::SetTrue, toml::Value::Boolean(set)) => {
+                    if set {
+                        args.push(flag.clone().into());
+                    }
+                }
+                (clap::ArgAction::Count, toml::Value::Integer(count)) => {
+                    for _ in 0..count {
+                        args.push(flag.clone().into());
+                    }
+                }
+                (clap::ArgAction::Set | clap::ArgAction::Append, value) => {
+                    let value = match value {
+                        toml::Value::String(s) => s,
+                        toml::Value::Integer(i) => i.to_string(),
+                        toml::Value::Float(f) => f.to_string(),
+                        toml::Value::Boolean(b) => b.to_string(),
+                        _ => return Err(format!("unsupported value for '{}'", key)),
+                    };
+                    args.push(flag.clone().into());
+                    args.push(value.into());
+                }
+                _ => return Err(format!("unsupported value for '{}'", key)),
+            }
+        }
+    }
+    Ok(args)
+}
+
+/// Have `clients` follow `rules`, with clients assigned to tiers by
+/// `metadata`.
+fn apply_rules(clients: &mut Clients, mut rules: Rules, metadata: Option<&Arc<ClientMetadata>>) {
+    match metadata {
+        Some(metadata) => rules.set_metadata(metadata.clone()),
+        None if !rules.tiers.is_empty() => {
+            Exit::Usage.fail("tiers in the config's [rules] need --client-metadata to assign them")
+        }
+        None => {}
+    }
+    clients.set_risk_limits(rules.risk_limits());
+    clients.set_policy(Arc::new(rules));
+}
+
+/// Read the `[rules]` table of a TOML config file, if it has one.
+fn config_rules(config: &str) -> Result<Option<Rules>, String> {
+    let mut table: toml::Table = config.parse().map_err(|e: toml::de::Error| e.to_string())?;
+    match table.remove("rules") {
+        Some(toml::Value::Table(rules)) => Rules::from_toml(rules)
+            .map(Some)
+            .map_err(|e| format!("invalid rules: {}", e)),
+        Some(_) => Err("'rules' must be a table".to_string()),
+        None => Ok(None),
+    }
+}
+
+fn main() -> std::process::ExitCode {
+    let cli = parse_args();
+    let _logging = init_logging(&cli);
+    if cli.rules.is_some() && !matches!(cli.command, Command::Process(_) | Command::Consume { .. })
+    {
+        Exit::Usage.fail("the config's [rules] only apply to process and consume");
+    }
+    let exit = match cli.command {
+        Command::Process(args) => process(*args, cli.rules),
+        Command::Stats {
+            input,
+            format,
+            json,
+        } => {
+            let (file, options) = input.open(format.format);
+            let mut reader =
+                TransactionReader::new(file, options).or_exit("failed to read headers");
+            let mut outputs = Outputs {
+                stats: Some(StatsReporting::default()),
+                ..Default::default()
+            };
+            process_transactions(&mut Clients::new(), &mut reader, &mut outputs)
+                .expect("lenient processing doesn't stop early");
+            report_duplicates(&reader);
+            let stats = outputs.stats.unwrap().stats;
+            if json {
+                stats
+                    .write(std::io::stdout())
+                    .or_exit("failed to write stats");
+                println!();
+            } else {
+                print!("{}", stats);
+            }
+            Exit::from_stats(&stats)
+        }
+        Command::Validate { input, format } => {
+            let (file, options) = input.open(format.format);
+            let mut reader =
+                TransactionReader::new(file, options).or_exit("failed to read headers");
+            let stats = validate(&mut reader, std::io::stdout());
+            print!("{}", stats);
+            report_duplicates(&reader);
+            check_trailer(&reader);
+            Exit::from_stats(&stats)
+        }
+        Command::History {
+            input,
+            client,
+            format,
+        } => {
+            // --format is the output's here, so the input's is detected.
+            let (file, options) = input.open(InputFormat::Auto);
+            let mut reader =
+                TransactionReader::new(file, options).or_exit("failed to read headers");
+            let id = ClientId::from(client);
+            let mut clients = Clients::new();
+            clients.set_keep_history();
+            let mut outputs = Outputs {
+                filter: ClientFilter {
+                    ranges: vec![ClientRange {
+                        first: id,
+                        last: id,
+                    }],
+                },
+                stats: Some(StatsReporting::default()),
+                ..Default::default()
+            };
+            process_transactions(&mut clients, &mut reader, &mut outputs)
+                .expect("lenient processing doesn't stop early");
+            report_duplicates(&reader);
+            let client = clients
+                .get(id)
+                .or_exit("failed to read client")
+                .unwrap_or_default();
+            if format == HistoryFormat::Ofx {
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs());
+                ofx::write_statement(std::io::stdout().lock(), id, &client, now)
+                    .or_exit("failed to write statement");
+            } else {
+                let mut writer = TransactionWriter::new(std::io::stdout().lock());
+                if reader.has_memo() {
+                    writer = writer.with_memos();
+                }
+                for entry in client.history() {
+                    writer
+                        .write_with_memo(&entry.transaction, entry.memo.as_deref())
+                        .or_exit("failed to write history");
+                }
+                writer.flush().or_exit("failed to write history");
+            }
+            Exit::from_stats(&outputs.stats.unwrap().stats)
+        }
+        Command::Convert { file_path, from } => {
+            let transactions = convert(&file_path, from);
+            let mut writer = TransactionWriter::new(std::io::stdout().lock());
+            for transaction in &transactions {
+                writer
+                    .write(transaction)
+                    .or_exit("failed to write transactions");
+            }
+            writer.flush().or_exit("failed to write transactions");
+            Exit::Success
+        }
+        Command::Diff { first, second } => {
+            let first = diff::load(first).or_exit("failed to load first state");
+            let second = diff::load(second).or_exit("failed to load second state");
+            let differences = diff::diff(&first, &second);
+            diff::write(
+                std::io::stdout(),
+                &differences,
+                first.multi_currency || second.multi_currency,
+            )
+            .or_exit("failed to write differences");
+            if differences.is_empty() {
+                Exit::Success
+            } else {
+                Exit::Mismatch
+            }
+        }
+        Command::Compare {
+            expected,
+            actual,
+            tolerance,
+        } => {
+            let expected = diff::load(expected).or_exit("failed to load expected state");
+            let actual = diff::load(actual).or_exit("failed to load actual state");
+            let differences = diff::diff_within(&expected, &actual, tolerance);
+            diff::write_report(std::io::stdout(), &differences).or_exit("failed to write report");
+            if differences.is_empty() {
+                println!("{} clients match", actual.clients.len());
+                Exit::Success
+            } else {
+                Exit::Mismatch
+            }
+        }
+        Command::Bisect {
+            input,
+            format,
+            expected,
+            tolerance,
+        } => {
+            let expected = diff::load(expected).or_exit("failed to load expected state");
+            let (file, options) = input.open(format.format);
+            let mut reader =
+                TransactionReader::new(file, options).or_exit("failed to read headers");
+            // Invalid records don't change the state, so they're left out.
+            let mut records = Vec::new();
+            loop {
+                let line = reader.input_position().line();
+                match reader.next() {
+                    Some(Ok(transaction)) => records.push((line, transaction)),
+                    Some(Err(_)) => {}
+                    None => break,
+                }
+            }
+            let divergence =
+                bisect::bisect(&records, &expected, tolerance).or_exit("failed to process");
+            let Some(divergence) = divergence else {
+                println!("{} records processed without diverging", records.len());
+                return Exit::Success.into();
+            };
+            match divergence.line {
+                Some(line) => println!(
+                    "diverges after line {} (record {} of {}):",
+                    line,
+                    divergence.records,
+                    records.len()
+                ),
+                None => println!("diverges before the first record:"),
+            }
+            diff::write_report(std::io::stdout(), &divergence.differences)
+                .or_exit("failed to write report");
+            if !divergence.lines.is_empty() {
+                let lines: Vec<_> = divergence.lines.iter().map(u64::to_string).collect();
+                println!("records of those clients: lines {}", lines.join(", "));
+            }
+            Exit::Mismatch
+        }
+        Command::Merge {
+            inputs,
+            output,
+            amount_format,
+        } => {
+            let states = inputs.iter().map(|path| {
+                diff::load(path).or_exit(format_args!("failed to load {}", path.display()))
+            });
+            let merged = merge::merge(states).or_exit("failed to merge");
+            let clients = Clients::restore_partial(merged).or_exit("failed to merge");
+            let options = WriteOptions {
+                amount_format,
+                ..Default::default()
+            };
+            match &output {
+                Some(path) => clients.write_with(
+                    std::fs::File::create(path).or_exit("failed to create output file"),
+                    &options,
+                ),
+                None => clients.write_with(std::io::stdout(), &options),
+            }
+            .or_exit("failed to write summary");
+            Exit::Success
+        }
+        Command::VerifyAudit { path } => {
+            let file = std::fs::File::open(path).or_exit("failed to open audit log");
+            match audit::verify(file) {
+                Ok((count, _)) => {
+                    println!("{} records verified", count);
+                    Exit::Success
+                }
+                Err(AuditError::Io(e)) => {
+                    Exit::Io.fail(format_args!("failed to read audit log: {}", e))
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    Exit::Mismatch
+                }
+            }
+        }
+        Command::Completions { shell } => {
+            use clap::CommandFactory;
+            clap_complete::generate(
+                shell,
+                &mut Cli::command(),
+                "transactions",
+                &mut std::io::stdout(),
+            );
+            Exit::Success
+        }
+        Command::Generate(args) => {
+            let options = GenerateOptions {
+                clients: args.clients,
+                rows: args.rows,
+                dispute_rate: args.dispute_rate,
+                invalid_rate: args.invalid_rate,
+                seed: args.seed,
+            };
+            match &args.output {
+                Some(path) => generator::generate(
+                    std::fs::File::create(path).or_exit("failed to create output file"),
+                    &options,
+                ),
+                None => generator::generate(std::io::stdout().lock(), &options),
+            }
+            .or_exit("failed to write transactions");
+            Exit::Success
+        }
+        Command::Repl { snapshot_in } => {
+            use std::io::IsTerminal;
+            let mut clients = match snapshot_in {
+                Some(path) => Clients::load_snapshot(path).or_exit("failed to load snapshot"),
+                None => Clients::new(),
+            };
+            let stdin = std::io::stdin();
+            let prompt = stdin.is_terminal();
+            repl::run(&mut clients, stdin.lock(), std::io::stdout(), prompt).or_exit("repl failed");
+            Exit::Success
+        }
+        Command::Consume {
+            source,
+            store,
+            emit,
+            alerts_out,
+        } => {
+            if !store.is_persistent() {
+                // Acknowledged messages would be lost if the program stopped.
+                Exit::Usage.fail("consume needs a persistent --store");
+            }
+            let mut clients = Clients::open(store.open_durable().or_exit("failed to open store"))
+                .or_exit("failed to open store");
+            if let Some(rules) = cli.rules {
+                apply_rules(&mut clients, rules, None);
+            }
+            let mut source = source.open().or_exit("failed to connect");
+            let mut stats = Stats::default();
+            let mut emitter = emit.emitter(WriteOptions::default());
+            let mut alerts = alerts_out.map(|path| {
+                std::io::BufWriter::new(
+                    std::fs::File::create(path).or_exit("failed to create alerts file"),
+                )
+            });
+            let result = source::consume(
+                &mut clients,
+                source.as_mut(),
+                &mut stats,
+                emitter.as_mut(),
+                alerts
+                    .as_mut()
+                    .map(|writer| writer as &mut dyn std::io::Write),
+            );
+            clients.flush().or_exit("failed to flush store");
+            result.or_exit("failed to consume");
+            match &mut emitter {
+                Some(emitter) => emitter.write(&clients).or_exit("failed to write summary"),
+                None => clients
+                    .write(std::io::stdout())
+                    .or_exit("failed to write clients"),
+            }
+            Exit::from_stats(&stats)
+        }
+        Command::Serve {
+            listen,
+            protocol,
+            snapshot_in,
+            snapshot_out,
+            multi_tenant,
+            tenants,
+            tenant_snapshots,
+            save_every,
+            shards,
+            webhook,
+            access,
+            emit,
+            #[cfg(unix)]
+            admin_socket,
+        } => {
+            if multi_tenant && !matches!(protocol, Protocol::Http) {
+                Exit::Usage.fail("--multi-tenant is only supported over HTTP");
+            }
+            if multi_tenant && tenants.is_empty() && tenant_snapshots.is_none() {
+                Exit::Usage.fail("--multi-tenant needs --tenant or --tenant-snapshots");
+            }
+            if multi_tenant && emit.summary_out.is_some() {
+                Exit::Usage.fail("--summary-out isn't supported with --multi-tenant");
+            }
+            if access.configured() && matches!(protocol, Protocol::Lines) {
+                Exit::Usage.fail("--protocol lines doesn't support API keys or TLS");
+            }
+            if access.api_keys.is_none() {
+                tracing::warn!(
+                    "serving without API keys: anyone who can connect can submit transactions"
+                );
+            }
+            let saving = snapshot_out.is_some() || tenant_snapshots.is_some();
+            let save_every = save_every.map(|every| match every {
+                EmitEvery::Interval(interval) if saving => interval,
+                EmitEvery::Interval(_) => {
+                    Exit::Usage.fail("--save-every needs --snapshot-out or --tenant-snapshots")
+                }
+                EmitEvery::Transactions(_) => {
+                    Exit::Usage.fail("--save-every needs a duration, e.g. '5m'")
+                }
+            });
+            let snapshot = snapshot_in
+                .map(Snapshot::load)
+                .transpose()
+                .or_exit("failed to load snapshot");
+            let clients: SharedClients = if shards.get() > 1 {
+                #[cfg(unix)]
+                if admin_socket.is_some() {
+                    Exit::Usage.fail("--shards doesn't support --admin-socket");
+                }
+                SharedClients::start(snapshot, shards).or_exit("failed to load snapshot")
+            } else {
+                let engine = Engine::start(snapshot).or_exit("failed to load snapshot");
+                #[cfg(unix)]
+                if let Some(path) = admin_socket {
+                    let listener = admin::bind(&path)
+                        .or_exit(format_args!("failed to listen on {}", path.display()));
+                    let engine = engine.clone();
+                    std::thread::spawn(move || admin::serve(listener, engine));
+                }
+                engine.into()
+            };
+            emit_periodically(clients.clone(), &emit);
+            let mut tenants = if multi_tenant {
+                Tenants::multi(clients, tenant_snapshots)
+                    .with_tenants(tenants)
+                    .or_exit("invalid --tenant")
+            } else {
+                Tenants::single(clients)
+            };
+            // Runs until killed, so there's no need to close it.
+            if let Some(webhook) = webhook.start() {
+                tenants = tenants.with_listener(move |tenant| webhook.listener(tenant, None));
+            }
+            let tenants = Arc::new(tenants);
+            let save = saving.then(|| {
+                let tenants = tenants.clone();
+                move || save_state(&tenants, snapshot_out.as_deref())
+            });
+            if let (Some(save), Some(interval)) = (save.clone(), save_every) {
+                std::thread::spawn(move || loop {
+                    std::thread::sleep(interval);
+                    if let Err(e) = save() {
+                        tracing::warn!("failed to save clients: {}", e);
+                    }
+                });
+            }
+            serve(&listen, protocol, &tenants, &access, save);
+            Exit::Success
+        }
+    };
+    exit.into()
+}
+
+/// Write the summary of `clients` to --summary-out every --emit-every, which
+/// must be a duration, until the process exits. With several shards, a
+/// transaction submitted while the summary is taken may be in some clients'
+/// rows but not others'.
+fn emit_periodically(clients: SharedClients, emit: &EmitArgs) {
+    let Some(mut emitter) = emit.emitter(WriteOptions::default()) else {
+        return;
+    };
+    let Some(EmitEvery::Interval(interval)) = emit.emit_every else {
+        Exit::Usage.fail("serve needs --emit-every DURATION, e.g. '60s'");
+    };
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        let snapshot = match clients.snapshot() {
+            Ok(snapshot) => snapshot,
+            Err(SharedError::Stopped(_)) => return,
+            Err(e) => {
+                tracing::warn!("failed to write summary: {}", e);
+                continue;
+            }
+        };
+        let result = Clients::restore(snapshot)
+            .map_err(EmitError::from)
+            .and_then(|clients| emitter.write(&clients));
+        if let Err(e) = result {
+            tracing::warn!("failed to write summary: {}", e);
+        }
+    });
+}
+
+/// Save the default clients to `snapshot_out`, if given, and each tenant's
+/// to its snapshot, if the tenants have a snapshot directory.
+fn save_state(tenants: &Tenants, snapshot_out: Option<&Path>) -> Result<(), SaveError> {
+    if let Some(path) = snapshot_out {
+        let snapshot = tenants.default_clients().snapshot()?;
+        snapshot.save(path).map_err(SharedError::from)?;
+    }
+    tenants.save()?;
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+enum SaveError {
+    #[error(transparent)]
+    Default(#[from] SharedError),
+    #[error(transparent)]
+    Tenant(#[from] TenantError),
+}
+
+/// Run `shutdown` on a thread of its own once SIGINT or SIGTERM arrives. A
+/// second signal exits at once.
+fn on_signal(shutdown: impl FnOnce() + Send + 'static) {
+    let stopping = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        flag::register_conditional_shutdown(signal, Exit::Interrupted as i32, stopping.clone())
+            .or_exit("failed to handle signals");
+    }
+    let mut signals = Signals::new([SIGINT, SIGTERM]).or_exit("failed to handle signals");
+    std::thread::spawn(move || {
+        if signals.forever().next().is_some() {
+            stopping.store(true, Ordering::SeqCst);
+            shutdown();
+        }
+    });
+}
+
+/// Serve `tenants` until killed, or if there's a way to `save` them, until
+/// SIGINT or SIGTERM, saving them before returning.
+fn serve(
+    listen: &str,
+    protocol: Protocol,
+    tenants: &Tenants,
+    access: &AccessArgs,
+    save: Option<impl Fn() -> Result<(), SaveError> + Send + 'static>,
+) {
+    if !matches!(protocol, Protocol::Http) {
+        if let Some(save) = save {
+            // Neither server can be stopped part way, so the process exits
+            // from the signal's thread.
+            on_signal(move || {
+                save().or_exit("failed to save clients");
+                std::process::exit(Exit::Success as i32);
+            });
+        }
+        // Only HTTP serves tenants other than the default.
+        let clients = tenants.default_clients().clone();
+        serve_shared(listen, protocol, clients, access);
+        return;
+    }
+    let bind_failed = format_args!("failed to listen on {}", listen);
+    let keys = access.keys();
+    #[cfg(feature = "tls")]
+    let server = match access.tls() {
+        Some((cert, key)) => Server::bind_tls(listen, cert, key),
+        None => Server::bind(listen),
+    };
+    #[cfg(not(feature = "tls"))]
+    let server = Server::bind(listen);
+    let mut server = server.or_exit(bind_failed);
+    if let Some(keys) = keys {
+        server = server.with_api_keys(keys);
+    }
+    let server = Arc::new(server);
+    if save.is_some() {
+        let server = server.clone();
+        on_signal(move || server.stop());
+    }
+    tracing::info!("serving HTTP on {}", listen);
+    server.run(tenants);
+    if let Some(save) = save {
+        save().or_exit("failed to save clients");
+    }
+}
+
+fn serve_shared(listen: &str, protocol: Protocol, clients: SharedClients, access: &AccessArgs) {
+    let bind_failed = format_args!("failed to listen on {}", listen);
+    // Only gRPC takes API keys or TLS here.
+    #[cfg(not(feature = "grpc"))]
+    let _ = access;
+    match protocol {
+        Protocol::Http => unreachable!("HTTP serves tenants rather than shared clients"),
+        #[cfg(feature = "grpc")]
+        Protocol::Grpc => {
+            let runtime = tokio::runtime::Runtime::new().or_exit("failed to start runtime");
+            runtime.block_on(async {
+                let listener = tokio::net::TcpListener::bind(listen)
+                    .await
+                    .or_exit(bind_failed);
+                tracing::info!("serving gRPC on {}", listen);
+                let mut service = transactions::grpc::Service::new(clients);
+                if let Some(keys) = access.keys() {
+                    service = service.with_api_keys(keys);
+                }
+                #[cfg(feature = "tls")]
+                if let Some((cert, key)) = access.tls() {
+                    service = service.with_tls(cert, key);
+                }
+                service.serve(listener).await.or_exit("gRPC server failed");
+            });
+        }
+        Protocol::Lines => {
+            let listener = std::net::TcpListener::bind(listen).or_exit(bind_failed);
+            tracing::info!("accepting records on {}", listen);
+            tcp::serve(listener, clients);
+        }
+    }
+}
+
+/// Parse every record, writing a line to `out` for each invalid one, and
+/// process the valid ones against empty state to count rejections.
+fn validate<R: std::io::Read>(
+    reader: &mut TransactionReader<R>,
+    mut out: impl std::io::Write,
+) -> Stats {
+    let mut clients = Clients::new();
+    let mut stats = Stats::default();
+    loop {
+        let line = reader.input_position().line();
+        let Some(transaction) = reader.next() else {
+            break;
+        };
+        if let Some(missing) = reader.sequence_gap() {
+            writeln!(
+                out,
+                "line {}: sequence numbers {} to {} missing",
+                line,
+                missing.start(),
+                missing.end()
+            )
+            .or_exit("failed to write");
+        }
+        match transaction {
+            Ok(transaction) => match clients.process_transaction(transaction) {
+                Ok(_) => stats.record_processed(Ok(())),
+                Err(ProcessError::Rejected(e)) => stats.record_processed(Err(&e)),
+                Err(ProcessError::Store(e)) => {
+                    Exit::Failure.fail(format_args!("store error at line {}: {}", line, e))
+                }
+                Err(ProcessError::Pending) => {}
+            },
+            Err(e) => {
+                stats.record_invalid(&e);
+                writeln!(out, "line {}: {}", line, e).or_exit("failed to write");
+            }
+        }
+    }
+    stats
+}
+
+fn process(args: ProcessArgs, rules: Option<Rules>) -> Exit {
+    let filter = ClientFilter {
+        ranges: args.clients,
+    };
+    let metadata = args
+        .client_metadata
+        .as_ref()
+        .map(|path| Arc::new(ClientMetadata::load(path).or_exit("failed to load client metadata")));
+    let client_keys = args.string_client_ids.then(|| Arc::new(ClientKeys::new()));
+    let write_options = WriteOptions {
+        amount_format: args.amount_format,
+        clients: filter.clone(),
+        totals: args.totals && args.totals_out.is_none(),
+        metadata: metadata.clone(),
+        extended: args.extended_output,
+        client_keys: client_keys.clone(),
+    };
+
+    let mut outputs = Outputs {
+        checkpointing: args.checkpoint.map(|path| Checkpointing {
+            path,
+            every: args.checkpoint_every.unwrap(),
+        }),
+        verbose: args.verbose,
+        strict: args.strict,
+        filter,
+        limit: args.limit,
+        stop_after: match (args.stop_after_tx, args.stop_after_line) {
+            (Some(id), _) => Some(StopAfter::Transaction(id.into())),
+            (None, Some(line)) => Some(StopAfter::Line(line)),
+            (None, None) => None,
+        },
+        // Always counted, for the exit status.
+        stats: Some(StatsReporting {
+            stats: Stats::default(),
+            print: args.stats,
+            path: args.stats_out.clone(),
+        }),
+        ..Default::default()
+    };
+
+    // Stop before the next record on SIGINT or SIGTERM, and write everything
+    // as usual for the transactions processed so far. A second signal exits
+    // at once.
+    for signal in [SIGINT, SIGTERM] {
+        flag::register_conditional_shutdown(
+            signal,
+            Exit::Interrupted as i32,
+            outputs.interrupt.clone(),
+        )
+        .and_then(|_| flag::register(signal, outputs.interrupt.clone()))
+        .or_exit("failed to handle signals");
+    }
 
-#[derive(Parser)]
-struct Args {
-    file_path: PathBuf,
+    outputs.activity = args.summary_by.map(Activity::new);
+    outputs.emitter = args.emit.emitter(write_options.clone());
+    if !(0.0..=1.0).contains(&args.chargeback_threshold) {
+        Exit::Usage.fail("--chargeback-threshold must be between 0 and 1");
+    }
+    outputs.chargebacks = args.chargeback_report.is_some().then(ChargebackRates::new);
+    outputs.journal = args.journal.as_ref().map(|path| {
+        Journal::new(
+            std::fs::File::create(path).or_exit("failed to create journal"),
+            args.journal_format,
+        )
+    });
+    outputs.errors = args.errors_json.as_ref().map(|path| {
+        ErrorWriter::new(std::fs::File::create(path).or_exit("failed to create errors file"))
+    });
+    let (file, mut options) = args.input.open(args.format.format);
+    options.client_keys = client_keys.clone();
+    outputs.client_keys = client_keys;
+    if args.string_client_ids && !matches!(args.store, StoreSpec::Memory) {
+        // Clients already in the store were numbered by an earlier run.
+        Exit::Usage.fail("--string-client-ids only works with the in-memory store");
+    }
+    if options.encoding != InputEncoding::Utf8
+        && (args.quarantine.is_some() || args.resume || args.recover)
+    {
+        // These need positions in the file, not in the transcoded text.
+        Exit::Usage.fail("--quarantine, --resume, and --recover only work with UTF-8 input");
+    }
+    if !file.is_plain()
+        && (args.quarantine.is_some() || args.resume || args.recover || args.progress)
+    {
+        // As are these, and the size of the file, for progress.
+        Exit::Usage.fail(
+            "--quarantine, --resume, --recover, and --progress only work with uncompressed CSV",
+        );
+    }
+    outputs.quarantine = args.quarantine.map(|path| Quarantining {
+        quarantine: Quarantine::new(
+            std::fs::File::create(&path).or_exit("failed to create quarantine file"),
+        )
+        .or_exit("failed to write quarantine file"),
+        // Not a clone of `file`, which would share its position.
+        input: std::fs::File::open(&args.input.file_path).or_exit("failed to open file"),
+        path,
+    });
+    if args.progress {
+        let len = std::fs::metadata(&args.input.file_path)
+            .or_exit("failed to read file size")
+            .len();
+        outputs.progress = Some(Progress::new(len));
+    }
+    let mut reader = TransactionReader::new(file, options).or_exit("failed to read headers");
+    for _ in 0..args.skip.unwrap_or(0) {
+        if reader.next().is_none() {
+            break;
+        }
+    }
+    let store = args.store.open().or_exit("failed to open store");
+    // Where to start replaying the write-ahead log from when recovering.
+    let mut wal_position = 0;
+    let mut clients = match &args.snapshot_in {
+        Some(path) => Clients::restore_into(
+            store,
+            Snapshot::load(path).or_exit("failed to load snapshot"),
+        )
+        .or_exit("failed to restore snapshot"),
+        None => match outputs
+            .checkpointing
+            .as_ref()
+            .filter(|c| (args.resume || args.recover) && c.path.exists())
+        {
+            Some(checkpointing) => {
+                let checkpoint =
+                    Checkpoint::load(&checkpointing.path).or_exit("failed to load checkpoint");
+                if args.recover {
+                    wal_position = checkpoint.wal_position.unwrap_or_else(|| {
+                        Exit::Usage.fail("checkpoint was saved without a write-ahead log")
+                    });
+                }
+                resume(&mut reader, store, checkpoint)
+            }
+            None => match &args.seed {
+                Some(path) => Clients::seed(
+                    store,
+                    std::fs::File::open(path).or_exit("failed to open seed summary"),
+                )
+                .or_exit("failed to read seed summary"),
+                None => Clients::open(store).or_exit("failed to open store"),
+            },
+        },
+    };
+    if args.idempotent {
+        clients.set_idempotent();
+    }
+    let rules = match args.reject_zero_amounts {
+        true => {
+            let mut rules = rules.unwrap_or_default();
+            rules.reject_zero_amounts = true;
+            Some(rules)
+        }
+        false => rules,
+    };
+    if let Some(rules) = rules {
+        apply_rules(&mut clients, rules, metadata.as_ref());
+    }
+    if let Some(limit) = args.pending_disputes {
+        clients.set_pending_limit(limit);
+    }
+    if let Some(max) = args.max_deposits {
+        if !matches!(args.store, StoreSpec::Memory) {
+            Exit::Usage.fail("--max-deposits only works with the in-memory store");
+        }
+        clients.set_max_deposits(max);
+    }
+    if args.dispute_ageing.is_some() {
+        clients.set_track_disputes();
+    }
+    if let Some(path) = &args.events_in {
+        let file = std::fs::File::open(path).or_exit("failed to open events");
+        event::replay(file, &mut clients).or_exit("failed to replay events");
+    }
+    if let Some(path) = args.wal.as_ref().filter(|_| args.recover) {
+        let count = wal::replay_from(path, wal_position, &mut clients)
+            .or_exit("failed to replay write-ahead log");
+        skip_transactions(&mut reader, count);
+        clients.verify().or_exit("recovered state is inconsistent");
+    }
+    if args.dry_run {
+        let before = clients.snapshot().or_exit("failed to read clients");
+        let mut copy = copy_clients(before.clone(), &clients);
+        outputs.verbose = outputs.verbose.max(1);
+        let result = process_transactions(&mut copy, &mut reader, &mut outputs);
+        report_unmatched(&mut copy, &mut outputs);
+        if let Some(reporting) = &outputs.stats {
+            reporting.report();
+        }
+        report_duplicates(&reader);
+        if let Err(e) = result {
+            eprintln!("error: {}", e);
+            return e.exit();
+        }
+        let interrupted = check_interrupted(&outputs, &reader);
+        report_stopped(&outputs, args.dump_client, &copy);
+        let after = copy.snapshot().or_exit("failed to read clients");
+        diff::write(
+            std::io::stdout(),
+            &diff::diff(&before, &after),
+            after.multi_currency,
+        )
+        .or_exit("failed to write differences");
+        if interrupted {
+            return Exit::Interrupted;
+        }
+        return Exit::from_stats(&outputs.stats.unwrap().stats);
+    }
+    if let Some(path) = &args.shadow {
+        let config = std::fs::read_to_string(path).or_exit("failed to read shadow rules");
+        let rules = config_rules(&config).unwrap_or_else(|e| {
+            Exit::Usage.fail(format_args!(
+                "invalid shadow rules {}: {}",
+                path.display(),
+                e
+            ))
+        });
+        let snapshot = clients.snapshot().or_exit("failed to read clients");
+        let mut shadow = copy_clients(snapshot, &clients);
+        apply_rules(&mut shadow, rules.unwrap_or_default(), metadata.as_ref());
+        outputs.shadow = Some(shadow);
+    }
+    outputs.wal = args
+        .wal
+        .map(|path| Wal::open(path, args.wal_sync).or_exit("failed to open write-ahead log"));
+    outputs.audit = args
+        .audit_log
+        .map(|path| AuditLog::open(path).or_exit("failed to open audit log"));
+    outputs.events = args.events_out.map(|path| {
+        EventWriter::new(std::fs::File::create(path).or_exit("failed to create events file"))
+    });
+    outputs.alerts = args.alerts_out.map(|path| {
+        std::io::BufWriter::new(std::fs::File::create(path).or_exit("failed to create alerts file"))
+    });
+    outputs.webhook = args.webhook.start();
+    if let Some(webhook) = &outputs.webhook {
+        clients.add_listener(webhook.listener(None, metadata.clone()));
+    }
+    let threads = args
+        .threads
+        .unwrap_or_else(|| std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN));
+    let result = if threads.get() > 1
+        && matches!(args.store, StoreSpec::Memory)
+        && !outputs.need_input_order()
+        && !clients.is_idempotent()
+        && !clients.has_pending_queue()
+        && clients.max_deposits().is_none()
+        && clients.risk_limits().is_none()
+        && !clients.tracks_disputes()
+        && !reader.has_sequence()
+        && !reader.has_reference()
+        // Needs the line of each transaction.
+        && !args.extended_output
+    {
+        process_in_parallel(&mut clients, &mut reader, &mut outputs, threads);
+        Ok(())
+    } else {
+        process_transactions(&mut clients, &mut reader, &mut outputs)
+    };
+    report_unmatched(&mut clients, &mut outputs);
+    if let Some(webhook) = outputs.webhook.take() {
+        webhook.close();
+    }
+    if let Some(reporting) = &outputs.stats {
+        reporting.report();
+    }
+    clients.flush().or_exit("failed to flush store");
+    if clients.duplicates() > 0 {
+        eprintln!(
+            "skipped {} transactions already processed",
+            clients.duplicates()
+        );
+    }
+    report_duplicates(&reader);
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        return e.exit();
+    }
+    let interrupted = check_interrupted(&outputs, &reader);
+    report_stopped(&outputs, args.dump_client, &clients);
+    if let (Some(path), Some(shadow)) = (&args.shadow_out, &outputs.shadow) {
+        let live = clients.snapshot().or_exit("failed to read clients");
+        let shadow = shadow.snapshot().or_exit("failed to read shadow clients");
+        diff::write(
+            std::fs::File::create(path).or_exit("failed to create shadow differences file"),
+            &diff::diff(&live, &shadow),
+            live.multi_currency || shadow.multi_currency,
+        )
+        .or_exit("failed to write shadow differences");
+    }
+    if let Some(path) = &args.snapshot_out {
+        clients
+            .save_snapshot_as(path, args.snapshot_format)
+            .or_exit("failed to save snapshot");
+    }
+    match &outputs.activity {
+        Some(activity) => activity
+            .write(std::io::stdout(), write_options.amount_format)
+            .or_exit("failed to write activity"),
+        None => match &mut outputs.emitter {
+            Some(emitter) => emitter.write(&clients).or_exit("failed to write summary"),
+            None => clients
+                .write_with(std::io::stdout(), &write_options)
+                .or_exit("failed to write clients"),
+        },
+    }
+    if let Some(path) = &args.export_ledger {
+        clients
+            .write_ledger(
+                std::fs::File::create(path).or_exit("failed to create ledger file"),
+                &write_options,
+            )
+            .or_exit("failed to write ledger");
+    }
+    if let Some(path) = &args.held_breakdown {
+        clients
+            .write_held(
+                std::fs::File::create(path).or_exit("failed to create held breakdown file"),
+                &write_options,
+            )
+            .or_exit("failed to write held breakdown");
+    }
+    if let (Some(path), Some(chargebacks)) = (&args.chargeback_report, &outputs.chargebacks) {
+        chargebacks
+            .write(
+                std::fs::File::create(path).or_exit("failed to create chargeback report"),
+                write_options.amount_format,
+                args.chargeback_threshold,
+                metadata.as_deref(),
+            )
+            .or_exit("failed to write chargeback report");
+    }
+    if let Some(path) = &args.totals_out {
+        clients
+            .write_totals(
+                std::fs::File::create(path).or_exit("failed to create totals file"),
+                &write_options,
+            )
+            .or_exit("failed to write totals");
+    }
+    if let Some(path) = &args.dispute_ageing {
+        clients
+            .write_dispute_ageing(
+                std::fs::File::create(path).or_exit("failed to create dispute ageing file"),
+                &write_options,
+            )
+            .or_exit("failed to write dispute ageing");
+    }
+    if args.state_hash {
+        let hash = clients.state_hash().or_exit("failed to hash state");
+        eprintln!("state hash: {}", hash);
+    }
+    if interrupted {
+        return Exit::Interrupted;
+    }
+    Exit::from_stats(&outputs.stats.unwrap().stats)
+}
+
+/// A copy of `clients`, whose state is `snapshot`, to process the input
+/// against separately, e.g. for --dry-run, with the same settings.
+fn copy_clients(snapshot: Snapshot, clients: &Clients) -> Clients {
+    let mut copy = Clients::restore(snapshot).or_exit("failed to copy clients");
+    copy.set_policy(clients.policy().clone());
+    copy.set_risk_limits(clients.risk_limits());
+    if clients.is_idempotent() {
+        copy.set_idempotent();
+    }
+    if clients.tracks_disputes() {
+        copy.set_track_disputes();
+    }
+    if clients.keeps_history() {
+        copy.set_keep_history();
+    }
+    if let Some(limit) = clients.pending_limit() {
+        copy.set_pending_limit(limit);
+    }
+    if let Some(max) = clients.max_deposits() {
+        copy.set_max_deposits(max);
+    }
+    copy
+}
+
+/// Whether processing was stopped by a signal, reporting it if so. If not,
+/// the whole input was read, so check its trailer.
+fn check_interrupted<R: std::io::Read>(outputs: &Outputs, reader: &TransactionReader<R>) -> bool {
+    if outputs.interrupt.load(Ordering::Relaxed) {
+        eprintln!(
+            "interrupted at line {}: writing output for the transactions processed so far",
+            reader.input_position().line()
+        );
+        return true;
+    }
+    check_trailer(reader);
+    false
+}
+
+/// Report where --stop-after-tx or --stop-after-line stopped processing, or
+/// that it never did, and write the state of the client to dump, if any.
+fn report_stopped(outputs: &Outputs, dump_client: Option<u16>, clients: &Clients) {
+    match (outputs.stop_after, outputs.stopped) {
+        (_, Some(line)) => eprintln!("stopped after line {}", line),
+        (Some(StopAfter::Transaction(id)), None) => {
+            eprintln!("transaction {} not found: processed the whole input", id)
+        }
+        (Some(StopAfter::Line(line)), None) => {
+            eprintln!("input ends before line {}: processed all of it", line)
+        }
+        (None, None) => {}
+    }
+    if let Some(id) = dump_client {
+        repl::show(clients, ClientId::from(id), std::io::stderr()).or_exit("failed to dump client");
+    }
+}
+
+/// Read the transactions in `path`, a file in another format.
+fn convert(path: &Path, from: ConvertFormat) -> Vec<Transaction> {
+    match from {
+        #[cfg(feature = "iso20022")]
+        ConvertFormat::Iso20022 => {
+            let xml = std::fs::read_to_string(path).or_exit("failed to read file");
+            transactions::iso20022::read_transactions(&xml)
+                .or_exit("failed to read ISO 20022 message")
+        }
+        #[cfg(not(feature = "iso20022"))]
+        ConvertFormat::Iso20022 => Exit::Usage.fail("built without support for iso20022"),
+        ConvertFormat::Card => {
+            let file = std::fs::File::open(path).or_exit("failed to open file");
+            card::read_transactions(file).or_exit("failed to read card messages")
+        }
+    }
+}
+
+/// List the disputes, resolves, and chargebacks held for a deposit that could
+/// never be applied, and count them as rejected.
+fn report_unmatched(clients: &mut Clients, outputs: &mut Outputs) {
+    for (parked, error) in clients.take_unmatched() {
+        let transaction = &parked.transaction;
+        eprintln!(
+            "client {}: {} of transaction {} never applied: {}",
+            outputs.client(transaction.client_id),
+            transaction.data.name(),
+            transaction.data.transaction_id(),
+            error
+        );
+        if let Some(reporting) = &mut outputs.stats {
+            reporting.stats.record_processed(Err(&error));
+        }
+        let parked = parked
+            .line
+            .and_then(|line| Some((line, outputs.parked.remove(&line)?)));
+        if let (Some(writer), Some((line, record))) = (&mut outputs.errors, parked) {
+            writer
+                .write(&Problem::rejected(line, &record, &error))
+                .or_exit("failed to write errors");
+        }
+    }
+}
+
+/// Count and audit the transactions held for a deposit that were applied
+/// along with it.
+fn report_released(clients: &mut Clients, outputs: &mut Outputs) {
+    for parked in clients.take_released() {
+        let transaction = parked.transaction;
+        if let Some(line) = parked.line {
+            outputs.parked.remove(&line);
+            if outputs.verbose >= 2 {
+                eprintln!(
+                    "line {}: client {}, tx {} {} applied once its deposit arrived",
+                    line,
+                    outputs.client(transaction.client_id),
+                    transaction.data.transaction_id(),
+                    transaction.data.name()
+                );
+            }
+        }
+        if let Some(audit) = &mut outputs.audit {
+            let state = clients
+                .get(transaction.client_id)
+                .or_exit("failed to read client")
+                .expect("client exists after an accepted transaction");
+            audit
+                .append(&transaction, None, &state)
+                .or_exit("failed to append to audit log");
+        }
+        if let Some(reporting) = &mut outputs.stats {
+            reporting.stats.record_processed(Ok(()));
+        }
+    }
+}
+
+/// Exit without writing anything if the input doesn't match its trailer, as
+/// it's probably been truncated or corrupted.
+fn check_trailer<R: std::io::Read>(reader: &TransactionReader<R>) {
+    match reader.check_trailer() {
+        Ok(()) => {}
+        Err(e @ TrailerError::Unverifiable) => tracing::warn!("{}", e),
+        Err(e) => Exit::InvalidRecords.fail(format_args!("input doesn't match its trailer: {}", e)),
+    }
+}
+
+fn report_duplicates<R: std::io::Read>(reader: &TransactionReader<R>) {
+    if reader.duplicates() > 0 {
+        eprintln!("dropped {} duplicate rows", reader.duplicates());
+    }
 }
 
-fn main() {
-    let args = Args::parse();
-    summarize_transactions(
-        std::fs::File::open(args.file_path).expect("failed to open file"),
-        std::io::stdout(),
-    );
+fn resume<R: std::io::Read + std::io::Seek>(
+    reader: &mut TransactionReader<R>,
+    store: Box<dyn Store>,
+    checkpoint: Checkpoint,
+) -> Clients {
+    reader
+        .seek((&checkpoint.position).into())
+        .or_exit("failed to seek to checkpoint");
+    Clients::restore_into(store, checkpoint.snapshot).or_exit("failed to restore checkpoint")
 }
 
-fn summarize_transactions(input: impl std::io::Read, output: impl std::io::Write) {
-    let mut clients = clients::Clients::new();
-    for (index, transaction) in load_transactions(input).enumerate() {
-        let transaction = transaction
-            .unwrap_or_else(|e| panic!("invalid transaction at line {}: {}", index + 1, e));
-        if clients.process_transaction(transaction).is_err() {
-            // In a real system, we'd want to do something with these errors,
-            // e.g. reporting them to the client.
+/// Skip the first `count` transactions of the input, which have already been
+/// applied. Invalid records are skipped without counting, as they were never
+/// written to the write-ahead log.
+fn skip_transactions<R: std::io::Read>(reader: &mut TransactionReader<R>, count: u64) {
+    let mut skipped = 0;
+    while skipped < count {
+        match reader.next() {
+            Some(Ok(_)) => skipped += 1,
+            Some(Err(_)) => {}
+            None => Exit::Usage.fail("input is shorter than the write-ahead log"),
+        }
+    }
+}
+
+fn process_transactions<R: std::io::Read>(
+    clients: &mut Clients,
+    reader: &mut TransactionReader<R>,
+    outputs: &mut Outputs,
+) -> Result<(), Abort> {
+    let _span = tracing::info_span!("process_transactions").entered();
+    let mut count = 0u64;
+    let mut records = 0u64;
+    let result = loop {
+        if outputs.limit.is_some_and(|limit| records >= limit)
+            || outputs.interrupt.load(Ordering::Relaxed)
+            || outputs.stopped.is_some()
+        {
+            break Ok(());
+        }
+        let line = reader.input_position().line();
+        if let Some(StopAfter::Line(last)) = outputs.stop_after {
+            if line > last {
+                outputs.stopped = Some(last);
+                break Ok(());
+            }
+        }
+        let start = reader.input_position().byte();
+        let Some(transaction) = tracing::debug_span!("parse", line).in_scope(|| reader.next())
+        else {
+            break Ok(());
+        };
+        records += 1;
+        // Stop once the last line has been processed, even if it's the end of
+        // the input.
+        if let Some(StopAfter::Line(last)) = outputs.stop_after {
+            if line >= last {
+                outputs.stopped = Some(line);
+            }
+        }
+        if let Some(missing) = reader.sequence_gap() {
+            if outputs.strict {
+                break Err(Abort::Gap {
+                    line,
+                    missing: missing.clone(),
+                });
+            }
+            eprintln!(
+                "line {}: sequence numbers {} to {} missing",
+                line,
+                missing.start(),
+                missing.end()
+            );
+        }
+        let transaction = match transaction {
+            Ok(transaction) => transaction,
+            Err(e) => {
+                if let Some(reporting) = &mut outputs.stats {
+                    reporting.stats.record_invalid(&e);
+                }
+                if let Some(writer) = &mut outputs.errors {
+                    writer
+                        .write(&Problem::invalid(line, reader.record(), &e))
+                        .or_exit("failed to write errors");
+                }
+                if let Some(quarantining) = &mut outputs.quarantine {
+                    let end = reader.input_position().byte();
+                    quarantining
+                        .write(line, &e, start..end)
+                        .or_exit("failed to write quarantine file");
+                }
+                if outputs.strict {
+                    break Err(Abort::Invalid { line, error: e });
+                }
+                if outputs.verbose >= 1 {
+                    eprintln!("line {}: invalid transaction: {}", line, e);
+                }
+                continue;
+            }
+        };
+        if let Some(StopAfter::Transaction(id)) = outputs.stop_after {
+            if transaction.data.transaction_id() == id {
+                outputs.stopped = Some(line);
+            }
+        }
+        if !outputs.filter.contains(transaction.client_id) {
+            continue;
+        }
+        if let Some(timestamp) = reader.timestamp() {
+            clients.set_time(timestamp);
+        }
+        clients.set_line(line);
+        if let Some(memo) = reader.memo() {
+            clients.set_memo(memo);
+        }
+        if let Some(reference) = reader.reference() {
+            clients.set_reference(reference);
+        }
+        if let Some(shadow) = &mut outputs.shadow {
+            if let Some(timestamp) = reader.timestamp() {
+                shadow.set_time(timestamp);
+            }
+            shadow.set_line(line);
+            if let Some(memo) = reader.memo() {
+                shadow.set_memo(memo);
+            }
+            if let Some(reference) = reader.reference() {
+                shadow.set_reference(reference);
+            }
+            if let Err(ProcessError::Store(e)) = shadow.process_transaction(transaction.clone()) {
+                Exit::Failure.fail(format_args!("store error at line {}: {}", line, e))
+            }
+        }
+        let _span = tracing::debug_span!("process", line).entered();
+        if let Some(wal) = &mut outputs.wal {
+            wal.append(&transaction, reader.reference())
+                .or_exit("failed to append to write-ahead log");
+        }
+        let client = transaction.client_id;
+        let transaction_id = transaction.data.transaction_id();
+        let audited = outputs.audit.is_some().then(|| transaction.clone());
+        let kind = transaction.data.name();
+        let currency = transaction.currency;
+        let describe = |clients: &Clients| {
+            let state = clients
+                .get(client)
+                .or_exit(format_args!("store error at line {}", line))
+                .unwrap_or_default();
+            format!(
+                "available {}, held {}, locked {}",
+                state.available(currency),
+                state.held(currency),
+                state.locked()
+            )
+        };
+        let before = (outputs.verbose >= 2).then(|| describe(clients));
+        // The deposit a dispute, resolve, or chargeback refers to. Looked up
+        // first, as it's gone once it's been charged back.
+        let deposit = match transaction.data {
+            TransactionData::Chargeback { transaction_id } if outputs.chargebacks.is_some() => {
+                Some(transaction_id)
+            }
+            TransactionData::Dispute { transaction_id }
+            | TransactionData::Resolve { transaction_id }
+            | TransactionData::Chargeback { transaction_id }
+                if outputs.journal.is_some() =>
+            {
+                Some(transaction_id)
+            }
+            _ => None,
+        }
+        .and_then(|transaction_id| {
+            clients
+                .find_deposit(client, transaction_id)
+                .or_exit(format_args!("store error at line {}", line))
+        });
+        match clients.process_transaction(transaction) {
+            Ok(events) => {
+                if let (Some(audit), Some(transaction)) = (&mut outputs.audit, audited) {
+                    let state = clients
+                        .get(client)
+                        .or_exit(format_args!("store error at line {}", line))
+                        .expect("client exists after an accepted transaction");
+                    audit
+                        .append(&transaction, reader.memo(), &state)
+                        .or_exit("failed to append to audit log");
+                }
+                if let Some(reporting) = &mut outputs.stats {
+                    reporting.stats.record_processed(Ok(()));
+                }
+                if let Some(before) = before {
+                    eprintln!(
+                        "line {}: client {}, tx {} {}: {} -> {}",
+                        line,
+                        outputs.client(client),
+                        transaction_id,
+                        kind,
+                        before,
+                        describe(clients)
+                    );
+                }
+                report_released(clients, outputs);
+                if let Some(activity) = &mut outputs.activity {
+                    activity
+                        .record(reader.timestamp(), &events, |tx| {
+                            clients.find_deposit(client, tx)
+                        })
+                        .or_exit(format_args!("store error at line {}", line));
+                }
+                if let Some(chargebacks) = &mut outputs.chargebacks {
+                    chargebacks.record(client, &events, deposit.as_ref());
+                }
+                if let Some(journal) = &mut outputs.journal {
+                    journal
+                        .record(client, reader.timestamp(), &events, deposit.as_ref())
+                        .or_exit("failed to write journal");
+                }
+                if let Some(writer) = &mut outputs.events {
+                    for event in events {
+                        writer
+                            .write(&ClientEvent { client, event })
+                            .or_exit("failed to write event");
+                    }
+                }
+                // Taken even if they're not written, so they don't pile up.
+                let alerts = clients.take_alerts();
+                if let Some(writer) = &mut outputs.alerts {
+                    risk::write_alerts(writer, &alerts).or_exit("failed to write alert");
+                }
+            }
+            Err(ProcessError::Rejected(e)) => {
+                if let Some(reporting) = &mut outputs.stats {
+                    reporting.stats.record_processed(Err(&e));
+                }
+                if let Some(writer) = &mut outputs.errors {
+                    writer
+                        .write(&Problem::rejected(line, reader.record(), &e))
+                        .or_exit("failed to write errors");
+                }
+                if outputs.strict {
+                    break Err(Abort::Rejected { line, error: e });
+                }
+                if outputs.verbose >= 1 {
+                    eprintln!(
+                        "line {}: client {}, tx {} {} rejected: {}",
+                        line,
+                        outputs.client(client),
+                        transaction_id,
+                        kind,
+                        e
+                    );
+                }
+            }
+            Err(ProcessError::Store(e)) => {
+                Exit::Failure.fail(format_args!("store error at line {}: {}", line, e))
+            }
+            // Counted, audited, and reported once it's applied or rejected.
+            Err(ProcessError::Pending) => {
+                outputs.parked.insert(line, reader.record().clone());
+                if outputs.verbose >= 1 {
+                    eprintln!(
+                        "line {}: client {}, tx {} {} waiting for its deposit",
+                        line,
+                        outputs.client(client),
+                        transaction_id,
+                        kind
+                    );
+                }
+            }
         }
+        count += 1;
+        if let Some(progress) = &outputs.progress {
+            progress.update(reader.input_position().byte(), count);
+        }
+        if let Some(checkpointing) = &outputs.checkpointing {
+            if count.is_multiple_of(checkpointing.every.get()) {
+                checkpointing.save(clients, reader, outputs.wal.as_mut());
+            }
+        }
+        if let Some(emitter) = &mut outputs.emitter {
+            emitter
+                .processed(clients)
+                .or_exit("failed to write summary");
+        }
+    };
+    if let Some(wal) = &mut outputs.wal {
+        wal.sync().or_exit("failed to sync write-ahead log");
+    }
+    if let Some(journal) = &mut outputs.journal {
+        journal.flush().or_exit("failed to write journal");
+    }
+    if let Some(writer) = &mut outputs.events {
+        writer.flush().or_exit("failed to write events");
+    }
+    if let Some(writer) = &mut outputs.alerts {
+        std::io::Write::flush(writer).or_exit("failed to write alerts");
+    }
+    if let Some(audit) = &mut outputs.audit {
+        audit.flush().or_exit("failed to write audit log");
+    }
+    if let Some(writer) = &mut outputs.errors {
+        writer.flush().or_exit("failed to write errors");
+    }
+    if let Some(quarantining) = &mut outputs.quarantine {
+        quarantining
+            .quarantine
+            .flush()
+            .or_exit("failed to write quarantine file");
+        eprintln!(
+            "quarantined {} records to {}",
+            quarantining.quarantine.count(),
+            quarantining.path.display()
+        );
+    }
+    // Resuming from after the problem would skip it.
+    if let Some(checkpointing) = outputs.checkpointing.as_ref().filter(|_| result.is_ok()) {
+        checkpointing.save(clients, reader, outputs.wal.as_mut());
+    }
+    if let Some(progress) = &outputs.progress {
+        progress.bar.finish_and_clear();
+    }
+    result
+}
+
+/// Process the rest of the input as for `process_transactions`, but on
+/// `threads` threads, for outputs that don't need transactions in input order.
+fn process_in_parallel<R: std::io::Read>(
+    clients: &mut Clients,
+    reader: &mut TransactionReader<R>,
+    outputs: &mut Outputs,
+    threads: NonZeroUsize,
+) {
+    let _span = tracing::info_span!("process_transactions", threads).entered();
+    let mut invalid = Stats::default();
+    let limit = outputs.limit.map_or(usize::MAX, |limit| {
+        usize::try_from(limit).unwrap_or(usize::MAX)
+    });
+    let filter = &outputs.filter;
+    let interrupt = &outputs.interrupt;
+    let transactions = reader
+        .take(limit)
+        .take_while(|_| !interrupt.load(Ordering::Relaxed))
+        .filter_map(|transaction| transaction.map_err(|e| invalid.record_invalid(&e)).ok())
+        .filter(|transaction| filter.contains(transaction.client_id));
+    let (processed, stats) =
+        parallel::process(clients, transactions, threads).or_exit("failed to process transactions");
+    *clients = processed;
+    if let Some(reporting) = &mut outputs.stats {
+        reporting.stats.merge(&stats);
+        reporting.stats.merge(&invalid);
     }
-    clients.write(output).expect("failed to write clients");
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use transactions::store::MemoryStore;
+
+    fn reader(input: &str) -> TransactionReader<std::io::Cursor<&str>> {
+        TransactionReader::new(std::io::Cursor::new(input), LoadOptions::default()).unwrap()
+    }
+
+    fn summary(clients: &Clients) -> String {
+        let mut buf = Vec::new();
+        clients.write(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    fn summarize_transactions(input: &str) -> String {
+        let mut clients = Clients::new();
+        process_transactions(&mut clients, &mut reader(input), &mut Outputs::default()).unwrap();
+        summary(&clients)
+    }
 
     // High-level test covering a vertial slice of the whole program to make
     // sure everything fits together.
@@ -55,9 +2816,7 @@ deposit, 8, 1007, 1.0
 dispute, 8, 1007
 chargeback, 8, 1007
 ";
-        let mut buf = Vec::new();
-        summarize_transactions(input.as_bytes(), &mut buf);
-        let actual = String::from_utf8(buf).unwrap();
+        let actual = summarize_transactions(input);
         assert_eq!(
             actual,
             "client,available,held,total,locked
@@ -66,4 +2825,582 @@ chargeback, 8, 1007
 "
         );
     }
+
+    #[test]
+    fn test_summarize_transactions_multi_currency() {
+        // One row per (client, currency). Transactions without a currency go
+        // into their own balance, written with an empty currency.
+        let input = "type, client, tx, amount, currency
+deposit, 7, 1001, 1.0, USD
+deposit, 7, 1002, 2.0, EUR
+deposit, 8, 1003, 3.0,
+withdrawal, 7, 1004, 0.5, USD
+dispute, 7, 1002, , EUR
+withdrawal, 7, 1005, 1.0, EUR
+";
+        let actual = summarize_transactions(input);
+        assert_eq!(
+            actual,
+            "client,currency,available,held,total,locked
+7,EUR,0.0000,2.0000,2.0000,false
+7,USD,0.5000,0.0000,0.5000,false
+8,,3.0000,0.0000,3.0000,false
+"
+        );
+    }
+
+    const CHECKPOINT_INPUT: &str = "type, client, tx, amount
+deposit, 1, 1, 1.0
+deposit, 1, 2, 2.0
+deposit, 2, 3, 3.0
+dispute, 1, 1
+withdrawal, 2, 4, 1.0
+";
+
+    #[test]
+    fn test_checkpoint() {
+        // The final checkpoint should have the full state and point to the end
+        // of the input.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint.json");
+        let mut outputs = Outputs {
+            checkpointing: Some(Checkpointing {
+                path: path.clone(),
+                every: NonZeroU64::new(2).unwrap(),
+            }),
+            ..Default::default()
+        };
+        let mut clients = Clients::new();
+        let mut reader = reader(CHECKPOINT_INPUT);
+        process_transactions(&mut clients, &mut reader, &mut outputs).unwrap();
+
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        assert_eq!(checkpoint.snapshot, clients.snapshot().unwrap());
+        assert_eq!(checkpoint.position.byte, CHECKPOINT_INPUT.len() as u64);
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_resume() {
+        // Simulate a run that crashed after checkpointing part way through,
+        // then resume it.
+        let mut clients = Clients::new();
+        let mut crashed = reader(CHECKPOINT_INPUT);
+        for transaction in crashed.by_ref().take(3) {
+            clients.process_transaction(transaction.unwrap()).unwrap();
+        }
+        let checkpoint = Checkpoint {
+            position: (&crashed.input_position()).into(),
+            wal_position: None,
+            snapshot: clients.snapshot().unwrap(),
+        };
+
+        let mut reader = reader(CHECKPOINT_INPUT);
+        let mut clients = resume(&mut reader, Box::new(MemoryStore::new()), checkpoint);
+        process_transactions(&mut clients, &mut reader, &mut Outputs::default()).unwrap();
+        assert_eq!(summary(&clients), summarize_transactions(CHECKPOINT_INPUT));
+    }
+
+    #[test]
+    fn test_recover() {
+        // Simulate a run that crashed part way through, with everything up to
+        // that point in the write-ahead log, then recover it.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.csv");
+        let mut wal = Wal::open(&path, SyncPolicy::Always).unwrap();
+        for transaction in reader(CHECKPOINT_INPUT).take(3) {
+            wal.append(&transaction.unwrap(), None).unwrap();
+        }
+        drop(wal);
+
+        let mut clients = Clients::new();
+        let mut reader = reader(CHECKPOINT_INPUT);
+        let count = wal::replay(&path, &mut clients).unwrap();
+        skip_transactions(&mut reader, count);
+        let mut outputs = Outputs {
+            wal: Some(Wal::open(&path, SyncPolicy::Always).unwrap()),
+            ..Default::default()
+        };
+        process_transactions(&mut clients, &mut reader, &mut outputs).unwrap();
+        assert_eq!(summary(&clients), summarize_transactions(CHECKPOINT_INPUT));
+
+        // The log now has every transaction.
+        let mut replayed = Clients::new();
+        assert_eq!(wal::replay(&path, &mut replayed).unwrap(), 5);
+        assert_eq!(summary(&replayed), summary(&clients));
+
+        // Replayed transactions are recorded as processed, so running the
+        // whole input again after recovering changes nothing.
+        let mut idempotent = Clients::new();
+        idempotent.set_idempotent();
+        wal::replay(&path, &mut idempotent).unwrap();
+        let mut input = self::reader(CHECKPOINT_INPUT);
+        process_transactions(&mut idempotent, &mut input, &mut Outputs::default()).unwrap();
+        assert_eq!(idempotent.duplicates(), 5);
+        assert_eq!(summary(&idempotent), summary(&clients));
+    }
+
+    #[test]
+    fn test_recover_from_checkpoint() {
+        // Simulate a run that checkpointed after two transactions then crashed
+        // after a third, then recover it from the checkpoint and the log.
+        let dir = tempfile::tempdir().unwrap();
+        let wal_path = dir.path().join("wal.csv");
+        let checkpointing = Checkpointing {
+            path: dir.path().join("checkpoint.json"),
+            every: NonZeroU64::new(2).unwrap(),
+        };
+        let mut clients = Clients::new();
+        let mut wal = Wal::open(&wal_path, SyncPolicy::Always).unwrap();
+        let mut crashed = reader(CHECKPOINT_INPUT);
+        for i in 0..3 {
+            let transaction = crashed.next().unwrap().unwrap();
+            wal.append(&transaction, None).unwrap();
+            clients.process_transaction(transaction).unwrap();
+            if i == 1 {
+                checkpointing.save(&clients, &crashed, Some(&mut wal));
+            }
+        }
+        drop(wal);
+
+        let checkpoint = Checkpoint::load(&checkpointing.path).unwrap();
+        let wal_position = checkpoint.wal_position.unwrap();
+        let mut reader = reader(CHECKPOINT_INPUT);
+        let mut clients = resume(&mut reader, Box::new(MemoryStore::new()), checkpoint);
+        let count = wal::replay_from(&wal_path, wal_position, &mut clients).unwrap();
+        assert_eq!(count, 1);
+        skip_transactions(&mut reader, count);
+        clients.verify().unwrap();
+        let mut outputs = Outputs {
+            wal: Some(Wal::open(&wal_path, SyncPolicy::Always).unwrap()),
+            ..Default::default()
+        };
+        process_transactions(&mut clients, &mut reader, &mut outputs).unwrap();
+        assert_eq!(summary(&clients), summarize_transactions(CHECKPOINT_INPUT));
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut outputs = Outputs {
+            stats: Some(StatsReporting {
+                stats: Stats::default(),
+                print: false,
+                path: None,
+            }),
+            ..Default::default()
+        };
+        let input = "type, client, tx, amount
+deposit, 1, 1, 1.0
+withdrawal, 1, 2, 2.0
+dispute, 1, 3
+deposit, 1, 4, 1.0
+";
+        process_transactions(&mut Clients::new(), &mut reader(input), &mut outputs).unwrap();
+        let stats = outputs.stats.unwrap().stats;
+        assert_eq!(stats.processed, 4);
+        assert_eq!(stats.accepted(), 2);
+        assert_eq!(
+            stats.rejected.into_iter().collect::<Vec<_>>(),
+            vec![("insufficient_funds", 1), ("unknown_transaction_id", 1)]
+        );
+    }
+
+    #[test]
+    fn test_pending() {
+        // Disputes held for their deposit are only counted, audited, and
+        // reported once they're applied or rejected.
+        let dir = tempfile::tempdir().unwrap();
+        let (audit_path, errors_path) = (dir.path().join("audit"), dir.path().join("errors"));
+        let mut outputs = Outputs {
+            stats: Some(StatsReporting {
+                stats: Stats::default(),
+                print: false,
+                path: None,
+            }),
+            audit: Some(AuditLog::open(&audit_path).unwrap()),
+            errors: Some(ErrorWriter::new(
+                std::fs::File::create(&errors_path).unwrap(),
+            )),
+            ..Default::default()
+        };
+        let input = "type, client, tx, amount
+dispute, 1, 1
+deposit, 1, 1, 1.0
+dispute, 2, 5
+";
+        let mut clients = Clients::new();
+        clients.set_pending_limit(2);
+        process_transactions(&mut clients, &mut reader(input), &mut outputs).unwrap();
+        let stats = &outputs.stats.as_ref().unwrap().stats;
+        assert_eq!(stats.processed, 2);
+        assert!(stats.rejected.is_empty());
+
+        report_unmatched(&mut clients, &mut outputs);
+        let stats = outputs.stats.unwrap().stats;
+        assert_eq!(stats.processed, 3);
+        assert_eq!(stats.accepted(), 2);
+        assert_eq!(
+            stats.rejected.into_iter().collect::<Vec<_>>(),
+            vec![("unknown_transaction_id", 1)]
+        );
+        outputs.audit.unwrap().flush().unwrap();
+        let audit = std::fs::File::open(&audit_path).unwrap();
+        assert_eq!(audit::verify(audit).unwrap().0, 2);
+        drop(outputs.errors);
+        let errors = std::fs::read_to_string(&errors_path).unwrap();
+        assert_eq!(errors.lines().count(), 1);
+        assert!(errors.contains(r#""line":4"#), "{}", errors);
+    }
+
+    #[test]
+    fn test_strict_and_lenient() {
+        let input = "type, client, tx, amount
+deposit, 1, 1, 1.0
+deposit, 1, 2
+withdrawal, 1, 3, 2.0
+deposit, 1, 4, 1.0
+";
+        let mut clients = Clients::new();
+        process_transactions(&mut clients, &mut reader(input), &mut Outputs::default()).unwrap();
+        assert_eq!(
+            summary(&clients),
+            "client,available,held,total,locked\n1,2.0000,0.0000,2.0000,false\n"
+        );
+
+        let mut strict = Outputs {
+            strict: true,
+            ..Default::default()
+        };
+        let mut clients = Clients::new();
+        assert!(matches!(
+            process_transactions(&mut clients, &mut reader(input), &mut strict),
+            Err(Abort::Invalid { line: 3, .. })
+        ));
+        let input = input.replace("deposit, 1, 2\n", "");
+        assert!(matches!(
+            process_transactions(&mut Clients::new(), &mut reader(&input), &mut strict),
+            Err(Abort::Rejected {
+                line: 3,
+                error: ClientError::InsufficientFunds
+            })
+        ));
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let input = "type, client, tx, amount
+deposit, 1, 1, 1.0
+deposit, 1, 2
+deposit, 1, 3, 1.0
+\"deposit\", 1, 4, \"1,0\"
+deposit, 1, 5, 1.0
+";
+        let dir = tempfile::tempdir().unwrap();
+        let input_path = dir.path().join("input.csv");
+        std::fs::write(&input_path, input).unwrap();
+        let path = dir.path().join("quarantine.csv");
+        let mut outputs = Outputs {
+            quarantine: Some(Quarantining {
+                quarantine: Quarantine::new(std::fs::File::create(&path).unwrap()).unwrap(),
+                input: std::fs::File::open(&input_path).unwrap(),
+                path: path.clone(),
+            }),
+            ..Default::default()
+        };
+        let mut clients = Clients::new();
+        let file = std::fs::File::open(&input_path).unwrap();
+        let mut reader = TransactionReader::new(file, LoadOptions::default()).unwrap();
+        process_transactions(&mut clients, &mut reader, &mut outputs).unwrap();
+        assert_eq!(outputs.quarantine.unwrap().quarantine.count(), 2);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            r#"line,kind,error,record
+3,missing_amount,missing amount,"deposit, 1, 2"
+5,invalid_amount,invalid amount: invalid format,"""deposit"", 1, 4, ""1,0"""
+"#
+        );
+        assert_eq!(
+            summary(&clients),
+            "client,available,held,total,locked\n1,3.0000,0.0000,3.0000,false\n"
+        );
+    }
+
+    #[test]
+    fn test_sequence_gap() {
+        let input = "type, client, tx, amount, seq
+deposit, 1, 1, 1.0, 1
+deposit, 1, 2, 1.0, 4
+deposit, 1, 3, 1.0, 5
+";
+        let mut clients = Clients::new();
+        process_transactions(&mut clients, &mut reader(input), &mut Outputs::default()).unwrap();
+        assert_eq!(
+            summary(&clients),
+            "client,available,held,total,locked\n1,3.0000,0.0000,3.0000,false\n"
+        );
+
+        let mut strict = Outputs {
+            strict: true,
+            ..Default::default()
+        };
+        let mut clients = Clients::new();
+        let result = process_transactions(&mut clients, &mut reader(input), &mut strict);
+        assert!(matches!(result, Err(Abort::Gap { line: 3, .. })));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "sequence numbers 2 to 3 missing before line 3"
+        );
+    }
+
+    #[test]
+    fn test_client_filter() {
+        let input = "type, client, tx, amount
+deposit, 1, 1, 1.0
+deposit, 2, 2, 2.0
+dispute, 2, 2
+deposit, 3, 3, 3.0
+";
+        let mut outputs = Outputs {
+            filter: ClientFilter {
+                ranges: vec!["2..3".parse().unwrap()],
+            },
+            ..Default::default()
+        };
+        let mut clients = Clients::new();
+        process_transactions(&mut clients, &mut reader(input), &mut outputs).unwrap();
+        assert_eq!(
+            summary(&clients),
+            "client,available,held,total,locked
+2,0.0000,2.0000,2.0000,false
+3,3.0000,0.0000,3.0000,false
+"
+        );
+    }
+
+    #[test]
+    fn test_limit() {
+        let input = "type, client, tx, amount
+deposit, 1, 1, 1.0
+deposit, 1, 2
+deposit, 1, 3, 2.0
+deposit, 1, 4, 4.0
+";
+        let mut outputs = Outputs {
+            limit: Some(3),
+            ..Default::default()
+        };
+        let mut clients = Clients::new();
+        let mut reader = reader(input);
+        process_transactions(&mut clients, &mut reader, &mut outputs).unwrap();
+        // The invalid record counts towards the limit.
+        assert_eq!(
+            summary(&clients),
+            "client,available,held,total,locked\n1,3.0000,0.0000,3.0000,false\n"
+        );
+        assert_eq!(reader.input_position().line(), 5);
+    }
+
+    #[test]
+    fn test_stop_after() {
+        let input = "type,client,tx,amount
+deposit,1,1,1.0
+deposit,1,2,2.0
+withdrawal,1,3,0.5
+deposit,1,4,4.0
+";
+        let stop = |stop_after| {
+            let mut outputs = Outputs {
+                stop_after: Some(stop_after),
+                ..Default::default()
+            };
+            let mut clients = Clients::new();
+            process_transactions(&mut clients, &mut reader(input), &mut outputs).unwrap();
+            (summary(&clients), outputs.stopped)
+        };
+        let expected = "client,available,held,total,locked\n1,2.5000,0.0000,2.5000,false\n";
+        assert_eq!(
+            stop(StopAfter::Transaction(TransactionId::from(3))),
+            (expected.to_string(), Some(4))
+        );
+        assert_eq!(stop(StopAfter::Line(4)), (expected.to_string(), Some(4)));
+        // The input has exactly five lines.
+        let expected = "client,available,held,total,locked\n1,6.5000,0.0000,6.5000,false\n";
+        assert_eq!(stop(StopAfter::Line(5)), (expected.to_string(), Some(5)));
+        assert_eq!(stop(StopAfter::Transaction(TransactionId::from(5))).1, None);
+        assert_eq!(stop(StopAfter::Line(9)).1, None);
+    }
+
+    #[test]
+    fn test_shadow() {
+        let input = "type,client,tx,amount
+deposit,1,1,10.0
+withdrawal,1,2,6.0
+deposit,2,3,1.0
+";
+        let mut shadow = Clients::new();
+        shadow.set_policy(Arc::new(
+            config_rules("[rules]\nmax_withdrawal = \"5\"")
+                .unwrap()
+                .unwrap(),
+        ));
+        let mut outputs = Outputs {
+            shadow: Some(shadow),
+            ..Default::default()
+        };
+        let mut clients = Clients::new();
+        process_transactions(&mut clients, &mut reader(input), &mut outputs).unwrap();
+        let live = clients.snapshot().unwrap();
+        let shadow = outputs.shadow.unwrap().snapshot().unwrap();
+        let mut out = Vec::new();
+        diff::write(&mut out, &diff::diff(&live, &shadow), false).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,change,available,held,total\n1,balance,+6.0000,+0.0000,+6.0000\n"
+        );
+    }
+
+    #[test]
+    fn test_copy_clients() {
+        let mut clients = Clients::new();
+        clients.set_idempotent();
+        clients.set_track_disputes();
+        clients.set_keep_history();
+        clients.set_pending_limit(3);
+        clients.set_max_deposits(2);
+        let copy = copy_clients(clients.snapshot().unwrap(), &clients);
+        assert!(copy.is_idempotent());
+        assert!(copy.tracks_disputes());
+        assert!(copy.keeps_history());
+        assert_eq!(copy.pending_limit(), Some(3));
+        assert_eq!(copy.max_deposits(), Some(2));
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let input = "type,client,tx,amount
+deposit,1,1,1.0
+deposit,1,2,2.0
+deposit,1,3,4.0
+";
+        let outputs = &mut Outputs::default();
+        let mut clients = Clients::new();
+        // As if a signal arrived while the second deposit was processed.
+        let interrupt = outputs.interrupt.clone();
+        clients.add_listener(Box::new(move |_, _, _, client| {
+            if client.total(None) > Amount::try_from("1").unwrap().into() {
+                interrupt.store(true, Ordering::Relaxed);
+            }
+        }));
+        let mut reader = reader(input);
+        process_transactions(&mut clients, &mut reader, outputs).unwrap();
+        assert_eq!(
+            summary(&clients),
+            "client,available,held,total,locked\n1,3.0000,0.0000,3.0000,false\n"
+        );
+        assert_eq!(reader.input_position().line(), 4);
+    }
+
+    #[test]
+    fn test_completions() {
+        use clap::CommandFactory;
+        let mut buf = Vec::new();
+        clap_complete::generate(
+            clap_complete::Shell::Bash,
+            &mut Cli::command(),
+            "transactions",
+            &mut buf,
+        );
+        let script = String::from_utf8(buf).unwrap();
+        assert!(script.contains("verify-audit"));
+        assert!(script.contains("--snapshot-format"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let input = "type, client, tx, amount
+deposit, 1, 1, 1.0
+deposit, 1, 2
+withdrawal, 1, 3, 2.0
+deposit, 1, 4, 1.23456
+deposit, 1, 5, 1.0
+";
+        let mut out = Vec::new();
+        let stats = validate(&mut reader(input), &mut out);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "line 3: missing amount\nline 5: invalid amount: invalid format\n"
+        );
+        assert_eq!(stats.processed, 3);
+        assert_eq!(stats.accepted(), 2);
+        assert_eq!(stats.invalid.values().sum::<u64>(), 2);
+    }
+
+    #[test]
+    fn test_config_args() {
+        use clap::CommandFactory;
+
+        let config = r#"
+verbose = 2
+log-level = "debug"
+
+[input]
+delimiter = ";"
+
+[output]
+amount_format = "trimmed"
+stats = true
+export-ledger = "ledger.csv"
+
+[rules]
+max-withdrawal = "10000"
+"#;
+        let mut command = Cli::command();
+        command.build();
+        let process = command.find_subcommand("process").unwrap();
+        // Options on the command line take precedence.
+        let matches = command.clone().get_matches_from([
+            "transactions",
+            "process",
+            "in.csv",
+            "--log-level",
+            "info",
+        ]);
+        let (_, matches) = matches.subcommand().unwrap();
+        let args = config_args(config, process, matches).unwrap();
+        assert_eq!(
+            args,
+            [
+                "--delimiter",
+                ";",
+                "--amount-format",
+                "trimmed",
+                "--export-ledger",
+                "ledger.csv",
+                "--stats",
+                "--verbose",
+                "--verbose",
+            ]
+        );
+
+        assert_eq!(
+            config_args("no-such-option = 1", process, matches),
+            Err("unknown option 'no-such-option'".to_string())
+        );
+
+        assert_eq!(
+            config_rules(config).unwrap().unwrap().max_withdrawal,
+            Some(Amount::try_from("10000").unwrap())
+        );
+        assert_eq!(config_rules("verbose = 2"), Ok(None));
+        assert!(config_rules("rules = 1").is_err());
+        assert!(
+            config_rules("[rules]\nallow_negative_available = true")
+                .unwrap()
+                .unwrap()
+                .allow_negative_available
+        );
+        assert!(config_rules("[rules]\nallow_negative = true")
+            .unwrap_err()
+            .contains("unknown field"));
+    }
 }
@@ -0,0 +1,145 @@
+//! Combining the partial states from runs over separate shards of the input,
+//! e.g. when each shard is a different file.
+
+use std::collections::BTreeMap;
+
+use crate::client::ClientActivity;
+use crate::snapshot::{BalanceSnapshot, ClientSnapshot, Snapshot};
+use crate::transaction::ClientId;
+use crate::{Amount, Currency, SignedAmount};
+
+#[derive(Debug, thiserror::Error)]
+pub enum MergeError {
+    #[error("client {0}: balance would overflow")]
+    Overflow(ClientId),
+}
+
+/// Merge states, summing the balances of clients that appear in more than one
+/// of them, by currency. A client is locked if it's locked in any of them, for
+/// the first reason given, if any, its activity is the sum of its activity in
+/// each, and its expired deposits are those expired in any. The order its
+/// deposits were made in isn't kept, so they're taken to be oldest by lowest
+/// ID if they're expired later.
+pub fn merge(states: impl IntoIterator<Item = Snapshot>) -> Result<Snapshot, MergeError> {
+    let mut multi_currency = false;
+    let mut clients: BTreeMap<ClientId, ClientSnapshot> = BTreeMap::new();
+    for state in states {
+        multi_currency |= state.multi_currency;
+        for client in state.clients {
+            let merged = clients.entry(client.id).or_insert_with(|| ClientSnapshot {
+                id: client.id,
+                locked: false,
+                lock_reason: None,
+                activity: ClientActivity::default(),
+                balances: Vec::new(),
+                deposits: Vec::new(),
+                order: Vec::new(),
+                expired: Vec::new(),
+            });
+            merged.locked |= client.locked;
+            if merged.lock_reason.is_none() {
+                merged.lock_reason = client.lock_reason;
+            }
+            merged.activity = merged.activity.combine(client.activity);
+            let mut balances: BTreeMap<Option<Currency>, BalanceSnapshot> = merged
+                .balances
+                .drain(..)
+                .map(|balance| (balance.currency, balance))
+                .collect();
+            for balance in client.balances {
+                let sum = balances.entry(balance.currency).or_insert(BalanceSnapshot {
+                    currency: balance.currency,
+                    available: SignedAmount::ZERO,
+                    held: Amount::ZERO,
+                });
+                let overflow = || MergeError::Overflow(client.id);
+                sum.available = sum
+                    .available
+                    .checked_add(balance.available)
+                    .ok_or_else(overflow)?;
+                sum.held = sum.held.checked_add(balance.held).ok_or_else(overflow)?;
+            }
+            merged.balances = balances.into_values().collect();
+            merged.deposits.extend(client.deposits);
+            merged
+                .deposits
+                .sort_by_key(|deposit| deposit.transaction_id);
+            merged.expired.extend(client.expired);
+            merged.expired.sort();
+        }
+    }
+    Ok(Snapshot {
+        multi_currency,
+        clients: clients.into_values().collect(),
+        processed: None,
+        references: Vec::new(),
+        risk_windows: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::Clients;
+    use crate::store::MemoryStore;
+
+    fn summary(text: &str) -> Snapshot {
+        Clients::seed(Box::new(MemoryStore::new()), text.as_bytes())
+            .unwrap()
+            .snapshot()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_merge() {
+        let merged = merge([
+            summary(
+                "client,available,held,total,locked
+1,1.0000,0.5000,1.5000,false
+2,2.0000,0.0000,2.0000,false
+",
+            ),
+            summary(
+                "client,available,held,total,locked
+1,3.0000,0.0000,3.0000,true
+3,1.0000,0.0000,1.0000,false
+",
+            ),
+        ])
+        .unwrap();
+        let mut buf = Vec::new();
+        Clients::restore_partial(merged)
+            .unwrap()
+            .write(&mut buf)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,available,held,total,locked
+1,4.0000,0.5000,4.5000,true
+2,2.0000,0.0000,2.0000,false
+3,1.0000,0.0000,1.0000,false
+"
+        );
+    }
+
+    #[test]
+    fn test_merge_currencies() {
+        let merged = merge([
+            summary("client,currency,available,held,total,locked\n1,USD,1.0,0,1.0,false\n"),
+            summary("client,currency,available,held,total,locked\n1,EUR,2.0,0,2.0,false\n1,USD,1.0,0,1.0,false\n"),
+        ])
+        .unwrap();
+        let mut buf = Vec::new();
+        Clients::restore_partial(merged)
+            .unwrap()
+            .write(&mut buf)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,currency,available,held,total,locked
+1,EUR,2.0000,0.0000,2.0000,false
+1,USD,2.0000,0.0000,2.0000,false
+"
+        );
+    }
+}
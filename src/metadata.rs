@@ -0,0 +1,119 @@
+//! Details of each client kept outside the engine, e.g. their name, email,
+//! and tier, read from a CSV sidecar file so that reports and notifications
+//! can say who a client is without a separate join.
+//!
+//! The file has a `client` column and any of `name`, `email`, and `tier`:
+//!
+//! ```text
+//! client,name,email,tier
+//! 7,Acme Ltd,ops@acme.example,gold
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::transaction::ClientId;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct ClientInfo {
+    pub name: String,
+    pub email: String,
+    pub tier: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MetadataError {
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid client metadata: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("client {0} is listed more than once")]
+    Duplicate(ClientId),
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ClientMetadata {
+    clients: HashMap<ClientId, ClientInfo>,
+}
+
+impl ClientMetadata {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, MetadataError> {
+        Self::read(std::fs::File::open(path)?)
+    }
+
+    pub fn read(reader: impl std::io::Read) -> Result<Self, MetadataError> {
+        // Not flattened, so that e.g. a numeric name is still read as text.
+        #[derive(Deserialize)]
+        struct Row {
+            client: ClientId,
+            #[serde(default)]
+            name: String,
+            #[serde(default)]
+            email: String,
+            #[serde(default)]
+            tier: String,
+        }
+
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let mut clients = HashMap::new();
+        for row in reader.deserialize() {
+            let Row {
+                client,
+                name,
+                email,
+                tier,
+            } = row?;
+            if clients
+                .insert(client, ClientInfo { name, email, tier })
+                .is_some()
+            {
+                return Err(MetadataError::Duplicate(client));
+            }
+        }
+        Ok(Self { clients })
+    }
+
+    /// The tier of `client`, if it's listed with one.
+    pub fn tier(&self, client: ClientId) -> Option<&str> {
+        self.clients
+            .get(&client)
+            .map(|info| info.tier.as_str())
+            .filter(|tier| !tier.is_empty())
+    }
+
+    /// The details of `client`, or empty ones if it isn't listed.
+    pub fn get(&self, client: ClientId) -> ClientInfo {
+        self.clients.get(&client).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let metadata =
+            ClientMetadata::read("client,name,tier\n7, Acme Ltd ,gold\n8,1234,\n".as_bytes())
+                .unwrap();
+        assert_eq!(
+            metadata.get(7.into()),
+            ClientInfo {
+                name: "Acme Ltd".into(),
+                email: String::new(),
+                tier: "gold".into(),
+            }
+        );
+        assert_eq!(metadata.get(8.into()).name, "1234");
+        assert_eq!(metadata.get(9.into()), ClientInfo::default());
+        assert_eq!(metadata.tier(7.into()), Some("gold"));
+        assert_eq!(metadata.tier(8.into()), None);
+        assert!(matches!(
+            ClientMetadata::read("client,name\n7,a\n7,b\n".as_bytes()),
+            Err(MetadataError::Duplicate(client)) if client == 7.into()
+        ));
+    }
+}
@@ -0,0 +1,283 @@
+//! A client's history as an [OFX](https://www.financialdataexchange.org)
+//! bank statement, which most accounting software can import.
+//!
+//! There's a statement for each currency the client has used. Deposits are
+//! credits and withdrawals and chargebacks are debits; disputes and resolves
+//! only move funds between available and held, so they're reflected in the
+//! available balance rather than as transactions.
+
+use std::collections::HashMap;
+
+use crate::client::{Client, HistoryEntry};
+use crate::transaction::{ClientId, TransactionData};
+use crate::{Amount, Currency, TransactionId};
+
+/// OFX needs a currency for every statement, so this is used for
+/// transactions without one. It's the ISO 4217 code for "no currency".
+const NO_CURRENCY: &str = "XXX";
+
+/// Write an OFX 2.2 statement of `client`'s history, which must have been
+/// kept; see `Clients::set_keep_history`. `as_of` is the time of the
+/// statement, in seconds since the Unix epoch, and is also used for
+/// transactions accepted without a time.
+///
+/// Chargebacks of deposits made before the history was kept are left out,
+/// as their amount isn't known.
+pub fn write_statement(
+    mut writer: impl std::io::Write,
+    client_id: ClientId,
+    client: &Client,
+    as_of: u64,
+) -> std::io::Result<()> {
+    let now = date_time(as_of);
+    writeln!(
+        writer,
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>"
+    )?;
+    writeln!(
+        writer,
+        "<?OFX OFXHEADER=\"200\" VERSION=\"220\" SECURITY=\"NONE\" OLDFILEUID=\"NONE\" NEWFILEUID=\"NONE\"?>"
+    )?;
+    writeln!(writer, "<OFX>")?;
+    writeln!(writer, "<SIGNONMSGSRSV1><SONRS>")?;
+    writeln!(
+        writer,
+        "<STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>"
+    )?;
+    writeln!(
+        writer,
+        "<DTSERVER>{}</DTSERVER><LANGUAGE>ENG</LANGUAGE>",
+        now
+    )?;
+    writeln!(writer, "</SONRS></SIGNONMSGSRSV1>")?;
+    writeln!(writer, "<BANKMSGSRSV1>")?;
+
+    let entries = entries(client, as_of);
+    for currency in client.currencies() {
+        let entries: Vec<_> = entries
+            .iter()
+            .filter(|entry| entry.currency == currency)
+            .collect();
+        let start = entries
+            .iter()
+            .map(|entry| entry.time)
+            .min()
+            .unwrap_or(as_of);
+        let end = entries
+            .iter()
+            .map(|entry| entry.time)
+            .max()
+            .unwrap_or(as_of);
+        writeln!(writer, "<STMTTRNRS>")?;
+        writeln!(
+            writer,
+            "<TRNUID>0</TRNUID><STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>"
+        )?;
+        writeln!(writer, "<STMTRS>")?;
+        writeln!(
+            writer,
+            "<CURDEF>{}</CURDEF>",
+            currency.map_or(NO_CURRENCY.to_string(), |c| c.to_string())
+        )?;
+        writeln!(
+            writer,
+            "<BANKACCTFROM><BANKID>0</BANKID><ACCTID>{}</ACCTID><ACCTTYPE>CHECKING</ACCTTYPE></BANKACCTFROM>",
+            client_id
+        )?;
+        writeln!(
+            writer,
+            "<BANKTRANLIST><DTSTART>{}</DTSTART><DTEND>{}</DTEND>",
+            date_time(start),
+            date_time(end)
+        )?;
+        for entry in entries {
+            let (kind, sign, name) = match entry.kind {
+                Kind::Deposit => ("CREDIT", "", "Deposit"),
+                Kind::Withdrawal => ("DEBIT", "-", "Withdrawal"),
+                Kind::Chargeback => ("DEBIT", "-", "Chargeback"),
+            };
+            // Chargebacks have the ID of the deposit, so need one of their own.
+            let id = match entry.kind {
+                Kind::Chargeback => format!("{}-chargeback", entry.transaction_id),
+                _ => entry.transaction_id.to_string(),
+            };
+            let memo = entry
+                .memo
+                .map(|memo| format!("<MEMO>{}</MEMO>", escape(memo)))
+                .unwrap_or_default();
+            writeln!(
+                writer,
+                "<STMTTRN><TRNTYPE>{}</TRNTYPE><DTPOSTED>{}</DTPOSTED><TRNAMT>{}{}</TRNAMT><FITID>{}</FITID><NAME>{}</NAME>{}</STMTTRN>",
+                kind,
+                date_time(entry.time),
+                sign,
+                entry.amount,
+                id,
+                name,
+                memo
+            )?;
+        }
+        writeln!(writer, "</BANKTRANLIST>")?;
+        writeln!(
+            writer,
+            "<LEDGERBAL><BALAMT>{}</BALAMT><DTASOF>{}</DTASOF></LEDGERBAL>",
+            client.total(currency),
+            now
+        )?;
+        writeln!(
+            writer,
+            "<AVAILBAL><BALAMT>{}</BALAMT><DTASOF>{}</DTASOF></AVAILBAL>",
+            client.available(currency),
+            now
+        )?;
+        writeln!(writer, "</STMTRS>")?;
+        writeln!(writer, "</STMTTRNRS>")?;
+    }
+    writeln!(writer, "</BANKMSGSRSV1>")?;
+    writeln!(writer, "</OFX>")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Deposit,
+    Withdrawal,
+    Chargeback,
+}
+
+struct Entry<'a> {
+    kind: Kind,
+    transaction_id: TransactionId,
+    currency: Option<Currency>,
+    amount: Amount,
+    time: u64,
+    memo: Option<&'a str>,
+}
+
+/// The transactions in `client`'s history that changed its total.
+fn entries(client: &Client, as_of: u64) -> Vec<Entry<'_>> {
+    let mut deposits = HashMap::new();
+    let mut entries = Vec::new();
+    for HistoryEntry {
+        transaction,
+        time,
+        memo,
+    } in client.history()
+    {
+        let time = time.unwrap_or(as_of);
+        let (kind, transaction_id, currency, amount) = match transaction.data {
+            TransactionData::Deposit {
+                transaction_id,
+                amount,
+            } => {
+                deposits.insert(transaction_id, (transaction.currency, amount));
+                (Kind::Deposit, transaction_id, transaction.currency, amount)
+            }
+            TransactionData::Withdrawal {
+                transaction_id,
+                amount,
+            } => (
+                Kind::Withdrawal,
+                transaction_id,
+                transaction.currency,
+                amount,
+            ),
+            TransactionData::Chargeback { transaction_id } => {
+                let Some(&(currency, amount)) = deposits.get(&transaction_id) else {
+                    continue;
+                };
+                (Kind::Chargeback, transaction_id, currency, amount)
+            }
+            TransactionData::Dispute { .. } | TransactionData::Resolve { .. } => continue,
+        };
+        entries.push(Entry {
+            kind,
+            transaction_id,
+            currency,
+            amount,
+            time,
+            memo: memo.as_deref(),
+        });
+    }
+    entries
+}
+
+/// `text` with the characters that are special in OFX's SGML escaped.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// A time in seconds since the Unix epoch in OFX's format, in UTC.
+fn date_time(timestamp: u64) -> String {
+    let time = i64::try_from(timestamp)
+        .ok()
+        .and_then(|timestamp| time::OffsetDateTime::from_unix_timestamp(timestamp).ok())
+        .unwrap_or(time::OffsetDateTime::UNIX_EPOCH);
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        time.year(),
+        u8::from(time.month()),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::Clients;
+    use crate::transaction::parse_record;
+
+    #[test]
+    fn test_statement() {
+        let mut clients = Clients::new();
+        clients.set_keep_history();
+        for (i, record) in [
+            "deposit,1,1,10.0,GBP",
+            "deposit,1,2,4.0,GBP",
+            "withdrawal,1,3,1.5,GBP",
+            "dispute,1,2,,GBP",
+            "chargeback,1,2,,GBP",
+            "deposit,2,4,1.0,GBP",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            clients.set_time(1_714_521_600 + 3600 * i as u64);
+            if record.starts_with("withdrawal") {
+                clients.set_memo("Rent <May>");
+            }
+            clients
+                .process_transaction(parse_record(record).unwrap())
+                .unwrap();
+        }
+        let client = clients.get(1.into()).unwrap().unwrap();
+        let mut output = Vec::new();
+        write_statement(&mut output, 1.into(), &client, 1_714_608_000).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        let transactions: Vec<_> = output
+            .lines()
+            .filter(|line| line.starts_with("<STMTTRN>"))
+            .collect();
+        assert_eq!(
+            transactions,
+            vec![
+                "<STMTTRN><TRNTYPE>CREDIT</TRNTYPE><DTPOSTED>20240501000000</DTPOSTED><TRNAMT>10.0000</TRNAMT><FITID>1</FITID><NAME>Deposit</NAME></STMTTRN>",
+                "<STMTTRN><TRNTYPE>CREDIT</TRNTYPE><DTPOSTED>20240501010000</DTPOSTED><TRNAMT>4.0000</TRNAMT><FITID>2</FITID><NAME>Deposit</NAME></STMTTRN>",
+                "<STMTTRN><TRNTYPE>DEBIT</TRNTYPE><DTPOSTED>20240501020000</DTPOSTED><TRNAMT>-1.5000</TRNAMT><FITID>3</FITID><NAME>Withdrawal</NAME><MEMO>Rent &lt;May&gt;</MEMO></STMTTRN>",
+                "<STMTTRN><TRNTYPE>DEBIT</TRNTYPE><DTPOSTED>20240501040000</DTPOSTED><TRNAMT>-4.0000</TRNAMT><FITID>2-chargeback</FITID><NAME>Chargeback</NAME></STMTTRN>",
+            ]
+        );
+        assert!(output.contains("<CURDEF>GBP</CURDEF>"));
+        assert!(output.contains("<ACCTID>1</ACCTID>"));
+        assert!(output.contains("<DTSTART>20240501000000</DTSTART><DTEND>20240501040000</DTEND>"));
+        assert!(
+            output.contains("<LEDGERBAL><BALAMT>8.5000</BALAMT><DTASOF>20240502000000</DTASOF>")
+        );
+        assert!(output.ends_with("</BANKMSGSRSV1>\n</OFX>\n"));
+    }
+}
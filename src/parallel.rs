@@ -0,0 +1,149 @@
+//! Processing transactions on several threads at once.
+//!
+//! Clients' accounts are independent of each other, so each thread handles a
+//! disjoint set of clients, in input order. The result is exactly the same as
+//! processing the transactions one at a time.
+
+use std::num::NonZeroUsize;
+use std::sync::{mpsc, Arc};
+
+use crate::clients::{Clients, ProcessError};
+use crate::policy::Policy;
+use crate::snapshot::{shard_of, Snapshot, SnapshotError};
+use crate::stats::Stats;
+use crate::store::StoreError;
+use crate::transaction::Transaction;
+
+/// Transactions are sent to the threads in batches of this many, to keep the
+/// cost of sending them low.
+const BATCH_SIZE: usize = 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParallelError {
+    #[error("store error: {0}")]
+    Store(#[from] StoreError),
+    #[error("snapshot error: {0}")]
+    Snapshot(#[from] SnapshotError),
+}
+
+/// Process `transactions`, starting from the state of `clients`, on `threads`
+/// threads. Returns the resulting state, in memory, and counts of the
+/// transactions processed and rejected.
+pub fn process(
+    clients: &Clients,
+    transactions: impl Iterator<Item = Transaction>,
+    threads: NonZeroUsize,
+) -> Result<(Clients, Stats), ParallelError> {
+    let threads = threads.get();
+    let parts = clients.snapshot()?.split(threads);
+    let policy = clients.policy();
+
+    let results = std::thread::scope(|scope| {
+        let mut senders = Vec::new();
+        let mut workers = Vec::new();
+        for part in parts {
+            let (sender, receiver) = mpsc::sync_channel::<Vec<Transaction>>(4);
+            senders.push(sender);
+            let policy = policy.clone();
+            workers.push(scope.spawn(move || work(part, receiver, policy)));
+        }
+
+        let mut batches: Vec<Vec<Transaction>> = (0..threads).map(|_| Vec::new()).collect();
+        for transaction in transactions {
+            let index = shard_of(transaction.client_id, threads);
+            batches[index].push(transaction);
+            if batches[index].len() == BATCH_SIZE {
+                let batch = std::mem::replace(&mut batches[index], Vec::with_capacity(BATCH_SIZE));
+                // If the thread has stopped, joining it reports why.
+                if senders[index].send(batch).is_err() {
+                    break;
+                }
+            }
+        }
+        for (sender, batch) in senders.into_iter().zip(batches) {
+            let _ = sender.send(batch);
+        }
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("worker thread panicked"))
+            .collect::<Vec<_>>()
+    });
+
+    let mut parts = Vec::new();
+    let mut stats = Stats::default();
+    for result in results {
+        let (snapshot, part_stats) = result?;
+        parts.push(snapshot);
+        stats.merge(&part_stats);
+    }
+    let mut processed = Clients::restore(Snapshot::merge(parts))?;
+    processed.set_policy(policy.clone());
+    Ok((processed, stats))
+}
+
+fn work(
+    start: Snapshot,
+    receiver: mpsc::Receiver<Vec<Transaction>>,
+    policy: Arc<dyn Policy>,
+) -> Result<(Snapshot, Stats), ParallelError> {
+    let mut clients = Clients::restore(start)?;
+    clients.set_policy(policy);
+    let mut stats = Stats::default();
+    for batch in receiver {
+        for transaction in batch {
+            match clients.process_transaction(transaction) {
+                Ok(_) => stats.record_processed(Ok(())),
+                Err(ProcessError::Rejected(e)) => stats.record_processed(Err(&e)),
+                Err(ProcessError::Store(e)) => return Err(e.into()),
+                // Only counted once they're applied or rejected.
+                Err(ProcessError::Pending) => {}
+            }
+        }
+    }
+    Ok((clients.snapshot()?, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{generate, GenerateOptions};
+    use crate::transaction::load_transactions;
+
+    #[test]
+    fn test_same_as_sequential() {
+        let mut input = Vec::new();
+        generate(
+            &mut input,
+            &GenerateOptions {
+                clients: 50,
+                rows: 20_000,
+                dispute_rate: 0.02,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let transactions = || load_transactions(input.as_slice()).map(|t| t.unwrap());
+
+        let mut sequential = Clients::new();
+        let mut sequential_stats = Stats::default();
+        for transaction in transactions() {
+            let result = sequential.process_transaction(transaction);
+            sequential_stats.record_processed(match &result {
+                Ok(_) => Ok(()),
+                Err(ProcessError::Rejected(e)) => Err(e),
+                Err(e) => panic!("unexpected error: {}", e),
+            });
+        }
+
+        for threads in [1, 3, 8] {
+            let (parallel, stats) = process(
+                &Clients::new(),
+                transactions(),
+                NonZeroUsize::new(threads).unwrap(),
+            )
+            .unwrap();
+            assert_eq!(parallel.snapshot().unwrap(), sequential.snapshot().unwrap());
+            assert_eq!(stats, sequential_stats);
+        }
+    }
+}
@@ -0,0 +1,336 @@
+//! The rules for which transactions are accepted, and what follows from them,
+//! beyond what the balances themselves allow, so that embedders can supply
+//! their own rule sets without forking the crate; see `Clients::set_policy`.
+//!
+//! By default only deposits can be disputed, neither a withdrawal nor a
+//! dispute can take the available funds below zero, and a chargeback locks
+//! the account, but each of these can be changed.
+
+use crate::client::{Client, ClientError};
+use crate::event::Event;
+use crate::transaction::{ClientId, Id, Transaction, TransactionKind};
+use crate::{Amount, TransactionId};
+
+pub trait Policy<C = ClientId, T = TransactionId>: Send + Sync {
+    /// Check `transaction` before it's applied to `client`, e.g. to limit
+    /// withdrawals. Rejecting it leaves the client unchanged. The default
+    /// accepts anything the balances allow.
+    fn check(
+        &self,
+        client: &Client<C, T>,
+        transaction: &Transaction<C, T>,
+    ) -> Result<(), ClientError> {
+        let _ = (client, transaction);
+        Ok(())
+    }
+
+    /// Whether `transaction` may be applied to `client` while its account is
+    /// locked. By default nothing may.
+    fn allowed_when_locked(&self, client: &Client<C, T>, transaction: &Transaction<C, T>) -> bool {
+        let _ = (client, transaction);
+        false
+    }
+
+    /// How far below zero `transaction`, a withdrawal, may take `client`'s
+    /// available balance. By default not at all.
+    fn overdraft(&self, client: &Client<C, T>, transaction: &Transaction<C, T>) -> Amount {
+        let _ = (client, transaction);
+        Amount::ZERO
+    }
+
+    /// Whether transactions of `kind` can be disputed. By default only
+    /// deposits can. Withdrawals are only kept, so that they can be disputed
+    /// later, if they're disputable when they're made.
+    fn disputable(&self, kind: TransactionKind) -> bool {
+        kind == TransactionKind::Deposit
+    }
+
+    /// Whether disputing a deposit may hold more than the available funds,
+    /// taking the available balance below zero. By default it may not, and
+    /// the dispute is rejected.
+    fn allow_negative_available(&self) -> bool {
+        false
+    }
+
+    /// Whether to lock `client`'s account once `transaction` has been
+    /// accepted, causing `events`. By default a chargeback locks it, until
+    /// it's unlocked by hand.
+    fn lock_after(
+        &self,
+        client: &Client<C, T>,
+        transaction: &Transaction<C, T>,
+        events: &[Event<T>],
+    ) -> bool {
+        let _ = (client, transaction);
+        events
+            .iter()
+            .any(|event| matches!(event, Event::ChargedBack { .. }))
+    }
+}
+
+/// The rules described in the README.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultPolicy;
+
+impl<C: Id, T: Id> Policy<C, T> for DefaultPolicy {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::{Clients, ProcessError};
+    use crate::transaction::{parse_record, TransactionData};
+    use crate::{Amount, SignedAmount};
+    use std::sync::Arc;
+
+    /// Limits withdrawals, and lets a charged-back account keep depositing.
+    struct Lenient;
+
+    impl Policy for Lenient {
+        fn check(&self, _: &Client, transaction: &Transaction) -> Result<(), ClientError> {
+            match transaction.data {
+                TransactionData::Withdrawal { amount, .. }
+                    if amount > Amount::try_from("5").unwrap() =>
+                {
+                    Err(ClientError::Policy("max_withdrawal"))
+                }
+                _ => Ok(()),
+            }
+        }
+
+        fn allowed_when_locked(&self, _: &Client, transaction: &Transaction) -> bool {
+            matches!(transaction.data, TransactionData::Deposit { .. })
+        }
+
+        fn lock_after(&self, _: &Client, _: &Transaction, _: &[Event]) -> bool {
+            false
+        }
+    }
+
+    /// Lets withdrawals, but not deposits, be disputed, even if that takes
+    /// available funds below zero.
+    struct Withdrawals;
+
+    impl Policy for Withdrawals {
+        fn disputable(&self, kind: TransactionKind) -> bool {
+            kind == TransactionKind::Withdrawal
+        }
+
+        fn allow_negative_available(&self) -> bool {
+            true
+        }
+    }
+
+    /// Lets deposits and withdrawals be disputed, even if a deposit's funds
+    /// have been withdrawn, and never locks an account.
+    struct Negative;
+
+    impl Policy for Negative {
+        fn disputable(&self, kind: TransactionKind) -> bool {
+            matches!(kind, TransactionKind::Deposit | TransactionKind::Withdrawal)
+        }
+
+        fn allow_negative_available(&self) -> bool {
+            true
+        }
+
+        fn lock_after(&self, _: &Client, _: &Transaction, _: &[Event]) -> bool {
+            false
+        }
+    }
+
+    fn process(clients: &mut Clients, records: &[&str]) -> Vec<Result<(), String>> {
+        records
+            .iter()
+            .map(|record| {
+                clients
+                    .process_transaction(parse_record(record).unwrap())
+                    .map(|_| ())
+                    .map_err(|e| match e {
+                        ProcessError::Rejected(e) => e.name().to_string(),
+                        e => panic!("unexpected error: {}", e),
+                    })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_policy() {
+        let records = [
+            "deposit,1,1,10.0",
+            "deposit,1,2,10.0",
+            "withdrawal,1,3,6.0",
+            "dispute,1,1",
+            "chargeback,1,1",
+            "deposit,1,4,2.0",
+        ];
+
+        let mut clients = Clients::new();
+        let results = process(&mut clients, &records);
+        assert_eq!(results[2], Ok(()));
+        assert_eq!(results[5], Err("locked".to_string()));
+        assert!(clients.get(1.into()).unwrap().unwrap().locked());
+
+        let mut clients = Clients::new();
+        clients.set_policy(Arc::new(Lenient));
+        let results = process(&mut clients, &records);
+        assert_eq!(results[2], Err("max_withdrawal".to_string()));
+        assert_eq!(results[5], Ok(()));
+        let client = clients.get(1.into()).unwrap().unwrap();
+        assert!(!client.locked());
+        assert_eq!(client.total(None), SignedAmount::try_from("12").unwrap());
+
+        // Anything else is still rejected once the account is locked.
+        let mut client = client.into_owned();
+        client.set_locked(true);
+        let withdrawal = parse_record("withdrawal,1,5,1.0").unwrap();
+        assert_eq!(
+            client.process(&withdrawal, &Lenient),
+            Err(ClientError::Locked)
+        );
+        let deposit = parse_record("deposit,1,6,1.0").unwrap();
+        assert!(client.process(&deposit, &Lenient).is_ok());
+    }
+
+    #[test]
+    fn test_disputable() {
+        let amount = |amount| Amount::try_from(amount).unwrap();
+
+        // By default withdrawals aren't kept, so can't be found to dispute.
+        let mut clients = Clients::new();
+        let results = process(
+            &mut clients,
+            &["deposit,1,1,10.0", "withdrawal,1,2,4.0", "dispute,1,2"],
+        );
+        assert_eq!(results[2], Err("unknown_transaction_id".to_string()));
+
+        let records = [
+            "deposit,1,1,10.0",
+            "withdrawal,1,2,4.0",
+            "dispute,1,1",
+            "dispute,1,2",
+            "resolve,1,2",
+            "dispute,1,2",
+            "chargeback,1,2",
+        ];
+        let mut clients = Clients::new();
+        clients.set_policy(Arc::new(Withdrawals));
+        let results = process(&mut clients, &records);
+        assert_eq!(results[2], Err("not_disputable".to_string()));
+        assert_eq!(results[3..], [Ok(()), Ok(()), Ok(()), Ok(())]);
+        // Charging back the withdrawal makes its funds available again.
+        let client = clients.get(1.into()).unwrap().unwrap();
+        assert_eq!(client.available(None), amount("10").into());
+        assert_eq!(client.held(None), Amount::ZERO);
+        assert!(client.locked());
+
+        let mut clients = Clients::new();
+        clients.set_policy(Arc::new(Withdrawals));
+        process(&mut clients, &records[..4]);
+        let client = clients.get(1.into()).unwrap().unwrap();
+        assert_eq!(client.available(None), amount("6").into());
+        assert_eq!(client.held(None), amount("4"));
+        assert_eq!(client.total(None), amount("10").into());
+        // The dispute survives a snapshot.
+        let restored = Client::restore(client.snapshot(1.into())).unwrap();
+        assert_eq!(
+            restored.find_deposit(2.into()),
+            client.find_deposit(2.into())
+        );
+        assert!(restored.find_deposit(2.into()).unwrap().withdrawal);
+
+        // `Client::dispute` applies the default policy, so the deposit can be
+        // disputed, but only if its funds are available.
+        let mut client = client.into_owned();
+        assert_eq!(
+            client.dispute(1.into(), None),
+            Err(ClientError::InsufficientFunds)
+        );
+        let mut client: Client = Client::new();
+        client.deposit(1.into(), None, amount("10")).unwrap();
+        client.withdraw(2.into(), None, amount("4")).unwrap();
+        assert_eq!(
+            client.dispute(2.into(), None),
+            Err(ClientError::UnknownTransactionId)
+        );
+    }
+
+    #[test]
+    fn test_allow_negative_available() {
+        let balance = |balance| SignedAmount::try_from(balance).unwrap();
+        let records = [
+            "deposit,1,1,10.0",
+            "withdrawal,1,2,8.0",
+            "dispute,1,1",
+            "withdrawal,1,3,1.0",
+            "chargeback,1,1",
+        ];
+
+        let mut clients = Clients::new();
+        let results = process(&mut clients, &records[..3]);
+        assert_eq!(results[2], Err("insufficient_funds".to_string()));
+
+        let mut clients = Clients::new();
+        clients.set_policy(Arc::new(Negative));
+        let results = process(&mut clients, &records);
+        assert_eq!(results[2], Ok(()));
+        // Nothing more can be withdrawn while available is negative.
+        assert_eq!(results[3], Err("insufficient_funds".to_string()));
+        assert_eq!(results[4], Ok(()));
+        let client = clients.get(1.into()).unwrap().unwrap();
+        assert_eq!(client.available(None), balance("-8"));
+        assert_eq!(client.held(None), Amount::ZERO);
+        assert_eq!(client.total(None), balance("-8"));
+
+        // Resolving a disputed withdrawal can take the total below zero too.
+        let mut clients = Clients::new();
+        clients.set_policy(Arc::new(Negative));
+        let records = [
+            "deposit,1,1,10.0",
+            "withdrawal,1,2,9.0",
+            "dispute,1,2",
+            "dispute,1,1",
+            "chargeback,1,1",
+            "resolve,1,2",
+        ];
+        assert!(process(&mut clients, &records).iter().all(Result::is_ok));
+        let client = clients.get(1.into()).unwrap().unwrap();
+        assert_eq!(client.available(None), balance("-9"));
+        assert_eq!(client.held(None), Amount::ZERO);
+        assert_eq!(client.total(None), balance("-9"));
+    }
+
+    #[test]
+    fn test_held_overflow() {
+        // Held funds can't go past the largest amount, even though available
+        // funds can go that far below zero.
+        let max = Amount::MAX.to_string();
+        let records = [
+            format!("deposit,1,1,{}", max),
+            format!("withdrawal,1,2,{}", max),
+            "dispute,1,1".to_string(),
+            format!("deposit,1,3,{}", max),
+            "dispute,1,3".to_string(),
+        ];
+        let records: Vec<_> = records.iter().map(String::as_str).collect();
+        let mut clients = Clients::new();
+        clients.set_policy(Arc::new(Negative));
+        let results = process(&mut clients, &records);
+        assert_eq!(results[3], Ok(()));
+        assert_eq!(results[4], Err("overflow".to_string()));
+        let client = clients.get(1.into()).unwrap().unwrap();
+        assert_eq!(client.held(None), Amount::MAX);
+        assert_eq!(client.available(None), SignedAmount::ZERO);
+        drop(client);
+        // The summary can still be written, with the total at the largest
+        // amount too.
+        let mut summary = Vec::new();
+        clients.write(&mut summary).unwrap();
+        assert_eq!(
+            String::from_utf8(summary).unwrap(),
+            format!(
+                "client,available,held,total,locked\n1,0.0000,{},{},false\n",
+                max, max
+            )
+        );
+    }
+}
@@ -0,0 +1,378 @@
+//! The summary as an Arrow `RecordBatch`, and transactions read from them, so
+//! that results can be handed to DataFusion, pyarrow, and the like without
+//! going through CSV.
+//!
+//! Amounts are `Decimal128` with four decimal places, so they're exact.
+
+use arrow_array::builder::{BooleanBuilder, Decimal128Builder, StringBuilder, UInt16Builder};
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Decimal128Type, UInt16Type, UInt32Type, UInt64Type};
+use arrow_array::{Array, ArrayRef, RecordBatch};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use std::sync::Arc;
+
+use crate::clients::Clients;
+use crate::store::StoreError;
+use crate::transaction::{ClientFilter, ClientId, Transaction, TransactionData, TransactionId};
+use crate::{Amount, Currency};
+
+/// Enough digits for any amount.
+const AMOUNT_PRECISION: u8 = 20;
+const AMOUNT_SCALE: i8 = 4;
+const AMOUNT_TYPE: DataType = DataType::Decimal128(AMOUNT_PRECISION, AMOUNT_SCALE);
+
+#[derive(Debug, thiserror::Error)]
+pub enum BatchError {
+    #[error("arrow error: {0}")]
+    Arrow(#[from] ArrowError),
+    #[error("store error: {0}")]
+    Store(#[from] StoreError),
+    #[error("missing column '{0}'")]
+    MissingColumn(&'static str),
+    #[error("column '{column}' should be {expected}, not {found}")]
+    ColumnType {
+        column: &'static str,
+        expected: &'static str,
+        found: DataType,
+    },
+    #[error("row {row}: missing {column}")]
+    MissingValue { row: usize, column: &'static str },
+    #[error("row {row}: unknown transaction type '{found}'")]
+    UnknownType { row: usize, found: String },
+    #[error("row {row}: transaction ID {found} is too large; see the wide-tx-ids feature")]
+    TransactionIdTooLarge { row: usize, found: u64 },
+    #[error("row {row}: invalid amount")]
+    InvalidAmount { row: usize },
+    #[error("row {row}: invalid currency '{found}'")]
+    InvalidCurrency { row: usize, found: String },
+}
+
+/// The schema of `summary_batch`. Unlike the CSV summary, it always has a
+/// currency column, null for transactions without one, so that it doesn't
+/// depend on the input.
+pub fn summary_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("client", DataType::UInt16, false),
+        Field::new("currency", DataType::Utf8, true),
+        Field::new("available", AMOUNT_TYPE, false),
+        Field::new("held", AMOUNT_TYPE, false),
+        Field::new("total", AMOUNT_TYPE, false),
+        Field::new("locked", DataType::Boolean, false),
+    ]))
+}
+
+/// The rows of the summary for `filter` as a single batch.
+pub fn summary_batch(clients: &Clients, filter: &ClientFilter) -> Result<RecordBatch, BatchError> {
+    let rows = clients.summary_rows(filter)?;
+    let mut client = UInt16Builder::with_capacity(rows.len());
+    let mut currency = StringBuilder::new();
+    let amounts = || {
+        Decimal128Builder::with_capacity(rows.len())
+            .with_precision_and_scale(AMOUNT_PRECISION, AMOUNT_SCALE)
+    };
+    let (mut available, mut held, mut total) = (amounts()?, amounts()?, amounts()?);
+    let mut locked = BooleanBuilder::with_capacity(rows.len());
+    for row in &rows {
+        client.append_value(row.client.into());
+        currency.append_option(row.currency.map(|c| c.to_string()));
+        available.append_value(row.available.to_minor_units());
+        held.append_value(row.held.to_minor_units().into());
+        total.append_value(row.total.to_minor_units());
+        locked.append_value(row.locked);
+    }
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(client.finish()),
+        Arc::new(currency.finish()),
+        Arc::new(available.finish()),
+        Arc::new(held.finish()),
+        Arc::new(total.finish()),
+        Arc::new(locked.finish()),
+    ];
+    Ok(RecordBatch::try_new(summary_schema(), columns)?)
+}
+
+/// Read transactions from a batch with the same columns as the CSV input:
+/// `type` (Utf8), `client` (UInt16), `tx` (UInt32 or UInt64), `amount` (Decimal128
+/// with at most four decimal places, or Utf8 as in the CSV), and optionally
+/// `currency` (Utf8). Columns of the wrong type are reported up front, and
+/// invalid rows one at a time, so they can be skipped like invalid records.
+pub fn read_transactions(
+    batch: &RecordBatch,
+) -> Result<impl Iterator<Item = Result<Transaction, BatchError>> + '_, BatchError> {
+    let column = |name: &'static str| {
+        batch
+            .column_by_name(name)
+            .ok_or(BatchError::MissingColumn(name))
+    };
+    // The column `name`, checking that it has type `expected`.
+    let typed = |name: &'static str, expected: DataType| {
+        let column = column(name)?;
+        if column.data_type() != &expected {
+            return Err(BatchError::ColumnType {
+                column: name,
+                expected: match expected {
+                    DataType::UInt16 => "UInt16",
+                    DataType::UInt32 => "UInt32",
+                    _ => "Utf8",
+                },
+                found: column.data_type().clone(),
+            });
+        }
+        Ok(column)
+    };
+
+    let types = typed("type", DataType::Utf8)?.as_string::<i32>();
+    let clients = typed("client", DataType::UInt16)?.as_primitive::<UInt16Type>();
+    let ids = column("tx")?;
+    let ids = match ids.data_type() {
+        DataType::UInt32 => Ids::Narrow(ids.as_primitive::<UInt32Type>()),
+        DataType::UInt64 => Ids::Wide(ids.as_primitive::<UInt64Type>()),
+        found => {
+            return Err(BatchError::ColumnType {
+                column: "tx",
+                expected: "UInt32 or UInt64",
+                found: found.clone(),
+            })
+        }
+    };
+    let amounts = column("amount")?;
+    let amounts = match amounts.data_type() {
+        DataType::Decimal128(_, scale) if (0..=AMOUNT_SCALE).contains(scale) => {
+            Amounts::Decimal(amounts.as_primitive::<Decimal128Type>(), *scale)
+        }
+        DataType::Utf8 => Amounts::String(amounts.as_string::<i32>()),
+        found => {
+            return Err(BatchError::ColumnType {
+                column: "amount",
+                expected: "Decimal128 with a scale of at most 4, or Utf8",
+                found: found.clone(),
+            })
+        }
+    };
+    let currencies = match batch.column_by_name("currency") {
+        Some(_) => Some(typed("currency", DataType::Utf8)?.as_string::<i32>()),
+        None => None,
+    };
+
+    Ok((0..batch.num_rows()).map(move |row| {
+        let missing = |column| BatchError::MissingValue { row, column };
+        if types.is_null(row) {
+            return Err(missing("type"));
+        }
+        if clients.is_null(row) {
+            return Err(missing("client"));
+        }
+        let id = ids.get(row).ok_or(missing("tx"))?;
+        let transaction_id = TransactionId::from_u64(id)
+            .ok_or(BatchError::TransactionIdTooLarge { row, found: id })?;
+        let amount = || amounts.get(row)?.ok_or(missing("amount"));
+        let data = match types.value(row) {
+            "deposit" => TransactionData::Deposit {
+                transaction_id,
+                amount: amount()?,
+            },
+            "withdrawal" => TransactionData::Withdrawal {
+                transaction_id,
+                amount: amount()?,
+            },
+            "dispute" => TransactionData::Dispute { transaction_id },
+            "resolve" => TransactionData::Resolve { transaction_id },
+            "chargeback" => TransactionData::Chargeback { transaction_id },
+            found => {
+                return Err(BatchError::UnknownType {
+                    row,
+                    found: found.to_string(),
+                })
+            }
+        };
+        let currency = match currencies.filter(|c| c.is_valid(row)).map(|c| c.value(row)) {
+            None | Some("") => None,
+            Some(code) => {
+                Some(
+                    Currency::try_from(code).map_err(|_| BatchError::InvalidCurrency {
+                        row,
+                        found: code.to_string(),
+                    })?,
+                )
+            }
+        };
+        Ok(Transaction {
+            client_id: ClientId::from(clients.value(row)),
+            currency,
+            data,
+        })
+    }))
+}
+
+enum Ids<'a> {
+    Narrow(&'a arrow_array::UInt32Array),
+    Wide(&'a arrow_array::UInt64Array),
+}
+
+impl Ids<'_> {
+    /// The ID in `row`, if there is one.
+    fn get(&self, row: usize) -> Option<u64> {
+        match self {
+            Ids::Narrow(ids) => ids.is_valid(row).then(|| ids.value(row).into()),
+            Ids::Wide(ids) => ids.is_valid(row).then(|| ids.value(row)),
+        }
+    }
+}
+
+enum Amounts<'a> {
+    Decimal(&'a arrow_array::Decimal128Array, i8),
+    String(&'a arrow_array::StringArray),
+}
+
+impl Amounts<'_> {
+    /// The amount in `row`, if there is one.
+    fn get(&self, row: usize) -> Result<Option<Amount>, BatchError> {
+        let invalid = || BatchError::InvalidAmount { row };
+        if !self.is_valid(row) {
+            return Ok(None);
+        }
+        match self {
+            Amounts::Decimal(amounts, scale) => {
+                let factor = 10i128.pow((AMOUNT_SCALE - scale) as u32);
+                let minor_units = amounts
+                    .value(row)
+                    .checked_mul(factor)
+                    .and_then(|units| u64::try_from(units).ok())
+                    .ok_or_else(invalid)?;
+                Ok(Some(Amount::from_minor_units(minor_units)))
+            }
+            Amounts::String(amounts) => match amounts.value(row) {
+                "" => Ok(None),
+                amount => Amount::try_from(amount).map(Some).map_err(|_| invalid()),
+            },
+        }
+    }
+
+    fn is_valid(&self, row: usize) -> bool {
+        match self {
+            Amounts::Decimal(amounts, _) => amounts.is_valid(row),
+            Amounts::String(amounts) => amounts.is_valid(row),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{Decimal128Array, StringArray, UInt16Array, UInt32Array};
+
+    fn transactions(amounts: ArrayRef) -> RecordBatch {
+        RecordBatch::try_from_iter(vec![
+            (
+                "type",
+                Arc::new(StringArray::from(vec![
+                    "deposit",
+                    "deposit",
+                    "withdrawal",
+                    "dispute",
+                    "deposit",
+                    "refund",
+                ])) as ArrayRef,
+            ),
+            (
+                "client",
+                Arc::new(UInt16Array::from(vec![1, 2, 1, 2, 3, 1])),
+            ),
+            ("tx", Arc::new(UInt32Array::from(vec![1, 2, 3, 2, 4, 5]))),
+            ("amount", amounts),
+            (
+                "currency",
+                Arc::new(StringArray::from(vec![
+                    None,
+                    Some("GBP"),
+                    Some(""),
+                    None,
+                    Some("gbp"),
+                    None,
+                ])),
+            ),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let decimals =
+            Decimal128Array::from(vec![Some(105), Some(20), Some(5), None, Some(1), None])
+                .with_precision_and_scale(10, 2)
+                .unwrap();
+        let strings = StringArray::from(vec![
+            Some("1.05"),
+            Some("0.2"),
+            Some("0.05"),
+            Some(""),
+            Some("0.01"),
+            None,
+        ]);
+        for amounts in [Arc::new(decimals) as ArrayRef, Arc::new(strings)] {
+            let batch = transactions(amounts);
+            let mut clients = Clients::new();
+            let mut errors = Vec::new();
+            for transaction in read_transactions(&batch).unwrap() {
+                match transaction {
+                    Ok(transaction) => {
+                        clients.process_transaction(transaction).unwrap();
+                    }
+                    Err(e) => errors.push(e.to_string()),
+                }
+            }
+            assert_eq!(
+                errors,
+                vec![
+                    "row 4: invalid currency 'gbp'",
+                    "row 5: unknown transaction type 'refund'"
+                ]
+            );
+
+            let summary = summary_batch(&clients, &ClientFilter::default()).unwrap();
+            assert_eq!(summary.schema(), summary_schema());
+            assert_eq!(summary.num_rows(), 2);
+            let column = |name| summary.column_by_name(name).unwrap().clone();
+            assert_eq!(
+                column("client").as_primitive::<UInt16Type>().values(),
+                &[1, 2]
+            );
+            assert_eq!(column("currency").as_string::<i32>().value(1), "GBP");
+            assert!(column("currency").is_null(0));
+            assert_eq!(
+                column("available")
+                    .as_primitive::<Decimal128Type>()
+                    .values(),
+                &[10000, 0]
+            );
+            assert_eq!(
+                column("held").as_primitive::<Decimal128Type>().values(),
+                &[0, 2000]
+            );
+        }
+    }
+
+    #[test]
+    fn test_schema_errors() {
+        let batch = RecordBatch::try_from_iter(vec![(
+            "type",
+            Arc::new(StringArray::from(vec!["deposit"])) as ArrayRef,
+        )])
+        .unwrap();
+        assert!(matches!(
+            read_transactions(&batch),
+            Err(BatchError::MissingColumn("client"))
+        ));
+
+        let amounts = Decimal128Array::from(vec![1; 6])
+            .with_precision_and_scale(10, 6)
+            .unwrap();
+        assert!(matches!(
+            read_transactions(&transactions(Arc::new(amounts))),
+            Err(BatchError::ColumnType {
+                column: "amount",
+                ..
+            })
+        ));
+    }
+}
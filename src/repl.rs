@@ -0,0 +1,185 @@
+//! An interactive prompt for applying transactions one at a time and
+//! inspecting the result, e.g. to explore how disputes behave or to reproduce
+//! a bug report.
+
+use std::io::{BufRead, Write};
+
+use crate::clients::{Clients, ProcessError, WriteError};
+use crate::snapshot::{Snapshot, SnapshotError};
+use crate::store::StoreError;
+use crate::transaction::{parse_record, ClientId};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReplError {
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("store error: {0}")]
+    Store(#[from] StoreError),
+    #[error("snapshot error: {0}")]
+    Snapshot(#[from] SnapshotError),
+    #[error("write error: {0}")]
+    Write(#[from] WriteError),
+}
+
+const HELP: &str = "\
+<type> <client> <tx> [amount] [currency]
+                 apply a transaction, e.g. 'deposit 7 1 10.0' or 'dispute 7 1'
+show <client>    show a client's balances and disputed deposits
+undo             undo the last accepted transaction
+dump             write the summary of every client
+help             show this message
+quit             exit (as does end of input)
+";
+
+/// Read commands from `input` until it ends, writing the results to `output`.
+/// If `prompt`, write a prompt before each command.
+pub fn run(
+    clients: &mut Clients,
+    input: impl BufRead,
+    mut output: impl Write,
+    prompt: bool,
+) -> Result<(), ReplError> {
+    // The state before each accepted transaction, most recent last.
+    let mut history: Vec<Snapshot> = Vec::new();
+    let mut lines = input.lines();
+    loop {
+        if prompt {
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+        let Some(line) = lines.next() else {
+            break;
+        };
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => {}
+            ["quit" | "exit"] => break,
+            ["help"] => write!(output, "{}", HELP)?,
+            ["dump"] => clients.write(&mut output)?,
+            ["undo"] => match history.pop() {
+                Some(snapshot) => {
+                    *clients = Clients::restore(snapshot)?;
+                    writeln!(output, "undone")?;
+                }
+                None => writeln!(output, "nothing to undo")?,
+            },
+            ["show", id] => match id.parse::<u16>() {
+                Ok(id) => show(clients, ClientId::from(id), &mut output)?,
+                Err(_) => writeln!(output, "invalid client ID '{}'", id)?,
+            },
+            words if !(3..=5).contains(&words.len()) => {
+                writeln!(output, "unrecognised command (try 'help')")?
+            }
+            _ => {
+                // Parse the words as a CSV record, so that transactions are
+                // read exactly as from a file.
+                let transaction = match parse_record(&words.join(",")) {
+                    Ok(transaction) => transaction,
+                    Err(e) => {
+                        writeln!(output, "invalid transaction: {} (try 'help')", e)?;
+                        continue;
+                    }
+                };
+                let before = clients.snapshot()?;
+                match clients.process_transaction(transaction) {
+                    Ok(_) => {
+                        history.push(before);
+                        writeln!(output, "ok")?;
+                    }
+                    Err(ProcessError::Rejected(e)) => writeln!(output, "rejected: {}", e)?,
+                    Err(e @ ProcessError::Pending) => writeln!(output, "{}", e)?,
+                    Err(ProcessError::Store(e)) => return Err(e.into()),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write `id`'s balances and disputed deposits to `output`, as the `show`
+/// command does.
+pub fn show(clients: &Clients, id: ClientId, mut output: impl Write) -> Result<(), ReplError> {
+    let Some(client) = clients.get(id)? else {
+        writeln!(output, "no client {}", id)?;
+        return Ok(());
+    };
+    let mut currencies: Vec<_> = client.currencies().collect();
+    if currencies.is_empty() {
+        currencies.push(None);
+    }
+    for currency in currencies {
+        writeln!(
+            output,
+            "client {}{}: available {}, held {}, total {}",
+            id,
+            currency.map(|c| format!(" {}", c)).unwrap_or_default(),
+            client.available(currency),
+            client.held(currency),
+            client.total(currency),
+        )?;
+    }
+    if client.locked() {
+        writeln!(output, "locked")?;
+    }
+    for deposit in client.snapshot(id).deposits {
+        if deposit.disputed {
+            writeln!(
+                output,
+                "disputed: tx {}, {}",
+                deposit.transaction_id, deposit.amount
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(input: &str) -> String {
+        let mut output = Vec::new();
+        run(&mut Clients::new(), input.as_bytes(), &mut output, false).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_session() {
+        assert_eq!(
+            session(
+                "deposit 7 1 10.0
+deposit 7 2 5
+withdrawal 7 3 20.0
+dispute 7 1
+show 7
+undo
+show 7
+dump
+deposit 7
+deposit 7 4 abc
+show 8
+"
+            ),
+            "ok
+ok
+rejected: insufficient funds
+ok
+client 7: available 5.0000, held 10.0000, total 15.0000
+disputed: tx 1, 10.0000
+undone
+client 7: available 15.0000, held 0.0000, total 15.0000
+client,available,held,total,locked
+7,15.0000,0.0000,15.0000,false
+unrecognised command (try 'help')
+invalid transaction: invalid amount: invalid format (try 'help')
+no client 8
+"
+        );
+    }
+
+    #[test]
+    fn test_undo_with_empty_history() {
+        assert_eq!(session("undo\n"), "nothing to undo\n");
+    }
+}
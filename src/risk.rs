@@ -0,0 +1,318 @@
+//! Locking an account as soon as its disputes look risky, rather than waiting
+//! for a chargeback, which is often too late: when too many disputes are
+//! opened, or too much of what was deposited is disputed, within the client's
+//! most recent transactions. Each such lock raises an alert.
+//!
+//! The windows are saved in snapshots, so that a client's recent disputes
+//! still count after a restart.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::num::NonZeroUsize;
+
+use crate::client::Client;
+use crate::event::Event;
+use crate::transaction::{ClientId, Id};
+use crate::{Amount, Currency, TransactionId};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RiskLimits {
+    /// How many of each client's most recent accepted transactions to look
+    /// at.
+    pub window: NonZeroUsize,
+    /// Lock once more disputes than this have been opened within the window.
+    pub max_disputes: Option<u64>,
+    /// Lock once the amount disputed within the window is more than this
+    /// share of the amount deposited within it, in the same currency.
+    pub max_disputed_ratio: Option<f64>,
+}
+
+/// A lock caused by a dispute that took a client over one of the limits.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct RiskAlert<C = ClientId, T = TransactionId> {
+    pub client: C,
+    /// The dispute.
+    pub transaction_id: T,
+    /// The limit exceeded: `max_disputes` or `max_disputed_ratio`.
+    pub rule: &'static str,
+    /// Disputes opened within the window.
+    pub disputes: u64,
+    /// The amounts disputed and deposited within the window, in the currency
+    /// of the dispute.
+    pub disputed: Amount,
+    pub deposited: Amount,
+}
+
+/// Write `alerts` to `writer` as JSON lines, e.g. for `--alerts-out`.
+pub fn write_alerts<C: Serialize, T: Serialize>(
+    mut writer: impl std::io::Write,
+    alerts: &[RiskAlert<C, T>],
+) -> std::io::Result<()> {
+    for alert in alerts {
+        serde_json::to_writer(&mut writer, alert)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// What a transaction contributed to its client's window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    #[serde(default, skip_serializing_if = "Amount::is_zero")]
+    pub deposited: Amount,
+    /// The amount of the deposit disputed, if it opened a dispute.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disputed: Option<Amount>,
+}
+
+pub(crate) struct Risk<C, T> {
+    limits: Option<RiskLimits>,
+    // Each client's most recent transactions, oldest first. Only recorded
+    // while there are limits, but kept if they're changed.
+    windows: HashMap<C, VecDeque<WindowEntry>>,
+    // Raised since they were last taken.
+    alerts: Vec<RiskAlert<C, T>>,
+}
+
+impl<C, T> Default for Risk<C, T> {
+    fn default() -> Self {
+        Self {
+            limits: None,
+            windows: HashMap::new(),
+            alerts: Vec::new(),
+        }
+    }
+}
+
+impl<C: Id, T: Id> Risk<C, T> {
+    pub(crate) fn limits(&self) -> Option<RiskLimits> {
+        self.limits
+    }
+
+    pub(crate) fn set_limits(&mut self, limits: Option<RiskLimits>) {
+        self.limits = limits;
+    }
+
+    /// Each client's window, oldest first, sorted by client.
+    pub(crate) fn windows(&self) -> Vec<(C, Vec<WindowEntry>)> {
+        let mut windows: Vec<_> = self
+            .windows
+            .iter()
+            .map(|(&id, window)| (id, window.iter().copied().collect()))
+            .collect();
+        windows.sort_by_key(|&(id, _)| id);
+        windows
+    }
+
+    pub(crate) fn set_window(&mut self, id: C, window: Vec<WindowEntry>) {
+        self.windows.insert(id, window.into());
+    }
+
+    /// Forget `id`'s window, e.g. once the client's been removed. Alerts
+    /// already raised are still taken as usual.
+    pub(crate) fn remove(&mut self, id: C) {
+        self.windows.remove(&id);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.windows.clear();
+    }
+
+    /// Record a transaction accepted for `id`, causing `events` and leaving
+    /// it as `client`. Returns the alert if it opened a dispute that took the
+    /// client over a limit, also keeping it to be taken with `take_alerts`.
+    pub(crate) fn record(
+        &mut self,
+        id: C,
+        client: &Client<C, T>,
+        events: &[Event<T>],
+    ) -> Option<RiskAlert<C, T>> {
+        let limits = self.limits?;
+        let mut entry = WindowEntry::default();
+        let mut dispute = None;
+        for event in events {
+            match *event {
+                Event::FundsDeposited {
+                    currency, amount, ..
+                } => {
+                    entry.currency = currency;
+                    entry.deposited = amount;
+                }
+                Event::DisputeOpened { transaction_id, .. } => {
+                    if let Some(deposit) = client.find_deposit(transaction_id) {
+                        entry.currency = deposit.currency;
+                        entry.disputed = Some(deposit.amount);
+                        dispute = Some(transaction_id);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let window = self.windows.entry(id).or_default();
+        window.push_back(entry);
+        // The window may have been restored under larger limits.
+        while window.len() > limits.window.get() {
+            window.pop_front();
+        }
+
+        let transaction_id = dispute?;
+        let disputes = window.iter().filter(|e| e.disputed.is_some()).count() as u64;
+        let same_currency = window.iter().filter(|e| e.currency == entry.currency);
+        let disputed: Amount = same_currency.clone().filter_map(|e| e.disputed).sum();
+        let deposited: Amount = same_currency.map(|e| e.deposited).sum();
+        let rule = if limits.max_disputes.is_some_and(|max| disputes > max) {
+            "max_disputes"
+        } else if limits.max_disputed_ratio.is_some_and(|max| {
+            disputed.to_minor_units() as f64 > max * deposited.to_minor_units() as f64
+        }) {
+            "max_disputed_ratio"
+        } else {
+            return None;
+        };
+        let alert = RiskAlert {
+            client: id,
+            transaction_id,
+            rule,
+            disputes,
+            disputed,
+            deposited,
+        };
+        self.alerts.push(alert);
+        Some(alert)
+    }
+
+    pub(crate) fn take_alerts(&mut self) -> Vec<RiskAlert<C, T>> {
+        std::mem::take(&mut self.alerts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::{Clients, ProcessError};
+    use crate::transaction::parse_record;
+
+    fn process(clients: &mut Clients, records: &[&str]) -> Vec<Result<(), String>> {
+        records
+            .iter()
+            .map(|record| {
+                clients
+                    .process_transaction(parse_record(record).unwrap())
+                    .map(|_| ())
+                    .map_err(|e| match e {
+                        ProcessError::Rejected(e) => e.name().to_string(),
+                        e => panic!("unexpected error: {}", e),
+                    })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_max_disputes() {
+        let mut clients = Clients::new();
+        clients.set_risk_limits(Some(RiskLimits {
+            window: NonZeroUsize::new(4).unwrap(),
+            max_disputes: Some(1),
+            max_disputed_ratio: None,
+        }));
+        let results = process(
+            &mut clients,
+            &[
+                "deposit,1,1,1.0",
+                "deposit,1,2,1.0",
+                "deposit,1,3,1.0",
+                "dispute,1,1",
+                "resolve,1,1",
+                "deposit,1,4,1.0",
+                "deposit,1,5,1.0",
+                // The first dispute is out of the window by now.
+                "dispute,1,2",
+                "dispute,1,3",
+                "deposit,1,6,1.0",
+            ],
+        );
+        assert!(results[..9].iter().all(Result::is_ok));
+        assert_eq!(results[9], Err("locked".to_string()));
+        assert_eq!(
+            clients.take_alerts(),
+            [RiskAlert {
+                client: 1.into(),
+                transaction_id: 3.into(),
+                rule: "max_disputes",
+                disputes: 2,
+                disputed: Amount::try_from("2").unwrap(),
+                deposited: Amount::try_from("2").unwrap(),
+            }]
+        );
+        assert!(clients.take_alerts().is_empty());
+    }
+
+    #[test]
+    fn test_max_disputed_ratio() {
+        let mut clients = Clients::new();
+        clients.set_risk_limits(Some(RiskLimits {
+            window: NonZeroUsize::new(10).unwrap(),
+            max_disputes: None,
+            max_disputed_ratio: Some(0.5),
+        }));
+        process(
+            &mut clients,
+            &[
+                "deposit,1,1,1.0",
+                "deposit,1,2,3.0",
+                "dispute,1,1",
+                "deposit,2,3,1.0",
+                "dispute,2,3",
+            ],
+        );
+        // A quarter of client 1's deposits are disputed, and all of client 2's.
+        let alerts = clients.take_alerts();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].client, 2.into());
+        assert_eq!(alerts[0].rule, "max_disputed_ratio");
+        assert!(!clients.get(1.into()).unwrap().unwrap().locked());
+        assert!(clients.get(2.into()).unwrap().unwrap().locked());
+    }
+
+    #[test]
+    fn test_windows() {
+        let limits = Some(RiskLimits {
+            window: NonZeroUsize::new(10).unwrap(),
+            max_disputes: Some(1),
+            max_disputed_ratio: None,
+        });
+        let mut clients = Clients::new();
+        clients.set_risk_limits(limits);
+        process(
+            &mut clients,
+            &["deposit,1,1,1.0", "deposit,1,2,1.0", "dispute,1,1"],
+        );
+
+        // The dispute still counts after a restart.
+        let mut restored = Clients::restore(clients.snapshot().unwrap()).unwrap();
+        restored.set_risk_limits(limits);
+        process(&mut restored, &["dispute,1,2"]);
+        assert_eq!(restored.take_alerts().len(), 1);
+
+        // But not once the client's been removed, or all of them cleared.
+        clients.remove(1.into()).unwrap();
+        process(
+            &mut clients,
+            &[
+                "deposit,1,3,1.0",
+                "dispute,1,3",
+                "deposit,2,4,1.0",
+                "dispute,2,4",
+            ],
+        );
+        clients.clear().unwrap();
+        assert!(clients.snapshot().unwrap().risk_windows.is_empty());
+        process(
+            &mut clients,
+            &["deposit,2,5,1.0", "deposit,2,6,1.0", "dispute,2,5"],
+        );
+        assert!(clients.take_alerts().is_empty());
+    }
+}
@@ -0,0 +1,418 @@
+//! Common variations on the rules transactions are accepted by, declared in
+//! the `[rules]` section of the config file rather than written as a `Policy`.
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::sync::Arc;
+
+use crate::client::{Client, ClientError};
+use crate::event::Event;
+use crate::metadata::ClientMetadata;
+use crate::policy::Policy;
+use crate::risk::RiskLimits;
+use crate::transaction::{ClientId, Transaction, TransactionData};
+use crate::Amount;
+
+/// The rules, each of which is the `DefaultPolicy`'s if not given.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rules {
+    /// Reject withdrawals of more than this.
+    pub max_withdrawal: Option<Amount>,
+    /// Lock an account once this many disputes have been opened against it.
+    pub lock_after_disputes: Option<NonZeroU64>,
+    /// Whether a chargeback locks the account.
+    pub lock_on_chargeback: bool,
+    /// How many of each client's most recent transactions the risk limits
+    /// look at; see `RiskLimits`.
+    pub risk_window: NonZeroUsize,
+    /// Lock an account once more disputes than this are opened within the
+    /// window.
+    pub risk_max_disputes: Option<u64>,
+    /// Lock an account once more than this share of the amount deposited
+    /// within the window is disputed within it, e.g. 0.5.
+    pub risk_max_disputed_ratio: Option<f64>,
+    /// Let a dispute hold more than the available funds, taking the
+    /// available balance below zero; see `Policy::allow_negative_available`.
+    pub allow_negative_available: bool,
+    /// Reject deposits and withdrawals of zero. Some feeds use them for
+    /// adjustments, but otherwise they only add deposits that can be
+    /// disputed to no effect.
+    pub reject_zero_amounts: bool,
+    /// Limits for the clients in each tier, by name, as given by the tier
+    /// column of the client metadata; see `set_metadata`. Clients in no tier,
+    /// or in one not listed, only have the limits above.
+    pub tiers: BTreeMap<String, TierLimits>,
+    #[serde(skip)]
+    metadata: Option<Arc<ClientMetadata>>,
+}
+
+/// The limits for the clients in a tier.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TierLimits {
+    /// Reject deposits that would take the total balance over this.
+    pub max_balance: Option<Amount>,
+    /// Reject withdrawals of more than this.
+    pub max_withdrawal: Option<Amount>,
+    /// How far below zero withdrawals may take the available balance.
+    pub overdraft: Amount,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            max_withdrawal: None,
+            lock_after_disputes: None,
+            lock_on_chargeback: true,
+            risk_window: NonZeroUsize::new(100).unwrap(),
+            risk_max_disputes: None,
+            risk_max_disputed_ratio: None,
+            allow_negative_available: false,
+            reject_zero_amounts: false,
+            tiers: BTreeMap::new(),
+            metadata: None,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RulesError {
+    #[error("{0}")]
+    Invalid(#[from] toml::de::Error),
+    #[error("{0} must be a number no less than 0")]
+    Negative(&'static str),
+}
+
+impl Rules {
+    /// Read the rules from a TOML table, e.g. the config file's `[rules]`.
+    /// Keys may use dashes or underscores, as options in the config file do.
+    pub fn from_toml(table: toml::Table) -> Result<Rules, RulesError> {
+        fn normalise(table: toml::Table) -> toml::Table {
+            table
+                .into_iter()
+                .map(|(key, value)| (key.replace('-', "_"), value))
+                .collect()
+        }
+
+        let mut table = normalise(table);
+        // Tier names are kept as they are.
+        if let Some(toml::Value::Table(tiers)) = table.get_mut("tiers") {
+            for (_, limits) in tiers.iter_mut() {
+                if let toml::Value::Table(limits) = limits {
+                    *limits = normalise(std::mem::take(limits));
+                }
+            }
+        }
+        let rules = Rules::deserialize(toml::Value::Table(table))?;
+        if rules
+            .risk_max_disputed_ratio
+            .is_some_and(|ratio| ratio.is_nan() || ratio < 0.0)
+        {
+            return Err(RulesError::Negative("risk_max_disputed_ratio"));
+        }
+        Ok(rules)
+    }
+
+    /// Assign clients to tiers by the tier column of `metadata`.
+    pub fn set_metadata(&mut self, metadata: Arc<ClientMetadata>) {
+        self.metadata = Some(metadata);
+    }
+
+    /// The limits for the tier `client` is in, if it has any.
+    fn tier(&self, client: ClientId) -> Option<&TierLimits> {
+        self.metadata
+            .as_ref()
+            .and_then(|metadata| metadata.tier(client))
+            .and_then(|tier| self.tiers.get(tier))
+    }
+
+    /// The risk limits, which `Clients::set_risk_limits` enforces rather than
+    /// the policy, if there are any.
+    pub fn risk_limits(&self) -> Option<RiskLimits> {
+        (self.risk_max_disputes.is_some() || self.risk_max_disputed_ratio.is_some()).then_some(
+            RiskLimits {
+                window: self.risk_window,
+                max_disputes: self.risk_max_disputes,
+                max_disputed_ratio: self.risk_max_disputed_ratio,
+            },
+        )
+    }
+}
+
+impl Policy for Rules {
+    fn check(&self, client: &Client, transaction: &Transaction) -> Result<(), ClientError> {
+        let tier = self.tier(transaction.client_id);
+        match transaction.data {
+            TransactionData::Deposit { amount, .. }
+            | TransactionData::Withdrawal { amount, .. }
+                if self.reject_zero_amounts && amount == Amount::ZERO =>
+            {
+                Err(ClientError::Policy("zero_amount"))
+            }
+            TransactionData::Withdrawal { amount, .. }
+                if self.max_withdrawal.is_some_and(|max| amount > max) =>
+            {
+                Err(ClientError::Policy("max_withdrawal"))
+            }
+            TransactionData::Withdrawal { amount, .. }
+                if tier
+                    .and_then(|t| t.max_withdrawal)
+                    .is_some_and(|max| amount > max) =>
+            {
+                Err(ClientError::Policy("tier_max_withdrawal"))
+            }
+            TransactionData::Deposit { amount, .. }
+                if tier.and_then(|t| t.max_balance).is_some_and(|max| {
+                    client
+                        .total(transaction.currency)
+                        .checked_add(amount.into())
+                        .is_none_or(|total| total > max.into())
+                }) =>
+            {
+                Err(ClientError::Policy("tier_max_balance"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn overdraft(&self, _: &Client, transaction: &Transaction) -> Amount {
+        self.tier(transaction.client_id)
+            .map_or(Amount::ZERO, |tier| tier.overdraft)
+    }
+
+    fn allow_negative_available(&self) -> bool {
+        self.allow_negative_available
+    }
+
+    fn lock_after(&self, client: &Client, _: &Transaction, events: &[Event]) -> bool {
+        events.iter().any(|event| match event {
+            Event::ChargedBack { .. } => self.lock_on_chargeback,
+            Event::DisputeOpened { .. } => self
+                .lock_after_disputes
+                .is_some_and(|n| client.activity().disputes >= n.get()),
+            _ => false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::Clients;
+    use crate::transaction::parse_record;
+    use crate::SignedAmount;
+    use std::sync::Arc;
+
+    fn rules(config: &str) -> Result<Rules, RulesError> {
+        Rules::from_toml(config.parse().unwrap())
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(rules("").unwrap(), Rules::default());
+        assert_eq!(
+            rules("max_withdrawal = \"10000\"\nlock-after-disputes = 3").unwrap(),
+            Rules {
+                max_withdrawal: Some(Amount::try_from("10000").unwrap()),
+                lock_after_disputes: NonZeroU64::new(3),
+                ..Default::default()
+            }
+        );
+        assert!(matches!(
+            rules("max_withdrawl = \"1\""),
+            Err(RulesError::Invalid(_))
+        ));
+        assert!(
+            rules("allow-negative-available = true")
+                .unwrap()
+                .allow_negative_available
+        );
+        assert!(matches!(
+            rules("risk_max_disputed_ratio = -0.5"),
+            Err(RulesError::Negative("risk_max_disputed_ratio"))
+        ));
+        assert!(matches!(
+            rules("max_withdrawal = \"-5\""),
+            Err(RulesError::Invalid(_))
+        ));
+
+        assert_eq!(rules("risk_window = 10").unwrap().risk_limits(), None);
+        assert_eq!(
+            rules("risk_window = 10\nrisk_max_disputes = 2")
+                .unwrap()
+                .risk_limits(),
+            Some(RiskLimits {
+                window: NonZeroUsize::new(10).unwrap(),
+                max_disputes: Some(2),
+                max_disputed_ratio: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_tiers() {
+        let mut tiered = rules(
+            "[tiers.basic]
+max-balance = \"10\"
+max_withdrawal = \"2\"
+overdraft = \"0\"",
+        )
+        .unwrap();
+        let metadata = ClientMetadata::read("client,tier\n1,basic\n2,gold\n".as_bytes()).unwrap();
+        tiered.set_metadata(Arc::new(metadata));
+        let mut clients = Clients::new();
+        clients.set_policy(Arc::new(tiered));
+        let mut process = |record: &str| {
+            clients
+                .process_transaction(parse_record(record).unwrap())
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        };
+        process("deposit,1,1,8.0").unwrap();
+        assert_eq!(
+            process("deposit,1,2,3.0").unwrap_err(),
+            "rejected by policy: tier_max_balance"
+        );
+        assert_eq!(
+            process("withdrawal,1,3,2.5").unwrap_err(),
+            "rejected by policy: tier_max_withdrawal"
+        );
+        process("withdrawal,1,4,2.0").unwrap();
+        process("deposit,1,5,4.0").unwrap();
+        // Client 2's tier has no limits, and client 3 has no tier.
+        process("deposit,2,6,100.0").unwrap();
+        process("deposit,3,7,100.0").unwrap();
+        process("withdrawal,3,8,50.0").unwrap();
+
+        assert!(matches!(
+            rules("[tiers.basic]\noverdraft = \"-1\""),
+            Err(RulesError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_overdraft() {
+        let withdraw = |amount: &str| {
+            let mut tiered = rules("[tiers.basic]\noverdraft = \"5\"").unwrap();
+            let metadata = ClientMetadata::read("client,tier\n1,basic\n".as_bytes()).unwrap();
+            tiered.set_metadata(Arc::new(metadata));
+            let mut clients = Clients::new();
+            clients.set_policy(Arc::new(tiered));
+            for record in ["deposit,1,1,10.0", "deposit,2,2,10.0"] {
+                clients
+                    .process_transaction(parse_record(record).unwrap())
+                    .unwrap();
+            }
+            let result = |record: String, clients: &mut Clients| {
+                clients
+                    .process_transaction(parse_record(&record).unwrap())
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            };
+            let results = [
+                result(format!("withdrawal,1,3,{}", amount), &mut clients),
+                result(format!("withdrawal,2,4,{}", amount), &mut clients),
+            ];
+            let available = clients.get(1.into()).unwrap().unwrap().available(None);
+            (results, available)
+        };
+        let insufficient = || Err("insufficient funds".to_string());
+
+        // Right up to the limit, but only in the tier with the overdraft.
+        let (results, available) = withdraw("15.0");
+        assert_eq!(results, [Ok(()), insufficient()]);
+        assert_eq!(available, SignedAmount::try_from("-5").unwrap());
+
+        // Just past it.
+        let (results, available) = withdraw("15.0001");
+        assert_eq!(results, [insufficient(), insufficient()]);
+        assert_eq!(available, SignedAmount::try_from("10").unwrap());
+    }
+
+    #[test]
+    fn test_rules() {
+        let mut clients = Clients::new();
+        clients.set_policy(Arc::new(
+            rules("max_withdrawal = \"5\"\nlock_after_disputes = 2\nlock_on_chargeback = false")
+                .unwrap(),
+        ));
+        let mut process = |record: &str| {
+            clients
+                .process_transaction(parse_record(record).unwrap())
+                .map_err(|e| e.to_string())
+        };
+        for record in ["deposit,1,1,10.0", "deposit,1,2,1.0", "deposit,2,3,1.0"] {
+            process(record).unwrap();
+        }
+        assert_eq!(
+            process("withdrawal,1,4,6.0").unwrap_err(),
+            "rejected by policy: max_withdrawal"
+        );
+        process("withdrawal,1,5,5.0").unwrap();
+
+        // A chargeback doesn't lock the account, but a second dispute does.
+        for record in ["dispute,2,3", "chargeback,2,3", "deposit,2,6,1.0"] {
+            process(record).unwrap();
+        }
+        process("dispute,1,2").unwrap();
+        process("resolve,1,2").unwrap();
+        let events = process("dispute,1,2").unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(process("resolve,1,2").unwrap_err(), "account locked");
+    }
+
+    #[test]
+    fn test_allow_negative_available() {
+        let dispute = |config: &str| {
+            let mut clients = Clients::new();
+            clients.set_policy(Arc::new(rules(config).unwrap()));
+            for record in ["deposit,1,1,10.0", "withdrawal,1,2,8.0"] {
+                clients
+                    .process_transaction(parse_record(record).unwrap())
+                    .unwrap();
+            }
+            let result = clients
+                .process_transaction(parse_record("dispute,1,1").unwrap())
+                .map(|_| ())
+                .map_err(|e| e.to_string());
+            let available = clients.get(1.into()).unwrap().unwrap().available(None);
+            (result, available)
+        };
+        assert_eq!(
+            dispute(""),
+            (
+                Err("insufficient funds".to_string()),
+                SignedAmount::try_from("2").unwrap()
+            )
+        );
+        assert_eq!(
+            dispute("allow_negative_available = true"),
+            (Ok(()), SignedAmount::try_from("-8").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_reject_zero_amounts() {
+        let results = |config: &str| -> Vec<Result<(), String>> {
+            let mut clients = Clients::new();
+            clients.set_policy(Arc::new(rules(config).unwrap()));
+            ["deposit,1,1,0.0", "withdrawal,1,2,0", "deposit,1,3,0.0001"]
+                .into_iter()
+                .map(|record| {
+                    clients
+                        .process_transaction(parse_record(record).unwrap())
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                })
+                .collect()
+        };
+        assert_eq!(results(""), [Ok(()), Ok(()), Ok(())]);
+        let rejected = Err("rejected by policy: zero_amount".to_string());
+        assert_eq!(
+            results("reject_zero_amounts = true"),
+            [rejected.clone(), rejected, Ok(())]
+        );
+    }
+}
@@ -0,0 +1,221 @@
+//! Clients that several threads can submit transactions to at once, e.g. for
+//! a server with many connections.
+//!
+//! An `Engine` runs one job at a time, so every request waits for every one
+//! before it. `SharedClients` splits the clients between several engines by
+//! client ID, as `parallel` does, so transactions for clients in different
+//! shards are processed at the same time. A client's transactions are still
+//! processed in the order they're submitted.
+//!
+//! The HTTP, gRPC, and line servers all serve clients through it, whether
+//! there's one shard or several.
+
+use std::num::NonZeroUsize;
+
+use crate::client::Client;
+use crate::clients::{Clients, Listener, ProcessError};
+use crate::engine::{Engine, Stopped};
+use crate::event::Event;
+use crate::snapshot::{shard_of, Snapshot, SnapshotError};
+use crate::store::StoreError;
+use crate::transaction::{ClientId, Transaction};
+
+/// A handle to the shards' engines, which stop once every clone of it, and
+/// of any engine it was made from, is dropped.
+#[derive(Clone)]
+pub struct SharedClients {
+    shards: Vec<Engine>,
+}
+
+/// Why a call to the shared clients failed.
+#[derive(Debug, thiserror::Error)]
+pub enum SharedError {
+    /// Only from `process_transaction`, including its store errors.
+    #[error(transparent)]
+    Process(#[from] ProcessError),
+    #[error("store error: {0}")]
+    Store(#[from] StoreError),
+    /// The shards' snapshots couldn't be combined; see `read_all`.
+    #[error(transparent)]
+    Snapshot(#[from] SnapshotError),
+    #[error(transparent)]
+    Stopped(#[from] Stopped),
+}
+
+impl SharedClients {
+    /// Start `shards` engines, starting from `snapshot` or from scratch.
+    pub fn start(snapshot: Option<Snapshot>, shards: NonZeroUsize) -> Result<Self, SnapshotError> {
+        Self::spawn(None, snapshot, shards)
+    }
+
+    /// As `start`, but for one tenant of several; see `Engine::start_tenant`.
+    pub fn start_tenant(
+        tenant: &str,
+        snapshot: Option<Snapshot>,
+        shards: NonZeroUsize,
+    ) -> Result<Self, SnapshotError> {
+        Self::spawn(Some(tenant), snapshot, shards)
+    }
+
+    fn spawn(
+        tenant: Option<&str>,
+        snapshot: Option<Snapshot>,
+        shards: NonZeroUsize,
+    ) -> Result<Self, SnapshotError> {
+        let start = |part| match tenant {
+            Some(tenant) => Engine::start_tenant(tenant, part),
+            None => Engine::start(part),
+        };
+        let shards = match snapshot {
+            Some(snapshot) => snapshot
+                .split(shards.get())
+                .into_iter()
+                .map(|part| start(Some(part)))
+                .collect::<Result<_, _>>()?,
+            None => (0..shards.get())
+                .map(|_| start(None))
+                .collect::<Result<_, _>>()?,
+        };
+        Ok(Self { shards })
+    }
+
+    /// How many shards the clients are split between.
+    pub fn shards(&self) -> NonZeroUsize {
+        NonZeroUsize::new(self.shards.len()).expect("there's always a shard")
+    }
+
+    /// Run `job` on the shard with client `id`, without waiting for it.
+    pub fn send(
+        &self,
+        id: ClientId,
+        job: impl FnOnce(&mut Clients) + Send + 'static,
+    ) -> Result<(), Stopped> {
+        self.shard(id).send(job)
+    }
+
+    /// Run `job` on the shard with client `id`, waiting for its result.
+    pub fn call<T: Send + 'static>(
+        &self,
+        id: ClientId,
+        job: impl FnOnce(&mut Clients) -> T + Send + 'static,
+    ) -> Result<T, Stopped> {
+        self.shard(id).call(job)
+    }
+
+    pub fn process_transaction(&self, transaction: Transaction) -> Result<Vec<Event>, SharedError> {
+        Ok(self.call(transaction.client_id, move |clients| {
+            clients.process_transaction(transaction)
+        })??)
+    }
+
+    pub fn get(&self, id: ClientId) -> Result<Option<Client>, SharedError> {
+        Ok(self.call(id, move |clients| {
+            clients.get(id).map(|client| client.map(|c| c.into_owned()))
+        })??)
+    }
+
+    /// Run `job` on every client at once, waiting for its result: on the
+    /// clients themselves if there's only one shard, or else on clients
+    /// restored from a snapshot of every shard, as `snapshot` takes.
+    pub fn read_all<T: Send + 'static>(
+        &self,
+        job: impl FnOnce(&Clients) -> T + Send + 'static,
+    ) -> Result<T, SharedError> {
+        if let [engine] = self.shards.as_slice() {
+            return Ok(engine.call(move |clients| job(clients))?);
+        }
+        Ok(job(&Clients::restore(self.snapshot()?)?))
+    }
+
+    /// Add a listener made by `make` to each shard's clients, so that between
+    /// them they see every transaction.
+    pub fn add_listener(&self, make: impl Fn() -> Listener) -> Result<(), Stopped> {
+        for shard in &self.shards {
+            let listener = make();
+            shard.send(move |clients| clients.add_listener(listener))?;
+        }
+        Ok(())
+    }
+
+    /// A snapshot of every shard's clients. Transactions submitted while it's
+    /// taken may be in some shards' part of it but not others'.
+    pub fn snapshot(&self) -> Result<Snapshot, SharedError> {
+        let mut parts = Vec::new();
+        for shard in &self.shards {
+            parts.push(shard.call(|clients| clients.snapshot())??);
+        }
+        Ok(Snapshot::merge(parts))
+    }
+
+    fn shard(&self, id: ClientId) -> &Engine {
+        &self.shards[shard_of(id, self.shards.len())]
+    }
+}
+
+/// One shard, sharing the engine's clients, e.g. with an admin socket.
+impl From<Engine> for SharedClients {
+    fn from(engine: Engine) -> Self {
+        Self {
+            shards: vec![engine],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::Workload;
+
+    #[test]
+    fn test_same_as_sequential() {
+        let workload = Workload::new(7).clients(50).rows(5000).dispute_rate(0.02);
+        let mut sequential = Clients::new();
+        for transaction in workload.clone() {
+            let _ = sequential.process_transaction(transaction);
+        }
+
+        let shared = SharedClients::start(None, NonZeroUsize::new(4).unwrap()).unwrap();
+        let transactions: Vec<_> = workload.collect();
+        std::thread::scope(|scope| {
+            // Each thread submits a disjoint set of clients' transactions,
+            // in order.
+            for submitter in 0..3 {
+                let shared = shared.clone();
+                let transactions = &transactions;
+                scope.spawn(move || {
+                    for transaction in transactions
+                        .iter()
+                        .filter(|t| u16::from(t.client_id) % 3 == submitter)
+                    {
+                        match shared.process_transaction(transaction.clone()) {
+                            Ok(_) | Err(SharedError::Process(ProcessError::Rejected(_))) => {}
+                            Err(e) => panic!("unexpected error: {}", e),
+                        }
+                    }
+                });
+            }
+        });
+        let snapshot = shared.snapshot().unwrap();
+        assert_eq!(snapshot, sequential.snapshot().unwrap());
+        assert_eq!(
+            shared.get(1.into()).unwrap().map(|c| c.total(None)),
+            sequential.get(1.into()).unwrap().map(|c| c.total(None))
+        );
+        // Reading every client at once sees every shard's.
+        assert_eq!(
+            shared
+                .read_all(|clients| clients.client_ids().unwrap().len())
+                .unwrap(),
+            50
+        );
+
+        let restarted = SharedClients::start(Some(snapshot.clone()), NonZeroUsize::MIN).unwrap();
+        assert_eq!(restarted.snapshot().unwrap(), snapshot);
+        assert_eq!(
+            restarted
+                .read_all(|clients| clients.snapshot().unwrap())
+                .unwrap(),
+            snapshot
+        );
+    }
+}
@@ -0,0 +1,400 @@
+//! Snapshots of the full engine state - balances, locks, and the deposits that
+//! can still be disputed - so that a run can carry on from where a previous
+//! one finished instead of replaying all of history.
+//!
+//! Snapshots are written either as JSON, or in the more compact binary format
+//! in `binary`. Reading detects which.
+
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+
+use crate::client::ClientActivity;
+use crate::risk::WindowEntry;
+use crate::store::StoreError;
+use crate::transaction::{ClientId, RawTransactionId, TransactionKind};
+use crate::{Amount, Currency, SignedAmount, TransactionId};
+
+pub mod binary;
+
+/// The file format to write snapshots in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SnapshotFormat {
+    #[default]
+    Json,
+    /// Versioned and checksummed; see `binary`.
+    Binary,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub multi_currency: bool,
+    // Sorted by client ID, so that the same state always produces the same
+    // snapshot.
+    pub clients: Vec<ClientSnapshot>,
+    // Every transaction processed, if processing is idempotent; see
+    // `Clients::set_idempotent`. Sorted by client, transaction ID, and type.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processed: Option<Vec<ProcessedTransaction>>,
+    // The references of transactions processed, with their clients; see
+    // `Clients::set_reference`. Sorted by client and reference.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub references: Vec<(ClientId, String)>,
+    // Each client's window of recent transactions, if there are risk limits;
+    // see `Clients::set_risk_limits`. Sorted by client.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub risk_windows: Vec<(ClientId, Vec<WindowEntry>)>,
+}
+
+/// A transaction processed, by what identifies it: its client, ID, and type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ProcessedTransaction {
+    pub client_id: ClientId,
+    pub transaction_id: TransactionId,
+    pub kind: TransactionKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientSnapshot {
+    pub id: ClientId,
+    // Locked by a chargeback or by hand; see `Client::set_locked`.
+    pub locked: bool,
+    // Locked by `Client::lock`, for this reason.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock_reason: Option<String>,
+    // How many of each kind of transaction the client has had accepted.
+    // Not part of `hash_into`, which only covers the ledger.
+    #[serde(default, skip_serializing_if = "ClientActivity::is_empty")]
+    pub activity: ClientActivity,
+    pub balances: Vec<BalanceSnapshot>,
+    // Sorted by transaction ID.
+    pub deposits: Vec<DepositSnapshot>,
+    // The IDs of `deposits`, oldest first, if the order they were made in is
+    // tracked; see `Client::expire_deposits`. Not part of `hash_into`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub order: Vec<TransactionId>,
+    // The IDs of the deposits that have been expired, sorted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub expired: Vec<TransactionId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceSnapshot {
+    pub currency: Option<Currency>,
+    pub available: SignedAmount,
+    pub held: Amount,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepositSnapshot<T = TransactionId> {
+    pub transaction_id: T,
+    pub currency: Option<Currency>,
+    pub amount: Amount,
+    pub disputed: bool,
+    // A withdrawal kept so that it can be disputed, rather than a deposit.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub withdrawal: bool,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError {
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("malformed snapshot: {0}")]
+    Malformed(#[from] serde_json::Error),
+    // The snapshot is well-formed but describes a state the engine could never
+    // have been in, e.g. held funds that don't match the disputed deposits.
+    #[error("inconsistent snapshot: {0}")]
+    Inconsistent(String),
+    #[error("store error: {0}")]
+    Store(#[from] StoreError),
+    // Written by a newer release than this one.
+    #[error("unsupported snapshot version {0}")]
+    UnsupportedVersion(u16),
+    // A binary snapshot that's truncated, fails its checksum, or doesn't
+    // decode.
+    #[error("corrupt snapshot: {0}")]
+    Corrupt(String),
+}
+
+impl Snapshot {
+    pub fn write(&self, writer: impl std::io::Write) -> Result<(), SnapshotError> {
+        self.write_as(writer, SnapshotFormat::Json)
+    }
+
+    pub fn write_as(
+        &self,
+        writer: impl std::io::Write,
+        format: SnapshotFormat,
+    ) -> Result<(), SnapshotError> {
+        let mut writer = std::io::BufWriter::new(writer);
+        match format {
+            SnapshotFormat::Json => serde_json::to_writer(&mut writer, self)?,
+            SnapshotFormat::Binary => binary::write(self, &mut writer)?,
+        }
+        Ok(writer.flush()?)
+    }
+
+    /// Read a snapshot in either format.
+    pub fn read(reader: impl std::io::Read) -> Result<Snapshot, SnapshotError> {
+        let mut reader = std::io::BufReader::new(reader);
+        // JSON snapshots always start with `{`, so can't be mistaken for
+        // binary ones.
+        if std::io::BufRead::fill_buf(&mut reader)?.starts_with(binary::MAGIC) {
+            binary::read(reader)
+        } else {
+            Ok(serde_json::from_reader(reader)?)
+        }
+    }
+
+    /// Save the snapshot to `path`, replacing any existing file atomically so
+    /// that a crash part way through never leaves a truncated snapshot behind.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        self.save_as(path, SnapshotFormat::Json)
+    }
+
+    pub fn save_as(
+        &self,
+        path: impl AsRef<Path>,
+        format: SnapshotFormat,
+    ) -> Result<(), SnapshotError> {
+        save_atomically(path.as_ref(), |file| self.write_as(file, format))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Snapshot, SnapshotError> {
+        Snapshot::read(std::fs::File::open(path)?)
+    }
+
+    /// Split the clients into `shards` snapshots, by client ID, as
+    /// `shard_of` assigns them. The processed transactions aren't kept, but
+    /// references and risk windows go with their clients.
+    pub(crate) fn split(self, shards: usize) -> Vec<Snapshot> {
+        let mut parts: Vec<Snapshot> = (0..shards)
+            .map(|_| Snapshot {
+                multi_currency: self.multi_currency,
+                clients: Vec::new(),
+                processed: None,
+                references: Vec::new(),
+                risk_windows: Vec::new(),
+            })
+            .collect();
+        for client in self.clients {
+            parts[shard_of(client.id, shards)].clients.push(client);
+        }
+        for (id, reference) in self.references {
+            parts[shard_of(id, shards)].references.push((id, reference));
+        }
+        for (id, window) in self.risk_windows {
+            parts[shard_of(id, shards)].risk_windows.push((id, window));
+        }
+        parts
+    }
+
+    /// Combine snapshots of disjoint sets of clients, e.g. from `split`.
+    pub(crate) fn merge(parts: impl IntoIterator<Item = Snapshot>) -> Snapshot {
+        let mut merged = Snapshot {
+            multi_currency: false,
+            clients: Vec::new(),
+            processed: None,
+            references: Vec::new(),
+            risk_windows: Vec::new(),
+        };
+        for part in parts {
+            merged.multi_currency |= part.multi_currency;
+            merged.clients.extend(part.clients);
+            merged.references.extend(part.references);
+            merged.risk_windows.extend(part.risk_windows);
+        }
+        merged.clients.sort_by_key(|client| client.id);
+        merged.references.sort();
+        merged.risk_windows.sort_by_key(|&(id, _)| id);
+        merged
+    }
+}
+
+/// Which of `shards` shards the client with ID `id` belongs to.
+pub(crate) fn shard_of(id: ClientId, shards: usize) -> usize {
+    usize::from(u16::from(id)) % shards
+}
+
+impl ClientSnapshot {
+    /// Feed the client's state to `hasher` in a canonical binary form, for
+    /// comparing state between runs. This is independent of the snapshot file
+    /// format, so that it stays stable if that changes. Transaction IDs are
+    /// hashed at this build's width, so hashes only compare between builds
+    /// that agree on the `wide-tx-ids` feature.
+    pub fn hash_into(&self, hasher: &mut blake3::Hasher) {
+        fn currency(hasher: &mut blake3::Hasher, currency: Option<Currency>) {
+            match currency {
+                Some(currency) => hasher.update(&[1]).update(currency.to_string().as_bytes()),
+                None => hasher.update(&[0]),
+            };
+        }
+
+        hasher
+            .update(&u16::from(self.id).to_le_bytes())
+            .update(&[self.locked as u8])
+            .update(&(self.balances.len() as u64).to_le_bytes());
+        for balance in &self.balances {
+            currency(hasher, balance.currency);
+            hasher
+                .update(&balance.available.magnitude().to_minor_units().to_le_bytes())
+                .update(&balance.held.to_minor_units().to_le_bytes());
+            // Only negative balances are marked, so that hashes of snapshots
+            // without any are unchanged.
+            if balance.available.is_negative() {
+                hasher.update(&[1]);
+            }
+        }
+        hasher.update(&(self.deposits.len() as u64).to_le_bytes());
+        for deposit in &self.deposits {
+            hasher.update(&RawTransactionId::from(deposit.transaction_id).to_le_bytes());
+            currency(hasher, deposit.currency);
+            hasher
+                .update(&deposit.amount.to_minor_units().to_le_bytes())
+                .update(&[deposit.disputed as u8]);
+            // Likewise, only withdrawals are marked.
+            if deposit.withdrawal {
+                hasher.update(&[1]);
+            }
+        }
+        // Only hashed if there is one, so that other clients' hashes are the
+        // same as before lock reasons were kept.
+        if let Some(reason) = &self.lock_reason {
+            hasher
+                .update(&[1])
+                .update(&(reason.len() as u64).to_le_bytes())
+                .update(reason.as_bytes());
+        }
+        // Likewise for expired deposits, only hashed if any are known.
+        if !self.expired.is_empty() {
+            hasher.update(&(self.expired.len() as u64).to_le_bytes());
+            for transaction_id in &self.expired {
+                hasher.update(&RawTransactionId::from(*transaction_id).to_le_bytes());
+            }
+        }
+    }
+}
+
+/// Write a file by writing to a temporary file alongside it then renaming, so
+/// that readers only ever see the old or the new contents.
+pub fn save_atomically(
+    path: &Path,
+    write: impl FnOnce(&std::fs::File) -> Result<(), SnapshotError>,
+) -> Result<(), SnapshotError> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let file = std::fs::File::create(&tmp_path)?;
+    write(&file)?;
+    file.sync_all()?;
+    Ok(std::fs::rename(&tmp_path, path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::Clients;
+    use crate::transaction::load_transactions;
+
+    fn load_clients(data: &str) -> Clients {
+        let mut clients = Clients::new();
+        for transaction in load_transactions(data.as_bytes()) {
+            let _ = clients.process_transaction(transaction.unwrap());
+        }
+        clients
+    }
+
+    fn summary(clients: &Clients) -> String {
+        let mut buf = Vec::new();
+        clients.write(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let clients = load_clients(
+            "type, client, tx, amount, currency
+deposit, 1, 1, 1.0,
+deposit, 1, 2, 2.0, USD
+dispute, 1, 2
+deposit, 2, 3, 3.0,
+dispute, 2, 3
+chargeback, 2, 3
+",
+        );
+        let mut buf = Vec::new();
+        clients.snapshot().unwrap().write(&mut buf).unwrap();
+        let restored = Clients::restore(Snapshot::read(buf.as_slice()).unwrap()).unwrap();
+        assert_eq!(restored.snapshot().unwrap(), clients.snapshot().unwrap());
+        assert_eq!(summary(&restored), summary(&clients));
+    }
+
+    #[test]
+    fn test_restored_state_can_be_disputed() {
+        // Deposits from before the snapshot can still be disputed after it.
+        let clients = load_clients("type, client, tx, amount\ndeposit, 1, 1, 1.0\n");
+        let mut restored = Clients::restore(clients.snapshot().unwrap()).unwrap();
+        for transaction in load_transactions("type, client, tx\ndispute, 1, 1\n".as_bytes()) {
+            restored.process_transaction(transaction.unwrap()).unwrap();
+        }
+        assert_eq!(
+            summary(&restored),
+            "client,available,held,total,locked\n1,0.0000,1.0000,1.0000,false\n"
+        );
+    }
+
+    #[test]
+    fn test_inconsistent_held() {
+        let mut snapshot =
+            load_clients("type, client, tx, amount\ndeposit, 1, 1, 1.0\ndispute, 1, 1\n")
+                .snapshot()
+                .unwrap();
+        snapshot.clients[0].deposits[0].disputed = false;
+        assert!(matches!(
+            Clients::restore(snapshot),
+            Err(SnapshotError::Inconsistent(_))
+        ));
+    }
+
+    #[test]
+    fn test_inconsistent_duplicate_client() {
+        let mut snapshot = load_clients("type, client, tx, amount\ndeposit, 1, 1, 1.0\n")
+            .snapshot()
+            .unwrap();
+        let client = ClientSnapshot {
+            id: snapshot.clients[0].id,
+            locked: false,
+            lock_reason: None,
+            activity: ClientActivity::default(),
+            balances: vec![],
+            deposits: vec![],
+            order: Vec::new(),
+            expired: vec![],
+        };
+        snapshot.clients.push(client);
+        assert!(matches!(
+            Clients::restore(snapshot),
+            Err(SnapshotError::Inconsistent(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_detects_format() {
+        let snapshot = load_clients("type, client, tx, amount\ndeposit, 1, 1, 1.0\n")
+            .snapshot()
+            .unwrap();
+        for format in [SnapshotFormat::Json, SnapshotFormat::Binary] {
+            let mut buf = Vec::new();
+            snapshot.write_as(&mut buf, format).unwrap();
+            assert_eq!(Snapshot::read(buf.as_slice()).unwrap(), snapshot);
+        }
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(matches!(
+            Snapshot::read("{\"clients\": 1}".as_bytes()),
+            Err(SnapshotError::Malformed(_))
+        ));
+    }
+}
@@ -0,0 +1,726 @@
+//! Compact binary snapshot format.
+//!
+//! A snapshot file is the magic bytes, the format version, the length of the
+//! payload, the payload itself, and a BLAKE3 checksum of the payload. All
+//! integers are little-endian.
+//!
+//! Snapshots are always written in the latest version, but every earlier
+//! version can still be read, so that snapshots survive upgrades. Changing the
+//! payload means adding a new version and a new `decode_vN`, never changing an
+//! existing one.
+
+use super::{
+    BalanceSnapshot, ClientSnapshot, DepositSnapshot, ProcessedTransaction, Snapshot, SnapshotError,
+};
+use crate::client::{ClientActivity, Seen};
+use crate::risk::WindowEntry;
+use crate::transaction::TransactionKind;
+use crate::{Amount, Currency, SignedAmount, TransactionId};
+
+pub const MAGIC: &[u8; 4] = b"TXSN";
+const VERSION: u16 = 2;
+const CHECKSUM_LEN: usize = blake3::OUT_LEN;
+
+pub fn write(snapshot: &Snapshot, mut writer: impl std::io::Write) -> Result<(), SnapshotError> {
+    let payload = encode(snapshot);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&(payload.len() as u64).to_le_bytes())?;
+    writer.write_all(&payload)?;
+    writer.write_all(blake3::hash(&payload).as_bytes())?;
+    Ok(writer.flush()?)
+}
+
+pub fn read(mut reader: impl std::io::Read) -> Result<Snapshot, SnapshotError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let mut header = Decoder::new(&data, false);
+    if header.bytes(MAGIC.len())? != MAGIC {
+        return Err(corrupt("not a binary snapshot"));
+    }
+    let version = header.u16()?;
+    let len = usize::try_from(header.u64()?).map_err(|_| corrupt("payload too long"))?;
+    let payload = header.bytes(len)?;
+    if header.bytes(CHECKSUM_LEN)? != blake3::hash(payload).as_bytes() {
+        return Err(corrupt("checksum mismatch"));
+    }
+    if !header.bytes.is_empty() {
+        return Err(corrupt("trailing data"));
+    }
+
+    // Transaction IDs are 64-bit from version 2, so that snapshots can be
+    // read by builds with and without the `wide-tx-ids` feature.
+    let mut payload = Decoder::new(payload, version >= 2);
+    let snapshot = match version {
+        1 => decode_v1(&mut payload)?,
+        2 => decode_v2(&mut payload)?,
+        _ => return Err(SnapshotError::UnsupportedVersion(version)),
+    };
+    if !payload.bytes.is_empty() {
+        return Err(corrupt("trailing data in payload"));
+    }
+    Ok(snapshot)
+}
+
+fn corrupt(message: &str) -> SnapshotError {
+    SnapshotError::Corrupt(message.to_string())
+}
+
+fn encode(snapshot: &Snapshot) -> Vec<u8> {
+    fn currency(out: &mut Vec<u8>, currency: Option<Currency>) {
+        match currency {
+            Some(currency) => {
+                out.push(1);
+                out.extend_from_slice(currency.to_string().as_bytes());
+            }
+            None => out.push(0),
+        }
+    }
+
+    fn string(out: &mut Vec<u8>, s: &str) {
+        out.extend_from_slice(&(s.len() as u32).to_le_bytes());
+        out.extend_from_slice(s.as_bytes());
+    }
+
+    fn optional(out: &mut Vec<u8>, value: Option<u64>) {
+        match value {
+            Some(value) => {
+                out.push(1);
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            None => out.push(0),
+        }
+    }
+
+    let mut out = Vec::new();
+    out.push(snapshot.multi_currency as u8);
+    out.extend_from_slice(&(snapshot.clients.len() as u32).to_le_bytes());
+    for client in &snapshot.clients {
+        out.extend_from_slice(&u16::from(client.id).to_le_bytes());
+        out.push(client.locked as u8);
+        match &client.lock_reason {
+            Some(reason) => {
+                out.push(1);
+                string(&mut out, reason);
+            }
+            None => out.push(0),
+        }
+        let activity = &client.activity;
+        for count in [
+            activity.deposits,
+            activity.withdrawals,
+            activity.disputes,
+            activity.resolves,
+            activity.chargebacks,
+        ] {
+            out.extend_from_slice(&count.to_le_bytes());
+        }
+        for seen in [activity.first, activity.last] {
+            match seen {
+                Some(seen) => {
+                    out.push(1);
+                    optional(&mut out, seen.line);
+                    optional(&mut out, seen.timestamp);
+                }
+                None => out.push(0),
+            }
+        }
+        out.extend_from_slice(&(client.balances.len() as u32).to_le_bytes());
+        for balance in &client.balances {
+            currency(&mut out, balance.currency);
+            // The magnitude, after whether it's below zero, so that a balance
+            // has the same range as an amount.
+            out.push(balance.available.is_negative() as u8);
+            out.extend_from_slice(&balance.available.magnitude().to_minor_units().to_le_bytes());
+            out.extend_from_slice(&balance.held.to_minor_units().to_le_bytes());
+        }
+        out.extend_from_slice(&(client.deposits.len() as u32).to_le_bytes());
+        for deposit in &client.deposits {
+            out.extend_from_slice(&u64::from(deposit.transaction_id).to_le_bytes());
+            currency(&mut out, deposit.currency);
+            out.extend_from_slice(&deposit.amount.to_minor_units().to_le_bytes());
+            out.push(deposit.disputed as u8);
+            out.push(deposit.withdrawal as u8);
+        }
+        for transaction_ids in [&client.order, &client.expired] {
+            out.extend_from_slice(&(transaction_ids.len() as u32).to_le_bytes());
+            for transaction_id in transaction_ids {
+                out.extend_from_slice(&u64::from(*transaction_id).to_le_bytes());
+            }
+        }
+    }
+    match &snapshot.processed {
+        Some(processed) => {
+            out.push(1);
+            out.extend_from_slice(&(processed.len() as u32).to_le_bytes());
+            for transaction in processed {
+                out.extend_from_slice(&u16::from(transaction.client_id).to_le_bytes());
+                out.extend_from_slice(&u64::from(transaction.transaction_id).to_le_bytes());
+                out.push(match transaction.kind {
+                    TransactionKind::Deposit => 0,
+                    TransactionKind::Withdrawal => 1,
+                    TransactionKind::Dispute => 2,
+                    TransactionKind::Resolve => 3,
+                    TransactionKind::Chargeback => 4,
+                });
+            }
+        }
+        None => out.push(0),
+    }
+    out.extend_from_slice(&(snapshot.references.len() as u32).to_le_bytes());
+    for (id, reference) in &snapshot.references {
+        out.extend_from_slice(&u16::from(*id).to_le_bytes());
+        string(&mut out, reference);
+    }
+    out.extend_from_slice(&(snapshot.risk_windows.len() as u32).to_le_bytes());
+    for (id, window) in &snapshot.risk_windows {
+        out.extend_from_slice(&u16::from(*id).to_le_bytes());
+        out.extend_from_slice(&(window.len() as u32).to_le_bytes());
+        for entry in window {
+            currency(&mut out, entry.currency);
+            out.extend_from_slice(&entry.deposited.to_minor_units().to_le_bytes());
+            optional(&mut out, entry.disputed.map(Amount::to_minor_units));
+        }
+    }
+    out
+}
+
+fn decode_v1(payload: &mut Decoder) -> Result<Snapshot, SnapshotError> {
+    let multi_currency = payload.bool()?;
+    let mut clients = Vec::new();
+    for _ in 0..payload.u32()? {
+        let id = payload.u16()?.into();
+        let locked = payload.bool()?;
+        let mut balances = Vec::new();
+        for _ in 0..payload.u32()? {
+            balances.push(BalanceSnapshot {
+                currency: payload.currency()?,
+                available: payload.amount()?.into(),
+                held: payload.amount()?,
+            });
+        }
+        let mut deposits = Vec::new();
+        for _ in 0..payload.u32()? {
+            deposits.push(DepositSnapshot {
+                transaction_id: payload.transaction_id()?,
+                currency: payload.currency()?,
+                amount: payload.amount()?,
+                disputed: payload.bool()?,
+                withdrawal: false,
+            });
+        }
+        clients.push(ClientSnapshot {
+            id,
+            locked,
+            lock_reason: None,
+            activity: ClientActivity::default(),
+            balances,
+            deposits,
+            order: Vec::new(),
+            expired: Vec::new(),
+        });
+    }
+    Ok(Snapshot {
+        multi_currency,
+        clients,
+        processed: None,
+        references: Vec::new(),
+        risk_windows: Vec::new(),
+    })
+}
+
+/// Version 1 with each client's lock reason, activity, withdrawals that can
+/// be disputed, negative balances, the order of its deposits and its expired
+/// deposits, followed by the transactions processed, if any were recorded,
+/// the references of transactions processed, and each client's window for
+/// the risk limits.
+fn decode_v2(payload: &mut Decoder) -> Result<Snapshot, SnapshotError> {
+    let multi_currency = payload.bool()?;
+    let mut clients = Vec::new();
+    for _ in 0..payload.u32()? {
+        let id = payload.u16()?.into();
+        let locked = payload.bool()?;
+        let lock_reason = match payload.bool()? {
+            true => Some(payload.string("lock reason")?),
+            false => None,
+        };
+        let mut activity = ClientActivity {
+            deposits: payload.u64()?,
+            withdrawals: payload.u64()?,
+            disputes: payload.u64()?,
+            resolves: payload.u64()?,
+            chargebacks: payload.u64()?,
+            ..Default::default()
+        };
+        for seen in [&mut activity.first, &mut activity.last] {
+            if payload.bool()? {
+                *seen = Some(Seen {
+                    line: payload.optional()?,
+                    timestamp: payload.optional()?,
+                });
+            }
+        }
+        let mut balances = Vec::new();
+        for _ in 0..payload.u32()? {
+            let currency = payload.currency()?;
+            let negative = payload.bool()?;
+            let magnitude = SignedAmount::from(payload.amount()?);
+            balances.push(BalanceSnapshot {
+                currency,
+                available: if negative { -magnitude } else { magnitude },
+                held: payload.amount()?,
+            });
+        }
+        let mut deposits = Vec::new();
+        for _ in 0..payload.u32()? {
+            deposits.push(DepositSnapshot {
+                transaction_id: payload.transaction_id()?,
+                currency: payload.currency()?,
+                amount: payload.amount()?,
+                disputed: payload.bool()?,
+                withdrawal: payload.bool()?,
+            });
+        }
+        let mut order = Vec::new();
+        for _ in 0..payload.u32()? {
+            order.push(payload.transaction_id()?);
+        }
+        let mut expired = Vec::new();
+        for _ in 0..payload.u32()? {
+            expired.push(payload.transaction_id()?);
+        }
+        clients.push(ClientSnapshot {
+            id,
+            locked,
+            lock_reason,
+            activity,
+            balances,
+            deposits,
+            order,
+            expired,
+        });
+    }
+    let processed = match payload.bool()? {
+        true => {
+            let mut processed = Vec::new();
+            for _ in 0..payload.u32()? {
+                let client_id = payload.u16()?.into();
+                let transaction_id = payload.transaction_id()?;
+                let kind = match payload.array::<1>()?[0] {
+                    0 => TransactionKind::Deposit,
+                    1 => TransactionKind::Withdrawal,
+                    2 => TransactionKind::Dispute,
+                    3 => TransactionKind::Resolve,
+                    4 => TransactionKind::Chargeback,
+                    _ => return Err(corrupt("invalid transaction type")),
+                };
+                processed.push(ProcessedTransaction {
+                    client_id,
+                    transaction_id,
+                    kind,
+                });
+            }
+            Some(processed)
+        }
+        false => None,
+    };
+    let mut references = Vec::new();
+    for _ in 0..payload.u32()? {
+        let id = payload.u16()?.into();
+        references.push((id, payload.string("reference")?));
+    }
+    let mut risk_windows = Vec::new();
+    for _ in 0..payload.u32()? {
+        let id = payload.u16()?.into();
+        let mut window = Vec::new();
+        for _ in 0..payload.u32()? {
+            window.push(WindowEntry {
+                currency: payload.currency()?,
+                deposited: payload.amount()?,
+                disputed: payload.optional()?.map(Amount::from_minor_units),
+            });
+        }
+        risk_windows.push((id, window));
+    }
+    Ok(Snapshot {
+        multi_currency,
+        clients,
+        processed,
+        references,
+        risk_windows,
+    })
+}
+
+/// Reads values from the front of a byte slice.
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    // Whether transaction IDs are 64-bit, as they are from version 2.
+    wide_ids: bool,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(bytes: &'a [u8], wide_ids: bool) -> Self {
+        Self { bytes, wide_ids }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.bytes.len() < len {
+            return Err(corrupt("truncated"));
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], SnapshotError> {
+        // This can't fail because `bytes` returns exactly N bytes.
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn bool(&mut self) -> Result<bool, SnapshotError> {
+        match self.array::<1>()? {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(corrupt("invalid boolean")),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, SnapshotError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn transaction_id(&mut self) -> Result<TransactionId, SnapshotError> {
+        let id = match self.wide_ids {
+            true => self.u64()?,
+            false => self.u32()?.into(),
+        };
+        TransactionId::from_u64(id).ok_or_else(|| {
+            corrupt(&format!(
+                "transaction ID {} needs the wide-tx-ids feature",
+                id
+            ))
+        })
+    }
+
+    fn amount(&mut self) -> Result<Amount, SnapshotError> {
+        Ok(Amount::from_minor_units(self.u64()?))
+    }
+
+    /// A `u64`, after whether there is one.
+    fn optional(&mut self) -> Result<Option<u64>, SnapshotError> {
+        Ok(match self.bool()? {
+            true => Some(self.u64()?),
+            false => None,
+        })
+    }
+
+    /// A UTF-8 string, after its length, naming it as `what` if it's corrupt.
+    fn string(&mut self, what: &str) -> Result<String, SnapshotError> {
+        let len =
+            usize::try_from(self.u32()?).map_err(|_| corrupt(&format!("{} too long", what)))?;
+        std::str::from_utf8(self.bytes(len)?)
+            .map(str::to_string)
+            .map_err(|_| corrupt(&format!("invalid {}", what)))
+    }
+
+    fn currency(&mut self) -> Result<Option<Currency>, SnapshotError> {
+        if !self.bool()? {
+            return Ok(None);
+        }
+        let code = self.bytes(3)?;
+        let code = std::str::from_utf8(code).map_err(|_| corrupt("invalid currency"))?;
+        Currency::try_from(code)
+            .map(Some)
+            .map_err(|_| corrupt("invalid currency"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::ClientId;
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            multi_currency: true,
+            clients: vec![
+                ClientSnapshot {
+                    id: ClientId::from(1),
+                    locked: false,
+                    lock_reason: None,
+                    activity: ClientActivity::default(),
+                    balances: vec![
+                        BalanceSnapshot {
+                            currency: None,
+                            available: Amount::from_minor_units(10_000).into(),
+                            held: Amount::from_minor_units(20_000),
+                        },
+                        BalanceSnapshot {
+                            currency: Some(Currency::try_from("USD").unwrap()),
+                            available: Amount::from_minor_units(5).into(),
+                            held: Amount::ZERO,
+                        },
+                    ],
+                    deposits: vec![DepositSnapshot {
+                        transaction_id: 2.into(),
+                        currency: None,
+                        amount: Amount::from_minor_units(20_000),
+                        disputed: true,
+                        withdrawal: false,
+                    }],
+                    order: Vec::new(),
+                    expired: Vec::new(),
+                },
+                ClientSnapshot {
+                    id: ClientId::from(2),
+                    locked: true,
+                    lock_reason: None,
+                    activity: ClientActivity::default(),
+                    balances: vec![],
+                    deposits: vec![],
+                    order: Vec::new(),
+                    expired: Vec::new(),
+                },
+            ],
+            processed: None,
+            references: Vec::new(),
+            risk_windows: Vec::new(),
+        }
+    }
+
+    fn written(snapshot: &Snapshot) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write(snapshot, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let snapshot = snapshot();
+        assert_eq!(read(written(&snapshot).as_slice()).unwrap(), snapshot);
+
+        let processed = [
+            (1, 2, TransactionKind::Deposit),
+            (1, 2, TransactionKind::Dispute),
+            (3, 4, TransactionKind::Withdrawal),
+        ]
+        .into_iter()
+        .map(|(client_id, transaction_id, kind)| ProcessedTransaction {
+            client_id: ClientId::from(client_id),
+            transaction_id: transaction_id.into(),
+            kind,
+        })
+        .collect();
+        let mut snapshot = Snapshot {
+            processed: Some(processed),
+            ..snapshot
+        };
+        assert_eq!(read(written(&snapshot).as_slice()).unwrap(), snapshot);
+
+        snapshot.clients[1].lock_reason = Some("suspected fraud".to_string());
+        assert_eq!(read(written(&snapshot).as_slice()).unwrap(), snapshot);
+
+        snapshot.clients[0].activity = ClientActivity {
+            deposits: 2,
+            disputes: 1,
+            ..Default::default()
+        };
+        assert_eq!(read(written(&snapshot).as_slice()).unwrap(), snapshot);
+
+        snapshot.clients[0].activity.first = Some(Seen {
+            line: Some(2),
+            timestamp: None,
+        });
+        snapshot.clients[0].activity.last = Some(Seen {
+            line: Some(4),
+            timestamp: Some(1_714_521_600),
+        });
+        assert_eq!(read(written(&snapshot).as_slice()).unwrap(), snapshot);
+
+        snapshot.references = vec![(ClientId::from(1), "pay-001".to_string())];
+        assert_eq!(read(written(&snapshot).as_slice()).unwrap(), snapshot);
+
+        snapshot.clients[0].deposits[0].withdrawal = true;
+        assert_eq!(read(written(&snapshot).as_slice()).unwrap(), snapshot);
+
+        snapshot.clients[0].expired = vec![4.into(), 7.into()];
+        snapshot.clients[0].order = vec![2.into()];
+        assert_eq!(read(written(&snapshot).as_slice()).unwrap(), snapshot);
+
+        snapshot.risk_windows = vec![(
+            ClientId::from(1),
+            vec![
+                WindowEntry {
+                    currency: None,
+                    deposited: Amount::from_minor_units(20_000),
+                    disputed: None,
+                },
+                WindowEntry {
+                    currency: Some(Currency::try_from("USD").unwrap()),
+                    deposited: Amount::ZERO,
+                    disputed: Some(Amount::from_minor_units(5)),
+                },
+            ],
+        )];
+        assert_eq!(read(written(&snapshot).as_slice()).unwrap(), snapshot);
+
+        snapshot.clients[0].balances[1].available = -SignedAmount::MAX;
+        assert_eq!(read(written(&snapshot).as_slice()).unwrap(), snapshot);
+    }
+
+    // A version 1 snapshot of `snapshot()`, exactly as written by the first
+    // release with binary snapshots. This must always load, whatever the
+    // current version.
+    const V1: &str = "5458534e01004e00000000000000010200000001000002000000001027000000\
+                      000000204e000000000000015553440500000000000000000000000000000001\
+                      0000000200000000204e00000000000001020001000000000000000076c2f943\
+                      6d2959e8a8ca5c42d0025023de38f5ce571ba214535087bca2f07d8b";
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_read_v1() {
+        assert_eq!(read(from_hex(V1).as_slice()).unwrap(), snapshot());
+    }
+
+    /// `snapshot()` with something in everything added since version 1.
+    fn full_snapshot() -> Snapshot {
+        let mut snapshot = snapshot();
+        snapshot.processed = Some(vec![
+            ProcessedTransaction {
+                client_id: ClientId::from(1),
+                transaction_id: 2.into(),
+                kind: TransactionKind::Deposit,
+            },
+            ProcessedTransaction {
+                client_id: ClientId::from(1),
+                transaction_id: 2.into(),
+                kind: TransactionKind::Dispute,
+            },
+        ]);
+        snapshot.references = vec![(ClientId::from(1), "pay-001".to_string())];
+        snapshot.risk_windows = vec![(
+            ClientId::from(1),
+            vec![WindowEntry {
+                currency: None,
+                deposited: Amount::from_minor_units(20_000),
+                disputed: Some(Amount::from_minor_units(20_000)),
+            }],
+        )];
+        let client = &mut snapshot.clients[0];
+        client.activity = ClientActivity {
+            deposits: 1,
+            disputes: 1,
+            first: Some(Seen {
+                line: Some(2),
+                timestamp: None,
+            }),
+            last: Some(Seen {
+                line: Some(3),
+                timestamp: Some(1_714_521_600),
+            }),
+            ..Default::default()
+        };
+        client.balances[1].available = -SignedAmount::from(Amount::from_minor_units(5));
+        client.deposits[0].withdrawal = true;
+        client.order = vec![2.into()];
+        client.expired = vec![4.into(), 7.into()];
+        snapshot.clients[1].lock_reason = Some("fraud".to_string());
+        snapshot
+    }
+
+    // A version 2 snapshot of `full_snapshot()`. This must always load,
+    // whatever the current version.
+    const V2: &str = "5458534e02004001000000000000010200000001000000010000000000000000\
+                      0000000000000001000000000000000000000000000000000000000000000001\
+                      0102000000000000000001010300000000000000010086316600000000020000\
+                      0000001027000000000000204e00000000000001555344010500000000000000\
+                      000000000000000001000000020000000000000000204e000000000000010101\
+                      0000000200000000000000020000000400000000000000070000000000000002\
+                      0001010500000066726175640000000000000000000000000000000000000000\
+                      0000000000000000000000000000000000000000000000000000000000000000\
+                      0000000000000102000000010002000000000000000001000200000000000000\
+                      02010000000100070000007061792d3030310100000001000100000000204e00\
+                      000000000001204e0000000000000c43613204bfe77397b212c5a0f5a6c88a9e\
+                      af4973c0b9a16e1dd5c40c58840e";
+
+    #[test]
+    fn test_read_v2() {
+        assert_eq!(read(from_hex(V2).as_slice()).unwrap(), full_snapshot());
+    }
+
+    #[test]
+    fn test_wide_transaction_ids() {
+        // Transaction IDs are written 64-bit whatever the build, and only
+        // those that fit in this build's are read.
+        let mut snapshot = snapshot();
+        let wide = u64::from(u32::MAX) + 1;
+        match TransactionId::from_u64(wide) {
+            Some(id) => {
+                snapshot.clients[0].deposits[0].transaction_id = id;
+                assert_eq!(read(written(&snapshot).as_slice()).unwrap(), snapshot);
+            }
+            None => {
+                let mut data = written(&snapshot);
+                let id = u64::from(snapshot.clients[0].deposits[0].transaction_id);
+                let at = data
+                    .windows(8)
+                    .position(|bytes| bytes == id.to_le_bytes())
+                    .unwrap();
+                data[at..at + 8].copy_from_slice(&wide.to_le_bytes());
+                // Fix the checksum, so that only the ID is wrong.
+                let end = data.len() - CHECKSUM_LEN;
+                let checksum = blake3::hash(&data[14..end]);
+                data[end..].copy_from_slice(checksum.as_bytes());
+                assert!(matches!(
+                    read(data.as_slice()),
+                    Err(SnapshotError::Corrupt(message)) if message.contains("wide-tx-ids")
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        // A snapshot from a newer release is rejected clearly, rather than
+        // misread.
+        let mut data = written(&snapshot());
+        data[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            read(data.as_slice()),
+            Err(SnapshotError::UnsupportedVersion(v)) if v == VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let mut data = written(&snapshot());
+        data[20] ^= 1;
+        assert!(matches!(
+            read(data.as_slice()),
+            Err(SnapshotError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let data = written(&snapshot());
+        assert!(matches!(
+            read(&data[..data.len() - 1]),
+            Err(SnapshotError::Corrupt(_))
+        ));
+    }
+}
@@ -0,0 +1,402 @@
+//! Consuming transactions from a message broker, e.g. NATS JetStream,
+//! RabbitMQ, or Kafka.
+//!
+//! Each message holds one or more CSV records without a header, as for the
+//! line protocol. A message is only acknowledged once all of its records have
+//! been processed and the store has durably stored them, which needs a
+//! persistent store opened with `StoreSpec::open_durable`, so a message being
+//! processed when the program stops, or crashes, is delivered again: every
+//! message is processed at least once, and none is acknowledged without
+//! being applied.
+//!
+//! Records that have already been processed are skipped when they're
+//! delivered again, as long as the broker gives the message a key that stays
+//! the same between deliveries: each record is keyed by it and its position
+//! in the message, and the key is committed to the store atomically with the
+//! record's effects; see `Clients::set_record_key`. So even a message
+//! delivered again after a crash between committing it and acknowledging it
+//! is applied exactly once.
+
+use crate::clients::{Clients, ProcessError};
+use crate::emit::{EmitError, Emitter};
+use crate::risk;
+use crate::stats::Stats;
+use crate::store::StoreError;
+use crate::transaction::parse_record;
+
+#[cfg(feature = "amqp")]
+pub mod amqp;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "nats")]
+pub mod nats;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SourceError {
+    #[cfg(feature = "amqp")]
+    #[error("amqp error: {0}")]
+    Amqp(#[from] lapin::Error),
+    #[cfg(feature = "nats")]
+    #[error("nats error: {0}")]
+    Nats(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[cfg(feature = "kafka")]
+    #[error("kafka error: {0}")]
+    Kafka(#[from] rdkafka::error::KafkaError),
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("built without support for {0}")]
+    Unsupported(&'static str),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConsumeError {
+    #[error(transparent)]
+    Source(#[from] SourceError),
+    #[error("store error: {0}")]
+    Store(#[from] StoreError),
+    #[error("failed to write summary: {0}")]
+    Emit(#[from] EmitError),
+    #[error("failed to write alerts: {0}")]
+    Alerts(#[source] std::io::Error),
+}
+
+/// Identifies a message to acknowledge, e.g. an AMQP delivery tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MessageId(pub u64);
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub id: MessageId,
+    /// Identifies the message durably, unlike `id`: the same each time it's
+    /// delivered, e.g. a JetStream stream sequence number. Without one, the
+    /// message's records are applied again if it's delivered again.
+    pub key: Option<String>,
+    pub payload: Vec<u8>,
+}
+
+pub trait MessageSource {
+    /// Wait for the next message. Returns `None` if there will be no more.
+    fn next(&mut self) -> Result<Option<Message>, SourceError>;
+
+    /// Acknowledge a message once it's been processed, so that it isn't
+    /// delivered again.
+    fn ack(&mut self, id: MessageId) -> Result<(), SourceError>;
+}
+
+/// Where to consume messages from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SourceSpec {
+    /// A NATS server URL and a JetStream stream, consumed by a durable
+    /// consumer named "transactions".
+    Nats { url: String, stream: String },
+    /// An AMQP URI and the queue to consume from.
+    Amqp { uri: String, queue: String },
+    /// Kafka brokers, comma-separated, and the topic to consume from, as the
+    /// consumer group "transactions".
+    Kafka { brokers: String, topic: String },
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum SourceSpecParseError {
+    #[error("expected 'nats:<url>#<stream>', 'amqp:<uri>#<queue>', or 'kafka:<brokers>#<topic>'")]
+    InvalidFormat,
+}
+
+impl std::str::FromStr for SourceSpec {
+    type Err = SourceSpecParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, rest) = s
+            .split_once(':')
+            .ok_or(SourceSpecParseError::InvalidFormat)?;
+        let (address, name) = match rest.rsplit_once('#') {
+            Some((address, name)) if !address.is_empty() && !name.is_empty() => (address, name),
+            _ => return Err(SourceSpecParseError::InvalidFormat),
+        };
+        match kind {
+            "nats" => Ok(SourceSpec::Nats {
+                url: address.to_string(),
+                stream: name.to_string(),
+            }),
+            "amqp" => Ok(SourceSpec::Amqp {
+                uri: address.to_string(),
+                queue: name.to_string(),
+            }),
+            "kafka" => Ok(SourceSpec::Kafka {
+                brokers: address.to_string(),
+                topic: name.to_string(),
+            }),
+            _ => Err(SourceSpecParseError::InvalidFormat),
+        }
+    }
+}
+
+impl SourceSpec {
+    pub fn open(&self) -> Result<Box<dyn MessageSource>, SourceError> {
+        match self {
+            #[cfg(feature = "nats")]
+            SourceSpec::Nats { url, stream } => {
+                Ok(Box::new(nats::NatsSource::connect(url, stream)?))
+            }
+            #[cfg(not(feature = "nats"))]
+            SourceSpec::Nats { .. } => Err(SourceError::Unsupported("nats")),
+            #[cfg(feature = "amqp")]
+            SourceSpec::Amqp { uri, queue } => Ok(Box::new(amqp::AmqpSource::connect(uri, queue)?)),
+            #[cfg(not(feature = "amqp"))]
+            SourceSpec::Amqp { .. } => Err(SourceError::Unsupported("amqp")),
+            #[cfg(feature = "kafka")]
+            SourceSpec::Kafka { brokers, topic } => {
+                Ok(Box::new(kafka::KafkaSource::connect(brokers, topic)?))
+            }
+            #[cfg(not(feature = "kafka"))]
+            SourceSpec::Kafka { .. } => Err(SourceError::Unsupported("kafka")),
+        }
+    }
+}
+
+/// Process every message from `source` until it ends, recording the results
+/// in `stats`, writing the summary with `emitter` as it comes due, and
+/// writing the alerts raised by the risk limits to `alerts` as JSON lines.
+/// The alerts are written before the message is acknowledged.
+///
+/// On error, the message being processed isn't acknowledged, so it will be
+/// delivered again, e.g. to the next run.
+pub fn consume(
+    clients: &mut Clients,
+    source: &mut dyn MessageSource,
+    stats: &mut Stats,
+    mut emitter: Option<&mut Emitter>,
+    mut alerts: Option<&mut dyn std::io::Write>,
+) -> Result<(), ConsumeError> {
+    while let Some(message) = source.next()? {
+        let payload = String::from_utf8_lossy(&message.payload);
+        // Numbered before skipping blank lines, so that a record's position
+        // doesn't depend on anything else.
+        for (index, record) in payload
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+        {
+            let transaction = match parse_record(record) {
+                Ok(transaction) => transaction,
+                Err(e) => {
+                    tracing::debug!("invalid record '{}': {}", record, e);
+                    stats.record_invalid(&e);
+                    continue;
+                }
+            };
+            if let Some(key) = &message.key {
+                clients.set_record_key(format!("{}#{}", key, index));
+            }
+            let duplicates = clients.duplicates();
+            match clients.process_transaction(transaction) {
+                Ok(_) if clients.duplicates() > duplicates => {
+                    tracing::debug!("skipping record delivered again: '{}'", record);
+                }
+                Ok(_) => stats.record_processed(Ok(())),
+                Err(ProcessError::Rejected(e)) => stats.record_processed(Err(&e)),
+                Err(ProcessError::Store(e)) => return Err(e.into()),
+                // Only counted once they're applied or rejected.
+                Err(ProcessError::Pending) => {}
+            }
+            // Taken even if they're not written, so they don't pile up.
+            let taken = clients.take_alerts();
+            if let Some(writer) = alerts.as_deref_mut() {
+                risk::write_alerts(writer, &taken).map_err(ConsumeError::Alerts)?;
+            }
+            if let Some(emitter) = emitter.as_deref_mut() {
+                emitter.processed(clients)?;
+            }
+        }
+        // Otherwise a store that commits in batches could lose records that
+        // were acknowledged, if the program crashed.
+        clients.flush()?;
+        if let Some(writer) = alerts.as_deref_mut() {
+            writer.flush().map_err(ConsumeError::Alerts)?;
+        }
+        source.ack(message.id)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::risk::RiskLimits;
+    use std::collections::VecDeque;
+    use std::num::NonZeroUsize;
+
+    /// Delivers messages in order, recording which are acknowledged.
+    #[derive(Default)]
+    struct Queue {
+        queue: VecDeque<Message>,
+        acked: Vec<MessageId>,
+        // Fail to acknowledge any message, as if the program crashed first.
+        crash_on_ack: bool,
+    }
+
+    impl MessageSource for Queue {
+        fn next(&mut self) -> Result<Option<Message>, SourceError> {
+            Ok(self.queue.pop_front())
+        }
+
+        fn ack(&mut self, id: MessageId) -> Result<(), SourceError> {
+            if self.crash_on_ack {
+                return Err(std::io::Error::other("crashed").into());
+            }
+            self.acked.push(id);
+            Ok(())
+        }
+    }
+
+    fn message(id: u64, payload: &str) -> Message {
+        Message {
+            id: MessageId(id),
+            key: Some(format!("test:{}", id)),
+            payload: payload.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_parse_source_spec() {
+        assert_eq!(
+            "nats:nats://localhost:4222#transactions".parse(),
+            Ok(SourceSpec::Nats {
+                url: "nats://localhost:4222".into(),
+                stream: "transactions".into()
+            })
+        );
+        assert_eq!(
+            "amqp:amqp://localhost:5672/%2f#transactions".parse(),
+            Ok(SourceSpec::Amqp {
+                uri: "amqp://localhost:5672/%2f".into(),
+                queue: "transactions".into()
+            })
+        );
+        assert_eq!(
+            "kafka:localhost:9092,localhost:9093#transactions".parse(),
+            Ok(SourceSpec::Kafka {
+                brokers: "localhost:9092,localhost:9093".into(),
+                topic: "transactions".into()
+            })
+        );
+        for spec in [
+            "nats:localhost",
+            "amqp:#queue",
+            "kafka:localhost",
+            "sqs:x#queue",
+        ] {
+            assert_eq!(
+                spec.parse::<SourceSpec>(),
+                Err(SourceSpecParseError::InvalidFormat)
+            );
+        }
+    }
+
+    #[test]
+    fn test_consume() {
+        let mut source = Queue::default();
+        source.queue.extend([
+            message(1, "deposit,1,1,10.0\ndeposit,2,2,5.0\n"),
+            message(2, "withdrawal,1,3,4.0"),
+            message(3, "withdrawal,2,4,50.0\ndeposit,2,5"),
+        ]);
+        // Delivered again, e.g. after the broker lost the acknowledgement,
+        // under a new delivery tag but the same key.
+        source.queue.push_back(Message {
+            id: MessageId(4),
+            ..message(2, "withdrawal,1,3,4.0")
+        });
+        // Disputed again after the first dispute was resolved, which isn't a
+        // redelivery.
+        source.queue.extend([
+            message(5, "dispute,2,2\nresolve,2,2"),
+            message(6, "dispute,2,2"),
+        ]);
+
+        let mut clients = Clients::new();
+        let mut stats = Stats::default();
+        consume(&mut clients, &mut source, &mut stats, None, None).unwrap();
+        assert_eq!(source.acked, [1, 2, 3, 4, 5, 6].map(MessageId));
+        assert_eq!(stats.accepted(), 6);
+        assert_eq!(stats.invalid.values().sum::<u64>(), 1);
+        assert_eq!(clients.duplicates(), 1);
+        let mut summary = Vec::new();
+        clients.write(&mut summary).unwrap();
+        assert_eq!(
+            String::from_utf8(summary).unwrap(),
+            "client,available,held,total,locked
+1,6.0000,0.0000,6.0000,false
+2,0.0000,5.0000,5.0000,false
+"
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_consume_after_crash() {
+        use crate::store::sqlite::SqliteStore;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clients.db");
+        let open = || Clients::open(Box::new(SqliteStore::open(&path).unwrap())).unwrap();
+        let messages = [
+            message(1, "deposit,1,1,10.0\nwithdrawal,1,2,4.0"),
+            message(2, "withdrawal,1,3,1.0"),
+        ];
+
+        // The first message is committed, but not acknowledged.
+        let mut source = Queue {
+            crash_on_ack: true,
+            ..Queue::default()
+        };
+        source.queue.extend(messages.clone());
+        let mut stats = Stats::default();
+        assert!(consume(&mut open(), &mut source, &mut stats, None, None).is_err());
+        assert_eq!(stats.accepted(), 2);
+
+        // So both are delivered again, and only the second is applied.
+        let mut source = Queue::default();
+        source.queue.extend(messages);
+        let mut clients = open();
+        let mut stats = Stats::default();
+        consume(&mut clients, &mut source, &mut stats, None, None).unwrap();
+        assert_eq!(source.acked, [1, 2].map(MessageId));
+        assert_eq!(stats.accepted(), 1);
+        assert_eq!(clients.duplicates(), 2);
+        assert_eq!(
+            clients.get(1.into()).unwrap().unwrap().available(None),
+            crate::SignedAmount::try_from("5").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_consume_alerts() {
+        let mut source = Queue::default();
+        source.queue.extend([
+            message(1, "deposit,1,1,10.0\ndeposit,1,2,5.0"),
+            message(2, "dispute,1,1\ndispute,1,2"),
+        ]);
+
+        let mut clients = Clients::new();
+        clients.set_risk_limits(Some(RiskLimits {
+            window: NonZeroUsize::new(10).unwrap(),
+            max_disputes: Some(1),
+            max_disputed_ratio: None,
+        }));
+        let mut stats = Stats::default();
+        let mut alerts = Vec::new();
+        consume(
+            &mut clients,
+            &mut source,
+            &mut stats,
+            None,
+            Some(&mut alerts),
+        )
+        .unwrap();
+        let alerts = String::from_utf8(alerts).unwrap();
+        assert_eq!(alerts.lines().count(), 1);
+        assert!(alerts.contains("\"rule\":\"max_disputes\""), "{}", alerts);
+        assert!(clients.take_alerts().is_empty());
+    }
+}
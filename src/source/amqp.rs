@@ -0,0 +1,80 @@
+//! Consuming from an AMQP queue, e.g. on RabbitMQ.
+
+use lapin::options::{BasicAckOptions, BasicConsumeOptions, BasicQosOptions};
+use lapin::types::FieldTable;
+use lapin::{Channel, Connection, ConnectionProperties, Consumer};
+use tokio_stream::StreamExt;
+
+use super::{Message, MessageId, MessageSource, SourceError};
+
+/// How many messages the broker sends before waiting for acknowledgements.
+const PREFETCH: u16 = 1000;
+
+/// Consumes messages with manual acknowledgement, identifying them by their
+/// delivery tags, and keying them by their `message_id` property, if the
+/// publisher set one. Like the PostgreSQL store, it's async internally, and
+/// runs on its own single-threaded runtime.
+pub struct AmqpSource {
+    runtime: tokio::runtime::Runtime,
+    // Kept so that the connection stays open.
+    _connection: Connection,
+    channel: Channel,
+    consumer: Consumer,
+}
+
+impl AmqpSource {
+    /// Connect to the broker at `uri`, e.g. `amqp://localhost:5672/%2f`, and
+    /// consume from `queue`, which must already exist.
+    pub fn connect(uri: &str, queue: &str) -> Result<Self, SourceError> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let (connection, channel, consumer) = runtime.block_on(async {
+            let connection = Connection::connect(uri, ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .basic_qos(PREFETCH, BasicQosOptions::default())
+                .await?;
+            let consumer = channel
+                .basic_consume(
+                    queue,
+                    "transactions",
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            Ok::<_, lapin::Error>((connection, channel, consumer))
+        })?;
+        Ok(Self {
+            runtime,
+            _connection: connection,
+            channel,
+            consumer,
+        })
+    }
+}
+
+impl MessageSource for AmqpSource {
+    fn next(&mut self) -> Result<Option<Message>, SourceError> {
+        let Some(delivery) = self.runtime.block_on(self.consumer.next()) else {
+            return Ok(None);
+        };
+        let delivery = delivery?;
+        let key = delivery
+            .properties
+            .message_id()
+            .as_ref()
+            .map(|id| format!("amqp:{}", id.as_str()));
+        Ok(Some(Message {
+            id: MessageId(delivery.delivery_tag),
+            key,
+            payload: delivery.data,
+        }))
+    }
+
+    fn ack(&mut self, id: MessageId) -> Result<(), SourceError> {
+        self.runtime
+            .block_on(self.channel.basic_ack(id.0, BasicAckOptions::default()))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+//! Consuming from a Kafka topic.
+
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer};
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::{Offset, TopicPartitionList};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use super::{Message, MessageId, MessageSource, SourceError};
+
+/// The consumer group, which the brokers use to remember the offsets
+/// committed between runs.
+const GROUP: &str = "transactions";
+
+/// How long to wait for a message before polling again.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Consumes messages as a member of a consumer group, committing offsets
+/// only when a message is acknowledged, and keying messages by their topic,
+/// partition, and offset.
+///
+/// Committing a message's offset commits every earlier message in its
+/// partition too, which is what's wanted, as `consume` acknowledges messages
+/// in the order they're delivered.
+pub struct KafkaSource {
+    consumer: BaseConsumer,
+    // Delivered but not yet acknowledged, by topic, partition, and offset.
+    pending: HashMap<MessageId, (String, i32, i64)>,
+    next_id: u64,
+    // Whether the brokers have been unreachable since the last message, so
+    // that it's only logged once.
+    waiting: bool,
+}
+
+impl KafkaSource {
+    /// Connect to `brokers`, a comma-separated list of `host:port`, and
+    /// consume from `topic`, starting from the earliest message the first
+    /// time the group consumes it.
+    pub fn connect(brokers: &str, topic: &str) -> Result<Self, SourceError> {
+        let consumer: BaseConsumer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("group.id", GROUP)
+            // Offsets are only ever committed by `ack`.
+            .set("enable.auto.commit", "false")
+            .set("enable.auto.offset.store", "false")
+            .set("auto.offset.reset", "earliest")
+            .create()?;
+        consumer.subscribe(&[topic])?;
+        Ok(Self {
+            consumer,
+            pending: HashMap::new(),
+            next_id: 0,
+            waiting: false,
+        })
+    }
+}
+
+impl MessageSource for KafkaSource {
+    fn next(&mut self) -> Result<Option<Message>, SourceError> {
+        use rdkafka::Message as _;
+
+        // A topic never ends, so this waits until there's a message.
+        let message = loop {
+            match self.consumer.poll(POLL_INTERVAL) {
+                Some(Ok(message)) => {
+                    self.waiting = false;
+                    break message;
+                }
+                // The client reconnects by itself.
+                Some(Err(KafkaError::MessageConsumption(
+                    code @ (RDKafkaErrorCode::BrokerTransportFailure
+                    | RDKafkaErrorCode::AllBrokersDown),
+                ))) => {
+                    if !self.waiting {
+                        tracing::warn!("waiting for brokers: {}", code);
+                    }
+                    self.waiting = true;
+                }
+                Some(Err(e)) => return Err(e.into()),
+                None => {}
+            }
+        };
+        let id = MessageId(self.next_id);
+        self.next_id += 1;
+        let (topic, partition, offset) = (message.topic(), message.partition(), message.offset());
+        let key = Some(format!("kafka:{}:{}:{}", topic, partition, offset));
+        let payload = message.payload().unwrap_or_default().to_vec();
+        self.pending
+            .insert(id, (topic.to_string(), partition, offset));
+        Ok(Some(Message { id, key, payload }))
+    }
+
+    fn ack(&mut self, id: MessageId) -> Result<(), SourceError> {
+        if let Some((topic, partition, offset)) = self.pending.remove(&id) {
+            // The committed offset is that of the next message to consume.
+            let mut offsets = TopicPartitionList::new();
+            offsets.add_partition_offset(&topic, partition, Offset::Offset(offset + 1))?;
+            self.consumer.commit(&offsets, CommitMode::Sync)?;
+        }
+        Ok(())
+    }
+}
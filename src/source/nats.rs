@@ -0,0 +1,87 @@
+//! Consuming from a NATS JetStream stream.
+
+use async_nats::jetstream;
+use async_nats::jetstream::consumer::pull;
+use std::collections::HashMap;
+use tokio_stream::StreamExt;
+
+use super::{Message, MessageId, MessageSource, SourceError};
+
+/// The name of the durable consumer, which the server uses to remember which
+/// messages have been acknowledged between runs.
+const CONSUMER: &str = "transactions";
+
+/// Consumes messages with a durable pull consumer, acknowledging each
+/// explicitly, and keying them by their stream sequence numbers. Like the PostgreSQL store, it's async internally, and runs on
+/// its own single-threaded runtime.
+pub struct NatsSource {
+    runtime: tokio::runtime::Runtime,
+    messages: pull::Stream,
+    // Delivered but not yet acknowledged.
+    pending: HashMap<MessageId, jetstream::Message>,
+    next_id: u64,
+}
+
+fn nats_error(e: impl std::error::Error + Send + Sync + 'static) -> SourceError {
+    SourceError::Nats(Box::new(e))
+}
+
+impl NatsSource {
+    /// Connect to the server at `url`, e.g. `nats://localhost:4222`, and
+    /// consume from `stream`, creating the consumer if it doesn't exist yet.
+    pub fn connect(url: &str, stream: &str) -> Result<Self, SourceError> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let messages = runtime.block_on(async {
+            let client = async_nats::connect(url).await.map_err(nats_error)?;
+            let stream = jetstream::new(client)
+                .get_stream(stream)
+                .await
+                .map_err(nats_error)?;
+            let consumer = stream
+                .get_or_create_consumer(
+                    CONSUMER,
+                    pull::Config {
+                        durable_name: Some(CONSUMER.to_string()),
+                        ack_policy: jetstream::consumer::AckPolicy::Explicit,
+                        ..Default::default()
+                    },
+                )
+                .await
+                .map_err(nats_error)?;
+            consumer.messages().await.map_err(nats_error)
+        })?;
+        Ok(Self {
+            runtime,
+            messages,
+            pending: HashMap::new(),
+            next_id: 0,
+        })
+    }
+}
+
+impl MessageSource for NatsSource {
+    fn next(&mut self) -> Result<Option<Message>, SourceError> {
+        let Some(message) = self.runtime.block_on(self.messages.next()) else {
+            return Ok(None);
+        };
+        let message = message.map_err(nats_error)?;
+        let id = MessageId(self.next_id);
+        self.next_id += 1;
+        let info = message.info().map_err(SourceError::Nats)?;
+        let key = Some(format!("nats:{}:{}", info.stream, info.stream_sequence));
+        let payload = message.payload.to_vec();
+        self.pending.insert(id, message);
+        Ok(Some(Message { id, key, payload }))
+    }
+
+    fn ack(&mut self, id: MessageId) -> Result<(), SourceError> {
+        if let Some(message) = self.pending.remove(&id) {
+            self.runtime
+                .block_on(message.ack())
+                .map_err(SourceError::Nats)?;
+        }
+        Ok(())
+    }
+}
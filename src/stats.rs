@@ -0,0 +1,117 @@
+//! Counts of what happened during a run - in particular why transactions were
+//! rejected, which otherwise leaves no trace in the output.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::client::ClientError;
+use crate::transaction::TransactionError;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Stats {
+    /// Transactions processed, whether or not they were accepted.
+    pub processed: u64,
+    /// Rejected transactions, by `ClientError::name`.
+    pub rejected: BTreeMap<&'static str, u64>,
+    /// Records that couldn't be parsed, by `TransactionError::name`.
+    pub invalid: BTreeMap<&'static str, u64>,
+}
+
+impl Stats {
+    pub fn record_processed(&mut self, result: Result<(), &ClientError>) {
+        self.processed += 1;
+        if let Err(error) = result {
+            *self.rejected.entry(error.name()).or_default() += 1;
+        }
+    }
+
+    pub fn record_invalid(&mut self, error: &TransactionError) {
+        *self.invalid.entry(error.name()).or_default() += 1;
+    }
+
+    /// Add the counts in `other` to these.
+    pub fn merge(&mut self, other: &Stats) {
+        self.processed += other.processed;
+        for (name, count) in &other.rejected {
+            *self.rejected.entry(name).or_default() += count;
+        }
+        for (name, count) in &other.invalid {
+            *self.invalid.entry(name).or_default() += count;
+        }
+    }
+
+    pub fn accepted(&self) -> u64 {
+        self.processed - self.rejected.values().sum::<u64>()
+    }
+
+    /// Write the stats as JSON, with totals as well as the counts by reason,
+    /// so that scripts can check the outcome without adding them up.
+    pub fn write(&self, writer: impl std::io::Write) -> Result<(), serde_json::Error> {
+        #[derive(Serialize)]
+        struct Totals<'a> {
+            #[serde(flatten)]
+            stats: &'a Stats,
+            accepted: u64,
+            rejected_total: u64,
+            invalid_total: u64,
+        }
+
+        serde_json::to_writer_pretty(
+            writer,
+            &Totals {
+                stats: self,
+                accepted: self.accepted(),
+                rejected_total: self.rejected.values().sum(),
+                invalid_total: self.invalid.values().sum(),
+            },
+        )
+    }
+}
+
+impl std::fmt::Display for Stats {
+    /// A human-readable report, one count per line.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "processed: {}", self.processed)?;
+        writeln!(f, "accepted: {}", self.accepted())?;
+        for (name, count) in &self.rejected {
+            writeln!(f, "rejected ({}): {}", name, count)?;
+        }
+        for (name, count) in &self.invalid {
+            writeln!(f, "invalid ({}): {}", name, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut stats = Stats::default();
+        stats.record_processed(Ok(()));
+        stats.record_processed(Err(&ClientError::InsufficientFunds));
+        stats.record_processed(Err(&ClientError::InsufficientFunds));
+        stats.record_processed(Err(&ClientError::Locked));
+        stats.record_invalid(&TransactionError::MissingAmount);
+        assert_eq!(
+            stats.to_string(),
+            "processed: 4
+accepted: 1
+rejected (insufficient_funds): 2
+rejected (locked): 1
+invalid (missing_amount): 1
+"
+        );
+
+        let mut buf = Vec::new();
+        stats.write(&mut buf).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(json["rejected"]["insufficient_funds"], 2);
+        assert_eq!(json["invalid"]["missing_amount"], 1);
+        assert_eq!(json["accepted"], 1);
+        assert_eq!(json["rejected_total"], 3);
+        assert_eq!(json["invalid_total"], 1);
+    }
+}
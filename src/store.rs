@@ -0,0 +1,276 @@
+//! Where client state is kept while processing.
+//!
+//! By default everything is kept in memory, but a `Store` can instead keep it
+//! e.g. in a database, so that it isn't limited by memory and survives
+//! restarts.
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use crate::client::{Client, ClientError};
+use crate::transaction::{ClientId, Id};
+use crate::TransactionId;
+
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "rocksdb")]
+pub mod rocks;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+    #[cfg(feature = "sqlite")]
+    #[error("sqlite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[cfg(feature = "rocksdb")]
+    #[error("rocksdb error: {0}")]
+    Rocksdb(#[from] ::rocksdb::Error),
+    #[cfg(feature = "postgres")]
+    #[error("postgres error: {0}")]
+    Postgres(#[from] tokio_postgres::Error),
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("another process is already writing to the store")]
+    InUse,
+    #[error("malformed stored value: {0}")]
+    Malformed(#[from] serde_json::Error),
+    // The stored data doesn't describe a valid client, e.g. because it was
+    // edited by hand.
+    #[error("corrupt store: {0}")]
+    Corrupt(String),
+    #[error("built without support for the {0} store")]
+    Unsupported(&'static str),
+}
+
+/// Generic over the client and transaction IDs, like `Client`, though the
+/// stores other than `MemoryStore` only store the default ones.
+#[allow(clippy::type_complexity)]
+pub trait Store<C: Id = ClientId, T: Id = TransactionId> {
+    /// Apply `f` to the client with ID `id`, creating the client if it doesn't
+    /// exist yet, and store the result.
+    ///
+    /// `transaction_id` is the transaction being processed, or `None` if no
+    /// deposit is touched, e.g. when locking an account. No transaction
+    /// touches any deposit other than the one with its own ID, so stores that
+    /// don't keep every deposit in memory need only give `f` that one.
+    fn update(
+        &mut self,
+        id: C,
+        transaction_id: Option<T>,
+        f: &mut dyn FnMut(&mut Client<C, T>) -> Result<(), ClientError>,
+    ) -> Result<Result<(), ClientError>, StoreError>;
+
+    /// Replace the client with ID `id`, including all of its deposits.
+    fn put(&mut self, id: C, client: &Client<C, T>) -> Result<(), StoreError>;
+
+    /// Get the client with ID `id`. If `with_deposits` is false the client may
+    /// be missing its deposits, which is still enough to report balances.
+    fn get(&self, id: C, with_deposits: bool) -> Result<Option<Cow<'_, Client<C, T>>>, StoreError>;
+
+    /// The IDs of all clients, in ascending order.
+    fn client_ids(&self) -> Result<Vec<C>, StoreError>;
+
+    /// Remove the client with ID `id`, including all of its deposits.
+    /// Returns false if there's no such client.
+    fn remove(&mut self, id: C) -> Result<bool, StoreError>;
+
+    /// Make sure all updates so far are durably stored.
+    fn flush(&mut self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// Whether the record with this key, e.g. a message broker's ID for it,
+    /// has been stored as processed by `mark_consumed`.
+    fn is_consumed(&self, key: &str) -> Result<bool, StoreError>;
+
+    /// Store the record with this key as processed along with the next
+    /// `update`, committed atomically with it, so that a record is never
+    /// recorded as processed without its effects or the other way around.
+    fn mark_consumed(&mut self, key: String);
+}
+
+/// Which of a client's deposits a database-backed store should load.
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+#[derive(Clone, Copy)]
+enum Deposits {
+    All,
+    One(TransactionId),
+    None,
+}
+
+/// The default store, keeping everything in memory.
+pub struct MemoryStore<C = ClientId, T = TransactionId> {
+    clients: HashMap<C, Client<C, T>>,
+    consumed: HashSet<String>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<C: Id, T: Id> Default for MemoryStore<C, T> {
+    fn default() -> Self {
+        Self {
+            clients: HashMap::new(),
+            consumed: HashSet::new(),
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+impl<C: Id, T: Id> Store<C, T> for MemoryStore<C, T> {
+    fn update(
+        &mut self,
+        id: C,
+        _transaction_id: Option<T>,
+        f: &mut dyn FnMut(&mut Client<C, T>) -> Result<(), ClientError>,
+    ) -> Result<Result<(), ClientError>, StoreError> {
+        Ok(f(self.clients.entry(id).or_insert_with(Client::new)))
+    }
+
+    fn put(&mut self, id: C, client: &Client<C, T>) -> Result<(), StoreError> {
+        self.clients.insert(id, client.clone());
+        Ok(())
+    }
+
+    fn get(
+        &self,
+        id: C,
+        _with_deposits: bool,
+    ) -> Result<Option<Cow<'_, Client<C, T>>>, StoreError> {
+        Ok(self.clients.get(&id).map(Cow::Borrowed))
+    }
+
+    fn client_ids(&self) -> Result<Vec<C>, StoreError> {
+        // HashMaps aren't ordered. Return the clients in a stable order to
+        // make testing easier.
+        let mut ids: Vec<_> = self.clients.keys().copied().collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn remove(&mut self, id: C) -> Result<bool, StoreError> {
+        Ok(self.clients.remove(&id).is_some())
+    }
+
+    fn is_consumed(&self, key: &str) -> Result<bool, StoreError> {
+        Ok(self.consumed.contains(key))
+    }
+
+    // Nothing here outlives the process, so there's nothing to keep in step.
+    fn mark_consumed(&mut self, key: String) {
+        self.consumed.insert(key);
+    }
+}
+
+/// Which store to use, as given on the command line: `memory`,
+/// `sqlite:<path>`, `rocksdb:<path>`, or `postgres:<config>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreSpec {
+    Memory,
+    Sqlite(PathBuf),
+    Rocksdb(PathBuf),
+    // A connection string, e.g. `host=localhost user=postgres`.
+    Postgres(String),
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum StoreSpecParseError {
+    #[error("expected 'memory', 'sqlite:<path>', 'rocksdb:<path>', or 'postgres:<config>'")]
+    InvalidFormat,
+}
+
+impl std::str::FromStr for StoreSpec {
+    type Err = StoreSpecParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "memory" => Ok(StoreSpec::Memory),
+            Some(("sqlite", path)) if !path.is_empty() => Ok(StoreSpec::Sqlite(path.into())),
+            Some(("rocksdb", path)) if !path.is_empty() => Ok(StoreSpec::Rocksdb(path.into())),
+            Some(("postgres", config)) if !config.is_empty() => {
+                Ok(StoreSpec::Postgres(config.to_string()))
+            }
+            _ => Err(StoreSpecParseError::InvalidFormat),
+        }
+    }
+}
+
+impl StoreSpec {
+    pub fn open(&self) -> Result<Box<dyn Store>, StoreError> {
+        match self {
+            StoreSpec::Memory => Ok(Box::new(MemoryStore::new())),
+            #[cfg(feature = "sqlite")]
+            StoreSpec::Sqlite(path) => Ok(Box::new(sqlite::SqliteStore::open(path)?)),
+            #[cfg(not(feature = "sqlite"))]
+            StoreSpec::Sqlite(_) => Err(StoreError::Unsupported("sqlite")),
+            #[cfg(feature = "rocksdb")]
+            StoreSpec::Rocksdb(path) => Ok(Box::new(rocks::RocksStore::open(path)?)),
+            #[cfg(not(feature = "rocksdb"))]
+            StoreSpec::Rocksdb(_) => Err(StoreError::Unsupported("rocksdb")),
+            #[cfg(feature = "postgres")]
+            StoreSpec::Postgres(config) => Ok(Box::new(postgres::PostgresStore::connect(config)?)),
+            #[cfg(not(feature = "postgres"))]
+            StoreSpec::Postgres(_) => Err(StoreError::Unsupported("postgres")),
+        }
+    }
+
+    /// Whether the store keeps what's flushed to it after the process exits,
+    /// i.e. isn't `memory`.
+    pub fn is_persistent(&self) -> bool {
+        !matches!(self, StoreSpec::Memory)
+    }
+
+    /// Open the store as for `open`, but so that what's flushed survives a
+    /// power failure too, e.g. before a message broker is told it's stored.
+    pub fn open_durable(&self) -> Result<Box<dyn Store>, StoreError> {
+        match self {
+            #[cfg(feature = "sqlite")]
+            StoreSpec::Sqlite(path) => {
+                let store = sqlite::SqliteStore::open(path)?;
+                store.sync_commits()?;
+                Ok(Box::new(store))
+            }
+            // The others don't lose flushed writes.
+            _ => self.open(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_store_spec() {
+        assert_eq!("memory".parse(), Ok(StoreSpec::Memory));
+        assert_eq!(
+            "sqlite:accounts.db".parse(),
+            Ok(StoreSpec::Sqlite("accounts.db".into()))
+        );
+        assert_eq!(
+            "rocksdb:accounts".parse(),
+            Ok(StoreSpec::Rocksdb("accounts".into()))
+        );
+        assert_eq!(
+            "postgres:postgresql://localhost/accounts".parse(),
+            Ok(StoreSpec::Postgres(
+                "postgresql://localhost/accounts".into()
+            ))
+        );
+        assert_eq!(
+            "sqlite:".parse::<StoreSpec>(),
+            Err(StoreSpecParseError::InvalidFormat)
+        );
+        assert_eq!(
+            "postgres".parse::<StoreSpec>(),
+            Err(StoreSpecParseError::InvalidFormat)
+        );
+        assert!(!StoreSpec::Memory.is_persistent());
+        assert!(StoreSpec::Sqlite("accounts.db".into()).is_persistent());
+    }
+}
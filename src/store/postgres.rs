@@ -0,0 +1,512 @@
+//! A store keeping client state in PostgreSQL, so that the engine can be the
+//! single writer to a shared operational database.
+
+use std::borrow::Cow;
+use tokio_postgres::{GenericClient, NoTls};
+
+use super::{Deposits, Store, StoreError};
+use crate::client::{Client, ClientActivity, ClientError, Seen};
+use crate::snapshot::{BalanceSnapshot, ClientSnapshot, DepositSnapshot};
+use crate::transaction::ClientId;
+use crate::{Amount, Currency, SignedAmount, TransactionId};
+
+// As for SQLite: amounts and transaction IDs are integers, stored as signed
+// integers, negative balances are stored as their magnitude and whether
+// they're overdrawn, and transactions without a currency have an empty
+// currency.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS clients (
+        id INTEGER PRIMARY KEY,
+        locked BOOLEAN NOT NULL
+    );
+    -- Added after the table, so may be missing from existing databases.
+    ALTER TABLE clients ADD COLUMN IF NOT EXISTS lock_reason TEXT;
+    ALTER TABLE clients ADD COLUMN IF NOT EXISTS deposit_count BIGINT NOT NULL DEFAULT 0;
+    ALTER TABLE clients ADD COLUMN IF NOT EXISTS withdrawal_count BIGINT NOT NULL DEFAULT 0;
+    ALTER TABLE clients ADD COLUMN IF NOT EXISTS dispute_count BIGINT NOT NULL DEFAULT 0;
+    ALTER TABLE clients ADD COLUMN IF NOT EXISTS resolve_count BIGINT NOT NULL DEFAULT 0;
+    ALTER TABLE clients ADD COLUMN IF NOT EXISTS chargeback_count BIGINT NOT NULL DEFAULT 0;
+    ALTER TABLE clients ADD COLUMN IF NOT EXISTS first_line BIGINT;
+    ALTER TABLE clients ADD COLUMN IF NOT EXISTS first_timestamp BIGINT;
+    ALTER TABLE clients ADD COLUMN IF NOT EXISTS last_line BIGINT;
+    ALTER TABLE clients ADD COLUMN IF NOT EXISTS last_timestamp BIGINT;
+    CREATE TABLE IF NOT EXISTS balances (
+        client INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        available BIGINT NOT NULL,
+        held BIGINT NOT NULL,
+        PRIMARY KEY (client, currency)
+    );
+    CREATE TABLE IF NOT EXISTS deposits (
+        client INTEGER NOT NULL,
+        tx BIGINT NOT NULL,
+        currency TEXT NOT NULL,
+        amount BIGINT NOT NULL,
+        disputed BOOLEAN NOT NULL,
+        PRIMARY KEY (client, tx)
+    );
+    ALTER TABLE deposits ADD COLUMN IF NOT EXISTS withdrawal BOOLEAN NOT NULL DEFAULT FALSE;
+    ALTER TABLE balances ADD COLUMN IF NOT EXISTS overdrawn BOOLEAN NOT NULL DEFAULT FALSE;
+    CREATE TABLE IF NOT EXISTS consumed (
+        key TEXT PRIMARY KEY
+    );
+";
+
+// Key of the advisory lock held while connected, to make sure this is the only
+// writer.
+const WRITER_LOCK: i64 = 0x7472_616e_7361_6374;
+
+/// Keeps client state in PostgreSQL, loading deposits on demand.
+///
+/// Every update is committed in its own database transaction, so the database
+/// always reflects exactly the transactions processed so far. The store is
+/// async internally, and runs its queries on its own single-threaded runtime
+/// to implement the synchronous `Store` trait.
+pub struct PostgresStore {
+    runtime: tokio::runtime::Runtime,
+    client: tokio_postgres::Client,
+    // The key given to `mark_consumed`, until the update it's stored with.
+    consumed: Option<String>,
+}
+
+impl PostgresStore {
+    /// Connect to the database described by `config`, e.g.
+    /// `host=localhost user=postgres` or `postgresql://localhost/accounts`,
+    /// creating the tables if they don't exist.
+    ///
+    /// Fails with `StoreError::InUse` if another process is already writing
+    /// to the database.
+    pub fn connect(config: &str) -> Result<Self, StoreError> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let client = runtime.block_on(connect(config))?;
+        Ok(Self {
+            runtime,
+            client,
+            consumed: None,
+        })
+    }
+}
+
+async fn connect(config: &str) -> Result<tokio_postgres::Client, StoreError> {
+    let (client, connection) = tokio_postgres::connect(config, NoTls).await?;
+    // The connection does the actual communication with the database. It runs
+    // whenever the runtime is driving one of our queries.
+    tokio::spawn(connection);
+    client.batch_execute(SCHEMA).await?;
+    let locked: bool = client
+        .query_one("SELECT pg_try_advisory_lock($1)", &[&WRITER_LOCK])
+        .await?
+        .get(0);
+    if !locked {
+        return Err(StoreError::InUse);
+    }
+    Ok(client)
+}
+
+async fn load(
+    client: &impl GenericClient,
+    id: ClientId,
+    which: Deposits,
+) -> Result<Option<Client>, StoreError> {
+    let client_id = i32::from(u16::from(id));
+    let Some(row) = client
+        .query_opt(
+            "SELECT locked, lock_reason, deposit_count, withdrawal_count, dispute_count,
+                resolve_count, chargeback_count, first_line, first_timestamp,
+                last_line, last_timestamp
+             FROM clients WHERE id = $1 FOR UPDATE",
+            &[&client_id],
+        )
+        .await?
+    else {
+        return Ok(None);
+    };
+    let locked = row.get(0);
+    let lock_reason = row.get(1);
+    let count = |i| row.get::<_, i64>(i) as u64;
+    let seen = |i| {
+        let line: Option<i64> = row.get(i);
+        let timestamp: Option<i64> = row.get(i + 1);
+        (line.is_some() || timestamp.is_some()).then(|| Seen {
+            line: line.map(|line| line as u64),
+            timestamp: timestamp.map(|timestamp| timestamp as u64),
+        })
+    };
+    let activity = ClientActivity {
+        deposits: count(2),
+        withdrawals: count(3),
+        disputes: count(4),
+        resolves: count(5),
+        chargebacks: count(6),
+        first: seen(7),
+        last: seen(9),
+    };
+
+    let balances = client
+        .query(
+            "SELECT currency, available, overdrawn, held FROM balances WHERE client = $1",
+            &[&client_id],
+        )
+        .await?
+        .iter()
+        .map(|row| {
+            Ok(BalanceSnapshot {
+                currency: currency_from_sql(row.get(0))?,
+                available: balance_from_sql(row.get(1), row.get(2)),
+                held: amount_from_sql(row.get(3)),
+            })
+        })
+        .collect::<Result<_, StoreError>>()?;
+
+    let rows = match which {
+        Deposits::All => client
+            .query(
+                "SELECT tx, currency, amount, disputed, withdrawal FROM deposits WHERE client = $1",
+                &[&client_id],
+            )
+            .await?,
+        Deposits::One(transaction_id) => {
+            client
+                .query(
+                    "SELECT tx, currency, amount, disputed, withdrawal FROM deposits
+                     WHERE client = $1 AND tx = $2",
+                    &[&client_id, &transaction_id_to_sql(transaction_id)],
+                )
+                .await?
+        }
+        Deposits::None => Vec::new(),
+    };
+    let deposits = rows
+        .iter()
+        .map(|row| {
+            let tx: i64 = row.get(0);
+            Ok(DepositSnapshot {
+                transaction_id: transaction_id_from_sql(tx)?,
+                currency: currency_from_sql(row.get(1))?,
+                amount: amount_from_sql(row.get(2)),
+                disputed: row.get(3),
+                withdrawal: row.get(4),
+            })
+        })
+        .collect::<Result<_, StoreError>>()?;
+
+    let snapshot = ClientSnapshot {
+        id,
+        locked,
+        lock_reason,
+        activity,
+        balances,
+        deposits,
+        // Only the in-memory store expires deposits.
+        order: Vec::new(),
+        expired: Vec::new(),
+    };
+    let client = match which {
+        Deposits::All => Client::restore(snapshot),
+        Deposits::One(_) | Deposits::None => Client::restore_partial(snapshot),
+    };
+    client
+        .map(Some)
+        .map_err(|e| StoreError::Corrupt(e.to_string()))
+}
+
+/// Store the client's balances and lock, and the given deposits.
+async fn save(client: &impl GenericClient, snapshot: &ClientSnapshot) -> Result<(), StoreError> {
+    let client_id = i32::from(u16::from(snapshot.id));
+    let activity = &snapshot.activity;
+    client
+        .execute(
+            "INSERT INTO clients (id, locked, lock_reason, deposit_count, withdrawal_count,
+                dispute_count, resolve_count, chargeback_count, first_line,
+                first_timestamp, last_line, last_timestamp)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+             ON CONFLICT (id) DO UPDATE
+             SET locked = excluded.locked, lock_reason = excluded.lock_reason,
+                deposit_count = excluded.deposit_count,
+                withdrawal_count = excluded.withdrawal_count,
+                dispute_count = excluded.dispute_count,
+                resolve_count = excluded.resolve_count,
+                chargeback_count = excluded.chargeback_count,
+                first_line = excluded.first_line,
+                first_timestamp = excluded.first_timestamp,
+                last_line = excluded.last_line,
+                last_timestamp = excluded.last_timestamp",
+            &[
+                &client_id,
+                &snapshot.locked,
+                &snapshot.lock_reason,
+                &(activity.deposits as i64),
+                &(activity.withdrawals as i64),
+                &(activity.disputes as i64),
+                &(activity.resolves as i64),
+                &(activity.chargebacks as i64),
+                &activity
+                    .first
+                    .and_then(|seen| seen.line.map(|line| line as i64)),
+                &activity
+                    .first
+                    .and_then(|seen| seen.timestamp)
+                    .map(|t| t as i64),
+                &activity
+                    .last
+                    .and_then(|seen| seen.line.map(|line| line as i64)),
+                &activity
+                    .last
+                    .and_then(|seen| seen.timestamp)
+                    .map(|t| t as i64),
+            ],
+        )
+        .await?;
+    for balance in &snapshot.balances {
+        client
+            .execute(
+                "INSERT INTO balances (client, currency, available, overdrawn, held)
+                 VALUES ($1, $2, $3, $4, $5)
+                 ON CONFLICT (client, currency) DO UPDATE
+                 SET available = excluded.available, overdrawn = excluded.overdrawn,
+                     held = excluded.held",
+                &[
+                    &client_id,
+                    &currency_to_sql(balance.currency),
+                    &amount_to_sql(balance.available.magnitude()),
+                    &balance.available.is_negative(),
+                    &amount_to_sql(balance.held),
+                ],
+            )
+            .await?;
+    }
+    for deposit in &snapshot.deposits {
+        client
+            .execute(
+                "INSERT INTO deposits (client, tx, currency, amount, disputed, withdrawal)
+                 VALUES ($1, $2, $3, $4, $5, $6)
+                 ON CONFLICT (client, tx) DO UPDATE
+                 SET currency = excluded.currency, amount = excluded.amount,
+                     disputed = excluded.disputed, withdrawal = excluded.withdrawal",
+                &[
+                    &client_id,
+                    &transaction_id_to_sql(deposit.transaction_id),
+                    &currency_to_sql(deposit.currency),
+                    &amount_to_sql(deposit.amount),
+                    &deposit.disputed,
+                    &deposit.withdrawal,
+                ],
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+async fn update(
+    client: &mut tokio_postgres::Client,
+    id: ClientId,
+    transaction_id: Option<TransactionId>,
+    consumed: Option<String>,
+    f: &mut dyn FnMut(&mut Client) -> Result<(), ClientError>,
+) -> Result<Result<(), ClientError>, StoreError> {
+    let transaction = client.transaction().await?;
+    if let Some(key) = consumed {
+        transaction
+            .execute("INSERT INTO consumed (key) VALUES ($1)", &[&key])
+            .await?;
+    }
+    let which = transaction_id.map_or(Deposits::None, Deposits::One);
+    let mut state = load(&transaction, id, which).await?.unwrap_or_default();
+    let result = f(&mut state);
+    let snapshot = state.snapshot(id);
+    save(&transaction, &snapshot).await?;
+    // The deposit might have been removed, e.g. by a chargeback.
+    if let Some(transaction_id) = transaction_id.filter(|_| snapshot.deposits.is_empty()) {
+        transaction
+            .execute(
+                "DELETE FROM deposits WHERE client = $1 AND tx = $2",
+                &[
+                    &i32::from(u16::from(id)),
+                    &transaction_id_to_sql(transaction_id),
+                ],
+            )
+            .await?;
+    }
+    transaction.commit().await?;
+    Ok(result)
+}
+
+async fn put(
+    client: &mut tokio_postgres::Client,
+    id: ClientId,
+    state: &Client,
+) -> Result<(), StoreError> {
+    let transaction = client.transaction().await?;
+    let client_id = i32::from(u16::from(id));
+    for query in [
+        "DELETE FROM balances WHERE client = $1",
+        "DELETE FROM deposits WHERE client = $1",
+    ] {
+        transaction.execute(query, &[&client_id]).await?;
+    }
+    save(&transaction, &state.snapshot(id)).await?;
+    Ok(transaction.commit().await?)
+}
+
+async fn remove(client: &mut tokio_postgres::Client, id: ClientId) -> Result<bool, StoreError> {
+    let transaction = client.transaction().await?;
+    let client_id = i32::from(u16::from(id));
+    for query in [
+        "DELETE FROM balances WHERE client = $1",
+        "DELETE FROM deposits WHERE client = $1",
+    ] {
+        transaction.execute(query, &[&client_id]).await?;
+    }
+    let removed = transaction
+        .execute("DELETE FROM clients WHERE id = $1", &[&client_id])
+        .await?;
+    transaction.commit().await?;
+    Ok(removed > 0)
+}
+
+impl Store for PostgresStore {
+    fn update(
+        &mut self,
+        id: ClientId,
+        transaction_id: Option<TransactionId>,
+        f: &mut dyn FnMut(&mut Client) -> Result<(), ClientError>,
+    ) -> Result<Result<(), ClientError>, StoreError> {
+        let consumed = self.consumed.take();
+        self.runtime
+            .block_on(update(&mut self.client, id, transaction_id, consumed, f))
+    }
+
+    fn put(&mut self, id: ClientId, client: &Client) -> Result<(), StoreError> {
+        self.runtime.block_on(put(&mut self.client, id, client))
+    }
+
+    fn get(
+        &self,
+        id: ClientId,
+        with_deposits: bool,
+    ) -> Result<Option<Cow<'_, Client>>, StoreError> {
+        let which = if with_deposits {
+            Deposits::All
+        } else {
+            Deposits::None
+        };
+        let client = self.runtime.block_on(load(&self.client, id, which))?;
+        Ok(client.map(Cow::Owned))
+    }
+
+    fn client_ids(&self) -> Result<Vec<ClientId>, StoreError> {
+        let rows = self
+            .runtime
+            .block_on(self.client.query("SELECT id FROM clients ORDER BY id", &[]))?;
+        rows.iter()
+            .map(|row| {
+                let id: i32 = row.get(0);
+                u16::try_from(id)
+                    .map(ClientId::from)
+                    .map_err(|_| StoreError::Corrupt(format!("client ID {}", id)))
+            })
+            .collect()
+    }
+
+    fn remove(&mut self, id: ClientId) -> Result<bool, StoreError> {
+        self.runtime.block_on(remove(&mut self.client, id))
+    }
+
+    fn is_consumed(&self, key: &str) -> Result<bool, StoreError> {
+        let row = self.runtime.block_on(
+            self.client
+                .query_opt("SELECT 1 FROM consumed WHERE key = $1", &[&key]),
+        )?;
+        Ok(row.is_some())
+    }
+
+    fn mark_consumed(&mut self, key: String) {
+        self.consumed = Some(key);
+    }
+}
+
+fn amount_to_sql(amount: Amount) -> i64 {
+    amount.to_minor_units() as i64
+}
+
+fn amount_from_sql(value: i64) -> Amount {
+    Amount::from_minor_units(value as u64)
+}
+
+fn balance_from_sql(value: i64, overdrawn: bool) -> SignedAmount {
+    let balance = SignedAmount::from(amount_from_sql(value));
+    if overdrawn {
+        -balance
+    } else {
+        balance
+    }
+}
+
+fn transaction_id_to_sql(id: TransactionId) -> i64 {
+    u64::from(id) as i64
+}
+
+fn transaction_id_from_sql(value: i64) -> Result<TransactionId, StoreError> {
+    TransactionId::from_u64(value as u64)
+        .ok_or_else(|| StoreError::Corrupt(format!("transaction ID {}", value as u64)))
+}
+
+fn currency_to_sql(currency: Option<Currency>) -> String {
+    currency.map(|c| c.to_string()).unwrap_or_default()
+}
+
+fn currency_from_sql(value: &str) -> Result<Option<Currency>, StoreError> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    Currency::try_from(value)
+        .map(Some)
+        .map_err(|e| StoreError::Corrupt(format!("{}: {:?}", e, value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::Clients;
+    use crate::transaction::load_transactions;
+
+    // Needs a database to test against, e.g.
+    // TRANSACTIONS_TEST_POSTGRES="host=localhost user=postgres". Its tables
+    // are emptied first.
+    #[test]
+    fn test_matches_memory_store() {
+        let Ok(config) = std::env::var("TRANSACTIONS_TEST_POSTGRES") else {
+            return;
+        };
+        let store = PostgresStore::connect(&config).unwrap();
+        store
+            .runtime
+            .block_on(
+                store
+                    .client
+                    .batch_execute("TRUNCATE clients, balances, deposits"),
+            )
+            .unwrap();
+
+        let input = "type, client, tx, amount, currency
+deposit, 1, 1, 1.0, USD
+deposit, 1, 2, 2.0, EUR
+deposit, 2, 3, 3.0,
+withdrawal, 1, 4, 0.5, USD
+dispute, 1, 2, , EUR
+deposit, 2, 5, 1.0,
+dispute, 2, 5
+chargeback, 2, 5
+";
+        let mut expected = Clients::new();
+        let mut actual = Clients::open(Box::new(store)).unwrap();
+        for transaction in load_transactions(input.as_bytes()) {
+            let transaction = transaction.unwrap();
+            let _ = expected.process_transaction(transaction.clone());
+            let _ = actual.process_transaction(transaction);
+        }
+        assert_eq!(actual.snapshot().unwrap(), expected.snapshot().unwrap());
+    }
+}
@@ -0,0 +1,271 @@
+//! A store keeping client state in RocksDB, for workloads with too many
+//! deposits to keep in memory.
+
+use ::rocksdb::{Direction, IteratorMode, WriteBatch, DB};
+use std::borrow::Cow;
+use std::path::Path;
+
+use super::{Store, StoreError};
+use crate::client::{Client, ClientError};
+use crate::snapshot::{ClientSnapshot, DepositSnapshot};
+use crate::transaction::{ClientId, RawTransactionId};
+use crate::TransactionId;
+
+// Each client's balances and lock are stored under `c` followed by the client
+// ID, and each of its deposits under `d` followed by the client ID and the
+// transaction ID. IDs are big-endian so that keys sort in ID order, which
+// keeps a client's deposits together. Values are JSON snapshots. The keys of
+// records consumed are stored under `m` followed by the key, with no value.
+const CLIENT_PREFIX: u8 = b'c';
+const DEPOSIT_PREFIX: u8 = b'd';
+const CONSUMED_PREFIX: u8 = b'm';
+
+/// Keeps client state in RocksDB, keyed by client and by (client,
+/// transaction) for deposits, loading deposits on demand.
+pub struct RocksStore {
+    db: DB,
+    // The key given to `mark_consumed`, until the update it's stored with.
+    consumed: Option<String>,
+}
+
+impl RocksStore {
+    /// Open the database at `path`, creating it if it doesn't exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        Ok(Self {
+            db: DB::open_default(path)?,
+            consumed: None,
+        })
+    }
+
+    fn load_client(&self, id: ClientId) -> Result<Option<ClientSnapshot>, StoreError> {
+        self.db
+            .get(client_key(id))?
+            .map(|value| Ok(serde_json::from_slice(&value)?))
+            .transpose()
+    }
+
+    fn load_deposits(&self, id: ClientId) -> Result<Vec<DepositSnapshot>, StoreError> {
+        let prefix = deposit_prefix(id);
+        let mut deposits = Vec::new();
+        for item in self
+            .db
+            .iterator(IteratorMode::From(&prefix, Direction::Forward))
+        {
+            let (key, value) = item?;
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            deposits.push(serde_json::from_slice(&value)?);
+        }
+        Ok(deposits)
+    }
+
+    fn restore(snapshot: ClientSnapshot, complete: bool) -> Result<Client, StoreError> {
+        let client = if complete {
+            Client::restore(snapshot)
+        } else {
+            Client::restore_partial(snapshot)
+        };
+        client.map_err(|e| StoreError::Corrupt(e.to_string()))
+    }
+
+    /// Add writes for the client's balances and lock to `batch`.
+    fn save_client(batch: &mut WriteBatch, snapshot: &ClientSnapshot) -> Result<(), StoreError> {
+        let client = ClientSnapshot {
+            id: snapshot.id,
+            locked: snapshot.locked,
+            lock_reason: snapshot.lock_reason.clone(),
+            activity: snapshot.activity,
+            balances: snapshot.balances.clone(),
+            deposits: Vec::new(),
+            order: Vec::new(),
+            expired: Vec::new(),
+        };
+        batch.put(client_key(snapshot.id), serde_json::to_vec(&client)?);
+        Ok(())
+    }
+}
+
+impl Store for RocksStore {
+    fn update(
+        &mut self,
+        id: ClientId,
+        transaction_id: Option<TransactionId>,
+        f: &mut dyn FnMut(&mut Client) -> Result<(), ClientError>,
+    ) -> Result<Result<(), ClientError>, StoreError> {
+        let mut client = match self.load_client(id)? {
+            Some(mut snapshot) => {
+                if let Some(transaction_id) = transaction_id {
+                    if let Some(value) = self.db.get(deposit_key(id, transaction_id))? {
+                        snapshot.deposits.push(serde_json::from_slice(&value)?);
+                    }
+                }
+                Self::restore(snapshot, false)?
+            }
+            None => Client::default(),
+        };
+        let result = f(&mut client);
+
+        let snapshot = client.snapshot(id);
+        let mut batch = WriteBatch::default();
+        if let Some(key) = self.consumed.take() {
+            batch.put(consumed_key(&key), []);
+        }
+        Self::save_client(&mut batch, &snapshot)?;
+        // The deposit might have been added, changed, or removed, e.g. by a
+        // chargeback.
+        if let Some(transaction_id) = transaction_id {
+            let key = deposit_key(id, transaction_id);
+            match snapshot.deposits.first() {
+                Some(deposit) => batch.put(key, serde_json::to_vec(deposit)?),
+                None => batch.delete(key),
+            }
+        }
+        self.db.write(batch)?;
+        Ok(result)
+    }
+
+    fn put(&mut self, id: ClientId, client: &Client) -> Result<(), StoreError> {
+        let snapshot = client.snapshot(id);
+        let mut batch = WriteBatch::default();
+        let end = deposit_key(id, TransactionId::MAX);
+        batch.delete_range(deposit_key(id, TransactionId::from(0)), end);
+        // `delete_range` excludes the end of the range.
+        batch.delete(end);
+        Self::save_client(&mut batch, &snapshot)?;
+        for deposit in &snapshot.deposits {
+            batch.put(
+                deposit_key(id, deposit.transaction_id),
+                serde_json::to_vec(deposit)?,
+            );
+        }
+        Ok(self.db.write(batch)?)
+    }
+
+    fn get(
+        &self,
+        id: ClientId,
+        with_deposits: bool,
+    ) -> Result<Option<Cow<'_, Client>>, StoreError> {
+        let Some(mut snapshot) = self.load_client(id)? else {
+            return Ok(None);
+        };
+        if with_deposits {
+            snapshot.deposits = self.load_deposits(id)?;
+        }
+        Ok(Some(Cow::Owned(Self::restore(snapshot, with_deposits)?)))
+    }
+
+    fn client_ids(&self) -> Result<Vec<ClientId>, StoreError> {
+        let mut ids = Vec::new();
+        for item in self
+            .db
+            .iterator(IteratorMode::From(&[CLIENT_PREFIX], Direction::Forward))
+        {
+            let (key, _) = item?;
+            match *key {
+                [CLIENT_PREFIX, high, low] => ids.push(u16::from_be_bytes([high, low]).into()),
+                _ => break,
+            }
+        }
+        Ok(ids)
+    }
+
+    fn remove(&mut self, id: ClientId) -> Result<bool, StoreError> {
+        if self.db.get(client_key(id))?.is_none() {
+            return Ok(false);
+        }
+        let mut batch = WriteBatch::default();
+        let end = deposit_key(id, TransactionId::MAX);
+        batch.delete_range(deposit_key(id, TransactionId::from(0)), end);
+        // `delete_range` excludes the end of the range.
+        batch.delete(end);
+        batch.delete(client_key(id));
+        self.db.write(batch)?;
+        Ok(true)
+    }
+
+    fn flush(&mut self) -> Result<(), StoreError> {
+        // Every write so far is in the write-ahead log, so syncing it to
+        // disk makes them all durable, even against a power failure.
+        Ok(self.db.flush_wal(true)?)
+    }
+
+    fn is_consumed(&self, key: &str) -> Result<bool, StoreError> {
+        Ok(self.db.get(consumed_key(key))?.is_some())
+    }
+
+    fn mark_consumed(&mut self, key: String) {
+        self.consumed = Some(key);
+    }
+}
+
+fn consumed_key(key: &str) -> Vec<u8> {
+    let mut bytes = vec![CONSUMED_PREFIX];
+    bytes.extend_from_slice(key.as_bytes());
+    bytes
+}
+
+fn client_key(id: ClientId) -> [u8; 3] {
+    let [high, low] = u16::from(id).to_be_bytes();
+    [CLIENT_PREFIX, high, low]
+}
+
+fn deposit_prefix(id: ClientId) -> [u8; 3] {
+    let [high, low] = u16::from(id).to_be_bytes();
+    [DEPOSIT_PREFIX, high, low]
+}
+
+// The transaction ID in a deposit's key is as wide as this build's, so a store
+// can't be shared between builds with and without the `wide-tx-ids` feature.
+const DEPOSIT_KEY_LEN: usize = 3 + std::mem::size_of::<RawTransactionId>();
+
+fn deposit_key(id: ClientId, transaction_id: TransactionId) -> [u8; DEPOSIT_KEY_LEN] {
+    let mut key = [0; DEPOSIT_KEY_LEN];
+    key[..3].copy_from_slice(&deposit_prefix(id));
+    key[3..].copy_from_slice(&RawTransactionId::from(transaction_id).to_be_bytes());
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::Clients;
+    use crate::transaction::load_transactions;
+
+    const INPUT: &str = "type, client, tx, amount, currency
+deposit, 1, 1, 1.0, USD
+deposit, 1, 2, 2.0, EUR
+deposit, 2, 3, 3.0,
+withdrawal, 1, 4, 0.5, USD
+dispute, 1, 2, , EUR
+deposit, 2, 5, 1.0,
+dispute, 2, 5
+chargeback, 2, 5
+deposit, 2, 3, 3.0,
+";
+
+    fn process(clients: &mut Clients, input: &str) {
+        for transaction in load_transactions(input.as_bytes()) {
+            let _ = clients.process_transaction(transaction.unwrap());
+        }
+        clients.flush().unwrap();
+    }
+
+    #[test]
+    fn test_reopen() {
+        // Matches the memory store, and state carries over between runs.
+        let dir = tempfile::tempdir().unwrap();
+        let open = || Clients::open(Box::new(RocksStore::open(dir.path()).unwrap())).unwrap();
+        let mut clients = open();
+        process(&mut clients, INPUT);
+        drop(clients);
+        let mut reopened = open();
+        process(&mut reopened, "type, client, tx, amount\ndispute, 2, 3\n");
+
+        let mut expected = Clients::new();
+        process(&mut expected, INPUT);
+        process(&mut expected, "type, client, tx, amount\ndispute, 2, 3\n");
+        assert_eq!(reopened.snapshot().unwrap(), expected.snapshot().unwrap());
+    }
+}
@@ -0,0 +1,658 @@
+//! A store keeping client state in a SQLite database.
+
+use rusqlite::{params, Connection, OptionalExtension};
+use std::borrow::Cow;
+use std::path::Path;
+
+use super::{Deposits, Store, StoreError};
+use crate::client::{Client, ClientActivity, ClientError, Seen};
+use crate::snapshot::{BalanceSnapshot, ClientSnapshot, DepositSnapshot};
+use crate::transaction::ClientId;
+use crate::{Amount, Currency, SignedAmount, TransactionId};
+
+// Amounts are stored as integer minor units. SQLite integers are signed, so
+// amounts above i64::MAX are stored as negative numbers - the conversion is
+// lossless either way. Transaction IDs are stored the same way, so that a
+// database can be read by builds with and without the `wide-tx-ids` feature,
+// as long as its IDs fit. Transactions without a currency are stored with an
+// empty currency, since NULLs don't play well with primary keys.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS clients (
+        id INTEGER PRIMARY KEY,
+        locked INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS balances (
+        client INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        available INTEGER NOT NULL,
+        held INTEGER NOT NULL,
+        PRIMARY KEY (client, currency)
+    );
+    CREATE TABLE IF NOT EXISTS deposits (
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        disputed INTEGER NOT NULL,
+        PRIMARY KEY (client, tx)
+    );
+    CREATE TABLE IF NOT EXISTS consumed (
+        key TEXT PRIMARY KEY
+    );
+";
+
+// The columns of `ClientActivity`, in the same order as its fields, and their
+// types. Counts are stored as signed integers in the same way as amounts. The
+// first and last activity are NULL until there's been any.
+const ACTIVITY_COLUMNS: [(&str, &str); 9] = [
+    ("deposit_count", "INTEGER NOT NULL DEFAULT 0"),
+    ("withdrawal_count", "INTEGER NOT NULL DEFAULT 0"),
+    ("dispute_count", "INTEGER NOT NULL DEFAULT 0"),
+    ("resolve_count", "INTEGER NOT NULL DEFAULT 0"),
+    ("chargeback_count", "INTEGER NOT NULL DEFAULT 0"),
+    ("first_line", "INTEGER"),
+    ("first_timestamp", "INTEGER"),
+    ("last_line", "INTEGER"),
+    ("last_timestamp", "INTEGER"),
+];
+
+// Committing after every transaction would be very slow, so commit in batches.
+// Each batch is applied atomically, so the database always reflects some
+// prefix of the transactions processed.
+const BATCH_SIZE: u64 = 10_000;
+
+/// Keeps client state in a SQLite database, loading deposits on demand.
+///
+/// Updates are committed in batches; call `flush` to commit the last one.
+pub struct SqliteStore {
+    conn: Connection,
+    // Number of updates in the currently open database transaction, if any.
+    pending: u64,
+    // The key given to `mark_consumed`, until the update it's stored with.
+    consumed: Option<String>,
+}
+
+impl SqliteStore {
+    /// Open the database at `path`, creating it if it doesn't exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// Sync every commit to disk before returning, so that a commit isn't
+    /// lost even on power failure, rather than only surviving a crash.
+    pub fn sync_commits(&self) -> Result<(), StoreError> {
+        Ok(self.conn.pragma_update(None, "synchronous", "FULL")?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, StoreError> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self, StoreError> {
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.execute_batch(SCHEMA)?;
+        // Added after the table, so may be missing from existing databases.
+        if conn.prepare("SELECT lock_reason FROM clients").is_err() {
+            conn.execute_batch("ALTER TABLE clients ADD COLUMN lock_reason TEXT")?;
+        }
+        if conn.prepare("SELECT withdrawal FROM deposits").is_err() {
+            conn.execute_batch(
+                "ALTER TABLE deposits ADD COLUMN withdrawal INTEGER NOT NULL DEFAULT 0",
+            )?;
+        }
+        if conn.prepare("SELECT overdrawn FROM balances").is_err() {
+            conn.execute_batch(
+                "ALTER TABLE balances ADD COLUMN overdrawn INTEGER NOT NULL DEFAULT 0",
+            )?;
+        }
+        for (column, kind) in ACTIVITY_COLUMNS {
+            if conn
+                .prepare(&format!("SELECT {} FROM clients", column))
+                .is_err()
+            {
+                conn.execute_batch(&format!(
+                    "ALTER TABLE clients ADD COLUMN {} {}",
+                    column, kind
+                ))?;
+            }
+        }
+        Ok(Self {
+            conn,
+            pending: 0,
+            consumed: None,
+        })
+    }
+
+    fn load(&self, id: ClientId, which: Deposits) -> Result<Option<Client>, StoreError> {
+        let client = u16::from(id);
+        let locked: Option<(bool, Option<String>, ClientActivity)> = self
+            .conn
+            .prepare_cached(
+                "SELECT locked, lock_reason, deposit_count, withdrawal_count, dispute_count,
+                    resolve_count, chargeback_count, first_line, first_timestamp,
+                    last_line, last_timestamp
+                 FROM clients WHERE id = ?1",
+            )?
+            .query_row([client], |row| {
+                let count = |i| row.get::<_, i64>(i).map(|count| count as u64);
+                let seen = |i| -> rusqlite::Result<Option<Seen>> {
+                    let line: Option<i64> = row.get(i)?;
+                    let timestamp: Option<i64> = row.get(i + 1)?;
+                    Ok((line.is_some() || timestamp.is_some()).then(|| Seen {
+                        line: line.map(|line| line as u64),
+                        timestamp: timestamp.map(|timestamp| timestamp as u64),
+                    }))
+                };
+                let activity = ClientActivity {
+                    deposits: count(2)?,
+                    withdrawals: count(3)?,
+                    disputes: count(4)?,
+                    resolves: count(5)?,
+                    chargebacks: count(6)?,
+                    first: seen(7)?,
+                    last: seen(9)?,
+                };
+                Ok((row.get(0)?, row.get(1)?, activity))
+            })
+            .optional()?;
+        let Some((locked, lock_reason, activity)) = locked else {
+            return Ok(None);
+        };
+
+        let balances = self
+            .conn
+            .prepare_cached(
+                "SELECT currency, available, overdrawn, held FROM balances WHERE client = ?1",
+            )?
+            .query_map([client], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                ))
+            })?
+            .map(|row| {
+                let (currency, available, overdrawn, held) = row?;
+                Ok(BalanceSnapshot {
+                    currency: currency_from_sql(&currency)?,
+                    available: balance_from_sql(available, overdrawn),
+                    held: amount_from_sql(held),
+                })
+            })
+            .collect::<Result<_, StoreError>>()?;
+
+        let deposit_from_row = |row: &rusqlite::Row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+            ))
+        };
+        let rows = match which {
+            Deposits::All => self
+                .conn
+                .prepare_cached(
+                    "SELECT tx, currency, amount, disputed, withdrawal FROM deposits WHERE client = ?1",
+                )?
+                .query_map([client], deposit_from_row)?
+                .collect::<Result<Vec<_>, _>>()?,
+            Deposits::One(transaction_id) => self
+                .conn
+                .prepare_cached(
+                    "SELECT tx, currency, amount, disputed, withdrawal FROM deposits
+                     WHERE client = ?1 AND tx = ?2",
+                )?
+                .query_map(
+                    params![client, transaction_id_to_sql(transaction_id)],
+                    deposit_from_row,
+                )?
+                .collect::<Result<Vec<_>, _>>()?,
+            Deposits::None => Vec::new(),
+        };
+        let deposits = rows
+            .into_iter()
+            .map(|(tx, currency, amount, disputed, withdrawal)| {
+                Ok(DepositSnapshot {
+                    transaction_id: transaction_id_from_sql(tx)?,
+                    currency: currency_from_sql(&currency)?,
+                    amount: amount_from_sql(amount),
+                    disputed,
+                    withdrawal,
+                })
+            })
+            .collect::<Result<_, StoreError>>()?;
+
+        let snapshot = ClientSnapshot {
+            id,
+            locked,
+            lock_reason,
+            activity,
+            balances,
+            deposits,
+            // Only the in-memory store expires deposits.
+            order: Vec::new(),
+            expired: Vec::new(),
+        };
+        let client = match which {
+            Deposits::All => Client::restore(snapshot),
+            Deposits::One(_) | Deposits::None => Client::restore_partial(snapshot),
+        };
+        client
+            .map(Some)
+            .map_err(|e| StoreError::Corrupt(e.to_string()))
+    }
+
+    /// Store the client's balances and lock, and the given deposits.
+    fn save(&self, snapshot: &ClientSnapshot) -> Result<(), StoreError> {
+        let client = u16::from(snapshot.id);
+        let activity = &snapshot.activity;
+        self.conn
+            .prepare_cached(
+                "INSERT INTO clients (id, locked, lock_reason, deposit_count, withdrawal_count,
+                    dispute_count, resolve_count, chargeback_count, first_line,
+                    first_timestamp, last_line, last_timestamp)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+                 ON CONFLICT (id) DO UPDATE
+                 SET locked = excluded.locked, lock_reason = excluded.lock_reason,
+                    deposit_count = excluded.deposit_count,
+                    withdrawal_count = excluded.withdrawal_count,
+                    dispute_count = excluded.dispute_count,
+                    resolve_count = excluded.resolve_count,
+                    chargeback_count = excluded.chargeback_count,
+                    first_line = excluded.first_line,
+                    first_timestamp = excluded.first_timestamp,
+                    last_line = excluded.last_line,
+                    last_timestamp = excluded.last_timestamp",
+            )?
+            .execute(params![
+                client,
+                snapshot.locked,
+                snapshot.lock_reason,
+                activity.deposits as i64,
+                activity.withdrawals as i64,
+                activity.disputes as i64,
+                activity.resolves as i64,
+                activity.chargebacks as i64,
+                activity
+                    .first
+                    .and_then(|seen| seen.line.map(|line| line as i64)),
+                activity
+                    .first
+                    .and_then(|seen| seen.timestamp)
+                    .map(|t| t as i64),
+                activity
+                    .last
+                    .and_then(|seen| seen.line.map(|line| line as i64)),
+                activity
+                    .last
+                    .and_then(|seen| seen.timestamp)
+                    .map(|t| t as i64),
+            ])?;
+        for balance in &snapshot.balances {
+            self.conn
+                .prepare_cached(
+                    "INSERT INTO balances (client, currency, available, overdrawn, held)
+                     VALUES (?1, ?2, ?3, ?4, ?5)
+                     ON CONFLICT (client, currency) DO UPDATE
+                     SET available = excluded.available, overdrawn = excluded.overdrawn,
+                         held = excluded.held",
+                )?
+                .execute(params![
+                    client,
+                    currency_to_sql(balance.currency),
+                    amount_to_sql(balance.available.magnitude()),
+                    balance.available.is_negative(),
+                    amount_to_sql(balance.held),
+                ])?;
+        }
+        for deposit in &snapshot.deposits {
+            self.conn
+                .prepare_cached(
+                    "INSERT INTO deposits (client, tx, currency, amount, disputed, withdrawal)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                     ON CONFLICT (client, tx) DO UPDATE
+                     SET currency = excluded.currency, amount = excluded.amount,
+                         disputed = excluded.disputed, withdrawal = excluded.withdrawal",
+                )?
+                .execute(params![
+                    client,
+                    transaction_id_to_sql(deposit.transaction_id),
+                    currency_to_sql(deposit.currency),
+                    amount_to_sql(deposit.amount),
+                    deposit.disputed,
+                    deposit.withdrawal,
+                ])?;
+        }
+        Ok(())
+    }
+
+    /// Start a database transaction for an update, if one isn't already open.
+    fn begin(&mut self) -> Result<(), StoreError> {
+        if self.pending == 0 {
+            self.conn.execute_batch("BEGIN")?;
+        }
+        self.pending += 1;
+        Ok(())
+    }
+
+    fn maybe_commit(&mut self) -> Result<(), StoreError> {
+        if self.pending >= BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Store the client as updated by `f`, and the key of the record the
+    /// update is for, if any.
+    fn save_update(
+        &mut self,
+        id: ClientId,
+        transaction_id: Option<TransactionId>,
+        f: &mut dyn FnMut(&mut Client) -> Result<(), ClientError>,
+    ) -> Result<Result<(), ClientError>, StoreError> {
+        if let Some(key) = self.consumed.take() {
+            self.conn
+                .prepare_cached("INSERT INTO consumed (key) VALUES (?1)")?
+                .execute([key])?;
+        }
+        let mut client = self
+            .load(id, transaction_id.map_or(Deposits::None, Deposits::One))?
+            .unwrap_or_default();
+        let result = f(&mut client);
+        let snapshot = client.snapshot(id);
+        self.save(&snapshot)?;
+        // The deposit might have been removed, e.g. by a chargeback.
+        if let Some(transaction_id) = transaction_id.filter(|_| snapshot.deposits.is_empty()) {
+            self.conn
+                .prepare_cached("DELETE FROM deposits WHERE client = ?1 AND tx = ?2")?
+                .execute(params![
+                    u16::from(id),
+                    transaction_id_to_sql(transaction_id)
+                ])?;
+        }
+        Ok(result)
+    }
+}
+
+impl Store for SqliteStore {
+    fn update(
+        &mut self,
+        id: ClientId,
+        transaction_id: Option<TransactionId>,
+        f: &mut dyn FnMut(&mut Client) -> Result<(), ClientError>,
+    ) -> Result<Result<(), ClientError>, StoreError> {
+        self.begin()?;
+        // An update that fails part way is undone, so that committing the
+        // batch afterwards, e.g. when the program stops on the error, can't
+        // store half of it, nor a record's key without its effects.
+        self.conn.execute_batch("SAVEPOINT client_update")?;
+        let result = self.save_update(id, transaction_id, f);
+        if result.is_err() {
+            self.conn.execute_batch("ROLLBACK TO client_update")?;
+        }
+        self.conn.execute_batch("RELEASE client_update")?;
+        let result = result?;
+        self.maybe_commit()?;
+        Ok(result)
+    }
+
+    fn put(&mut self, id: ClientId, client: &Client) -> Result<(), StoreError> {
+        self.begin()?;
+        for table in ["balances", "deposits"] {
+            self.conn
+                .prepare_cached(&format!("DELETE FROM {} WHERE client = ?1", table))?
+                .execute([u16::from(id)])?;
+        }
+        self.save(&client.snapshot(id))?;
+        self.maybe_commit()
+    }
+
+    fn get(
+        &self,
+        id: ClientId,
+        with_deposits: bool,
+    ) -> Result<Option<Cow<'_, Client>>, StoreError> {
+        let which = if with_deposits {
+            Deposits::All
+        } else {
+            Deposits::None
+        };
+        Ok(self.load(id, which)?.map(Cow::Owned))
+    }
+
+    fn client_ids(&self) -> Result<Vec<ClientId>, StoreError> {
+        Ok(self
+            .conn
+            .prepare_cached("SELECT id FROM clients ORDER BY id")?
+            .query_map([], |row| row.get::<_, u16>(0))?
+            .map(|id| id.map(ClientId::from))
+            .collect::<Result<_, _>>()?)
+    }
+
+    fn remove(&mut self, id: ClientId) -> Result<bool, StoreError> {
+        self.begin()?;
+        for table in ["balances", "deposits"] {
+            self.conn
+                .prepare_cached(&format!("DELETE FROM {} WHERE client = ?1", table))?
+                .execute([u16::from(id)])?;
+        }
+        let removed = self
+            .conn
+            .prepare_cached("DELETE FROM clients WHERE id = ?1")?
+            .execute([u16::from(id)])?;
+        self.maybe_commit()?;
+        Ok(removed > 0)
+    }
+
+    fn flush(&mut self) -> Result<(), StoreError> {
+        if self.pending > 0 {
+            self.conn.execute_batch("COMMIT")?;
+            self.pending = 0;
+        }
+        Ok(())
+    }
+
+    fn is_consumed(&self, key: &str) -> Result<bool, StoreError> {
+        Ok(self
+            .conn
+            .prepare_cached("SELECT 1 FROM consumed WHERE key = ?1")?
+            .exists([key])?)
+    }
+
+    fn mark_consumed(&mut self, key: String) {
+        self.consumed = Some(key);
+    }
+}
+
+fn amount_to_sql(amount: Amount) -> i64 {
+    amount.to_minor_units() as i64
+}
+
+fn amount_from_sql(value: i64) -> Amount {
+    Amount::from_minor_units(value as u64)
+}
+
+// A negative available balance is stored as its magnitude and whether it's
+// overdrawn, so that it has the same range as an amount.
+fn balance_from_sql(value: i64, overdrawn: bool) -> SignedAmount {
+    let balance = SignedAmount::from(amount_from_sql(value));
+    if overdrawn {
+        -balance
+    } else {
+        balance
+    }
+}
+
+fn transaction_id_to_sql(id: TransactionId) -> i64 {
+    u64::from(id) as i64
+}
+
+fn transaction_id_from_sql(value: i64) -> Result<TransactionId, StoreError> {
+    TransactionId::from_u64(value as u64)
+        .ok_or_else(|| StoreError::Corrupt(format!("transaction ID {}", value as u64)))
+}
+
+fn currency_to_sql(currency: Option<Currency>) -> String {
+    currency.map(|c| c.to_string()).unwrap_or_default()
+}
+
+fn currency_from_sql(value: &str) -> Result<Option<Currency>, StoreError> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    Currency::try_from(value)
+        .map(Some)
+        .map_err(|e| StoreError::Corrupt(format!("{}: {:?}", e, value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::Clients;
+    use crate::transaction::load_transactions;
+
+    const INPUT: &str = "type, client, tx, amount, currency
+deposit, 1, 1, 1.0, USD
+deposit, 1, 2, 2.0, EUR
+deposit, 2, 3, 3.0,
+withdrawal, 1, 4, 0.5, USD
+dispute, 1, 2, , EUR
+deposit, 2, 5, 1.0,
+dispute, 2, 5
+chargeback, 2, 5
+deposit, 2, 3, 3.0,
+";
+
+    fn process(clients: &mut Clients, input: &str) {
+        for transaction in load_transactions(input.as_bytes()) {
+            let _ = clients.process_transaction(transaction.unwrap());
+        }
+        clients.flush().unwrap();
+    }
+
+    #[test]
+    fn test_matches_memory_store() {
+        let mut expected = Clients::new();
+        process(&mut expected, INPUT);
+        let mut actual = Clients::open(Box::new(SqliteStore::open_in_memory().unwrap())).unwrap();
+        process(&mut actual, INPUT);
+        assert_eq!(actual.snapshot().unwrap(), expected.snapshot().unwrap());
+
+        expected.remove(2.into()).unwrap();
+        actual.remove(2.into()).unwrap();
+        assert_eq!(actual.snapshot().unwrap(), expected.snapshot().unwrap());
+    }
+
+    #[test]
+    fn test_reopen() {
+        // State, including deposits that can still be disputed, carries over
+        // between runs.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clients.db");
+        let open = || Clients::open(Box::new(SqliteStore::open(&path).unwrap())).unwrap();
+        process(&mut open(), INPUT);
+        let mut reopened = open();
+        process(&mut reopened, "type, client, tx, amount\ndispute, 2, 3\n");
+
+        reopened.lock(1.into(), "suspected fraud").unwrap();
+        reopened.flush().unwrap();
+
+        let mut expected = Clients::new();
+        process(&mut expected, INPUT);
+        process(&mut expected, "type, client, tx, amount\ndispute, 2, 3\n");
+        expected.lock(1.into(), "suspected fraud").unwrap();
+        assert_eq!(open().snapshot().unwrap(), expected.snapshot().unwrap());
+    }
+
+    #[test]
+    fn test_consumed() {
+        // A record's key is only stored when its update is committed.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clients.db");
+        let record = || load_transactions("type,client,tx,amount\ndeposit,1,1,1.0\n".as_bytes());
+        {
+            let mut clients = Clients::open(Box::new(SqliteStore::open(&path).unwrap())).unwrap();
+            clients.set_record_key("m1#0");
+            clients
+                .process_transaction(record().next().unwrap().unwrap())
+                .unwrap();
+            // Dropped without flushing, as if the program crashed.
+        }
+        let mut store = SqliteStore::open(&path).unwrap();
+        assert!(!store.is_consumed("m1#0").unwrap());
+        assert_eq!(store.client_ids().unwrap(), []);
+
+        // Even if the transaction is rejected.
+        store.mark_consumed("m1#0".to_string());
+        store
+            .update(1.into(), None, &mut |_| Err(ClientError::InsufficientFunds))
+            .unwrap()
+            .unwrap_err();
+        store.flush().unwrap();
+        let store = SqliteStore::open(&path).unwrap();
+        assert!(store.is_consumed("m1#0").unwrap());
+        assert!(!store.is_consumed("m1#1").unwrap());
+    }
+
+    #[test]
+    fn test_overdrawn() {
+        // Negative balances keep their sign, however far below zero they are.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clients.db");
+        let summary = "client,available,held,total,locked
+1,-1844674407370955.1615,0,-1844674407370955.1615,false
+2,-2.5,0,-2.5,false
+";
+        let mut seeded = Clients::seed(
+            Box::new(SqliteStore::open(&path).unwrap()),
+            summary.as_bytes(),
+        )
+        .unwrap();
+        seeded.flush().unwrap();
+        let expected = seeded.snapshot().unwrap();
+        drop(seeded);
+        let reopened = Clients::open(Box::new(SqliteStore::open(&path).unwrap())).unwrap();
+        assert_eq!(reopened.snapshot().unwrap(), expected);
+        let client = reopened.get(1.into()).unwrap().unwrap();
+        assert_eq!(client.available(None), SignedAmount::MIN);
+    }
+
+    #[test]
+    fn test_adds_lock_reason() {
+        // Databases created before lock reasons were kept get the column.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clients.db");
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE clients (id INTEGER PRIMARY KEY, locked INTEGER NOT NULL);
+                 INSERT INTO clients (id, locked) VALUES (1, 1);",
+            )
+            .unwrap();
+        let clients = Clients::open(Box::new(SqliteStore::open(&path).unwrap())).unwrap();
+        let client = clients.get(1.into()).unwrap().unwrap();
+        assert!(client.locked());
+        assert_eq!(client.lock_reason(), None);
+        assert!(client.activity().is_empty());
+    }
+
+    #[test]
+    fn test_sync_commits() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteStore::open(dir.path().join("clients.db")).unwrap();
+        let synchronous = |store: &SqliteStore| {
+            store
+                .conn
+                .query_row("PRAGMA synchronous", [], |row| row.get::<_, i64>(0))
+                .unwrap()
+        };
+        // NORMAL, then FULL.
+        assert_eq!(synchronous(&store), 1);
+        store.sync_commits().unwrap();
+        assert_eq!(synchronous(&store), 2);
+    }
+}
@@ -0,0 +1,122 @@
+//! A minimal network intake: CSV records over TCP, one per line, without a
+//! header, in the order `type,client,tx,amount,currency`.
+//!
+//! Each record gets a reply line, in order: `ack` if it was accepted, or
+//! `nack <code>` with the reason it was rejected or couldn't be read, e.g.
+//! `nack insufficient_funds` or `nack missing_amount`, or `pending` if it's
+//! held for the deposit it refers to. Blank lines are
+//! ignored. Records for a client are applied in the order they arrive, from
+//! whichever connection.
+
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::clients::ProcessError;
+use crate::shared::{SharedClients, SharedError};
+use crate::transaction::parse_record;
+
+/// Accept connections on `listener` until it fails, handling each on a
+/// thread of its own.
+pub fn serve(listener: TcpListener, clients: SharedClients) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                tracing::warn!("failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        let clients = clients.clone();
+        std::thread::spawn(move || {
+            let peer = stream.peer_addr();
+            if let Err(e) = connection(stream, &clients) {
+                tracing::warn!("connection from {:?} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+fn connection(stream: TcpStream, clients: &SharedClients) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(stream.try_clone()?);
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        if !line.trim().is_empty() {
+            let reply = apply(clients, line.trim()).map_err(std::io::Error::other)?;
+            writeln!(writer, "{}", reply)?;
+        }
+        // Only flush once every record received so far has a reply, so that
+        // replies to a batch go out together.
+        if reader.buffer().is_empty() {
+            writer.flush()?;
+        }
+    }
+    writer.flush()
+}
+
+/// Apply one record, returning the reply.
+fn apply(clients: &SharedClients, record: &str) -> Result<String, SharedError> {
+    let transaction = match parse_record(record) {
+        Ok(transaction) => transaction,
+        Err(e) => return Ok(format!("nack {}", e.name())),
+    };
+    Ok(match clients.process_transaction(transaction) {
+        Ok(_) => "ack".to_string(),
+        Err(SharedError::Process(ProcessError::Rejected(e))) => format!("nack {}", e.name()),
+        Err(SharedError::Process(ProcessError::Pending)) => "pending".to_string(),
+        Err(SharedError::Process(ProcessError::Store(e))) => {
+            tracing::error!("store error: {}", e);
+            "nack store".to_string()
+        }
+        Err(e) => return Err(e),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use std::io::Read;
+    use std::num::NonZeroUsize;
+
+    #[test]
+    fn test_apply() {
+        let clients = SharedClients::start(None, NonZeroUsize::new(2).unwrap()).unwrap();
+        let apply = |record| apply(&clients, record).unwrap();
+        assert_eq!(apply("deposit,1,1,10.0"), "ack");
+        assert_eq!(apply("withdrawal, 1, 2, 20.0"), "nack insufficient_funds");
+        assert_eq!(apply("deposit,1,3"), "nack missing_amount");
+        assert_eq!(apply("deposit,1,3,1.0.0"), "nack invalid_amount");
+        assert_eq!(apply("refund,1,3,1.0"), "nack csv");
+        assert_eq!(apply("deposit,1,4,1.0,EUR"), "ack");
+    }
+
+    #[test]
+    fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let engine = Engine::start(None).unwrap();
+        std::thread::spawn({
+            let engine = engine.clone();
+            move || serve(listener, engine.into())
+        });
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"deposit,7,1,10.0\n\nwithdrawal,7,2,20.0\ndispute,7,1\n")
+            .unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut replies = String::new();
+        stream.read_to_string(&mut replies).unwrap();
+        assert_eq!(replies, "ack\nnack insufficient_funds\nack\n");
+
+        let held = engine
+            .call(|clients| clients.get(7.into()).unwrap().unwrap().held(None))
+            .unwrap();
+        assert_eq!(held.to_string(), "10.0000");
+    }
+}
@@ -0,0 +1,156 @@
+//! Exporting traces and metrics over OTLP, so that processing spans and
+//! rejection rates show up alongside the rest of our services.
+//!
+//! Spans come from `tracing`, via the layer returned by `Telemetry::init`.
+//! Metrics are recorded through the global meter, which does nothing until
+//! `Telemetry::init` installs an exporter.
+
+use once_cell::sync::Lazy;
+use opentelemetry::metrics::Counter;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::runtime;
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::Resource;
+use std::cell::RefCell;
+
+use crate::client::ClientError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TelemetryError {
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("trace exporter: {0}")]
+    Trace(#[from] opentelemetry::trace::TraceError),
+    #[error("metric exporter: {0}")]
+    Metrics(#[from] opentelemetry_sdk::metrics::MetricError),
+}
+
+struct Instruments {
+    processed: Counter<u64>,
+    rejected: Counter<u64>,
+}
+
+static INSTRUMENTS: Lazy<Instruments> = Lazy::new(|| {
+    let meter = opentelemetry::global::meter("transactions");
+    Instruments {
+        processed: meter
+            .u64_counter("transactions.processed")
+            .with_description("Transactions processed, whether or not they were accepted")
+            .build(),
+        rejected: meter
+            .u64_counter("transactions.rejected")
+            .with_description("Transactions rejected, by reason")
+            .build(),
+    }
+});
+
+thread_local! {
+    // The tenant whose clients this thread processes transactions for, if the
+    // server hosts several.
+    static TENANT: RefCell<Option<KeyValue>> = const { RefCell::new(None) };
+}
+
+/// Label the metrics recorded on this thread from now on with `tenant`.
+pub fn set_tenant(tenant: String) {
+    TENANT.with(|t| *t.borrow_mut() = Some(KeyValue::new("tenant", tenant)));
+}
+
+/// Count a processed transaction, and why it was rejected, if it was.
+pub fn record_processed(result: Result<(), &ClientError>) {
+    TENANT.with(|tenant| {
+        let mut labels: Vec<KeyValue> = tenant.borrow().iter().cloned().collect();
+        INSTRUMENTS.processed.add(1, &labels);
+        if let Err(error) = result {
+            labels.push(KeyValue::new("reason", error.name()));
+            INSTRUMENTS.rejected.add(1, &labels);
+        }
+    })
+}
+
+/// Exporters sending to an OTLP collector. Dropping this flushes anything
+/// not yet exported.
+pub struct Telemetry {
+    tracer_provider: TracerProvider,
+    meter_provider: SdkMeterProvider,
+    // The exporters run on this runtime, in the background.
+    _runtime: tokio::runtime::Runtime,
+}
+
+impl Telemetry {
+    /// Start exporting to the OTLP/gRPC collector at `endpoint`, e.g.
+    /// `http://localhost:4317`. Returns a layer to add to the `tracing`
+    /// subscriber to export spans.
+    pub fn init<S>(
+        endpoint: &str,
+    ) -> Result<(Self, impl tracing_subscriber::Layer<S>), TelemetryError>
+    where
+        S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+    {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()?;
+        // The exporters start their background tasks on the current runtime.
+        let _guard = runtime.enter();
+        let resource = Resource::new([KeyValue::new("service.name", "transactions")]);
+
+        let span_exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()?;
+        let tracer_provider = TracerProvider::builder()
+            .with_batch_exporter(span_exporter, runtime::Tokio)
+            .with_resource(resource.clone())
+            .build();
+        let layer =
+            tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer("transactions"));
+
+        let metric_exporter = opentelemetry_otlp::MetricExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()?;
+        let meter_provider = SdkMeterProvider::builder()
+            .with_reader(PeriodicReader::builder(metric_exporter, runtime::Tokio).build())
+            .with_resource(resource)
+            .build();
+        opentelemetry::global::set_meter_provider(meter_provider.clone());
+
+        let telemetry = Self {
+            tracer_provider,
+            meter_provider,
+            _runtime: runtime,
+        };
+        Ok((telemetry, layer))
+    }
+
+    /// Export anything not yet exported and stop, e.g. before exiting
+    /// without dropping this. Later calls do nothing.
+    pub fn shutdown(&self) {
+        // There's nowhere to report errors to by now.
+        let _ = self.tracer_provider.shutdown();
+        let _ = self.meter_provider.shutdown();
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_without_exporter() {
+        // Recording is harmless when telemetry isn't set up.
+        record_processed(Ok(()));
+        record_processed(Err(&ClientError::InsufficientFunds));
+        set_tenant("acme".to_string());
+        record_processed(Err(&ClientError::Locked));
+    }
+}
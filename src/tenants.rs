@@ -0,0 +1,246 @@
+//! Hosting several independent ledgers in one server, e.g. one per brand,
+//! each with clients of its own.
+//!
+//! Each tenant's clients are owned by `SharedClients` of their own, with as
+//! many shards as the default clients, started the first time the tenant is
+//! used, so tenants never wait on each other.
+//!
+//! Only tenants configured up front, or with a snapshot to start from, are
+//! hosted, so that requests can't start any number of them. Each tenant's
+//! clients are saved back to its snapshot by `save`.
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::clients::Listener;
+use crate::shared::{SharedClients, SharedError};
+use crate::snapshot::{Snapshot, SnapshotError};
+
+/// The longest tenant name allowed.
+const MAX_NAME_LEN: usize = 64;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TenantError {
+    #[error("invalid tenant name '{0}'")]
+    InvalidName(String),
+    #[error("this server doesn't host tenants")]
+    NotMultiTenant,
+    #[error("no tenant '{0}'")]
+    Unknown(String),
+    #[error("failed to load tenant '{0}': {1}")]
+    Snapshot(String, #[source] SnapshotError),
+    #[error("failed to save tenant '{0}': {1}")]
+    Save(String, #[source] SharedError),
+}
+
+pub struct Tenants {
+    /// Used for requests that don't name a tenant.
+    default: SharedClients,
+    /// `None` unless the server hosts tenants.
+    tenants: Option<Mutex<HashMap<String, SharedClients>>>,
+    /// Tenants hosted whether or not they have a snapshot.
+    configured: HashSet<String>,
+    /// Where to look for `<tenant>.json` to start a tenant from, and save it
+    /// to, if anywhere.
+    snapshot_dir: Option<PathBuf>,
+    /// Makes a listener for each tenant's clients, given the tenant's name,
+    /// or `None` for the default clients.
+    listener: Option<MakeListener>,
+}
+
+type MakeListener = Box<dyn Fn(Option<&str>) -> Listener + Send + Sync>;
+
+impl Tenants {
+    /// A server with only one set of clients.
+    pub fn single(clients: impl Into<SharedClients>) -> Self {
+        Self {
+            default: clients.into(),
+            tenants: None,
+            configured: HashSet::new(),
+            snapshot_dir: None,
+            listener: None,
+        }
+    }
+
+    /// A server hosting tenants as well as `default`: those added by
+    /// `with_tenants`, and, if `snapshot_dir` is given, any with a snapshot
+    /// at `<snapshot_dir>/<tenant>.json`, which they're started from.
+    pub fn multi(default: impl Into<SharedClients>, snapshot_dir: Option<PathBuf>) -> Self {
+        Self {
+            default: default.into(),
+            tenants: Some(Mutex::new(HashMap::new())),
+            configured: HashSet::new(),
+            snapshot_dir,
+            listener: None,
+        }
+    }
+
+    /// Add a listener made by `make` to every tenant's clients, e.g. to
+    /// send webhooks labelled with the tenant.
+    pub fn with_listener(
+        mut self,
+        make: impl Fn(Option<&str>) -> Listener + Send + Sync + 'static,
+    ) -> Self {
+        // If the clients have stopped, so has everything else.
+        let _ = self.default.add_listener(|| make(None));
+        self.listener = Some(Box::new(make));
+        self
+    }
+
+    /// Also host `names`, starting from scratch if they have no snapshot.
+    pub fn with_tenants(
+        mut self,
+        names: impl IntoIterator<Item = String>,
+    ) -> Result<Self, TenantError> {
+        for name in names {
+            validate(&name)?;
+            self.configured.insert(name);
+        }
+        Ok(self)
+    }
+
+    pub fn default_clients(&self) -> &SharedClients {
+        &self.default
+    }
+
+    /// The clients for `tenant`, starting them if this is its first use.
+    pub fn get(&self, tenant: &str) -> Result<SharedClients, TenantError> {
+        let tenants = self.tenants.as_ref().ok_or(TenantError::NotMultiTenant)?;
+        validate(tenant)?;
+
+        // Held while a new tenant's snapshot loads, so that it's only started
+        // once.
+        let mut tenants = tenants.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(clients) = tenants.get(tenant) {
+            return Ok(clients.clone());
+        }
+        let path = self
+            .snapshot_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.json", tenant)));
+        let snapshot = match path {
+            Some(path) if path.exists() => {
+                let snapshot = Snapshot::load(&path)
+                    .map_err(|e| TenantError::Snapshot(tenant.to_string(), e))?;
+                Some(snapshot)
+            }
+            _ if self.configured.contains(tenant) => None,
+            _ => return Err(TenantError::Unknown(tenant.to_string())),
+        };
+        let clients = SharedClients::start_tenant(tenant, snapshot, self.default.shards())
+            .map_err(|e| TenantError::Snapshot(tenant.to_string(), e))?;
+        if let Some(make) = &self.listener {
+            // Sent before any other job, so it sees every transaction.
+            let _ = clients.add_listener(|| make(Some(tenant)));
+        }
+        tracing::info!(tenant, "started tenant");
+        tenants.insert(tenant.to_string(), clients.clone());
+        Ok(clients)
+    }
+
+    /// Save each started tenant's clients to `<snapshot_dir>/<tenant>.json`,
+    /// if there's a `snapshot_dir`, e.g. on shutdown. Tenants not yet
+    /// started keep the snapshot they have, if any.
+    pub fn save(&self) -> Result<(), TenantError> {
+        let (Some(tenants), Some(dir)) = (&self.tenants, &self.snapshot_dir) else {
+            return Ok(());
+        };
+        // Cloned so that tenants can still be started meanwhile.
+        let started: Vec<_> = tenants
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(tenant, clients)| (tenant.clone(), clients.clone()))
+            .collect();
+        for (tenant, clients) in started {
+            let path = dir.join(format!("{}.json", tenant));
+            clients
+                .snapshot()
+                .and_then(|snapshot| Ok(snapshot.save(path)?))
+                .map_err(|e| TenantError::Save(tenant, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Check `tenant` is a valid name: ASCII letters, digits, '-' and '_', as
+/// names are used in file names.
+fn validate(tenant: &str) -> Result<(), TenantError> {
+    let valid = !tenant.is_empty()
+        && tenant.len() <= MAX_NAME_LEN
+        && tenant
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if !valid {
+        return Err(TenantError::InvalidName(tenant.to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::Clients;
+    use crate::engine::Engine;
+    use crate::transaction::parse_record;
+
+    fn deposit(clients: &SharedClients, record: &str) {
+        clients
+            .process_transaction(parse_record(record).unwrap())
+            .unwrap();
+    }
+
+    fn client_count(clients: &SharedClients) -> usize {
+        clients.snapshot().unwrap().clients.len()
+    }
+
+    #[test]
+    fn test_tenants() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut saved = Clients::new();
+        saved
+            .process_transaction(parse_record("deposit,1,1,1.0").unwrap())
+            .unwrap();
+        saved.save_snapshot(dir.path().join("loaded.json")).unwrap();
+
+        let tenants = Tenants::multi(Engine::start(None).unwrap(), Some(dir.path().to_path_buf()))
+            .with_tenants(["acme".to_string(), "other".to_string()])
+            .unwrap();
+        deposit(&tenants.get("acme").unwrap(), "deposit,1,1,1.0");
+        deposit(&tenants.get("acme").unwrap(), "deposit,2,2,1.0");
+        assert_eq!(client_count(&tenants.get("acme").unwrap()), 2);
+        assert_eq!(client_count(&tenants.get("other").unwrap()), 0);
+        assert_eq!(client_count(tenants.default_clients()), 0);
+        assert_eq!(client_count(&tenants.get("loaded").unwrap()), 1);
+        // Only configured tenants, and those with snapshots, are hosted.
+        assert!(matches!(
+            tenants.get("unknown"),
+            Err(TenantError::Unknown(_))
+        ));
+
+        // Saved tenants start from where they left off.
+        deposit(&tenants.get("loaded").unwrap(), "deposit,2,2,1.0");
+        tenants.save().unwrap();
+        let restarted =
+            Tenants::multi(Engine::start(None).unwrap(), Some(dir.path().to_path_buf()));
+        assert_eq!(client_count(&restarted.get("acme").unwrap()), 2);
+        assert_eq!(client_count(&restarted.get("loaded").unwrap()), 2);
+        assert_eq!(client_count(&restarted.get("other").unwrap()), 0);
+
+        for name in ["", "../etc", "a b", &"x".repeat(65)] {
+            assert!(matches!(
+                tenants.get(name),
+                Err(TenantError::InvalidName(_))
+            ));
+        }
+        assert!(matches!(
+            Tenants::multi(Engine::start(None).unwrap(), None).with_tenants(["a/b".to_string()]),
+            Err(TenantError::InvalidName(_))
+        ));
+        assert!(matches!(
+            Tenants::single(Engine::start(None).unwrap()).get("acme"),
+            Err(TenantError::NotMultiTenant)
+        ));
+    }
+}
@@ -0,0 +1,257 @@
+//! Helpers for tests of code embedding the engine, with the `test-util`
+//! feature: builders for transactions, `assert_client!` to check a client's
+//! balances, and loaders for fixture files.
+//!
+//! ```ignore
+//! use transactions::assert_client;
+//! use transactions::test_util::{process, TransactionBuilder};
+//!
+//! let mut clients = process("type,client,tx,amount\ndeposit,7,1,10.0\n");
+//! clients
+//!     .process_transaction(TransactionBuilder::withdrawal(7, 2, "2.5").build())
+//!     .unwrap();
+//! assert_client!(clients, 7, "7.5", "0", "7.5", false);
+//! ```
+//!
+//! Everything here panics rather than returning errors, as it's only meant
+//! for tests.
+
+use std::path::Path;
+
+use crate::clients::Clients;
+use crate::transaction::{
+    load_transactions, ClientId, RawTransactionId, Transaction, TransactionData,
+};
+use crate::{Amount, Currency, SignedAmount, TransactionId};
+
+/// Builds a transaction, e.g. `TransactionBuilder::deposit(7, 1,
+/// "1.0").currency("GBP").build()`.
+#[derive(Debug, Clone)]
+pub struct TransactionBuilder {
+    transaction: Transaction,
+}
+
+impl TransactionBuilder {
+    fn new(client: u16, data: TransactionData) -> Self {
+        Self {
+            transaction: Transaction {
+                client_id: ClientId::from(client),
+                currency: None,
+                data,
+            },
+        }
+    }
+
+    pub fn deposit(client: u16, tx: RawTransactionId, amount: &str) -> Self {
+        Self::new(
+            client,
+            TransactionData::Deposit {
+                transaction_id: TransactionId::from(tx),
+                amount: amount_of(amount),
+            },
+        )
+    }
+
+    pub fn withdrawal(client: u16, tx: RawTransactionId, amount: &str) -> Self {
+        Self::new(
+            client,
+            TransactionData::Withdrawal {
+                transaction_id: TransactionId::from(tx),
+                amount: amount_of(amount),
+            },
+        )
+    }
+
+    pub fn dispute(client: u16, tx: RawTransactionId) -> Self {
+        Self::new(
+            client,
+            TransactionData::Dispute {
+                transaction_id: TransactionId::from(tx),
+            },
+        )
+    }
+
+    pub fn resolve(client: u16, tx: RawTransactionId) -> Self {
+        Self::new(
+            client,
+            TransactionData::Resolve {
+                transaction_id: TransactionId::from(tx),
+            },
+        )
+    }
+
+    pub fn chargeback(client: u16, tx: RawTransactionId) -> Self {
+        Self::new(
+            client,
+            TransactionData::Chargeback {
+                transaction_id: TransactionId::from(tx),
+            },
+        )
+    }
+
+    /// In `code`, e.g. "GBP", rather than without a currency.
+    pub fn currency(mut self, code: &str) -> Self {
+        self.transaction.currency = Some(currency_of(code));
+        self
+    }
+
+    pub fn build(self) -> Transaction {
+        self.transaction
+    }
+}
+
+impl From<TransactionBuilder> for Transaction {
+    fn from(builder: TransactionBuilder) -> Self {
+        builder.build()
+    }
+}
+
+/// Process the transactions in `csv`, which has a header as an input file
+/// does. Rejected transactions are skipped, as they are by the CLI, but
+/// invalid records panic.
+pub fn process(csv: &str) -> Clients {
+    let mut clients = Clients::new();
+    for transaction in load_transactions(csv.as_bytes()) {
+        let transaction = transaction.unwrap_or_else(|e| panic!("invalid record: {}", e));
+        let _ = clients.process_transaction(transaction);
+    }
+    clients
+}
+
+/// Process the transactions in the input file at `path`, as for `process`.
+pub fn process_fixture(path: impl AsRef<Path>) -> Clients {
+    process(&read_fixture(path.as_ref()))
+}
+
+/// The transactions in the input file at `path`.
+pub fn load_fixture(path: impl AsRef<Path>) -> Vec<Transaction> {
+    let path = path.as_ref();
+    load_transactions(read_fixture(path).as_bytes())
+        .map(|transaction| {
+            transaction.unwrap_or_else(|e| panic!("invalid record in {}: {}", path.display(), e))
+        })
+        .collect()
+}
+
+fn read_fixture(path: &Path) -> String {
+    std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("failed to read {}: {}", path.display(), e))
+}
+
+fn amount_of(amount: &str) -> Amount {
+    Amount::try_from(amount).unwrap_or_else(|e| panic!("invalid amount '{}': {}", amount, e))
+}
+
+fn balance_of(balance: &str) -> SignedAmount {
+    SignedAmount::try_from(balance)
+        .unwrap_or_else(|e| panic!("invalid balance '{}': {}", balance, e))
+}
+
+fn currency_of(code: &str) -> Currency {
+    Currency::try_from(code).unwrap_or_else(|e| panic!("invalid currency '{}': {}", code, e))
+}
+
+/// Check a client's balances, e.g. `assert_client!(clients, 7, "7.5", "2.5",
+/// "10", false)` for available, held, total, and locked, or
+/// `assert_client!(clients, 7, currency = "GBP", ...)` for those in a
+/// currency. Amounts are compared as amounts, so "1.5" matches "1.5000".
+#[macro_export]
+macro_rules! assert_client {
+    ($clients:expr, $client:expr, currency = $currency:expr, $available:expr, $held:expr, $total:expr, $locked:expr $(,)?) => {
+        $crate::test_util::check_client(
+            &$clients,
+            $client,
+            Some($currency),
+            [$available, $held, $total],
+            $locked,
+        )
+    };
+    ($clients:expr, $client:expr, $available:expr, $held:expr, $total:expr, $locked:expr $(,)?) => {
+        $crate::test_util::check_client(
+            &$clients,
+            $client,
+            None,
+            [$available, $held, $total],
+            $locked,
+        )
+    };
+}
+
+/// What `assert_client!` calls.
+#[doc(hidden)]
+#[track_caller]
+pub fn check_client(
+    clients: &Clients,
+    client: u16,
+    currency: Option<&str>,
+    [available, held, total]: [&str; 3],
+    locked: bool,
+) {
+    let id = ClientId::from(client);
+    let state = clients
+        .get(id)
+        .unwrap_or_else(|e| panic!("failed to read client {}: {}", client, e))
+        .unwrap_or_else(|| panic!("client {} doesn't exist", client));
+    let currency = currency.map(currency_of);
+    let expected = (
+        balance_of(available),
+        amount_of(held),
+        balance_of(total),
+        locked,
+    );
+    let actual = (
+        state.available(currency),
+        state.held(currency),
+        state.total(currency),
+        state.locked(),
+    );
+    assert!(
+        actual == expected,
+        "client {}: expected available {}, held {}, total {}, locked {}, \
+         but was available {}, held {}, total {}, locked {}",
+        client,
+        expected.0,
+        expected.1,
+        expected.2,
+        expected.3,
+        actual.0,
+        actual.1,
+        actual.2,
+        actual.3
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_helpers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fixture.csv");
+        std::fs::write(&path, "type,client,tx,amount\ndeposit,7,1,10.0\n").unwrap();
+        assert_eq!(
+            load_fixture(&path),
+            vec![TransactionBuilder::deposit(7, 1, "10").build()]
+        );
+
+        let mut clients = process_fixture(&path);
+        for transaction in [
+            TransactionBuilder::deposit(7, 2, "5").currency("GBP"),
+            TransactionBuilder::dispute(7, 1),
+        ] {
+            clients.process_transaction(transaction.into()).unwrap();
+        }
+        assert_client!(clients, 7, "0", "10", "10", false);
+        assert_client!(clients, 7, currency = "GBP", "5.0", "0", "5", false);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            assert_client!(clients, 7, "1", "0", "1", false)
+        }));
+        let message = result.unwrap_err();
+        assert!(message
+            .downcast_ref::<String>()
+            .unwrap()
+            .contains("but was available 0.0000, held 10.0000"));
+    }
+}
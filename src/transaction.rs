@@ -1,70 +1,234 @@
-use crate::Amount;
+use crate::amount::{AmountLocale, AmountParseError, AmountUnit};
+use crate::keys::{ClientKeys, KeyError};
+use crate::{Amount, Currency};
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct ClientId(u16);
 
+impl From<u16> for ClientId {
+    fn from(value: u16) -> Self {
+        Self(value)
+    }
+}
+
+impl From<ClientId> for u16 {
+    fn from(id: ClientId) -> Self {
+        id.0
+    }
+}
+
 impl std::fmt::Display for ClientId {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         self.0.fmt(f)
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct Transaction {
-    pub client_id: ClientId,
-    pub data: TransactionData,
+#[derive(Debug, thiserror::Error)]
+#[error("invalid client range '{0}': expected an ID, or a range like '8..15'")]
+pub struct ClientRangeError(String);
+
+/// A client ID, or an inclusive range of them, e.g. `7` or `8..15`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientRange {
+    pub first: ClientId,
+    pub last: ClientId,
+}
+
+impl std::str::FromStr for ClientRange {
+    type Err = ClientRangeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ClientRangeError(s.to_string());
+        let (first, last) = s.split_once("..").unwrap_or((s, s));
+        let first = first.trim().parse().map_err(|_| error())?;
+        let last = last.trim().parse().map_err(|_| error())?;
+        if first > last {
+            return Err(error());
+        }
+        Ok(Self {
+            first: ClientId(first),
+            last: ClientId(last),
+        })
+    }
+}
+
+/// Which clients to process. Empty means all of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientFilter {
+    pub ranges: Vec<ClientRange>,
+}
+
+impl ClientFilter {
+    pub fn contains(&self, id: ClientId) -> bool {
+        self.ranges.is_empty()
+            || self
+                .ranges
+                .iter()
+                .any(|range| (range.first..=range.last).contains(&id))
+    }
+}
+
+/// What the core engine, `Client` and `Clients`, needs of client and
+/// transaction IDs, so that embedders can use e.g. `u64` or their own
+/// composite keys. Everything else, including the command line, uses
+/// `ClientId` and `TransactionId`.
+pub trait Id:
+    Copy + Eq + Ord + std::hash::Hash + std::fmt::Display + std::fmt::Debug + Send + Sync + 'static
+{
+}
+
+impl<I> Id for I where
+    I: Copy
+        + Eq
+        + Ord
+        + std::hash::Hash
+        + std::fmt::Display
+        + std::fmt::Debug
+        + Send
+        + Sync
+        + 'static
+{
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum TransactionData {
-    Deposit {
-        transaction_id: TransactionId,
-        amount: Amount,
-    },
-    Withdrawal {
-        transaction_id: TransactionId,
-        amount: Amount,
-    },
-    Dispute {
-        transaction_id: TransactionId,
-    },
-    Resolve {
-        transaction_id: TransactionId,
-    },
-    Chargeback {
-        transaction_id: TransactionId,
-    },
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Transaction<C = ClientId, T = TransactionId> {
+    pub client_id: C,
+    // `None` if the input doesn't specify a currency.
+    pub currency: Option<Currency>,
+    #[serde(flatten)]
+    pub data: TransactionData<T>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TransactionData<T = TransactionId> {
+    Deposit { transaction_id: T, amount: Amount },
+    Withdrawal { transaction_id: T, amount: Amount },
+    Dispute { transaction_id: T },
+    Resolve { transaction_id: T },
+    Chargeback { transaction_id: T },
+}
+
+/// The type of a transaction, without its details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionKind {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+}
+
+impl TransactionKind {
+    /// The transaction type, as written in the input.
+    pub fn name(self) -> &'static str {
+        match self {
+            TransactionKind::Deposit => "deposit",
+            TransactionKind::Withdrawal => "withdrawal",
+            TransactionKind::Dispute => "dispute",
+            TransactionKind::Resolve => "resolve",
+            TransactionKind::Chargeback => "chargeback",
+        }
+    }
+}
+
+impl<T: Id> TransactionData<T> {
+    pub fn kind(&self) -> TransactionKind {
+        match self {
+            TransactionData::Deposit { .. } => TransactionKind::Deposit,
+            TransactionData::Withdrawal { .. } => TransactionKind::Withdrawal,
+            TransactionData::Dispute { .. } => TransactionKind::Dispute,
+            TransactionData::Resolve { .. } => TransactionKind::Resolve,
+            TransactionData::Chargeback { .. } => TransactionKind::Chargeback,
+        }
+    }
+
+    /// The transaction type, as written in the input.
+    pub fn name(&self) -> &'static str {
+        self.kind().name()
+    }
+
+    /// The amount, for deposits and withdrawals.
+    pub fn amount(&self) -> Option<Amount> {
+        match self {
+            TransactionData::Deposit { amount, .. }
+            | TransactionData::Withdrawal { amount, .. } => Some(*amount),
+            _ => None,
+        }
+    }
+
+    pub fn transaction_id(&self) -> T {
+        match self {
+            TransactionData::Deposit { transaction_id, .. }
+            | TransactionData::Withdrawal { transaction_id, .. }
+            | TransactionData::Dispute { transaction_id }
+            | TransactionData::Resolve { transaction_id }
+            | TransactionData::Chargeback { transaction_id } => *transaction_id,
+        }
+    }
+}
+
+/// The integer type transaction IDs are: `u32`, or `u64` with the
+/// `wide-tx-ids` feature, for upstreams that have run out of 32-bit IDs at
+/// the cost of more memory per deposit kept.
+#[cfg(not(feature = "wide-tx-ids"))]
+pub type RawTransactionId = u32;
+#[cfg(feature = "wide-tx-ids")]
+pub type RawTransactionId = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 #[serde(transparent)]
-pub struct TransactionId(u32);
+pub struct TransactionId(RawTransactionId);
 
-#[cfg(test)]
 impl TransactionId {
-    pub fn new(value: u32) -> Self {
+    pub const MAX: TransactionId = TransactionId(RawTransactionId::MAX);
+
+    /// The ID `value`, if it fits in a `RawTransactionId`, e.g. for formats
+    /// that have room for 64-bit IDs whatever the build.
+    pub fn from_u64(value: u64) -> Option<TransactionId> {
+        #[cfg(not(feature = "wide-tx-ids"))]
+        return u32::try_from(value).ok().map(Self);
+        #[cfg(feature = "wide-tx-ids")]
+        Some(Self(value))
+    }
+}
+
+impl From<RawTransactionId> for TransactionId {
+    fn from(value: RawTransactionId) -> Self {
         Self(value)
     }
 }
 
-impl std::fmt::Display for TransactionId {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        self.0.fmt(f)
+impl From<TransactionId> for RawTransactionId {
+    fn from(id: TransactionId) -> Self {
+        id.0
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct Deposit {
-    pub transaction_id: TransactionId,
-    pub amount: Amount,
+// With the feature, this is the conversion to `RawTransactionId` above.
+#[cfg(not(feature = "wide-tx-ids"))]
+impl From<TransactionId> for u64 {
+    fn from(id: TransactionId) -> Self {
+        id.0.into()
+    }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct Withdrawal {
-    pub transaction_id: TransactionId,
-    pub amount: Amount,
+#[cfg(test)]
+impl TransactionId {
+    pub fn new(value: RawTransactionId) -> Self {
+        Self(value)
+    }
+}
+
+impl std::fmt::Display for TransactionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -73,26 +237,817 @@ pub enum TransactionError {
     Csv(#[from] csv::Error),
     #[error("missing amount")]
     MissingAmount,
+    #[error("invalid amount: {0}")]
+    InvalidAmount(#[from] AmountParseError),
+    // The rest are only reported with `LoadOptions::strict_schema`.
+    #[error("invalid header: {0}")]
+    InvalidHeader(String),
+    #[error("expected {expected} fields, found {found}")]
+    FieldCount { expected: usize, found: usize },
+    #[error("unexpected amount for a {0}")]
+    UnexpectedAmount(&'static str),
+    #[error("invalid trailer: {0}")]
+    InvalidTrailer(String),
+    #[error("record after the trailer")]
+    AfterTrailer,
+    /// Only reported by `parse_record`.
+    #[error("expected a single record")]
+    NotSingleRecord,
+    #[error("invalid sequence number: {0}")]
+    InvalidSequence(std::num::ParseIntError),
+    #[error("sequence number {seq} is not after {last}")]
+    SequenceOutOfOrder { seq: u64, last: u64 },
+    #[error("invalid timestamp '{0}'")]
+    InvalidTimestamp(String),
+    #[error("record longer than {0} bytes")]
+    RecordTooLong(usize),
+    #[error("{found} fields, more than the limit of {limit}")]
+    TooManyFields { limit: usize, found: usize },
+    /// Only reported with `LoadOptions::client_keys`.
+    #[error("{0}")]
+    InvalidClient(#[from] KeyError),
+}
+
+impl TransactionError {
+    /// A short, stable name for the kind of error, e.g. for counting them.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionError::Csv(_) => "csv",
+            TransactionError::MissingAmount => "missing_amount",
+            TransactionError::InvalidAmount(_) => "invalid_amount",
+            TransactionError::InvalidHeader(_) => "invalid_header",
+            TransactionError::FieldCount { .. } => "field_count",
+            TransactionError::UnexpectedAmount(_) => "unexpected_amount",
+            TransactionError::InvalidTrailer(_) => "invalid_trailer",
+            TransactionError::AfterTrailer => "after_trailer",
+            TransactionError::NotSingleRecord => "not_single_record",
+            TransactionError::InvalidSequence(_) => "invalid_sequence",
+            TransactionError::SequenceOutOfOrder { .. } => "sequence_out_of_order",
+            TransactionError::InvalidTimestamp(_) => "invalid_timestamp",
+            TransactionError::RecordTooLong(_) => "record_too_long",
+            TransactionError::TooManyFields { .. } => "too_many_fields",
+            TransactionError::InvalidClient(_) => "invalid_client",
+        }
+    }
+}
+
+/// Options controlling how an input file is parsed.
+#[derive(Debug, Clone)]
+pub struct LoadOptions {
+    pub delimiter: u8,
+    pub amount_locale: AmountLocale,
+    /// Read amounts as whole units or as integer minor units. Minor units
+    /// ignore `amount_locale`.
+    pub amount_unit: AmountUnit,
+    /// Reject headers with missing, duplicate, or unknown columns, rows with
+    /// a different number of fields to the header, and amounts on
+    /// transactions that don't take one, rather than reading what we can.
+    pub strict_schema: bool,
+    /// Treat input without a trailer as incomplete.
+    pub require_trailer: bool,
+    /// Drop rows identical to any of the last `dedupe_window` rows, e.g.
+    /// where a batch was written twice upstream. Zero keeps every row.
+    pub dedupe_window: usize,
+    pub encoding: InputEncoding,
+    /// Records with a line longer than this, in bytes, are invalid. The rest
+    /// of the line is skipped without being read into memory, so that a
+    /// corrupted file, e.g. one without line breaks, can't exhaust it.
+    pub max_record_len: usize,
+    /// Records with more fields than this are invalid.
+    pub max_fields: usize,
+    /// Read the client column as any string, e.g. a UUID, interned here to a
+    /// `ClientId`, rather than as a number.
+    pub client_keys: Option<Arc<ClientKeys>>,
+    /// The input's columns, if it doesn't start with a header row of its
+    /// own, e.g. when it's converted from another format.
+    pub headers: Option<csv::StringRecord>,
+}
+
+/// How the input file is encoded. It's transcoded to UTF-8 before it's
+/// parsed, so positions in it, e.g. from `TransactionReader::input_position`,
+/// are only byte offsets into the file itself for UTF-8.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum InputEncoding {
+    /// UTF-8, with or without a byte order mark. Rows that aren't valid UTF-8
+    /// are invalid.
+    #[default]
+    #[value(name = "utf-8")]
+    Utf8,
+    /// UTF-8, with anything that isn't valid replaced by U+FFFD, so that only
+    /// the fields affected are invalid.
+    #[value(name = "utf-8-lossy")]
+    Utf8Lossy,
+    /// UTF-16, little-endian unless a byte order mark says otherwise, as
+    /// written by Excel's "Unicode text" export.
+    #[value(name = "utf-16")]
+    Utf16,
+    /// Latin-1, or strictly its superset Windows-1252.
+    #[value(name = "latin-1")]
+    Latin1,
+}
+
+/// The input with lines longer than a limit cut short, remembering which were,
+/// so that the csv reader never buffers more than the limit for a record.
+///
+/// The csv reader's byte offsets are into what's left, so they're moved on by
+/// the bytes dropped before them to get back to offsets into the input.
+struct LineLimit<R> {
+    inner: R,
+    limit: usize,
+    // The line being read, counting from one as `csv::Position` does, and
+    // how much of it has been read.
+    line: u64,
+    len: usize,
+    // Whether the rest of the current line is being skipped.
+    skipping: bool,
+    // Lines that were cut short, which the csv reader may not have reached
+    // yet, oldest first, with the bytes dropped from each.
+    cut: VecDeque<(u64, u64)>,
+    // The bytes dropped from lines cut short before those in `cut`.
+    dropped: u64,
+}
+
+impl<R> LineLimit<R> {
+    fn new(inner: R, limit: usize) -> Self {
+        Self {
+            inner,
+            limit,
+            line: 1,
+            len: 0,
+            skipping: false,
+            cut: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Carry on from the start of `line`, e.g. after seeking to it, with
+    /// offsets into the input from then on.
+    fn restart(&mut self, line: u64) {
+        self.line = line;
+        self.len = 0;
+        self.skipping = false;
+        self.cut.clear();
+        self.dropped = 0;
+    }
+
+    /// Whether any of the lines from `first` up to but not including `end`
+    /// were cut short. Earlier lines are forgotten, so this must be called
+    /// for each record in turn.
+    fn was_cut(&mut self, first: u64, end: u64) -> bool {
+        while let Some(&(line, dropped)) = self.cut.front() {
+            if line >= first {
+                break;
+            }
+            self.dropped += dropped;
+            self.cut.pop_front();
+        }
+        // A record at the end of the input without a line break ends on the
+        // line it starts on.
+        self.cut
+            .front()
+            .is_some_and(|&(line, _)| line < end.max(first + 1))
+    }
+
+    /// `position`, the start of a line no earlier than any passed to
+    /// `was_cut`, as an offset into the input.
+    fn input_position(&self, position: &csv::Position) -> csv::Position {
+        let dropped: u64 = self
+            .cut
+            .iter()
+            .take_while(|(line, _)| *line < position.line())
+            .map(|(_, dropped)| dropped)
+            .sum();
+        let mut position = position.clone();
+        position.set_byte(position.byte() + self.dropped + dropped);
+        position
+    }
+}
+
+impl<R: std::io::Read> std::io::Read for LineLimit<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let read = self.inner.read(buf)?;
+            if read == 0 {
+                return Ok(0);
+            }
+            // Keep what's within the limit, moving it to the front of `buf`.
+            let mut kept = 0;
+            let mut start = 0;
+            while start < read {
+                let newline = buf[start..read].iter().position(|&b| b == b'\n');
+                let end = newline.map_or(read, |i| start + i);
+                let mut len = 0;
+                if !self.skipping {
+                    let room = self.limit - self.len;
+                    len = (end - start).min(room);
+                    buf.copy_within(start..start + len, kept);
+                    kept += len;
+                    self.len += len;
+                    if end - start > room {
+                        self.skipping = true;
+                        self.cut.push_back((self.line, 0));
+                    }
+                }
+                if self.skipping {
+                    let (_, dropped) = self.cut.back_mut().expect("the line is cut");
+                    *dropped += (end - start - len) as u64;
+                }
+                match newline {
+                    Some(_) => {
+                        buf[kept] = b'\n';
+                        kept += 1;
+                        self.line += 1;
+                        self.len = 0;
+                        self.skipping = false;
+                        start = end + 1;
+                    }
+                    None => start = end,
+                }
+            }
+            // Returning nothing would mean the end of the input.
+            if kept > 0 {
+                return Ok(kept);
+            }
+        }
+    }
+}
+
+impl<R: std::io::Seek> std::io::Seek for LineLimit<R> {
+    fn seek(&mut self, position: std::io::SeekFrom) -> std::io::Result<u64> {
+        // The line is set by `TransactionReader::seek`, which knows it.
+        self.inner.seek(position)
+    }
+}
+
+/// The input, transcoded to UTF-8 if it's encoded otherwise.
+enum Input<R> {
+    Utf8(R),
+    Transcoded(encoding_rs_io::DecodeReaderBytes<R, Vec<u8>>),
+}
+
+impl<R: std::io::Read> Input<R> {
+    fn new(reader: R, encoding: InputEncoding) -> Self {
+        let encoding = match encoding {
+            // The csv crate skips a UTF-8 byte order mark itself.
+            InputEncoding::Utf8 => return Input::Utf8(reader),
+            InputEncoding::Utf8Lossy => encoding_rs::UTF_8,
+            InputEncoding::Utf16 => encoding_rs::UTF_16LE,
+            InputEncoding::Latin1 => encoding_rs::WINDOWS_1252,
+        };
+        Input::Transcoded(
+            encoding_rs_io::DecodeReaderBytesBuilder::new()
+                .encoding(Some(encoding))
+                // Only picks UTF-16BE over UTF-16LE in practice, as no other
+                // encoding has a byte order mark.
+                .bom_override(true)
+                .build(reader),
+        )
+    }
+}
+
+impl<R: std::io::Read> std::io::Read for Input<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Input::Utf8(reader) => reader.read(buf),
+            Input::Transcoded(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl<R: std::io::Seek> std::io::Seek for Input<R> {
+    fn seek(&mut self, position: std::io::SeekFrom) -> std::io::Result<u64> {
+        match self {
+            Input::Utf8(reader) => reader.seek(position),
+            // The position is in the transcoded text, so there's no telling
+            // where it is in the file.
+            Input::Transcoded(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "can't seek in input that isn't UTF-8",
+            )),
+        }
+    }
+}
+
+/// A control record at the end of the input, e.g. `trailer,,3,12.5`, giving
+/// the number of records before it in the `tx` column, and the total of the
+/// deposits, in every currency, in the `amount` column. Checking these
+/// catches input that was truncated or corrupted on its way to us.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Trailer {
+    pub records: u64,
+    pub deposits: Amount,
+}
+
+/// Why the input doesn't match its trailer.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum TrailerError {
+    #[error("missing trailer")]
+    Missing,
+    #[error("trailer can't be checked without reading the whole input")]
+    Unverifiable,
+    #[error("trailer gives {} records, but {} were read", .expected.records, .found.records)]
+    Records { expected: Trailer, found: Trailer },
+    #[error("trailer gives deposits totalling {}, but {} were read", .expected.deposits, .found.deposits)]
+    Deposits { expected: Trailer, found: Trailer },
+}
+
+impl LoadOptions {
+    /// Parse an amount from the input, including the trailer's total.
+    fn parse_amount(&self, s: &str) -> Result<Amount, AmountParseError> {
+        match self.amount_unit {
+            AmountUnit::Major => Amount::parse(s, self.amount_locale),
+            AmountUnit::Minor => Amount::parse_minor_units(s),
+        }
+    }
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            amount_locale: AmountLocale::default(),
+            amount_unit: AmountUnit::default(),
+            strict_schema: false,
+            require_trailer: false,
+            dedupe_window: 0,
+            encoding: InputEncoding::default(),
+            // Far more than any valid record needs.
+            max_record_len: 64 * 1024,
+            max_fields: 64,
+            client_keys: None,
+            headers: None,
+        }
+    }
+}
+
+/// The columns of an input file, of which only the first four are required.
+pub(crate) const COLUMNS: [&str; 9] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "currency",
+    "seq",
+    "timestamp",
+    "memo",
+    "reference",
+];
+const OPTIONAL_COLUMNS: [&str; 5] = ["currency", "seq", "timestamp", "memo", "reference"];
+
+/// Parse a single CSV record without a header, with the columns in the order
+/// `type,client,tx,amount,currency`, exactly as it would be read from a file,
+/// e.g. for a record received over the network.
+///
+/// Anything but exactly one transaction, e.g. an empty string, a trailer, or
+/// several lines, is an error.
+pub fn parse_record(record: &str) -> Result<Transaction, TransactionError> {
+    let input = format!("type,client,tx,amount,currency\n{}\n", record);
+    let mut reader = TransactionReader::new(input.as_bytes(), LoadOptions::default())?;
+    let transaction = reader.next();
+    let rest = reader.next();
+    if reader.trailer().is_some() {
+        return Err(TransactionError::InvalidTrailer(
+            "not allowed in a single record".to_string(),
+        ));
+    }
+    match (transaction, rest) {
+        (Some(transaction), None) => transaction,
+        _ => Err(TransactionError::NotSingleRecord),
+    }
 }
 
 pub fn load_transactions<R: std::io::Read>(
     reader: R,
 ) -> impl Iterator<Item = Result<Transaction, TransactionError>> {
-    csv::ReaderBuilder::new()
-        // 'dispute', 'resolve', and 'chargeback' transactions do not have an
-        // amount, the fourth field.
-        .flexible(true)
-        // The parser must be able to handle leading and trailing whitespace.
-        .trim(csv::Trim::All)
-        .from_reader(reader)
-        .into_deserialize::<Row>()
-        .map(|r| match r {
-            Ok(row) => {
-                let transaction: Transaction = row.try_into()?;
-                Ok(transaction)
+    load_transactions_with(reader, LoadOptions::default())
+}
+
+pub fn load_transactions_with<R: std::io::Read>(
+    reader: R,
+    options: LoadOptions,
+) -> impl Iterator<Item = Result<Transaction, TransactionError>> {
+    // If the headers can't be read, report that in place of the first
+    // transaction.
+    let (reader, error) = match TransactionReader::new(reader, options) {
+        Ok(reader) => (Some(reader), None),
+        Err(e) => (None, Some(Err(e))),
+    };
+    error.into_iter().chain(reader.into_iter().flatten())
+}
+
+/// Reads transactions one at a time, keeping track of where it is in the
+/// input.
+///
+/// Use this rather than `load_transactions` to record the input position, e.g.
+/// for checkpointing, and later seek back to it.
+pub struct TransactionReader<R> {
+    reader: csv::Reader<LineLimit<Input<R>>>,
+    headers: csv::StringRecord,
+    // Reused between records to avoid allocating for each one.
+    record: csv::StringRecord,
+    options: LoadOptions,
+    // What's been read so far, to check against the trailer.
+    read: Trailer,
+    trailer: Option<Trailer>,
+    // Whether every record has been read from the start of the input to the
+    // end, so that `read` can be checked.
+    from_start: bool,
+    at_end: bool,
+    // The last sequence number read, if the input has a `seq` column.
+    last_seq: Option<u64>,
+    // Sequence numbers missing just before the last record read.
+    gap: Option<std::ops::RangeInclusive<u64>>,
+    // The time of the last record read, if it has one.
+    timestamp: Option<u64>,
+    // The memo of the last record read, if it's a deposit or withdrawal with
+    // one.
+    memo: Option<String>,
+    // The reference of the last record read, if it has one.
+    reference: Option<String>,
+    // The last `dedupe_window` rows read, newest last, and how many rows
+    // were dropped for repeating one of them.
+    recent: VecDeque<csv::StringRecord>,
+    duplicates: u64,
+}
+
+impl<R: std::io::Read> TransactionReader<R> {
+    /// Create a reader, reading the headers from the start of the input
+    /// unless they're given in `options`.
+    pub fn new(reader: R, options: LoadOptions) -> Result<Self, TransactionError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(options.headers.is_none())
+            .delimiter(options.delimiter)
+            // 'dispute', 'resolve', and 'chargeback' transactions do not have
+            // an amount, the fourth field.
+            .flexible(true)
+            // The parser must be able to handle leading and trailing
+            // whitespace.
+            .trim(csv::Trim::All)
+            .from_reader(LineLimit::new(
+                Input::new(reader, options.encoding),
+                options.max_record_len,
+            ));
+        // Deserialize each record against the headers ourselves, rather than
+        // using `into_deserialize`, so that rows can borrow from the record.
+        let headers = match &options.headers {
+            Some(headers) => headers.clone(),
+            None => {
+                let headers = reader.headers()?.clone();
+                let end = reader.position().line();
+                if reader.get_mut().was_cut(1, end) {
+                    return Err(TransactionError::RecordTooLong(options.max_record_len));
+                }
+                headers
             }
-            Err(e) => Err(TransactionError::Csv(e)),
+        };
+        if options.strict_schema {
+            check_headers(&headers)?;
+        }
+        Ok(Self {
+            reader,
+            headers,
+            record: csv::StringRecord::new(),
+            options,
+            read: Trailer::default(),
+            trailer: None,
+            from_start: true,
+            at_end: false,
+            last_seq: None,
+            gap: None,
+            timestamp: None,
+            memo: None,
+            reference: None,
+            recent: VecDeque::new(),
+            duplicates: 0,
         })
+    }
+
+    /// Whether the input has a `seq` column, numbering its records.
+    pub fn has_sequence(&self) -> bool {
+        self.headers.iter().any(|h| h == "seq")
+    }
+
+    /// The time of the last record read, in seconds since the Unix epoch, if
+    /// the input has a `timestamp` column and the record has one.
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
+    /// Whether the input has a `memo` column, describing its deposits and
+    /// withdrawals.
+    pub fn has_memo(&self) -> bool {
+        self.headers.iter().any(|h| h == "memo")
+    }
+
+    /// The free-text memo of the last record read, if the input has a `memo`
+    /// column and the record is a deposit or withdrawal with one.
+    pub fn memo(&self) -> Option<&str> {
+        self.memo.as_deref()
+    }
+
+    /// Whether the input has a `reference` column, giving each record the
+    /// upstream system's own key for it.
+    pub fn has_reference(&self) -> bool {
+        self.headers.iter().any(|h| h == "reference")
+    }
+
+    /// The reference of the last record read, if the input has a `reference`
+    /// column and the record is a valid transaction with one.
+    pub fn reference(&self) -> Option<&str> {
+        self.reference.as_deref()
+    }
+
+    /// How many rows have been dropped as duplicates, with
+    /// `LoadOptions::dedupe_window`.
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    /// Check the record just read is within the limits on its size.
+    fn check_size(&mut self) -> Result<(), TransactionError> {
+        let first = self.record.position().map_or(1, csv::Position::line);
+        let end = self.reader.position().line();
+        if self.reader.get_mut().was_cut(first, end) {
+            return Err(TransactionError::RecordTooLong(self.options.max_record_len));
+        }
+        if self.record.len() > self.options.max_fields {
+            return Err(TransactionError::TooManyFields {
+                limit: self.options.max_fields,
+                found: self.record.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether the row just read repeats a recent one, remembering it if not.
+    fn is_duplicate(&mut self) -> bool {
+        let window = self.options.dedupe_window;
+        if window == 0 {
+            return false;
+        }
+        if self.recent.contains(&self.record) {
+            self.duplicates += 1;
+            return true;
+        }
+        if self.recent.len() == window {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(self.record.clone());
+        false
+    }
+
+    /// The sequence numbers missing just before the last record read, if
+    /// any, e.g. because the rows were dropped upstream.
+    pub fn sequence_gap(&self) -> Option<&std::ops::RangeInclusive<u64>> {
+        self.gap.as_ref()
+    }
+
+    /// The trailer, if it's been read.
+    pub fn trailer(&self) -> Option<&Trailer> {
+        self.trailer.as_ref()
+    }
+
+    /// Check that the records read match the trailer, once they've all been
+    /// read. Input without a trailer is fine unless
+    /// `LoadOptions::require_trailer` is set.
+    pub fn check_trailer(&self) -> Result<(), TrailerError> {
+        let Some(trailer) = self.trailer else {
+            return match self.options.require_trailer {
+                true if self.at_end => Err(TrailerError::Missing),
+                true => Err(TrailerError::Unverifiable),
+                false => Ok(()),
+            };
+        };
+        if !self.from_start {
+            return Err(TrailerError::Unverifiable);
+        }
+        if trailer.records != self.read.records {
+            return Err(TrailerError::Records {
+                expected: trailer,
+                found: self.read,
+            });
+        }
+        if trailer.deposits != self.read.deposits {
+            return Err(TrailerError::Deposits {
+                expected: trailer,
+                found: self.read,
+            });
+        }
+        Ok(())
+    }
+
+    /// The field of the current record in column `name`, if there is one.
+    fn field(&self, name: &str) -> Option<&str> {
+        let index = self.headers.iter().position(|h| h == name)?;
+        self.record.get(index)
+    }
+
+    /// Check the current record's sequence number follows the last one's,
+    /// noting any gap between them. Records without one aren't checked.
+    fn check_sequence(&mut self) -> Result<(), TransactionError> {
+        let seq = match self.field("seq") {
+            Some(seq) if !seq.is_empty() => {
+                seq.parse().map_err(TransactionError::InvalidSequence)?
+            }
+            _ => return Ok(()),
+        };
+        if let Some(last) = self.last_seq {
+            if seq <= last {
+                return Err(TransactionError::SequenceOutOfOrder { seq, last });
+            }
+            if seq > last + 1 {
+                self.gap = Some(last + 1..=seq - 1);
+            }
+        }
+        self.last_seq = Some(seq);
+        Ok(())
+    }
+
+    /// Read the current record's timestamp, either seconds since the Unix
+    /// epoch or RFC 3339, e.g. `2024-05-01T09:30:00Z`.
+    fn read_timestamp(&mut self) -> Result<(), TransactionError> {
+        self.timestamp = match self.field("timestamp") {
+            Some(timestamp) if !timestamp.is_empty() => Some(parse_timestamp(timestamp)?),
+            _ => None,
+        };
+        Ok(())
+    }
+
+    /// The trailer in the current record, if it is one.
+    fn parse_trailer(&self) -> Option<Result<Trailer, TransactionError>> {
+        let field = |name| self.field(name);
+        if field("type") != Some("trailer") {
+            return None;
+        }
+        let invalid = |message: String| Err(TransactionError::InvalidTrailer(message));
+        let records = match field("tx").unwrap_or_default().parse() {
+            Ok(records) => records,
+            Err(e) => return Some(invalid(format!("invalid record count: {}", e))),
+        };
+        let deposits = match self
+            .options
+            .parse_amount(field("amount").unwrap_or_default())
+        {
+            Ok(deposits) => deposits,
+            Err(e) => return Some(invalid(format!("invalid deposit total: {}", e))),
+        };
+        Some(Ok(Trailer { records, deposits }))
+    }
+
+    /// The position just after the last record read, i.e. where the next one
+    /// starts, counting the whole of any lines cut short by
+    /// `LoadOptions::max_record_len`. (Not called `position` to avoid
+    /// confusion with `Iterator::position`.)
+    pub fn input_position(&self) -> csv::Position {
+        self.reader.get_ref().input_position(self.reader.position())
+    }
+
+    /// The fields of the last record read, e.g. for reporting errors. Empty if
+    /// it couldn't be read at all.
+    pub fn record(&self) -> &csv::StringRecord {
+        &self.record
+    }
+
+    fn parse_record(&self) -> Result<Transaction, TransactionError> {
+        if self.options.strict_schema && self.record.len() != self.headers.len() {
+            return Err(TransactionError::FieldCount {
+                expected: self.headers.len(),
+                found: self.record.len(),
+            });
+        }
+        let transaction = match &self.options.client_keys {
+            None => {
+                let row: Row = self.record.deserialize(Some(&self.headers))?;
+                row.into_transaction(&self.options)?
+            }
+            Some(keys) => {
+                let row: Row<&str> = self.record.deserialize(Some(&self.headers))?;
+                let client = keys.intern(row.client)?;
+                row.with_client(client).into_transaction(&self.options)?
+            }
+        };
+        if self.options.strict_schema && transaction.data.amount().is_none() {
+            let amount = self.headers.iter().position(|h| h == "amount");
+            if amount.is_some_and(|i| !self.record[i].is_empty()) {
+                return Err(TransactionError::UnexpectedAmount(transaction.data.name()));
+            }
+        }
+        Ok(transaction)
+    }
+}
+
+fn parse_timestamp(timestamp: &str) -> Result<u64, TransactionError> {
+    let invalid = || TransactionError::InvalidTimestamp(timestamp.to_string());
+    if timestamp.bytes().all(|b| b.is_ascii_digit()) {
+        return timestamp.parse().map_err(|_| invalid());
+    }
+    let time =
+        time::OffsetDateTime::parse(timestamp, &time::format_description::well_known::Rfc3339)
+            .map_err(|_| invalid())?;
+    // Times before the epoch are surely mistakes.
+    u64::try_from(time.unix_timestamp()).map_err(|_| invalid())
+}
+
+fn check_headers(headers: &csv::StringRecord) -> Result<(), TransactionError> {
+    let invalid = |message: String| Err(TransactionError::InvalidHeader(message));
+    for (i, header) in headers.iter().enumerate() {
+        if !COLUMNS.contains(&header) {
+            return invalid(format!("unknown column '{}'", header));
+        }
+        if headers.iter().take(i).any(|h| h == header) {
+            return invalid(format!("duplicate column '{}'", header));
+        }
+    }
+    for column in COLUMNS.iter().filter(|c| !OPTIONAL_COLUMNS.contains(c)) {
+        if !headers.iter().any(|h| h == *column) {
+            return invalid(format!("missing column '{}'", column));
+        }
+    }
+    Ok(())
+}
+
+impl<R: std::io::Read + std::io::Seek> TransactionReader<R> {
+    /// Continue reading from a position previously returned by
+    /// `input_position`.
+    pub fn seek(&mut self, position: csv::Position) -> Result<(), TransactionError> {
+        // Whatever's skipped over isn't counted for the trailer, and the
+        // sequence carries on from wherever the next record says.
+        self.from_start = false;
+        self.last_seq = None;
+        self.recent.clear();
+        self.reader.seek(position.clone())?;
+        self.reader.get_mut().restart(position.line());
+        Ok(())
+    }
+}
+
+impl<R: std::io::Read> Iterator for TransactionReader<R> {
+    type Item = Result<Transaction, TransactionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.gap = None;
+        self.timestamp = None;
+        self.memo = None;
+        self.reference = None;
+        // Loops past the trailer and any duplicate rows.
+        let result = loop {
+            break match self.reader.read_record(&mut self.record) {
+                Ok(true) => match self.check_size() {
+                    Err(e) => Err(e),
+                    Ok(()) => match self.parse_trailer() {
+                        _ if self.trailer.is_some() => {
+                            return Some(Err(TransactionError::AfterTrailer))
+                        }
+                        Some(Ok(trailer)) => {
+                            self.trailer = Some(trailer);
+                            continue;
+                        }
+                        // Not counted, as it's not a transaction.
+                        Some(Err(e)) => return Some(Err(e)),
+                        // Dropped without a trace, other than the count.
+                        None if self.is_duplicate() => continue,
+                        None => self
+                            .check_sequence()
+                            .and_then(|()| self.read_timestamp())
+                            .and_then(|()| self.parse_record()),
+                    },
+                },
+                Ok(false) => {
+                    self.at_end = true;
+                    return None;
+                }
+                Err(e) => {
+                    self.record.clear();
+                    Err(e.into())
+                }
+            };
+        };
+        self.read.records += 1;
+        if let Ok(Transaction {
+            data: TransactionData::Deposit { amount, .. },
+            ..
+        }) = &result
+        {
+            self.read.deposits = self.read.deposits.saturating_add(*amount);
+        }
+        if let Ok(Transaction {
+            data: TransactionData::Deposit { .. } | TransactionData::Withdrawal { .. },
+            ..
+        }) = &result
+        {
+            self.memo = self
+                .field("memo")
+                .filter(|memo| !memo.is_empty())
+                .map(str::to_string);
+        }
+        if result.is_ok() {
+            self.reference = self
+                .field("reference")
+                .filter(|reference| !reference.is_empty())
+                .map(str::to_string);
+        }
+        Some(result)
+    }
 }
 
 // We can't just deserialize directly into `Transaction` because the csv crate
@@ -100,15 +1055,18 @@ pub fn load_transactions<R: std::io::Read>(
 // https://docs.rs/csv/latest/csv/struct.Reader.html#rules. Instead, deserialize
 // into an intermediate type then convert.
 #[derive(Deserialize)]
-struct Row {
+struct Row<'a, C = ClientId> {
     #[serde(rename = "type")]
     type_: TransactionType,
-    client: ClientId,
+    client: C,
     tx: TransactionId,
-    amount: Option<Amount>,
+    // Left as a string because how it's parsed depends on the `LoadOptions`.
+    amount: Option<&'a str>,
+    // Optional column; missing or empty means no currency was specified.
+    currency: Option<Currency>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 enum TransactionType {
     Deposit,
@@ -118,35 +1076,168 @@ enum TransactionType {
     Chargeback,
 }
 
-impl TryFrom<Row> for Transaction {
-    type Error = TransactionError;
+impl<'a, C> Row<'a, C> {
+    fn with_client<D>(self, client: D) -> Row<'a, D> {
+        Row {
+            type_: self.type_,
+            client,
+            tx: self.tx,
+            amount: self.amount,
+            currency: self.currency,
+        }
+    }
+}
 
-    fn try_from(row: Row) -> Result<Self, Self::Error> {
+impl Row<'_> {
+    fn into_transaction(self, options: &LoadOptions) -> Result<Transaction, TransactionError> {
+        let amount = || -> Result<Amount, TransactionError> {
+            let amount = self.amount.ok_or(TransactionError::MissingAmount)?;
+            Ok(options.parse_amount(amount)?)
+        };
         Ok(Transaction {
-            client_id: row.client,
-            data: match row.type_ {
+            client_id: self.client,
+            currency: self.currency,
+            data: match self.type_ {
                 TransactionType::Deposit => TransactionData::Deposit {
-                    transaction_id: row.tx,
-                    amount: row.amount.ok_or(TransactionError::MissingAmount)?,
+                    transaction_id: self.tx,
+                    amount: amount()?,
                 },
                 TransactionType::Withdrawal => TransactionData::Withdrawal {
-                    transaction_id: row.tx,
-                    amount: row.amount.ok_or(TransactionError::MissingAmount)?,
+                    transaction_id: self.tx,
+                    amount: amount()?,
                 },
                 TransactionType::Dispute => TransactionData::Dispute {
-                    transaction_id: row.tx,
+                    transaction_id: self.tx,
                 },
                 TransactionType::Resolve => TransactionData::Resolve {
-                    transaction_id: row.tx,
+                    transaction_id: self.tx,
                 },
                 TransactionType::Chargeback => TransactionData::Chargeback {
-                    transaction_id: row.tx,
+                    transaction_id: self.tx,
                 },
             },
         })
     }
 }
 
+/// Writes transactions in the same CSV format they're read from, always
+/// including the currency column, so that they can be read back with
+/// `TransactionReader`.
+pub struct TransactionWriter<W: std::io::Write> {
+    writer: csv::Writer<W>,
+    // Whether to write the memo column; see `with_memos`.
+    memos: bool,
+    // Whether to write the reference column; see `with_references`.
+    references: bool,
+}
+
+#[derive(Serialize)]
+struct OutputRow<'a> {
+    #[serde(rename = "type")]
+    type_: TransactionType,
+    client: ClientId,
+    tx: TransactionId,
+    amount: Option<Amount>,
+    currency: Option<Currency>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memo: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reference: Option<&'a str>,
+}
+
+impl<W: std::io::Write> TransactionWriter<W> {
+    /// Create a writer for a new file, writing the headers before the first
+    /// transaction.
+    pub fn new(writer: W) -> Self {
+        Self::with_headers(writer, true)
+    }
+
+    /// Create a writer appending to a file that already has headers.
+    pub fn append(writer: W) -> Self {
+        Self::with_headers(writer, false)
+    }
+
+    fn with_headers(writer: W, has_headers: bool) -> Self {
+        Self {
+            writer: csv::WriterBuilder::new()
+                .has_headers(has_headers)
+                .from_writer(writer),
+            memos: false,
+            references: false,
+        }
+    }
+
+    /// Also write a memo column, given by `write_with_memo`.
+    pub fn with_memos(mut self) -> Self {
+        self.memos = true;
+        self
+    }
+
+    /// Also write a reference column, given by `write_with_reference`.
+    pub fn with_references(mut self) -> Self {
+        self.references = true;
+        self
+    }
+
+    pub fn write(&mut self, transaction: &Transaction) -> Result<(), csv::Error> {
+        self.write_with_memo(transaction, None)
+    }
+
+    /// Write `transaction` with `memo` in the memo column, if it's written.
+    pub fn write_with_memo(
+        &mut self,
+        transaction: &Transaction,
+        memo: Option<&str>,
+    ) -> Result<(), csv::Error> {
+        self.write_row(transaction, memo, None)
+    }
+
+    /// Write `transaction` with `reference` in the reference column, if it's
+    /// written.
+    pub fn write_with_reference(
+        &mut self,
+        transaction: &Transaction,
+        reference: Option<&str>,
+    ) -> Result<(), csv::Error> {
+        self.write_row(transaction, None, reference)
+    }
+
+    fn write_row(
+        &mut self,
+        transaction: &Transaction,
+        memo: Option<&str>,
+        reference: Option<&str>,
+    ) -> Result<(), csv::Error> {
+        let (type_, amount) = match transaction.data {
+            TransactionData::Deposit { amount, .. } => (TransactionType::Deposit, Some(amount)),
+            TransactionData::Withdrawal { amount, .. } => {
+                (TransactionType::Withdrawal, Some(amount))
+            }
+            TransactionData::Dispute { .. } => (TransactionType::Dispute, None),
+            TransactionData::Resolve { .. } => (TransactionType::Resolve, None),
+            TransactionData::Chargeback { .. } => (TransactionType::Chargeback, None),
+        };
+        self.writer.serialize(OutputRow {
+            type_,
+            client: transaction.client_id,
+            tx: transaction.data.transaction_id(),
+            amount,
+            currency: transaction.currency,
+            memo: self.memos.then(|| memo.unwrap_or_default()),
+            reference: self.references.then(|| reference.unwrap_or_default()),
+        })
+    }
+
+    /// Flush buffered transactions to the underlying writer.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.writer.get_ref()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -164,6 +1255,7 @@ mod tests {
             load_transaction("deposit, 1, 2, 3.0").unwrap(),
             Transaction {
                 client_id: ClientId(1),
+                currency: None,
                 data: TransactionData::Deposit {
                     transaction_id: TransactionId(2),
                     amount: Amount::try_from("3.0").unwrap(),
@@ -178,6 +1270,7 @@ mod tests {
             load_transaction("withdrawal, 1, 2, 3.0").unwrap(),
             Transaction {
                 client_id: ClientId(1),
+                currency: None,
                 data: TransactionData::Withdrawal {
                     transaction_id: TransactionId(2),
                     amount: Amount::try_from("3.0").unwrap(),
@@ -192,6 +1285,7 @@ mod tests {
             load_transaction("dispute, 1, 2").unwrap(),
             Transaction {
                 client_id: ClientId(1),
+                currency: None,
                 data: TransactionData::Dispute {
                     transaction_id: TransactionId(2),
                 },
@@ -205,6 +1299,7 @@ mod tests {
             load_transaction("resolve, 1, 2").unwrap(),
             Transaction {
                 client_id: ClientId(1),
+                currency: None,
                 data: TransactionData::Resolve {
                     transaction_id: TransactionId(2),
                 },
@@ -218,6 +1313,7 @@ mod tests {
             load_transaction("chargeback, 1, 2").unwrap(),
             Transaction {
                 client_id: ClientId(1),
+                currency: None,
                 data: TransactionData::Chargeback {
                     transaction_id: TransactionId(2),
                 },
@@ -225,6 +1321,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_wide_transaction_id() {
+        // One more than fits in 32 bits.
+        let result = load_transaction("dispute, 1, 4294967296");
+        #[cfg(feature = "wide-tx-ids")]
+        assert_eq!(
+            result.unwrap().data.transaction_id(),
+            TransactionId(1 << 32)
+        );
+        #[cfg(not(feature = "wide-tx-ids"))]
+        assert!(matches!(result, Err(TransactionError::Csv(_))));
+    }
+
     #[test]
     fn test_parse_multiple() {
         // Don't include spaces after the commas, to test that the parser can
@@ -243,6 +1352,7 @@ mod tests {
             vec![
                 Transaction {
                     client_id: ClientId(1),
+                    currency: None,
                     data: TransactionData::Deposit {
                         transaction_id: TransactionId(2),
                         amount: Amount::try_from("3.0").unwrap(),
@@ -250,6 +1360,7 @@ mod tests {
                 },
                 Transaction {
                     client_id: ClientId(4),
+                    currency: None,
                     data: TransactionData::Withdrawal {
                         transaction_id: TransactionId(5),
                         amount: Amount::try_from("6.0").unwrap(),
@@ -257,18 +1368,21 @@ mod tests {
                 },
                 Transaction {
                     client_id: ClientId(7),
+                    currency: None,
                     data: TransactionData::Dispute {
                         transaction_id: TransactionId(8),
                     },
                 },
                 Transaction {
                     client_id: ClientId(9),
+                    currency: None,
                     data: TransactionData::Resolve {
                         transaction_id: TransactionId(10),
                     },
                 },
                 Transaction {
                     client_id: ClientId(11),
+                    currency: None,
                     data: TransactionData::Chargeback {
                         transaction_id: TransactionId(12),
                     },
@@ -276,4 +1390,610 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_parse_currency() {
+        let data = "type, client, tx, amount, currency\n\
+                    deposit, 1, 2, 3.0, USD\n\
+                    dispute, 1, 2, , USD\n\
+                    resolve, 1, 2\n\
+                    withdrawal, 1, 3, 1.0,\n";
+        let transactions: Vec<_> = load_transactions(data.as_bytes())
+            .map(|r| r.unwrap().currency)
+            .collect();
+        let usd = Some(Currency::try_from("USD").unwrap());
+        assert_eq!(transactions, vec![usd, usd, None, None]);
+    }
+
+    #[test]
+    fn test_parse_invalid_currency() {
+        let data = "type, client, tx, amount, currency\ndeposit, 1, 2, 3.0, usd\n";
+        let transactions: Vec<_> = load_transactions(data.as_bytes()).collect();
+        assert!(matches!(transactions[..], [Err(TransactionError::Csv(_))]));
+    }
+    #[test]
+    fn test_parse_invalid_amount() {
+        assert!(matches!(
+            load_transaction("deposit, 1, 2, abc"),
+            Err(TransactionError::InvalidAmount(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_comma_decimal() {
+        let data = "type;client;tx;amount\n\
+                    deposit;1;2;1.234,56\n\
+                    withdrawal;1;3;7,5\n";
+        let options = LoadOptions {
+            delimiter: b';',
+            amount_locale: AmountLocale::CommaDecimal,
+            ..Default::default()
+        };
+        let amounts: Vec<_> = load_transactions_with(data.as_bytes(), options)
+            .map(|r| match r.unwrap().data {
+                TransactionData::Deposit { amount, .. }
+                | TransactionData::Withdrawal { amount, .. } => amount,
+                _ => panic!("expected deposit or withdrawal"),
+            })
+            .collect();
+        assert_eq!(
+            amounts,
+            vec![
+                Amount::try_from("1234.56").unwrap(),
+                Amount::try_from("7.5").unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_minor_units() {
+        let data = "type,client,tx,amount\n\
+                    deposit,1,1,15000\n\
+                    withdrawal,1,2,7\n\
+                    deposit,1,3,1.5\n\
+                    trailer,,3,15000\n";
+        let options = LoadOptions {
+            amount_unit: AmountUnit::Minor,
+            require_trailer: true,
+            ..Default::default()
+        };
+        let results: Vec<_> = load_transactions_with(data.as_bytes(), options).collect();
+        assert!(matches!(
+            results[..],
+            [
+                Ok(Transaction {
+                    data: TransactionData::Deposit { amount: a, .. },
+                    ..
+                }),
+                Ok(Transaction {
+                    data: TransactionData::Withdrawal { amount: b, .. },
+                    ..
+                }),
+                Err(TransactionError::InvalidAmount(AmountParseError::InvalidFormat)),
+            ] if a == Amount::try_from("1.5").unwrap() && b == Amount::try_from("0.0007").unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_client_keys() {
+        let data = "type, client, tx, amount\n\
+                    deposit, F81D4FAE-7DEC-11D0-A765-00A0C91E6BF6, 1, 1.0\n\
+                    deposit, 7, 2, 1.0\n\
+                    dispute, F81D4FAE-7DEC-11D0-A765-00A0C91E6BF6, 1\n\
+                    deposit, , 3, 1.0\n";
+        // Not numbers, so only readable as keys.
+        assert!(load_transactions(data.as_bytes()).next().unwrap().is_err());
+        let keys = Arc::new(ClientKeys::new());
+        let options = LoadOptions {
+            client_keys: Some(keys.clone()),
+            ..Default::default()
+        };
+        let results: Vec<_> = load_transactions_with(data.as_bytes(), options).collect();
+        let clients: Vec<_> = results[..3]
+            .iter()
+            .map(|r| u16::from(r.as_ref().unwrap().client_id))
+            .collect();
+        assert_eq!(clients, [0, 1, 0]);
+        assert!(matches!(
+            results[3],
+            Err(TransactionError::InvalidClient(KeyError::Missing))
+        ));
+        assert_eq!(keys.key(ClientId(1)).as_deref(), Some("7"));
+    }
+
+    #[test]
+    fn test_trailer() {
+        let check = |data: &str, require_trailer: bool| {
+            let options = LoadOptions {
+                require_trailer,
+                ..Default::default()
+            };
+            let mut reader = TransactionReader::new(data.as_bytes(), options).unwrap();
+            let errors: Vec<_> = reader
+                .by_ref()
+                .filter_map(|r| r.err().map(|e| e.to_string()))
+                .collect();
+            (errors, reader.check_trailer())
+        };
+        let data = "type, client, tx, amount, currency\n\
+                    deposit, 1, 1, 10.0, USD\n\
+                    deposit, 2, 2, 2.5, EUR\n\
+                    withdrawal, 1, 3, 1.0, USD\n\
+                    deposit, 1, 4\n\
+                    trailer, , 4, 12.5\n";
+        assert_eq!(check(data, true), (vec!["missing amount".into()], Ok(())));
+        assert_eq!(
+            check(&data.replace("12.5", "13.5"), false).1,
+            Err(TrailerError::Deposits {
+                expected: Trailer {
+                    records: 4,
+                    deposits: Amount::try_from("13.5").unwrap()
+                },
+                found: Trailer {
+                    records: 4,
+                    deposits: Amount::try_from("12.5").unwrap()
+                },
+            })
+        );
+
+        // Truncated, with or without the trailer.
+        let truncated = data.replace("deposit, 2, 2, 2.5, EUR\n", "");
+        assert!(matches!(
+            check(&truncated, false).1,
+            Err(TrailerError::Records { .. })
+        ));
+        let untrailed = data.replace("trailer, , 4, 12.5\n", "");
+        assert_eq!(check(&untrailed, false).1, Ok(()));
+        assert_eq!(check(&untrailed, true).1, Err(TrailerError::Missing));
+
+        let (errors, _) = check(&format!("{}deposit, 1, 5, 1.0\n", data), false);
+        assert_eq!(errors, ["missing amount", "record after the trailer"]);
+        let (errors, result) = check(&data.replace(" 4, 12.5", " x, 12.5"), true);
+        assert_eq!(
+            errors[1],
+            "invalid trailer: invalid record count: invalid digit found in string"
+        );
+        assert_eq!(result, Err(TrailerError::Missing));
+    }
+
+    #[test]
+    fn test_parse_single_record() {
+        assert_eq!(
+            parse_record("deposit,1,2,3.0").unwrap(),
+            load_transaction("deposit, 1, 2, 3.0").unwrap()
+        );
+        // Anything else is an error, rather than a panic.
+        let error = |record| parse_record(record).map_err(|e| e.to_string());
+        for record in ["", "\n"] {
+            assert_eq!(error(record), Err("expected a single record".into()));
+        }
+        assert_eq!(
+            error("deposit,1,2,3.0\ndeposit,1,3,3.0"),
+            Err("expected a single record".into())
+        );
+        for record in ["trailer,,0,0", "deposit,1,2,3.0\ntrailer,,1,3.0"] {
+            assert_eq!(
+                error(record),
+                Err("invalid trailer: not allowed in a single record".into())
+            );
+        }
+    }
+
+    #[test]
+    fn test_sequence() {
+        let data = "type, client, tx, amount, seq\n\
+                    deposit, 1, 1, 1.0, 1\n\
+                    deposit, 1, 2, 1.0, 2\n\
+                    deposit, 1, 3, 1.0, 5\n\
+                    deposit, 1, 4, 1.0, 5\n\
+                    deposit, 1, 5, 1.0, 4\n\
+                    deposit, 1, 6, 1.0,\n\
+                    deposit, 1, 7, 1.0, x\n\
+                    deposit, 1, 8, 1.0, 6\n";
+        let strict = LoadOptions {
+            strict_schema: true,
+            ..Default::default()
+        };
+        let mut reader = TransactionReader::new(data.as_bytes(), strict).unwrap();
+        assert!(reader.has_sequence());
+        let mut results = Vec::new();
+        while let Some(result) = reader.next() {
+            let result = result.map(|_| ()).map_err(|e| e.to_string());
+            results.push((result, reader.sequence_gap().cloned()));
+        }
+        assert_eq!(
+            results,
+            [
+                (Ok(()), None),
+                (Ok(()), None),
+                (Ok(()), Some(3..=4)),
+                (Err("sequence number 5 is not after 5".into()), None),
+                (Err("sequence number 4 is not after 5".into()), None),
+                (Ok(()), None),
+                (
+                    Err("invalid sequence number: invalid digit found in string".into()),
+                    None
+                ),
+                (Ok(()), None),
+            ]
+        );
+        assert!(
+            !TransactionReader::new("type,client,tx,amount\n".as_bytes(), Default::default())
+                .unwrap()
+                .has_sequence()
+        );
+    }
+
+    #[test]
+    fn test_dedupe() {
+        let data = "type,client,tx,amount\n\
+                    deposit,1,1,1.0\n\
+                    deposit,1,1,1.0\n\
+                    deposit,1,2,2.0\n\
+                    deposit,1,3,3.0\n\
+                    deposit,1,2,2.0\n\
+                    deposit,1,3,3.0\n\
+                    deposit, 1, 3, 3.0\n\
+                    deposit,1,1,1.0\n";
+        let read = |dedupe_window| {
+            let options = LoadOptions {
+                dedupe_window,
+                ..Default::default()
+            };
+            let mut reader = TransactionReader::new(data.as_bytes(), options).unwrap();
+            let ids: Vec<_> = reader
+                .by_ref()
+                .map(|result| u64::from(result.unwrap().data.transaction_id()))
+                .collect();
+            (ids, reader.duplicates())
+        };
+        assert_eq!(read(0), (vec![1, 1, 2, 3, 2, 3, 3, 1], 0));
+        // Fields are trimmed before they're compared.
+        assert_eq!(read(1), (vec![1, 2, 3, 2, 3, 1], 2));
+        assert_eq!(read(2), (vec![1, 2, 3, 1], 4));
+        assert_eq!(read(3), (vec![1, 2, 3], 5));
+    }
+
+    #[test]
+    fn test_encodings() {
+        let read = |data: &[u8], encoding| -> Vec<Result<Transaction, String>> {
+            let options = LoadOptions {
+                encoding,
+                ..Default::default()
+            };
+            TransactionReader::new(data, options)
+                .unwrap()
+                .map(|result| result.map_err(|e| e.to_string()))
+                .collect()
+        };
+        // Unknown columns are ignored, but must still be decoded.
+        let text = "type,client,tx,amount,note\ndeposit,1,1,1.5,café\n";
+        let expected = vec![Ok(parse_record("deposit,1,1,1.5").unwrap())];
+        let utf16 = |big_endian: bool, bom: bool| -> Vec<u8> {
+            let bom = bom.then_some('\u{feff}');
+            bom.into_iter()
+                .chain(text.chars())
+                .collect::<String>()
+                .encode_utf16()
+                .flat_map(|unit| match big_endian {
+                    true => unit.to_be_bytes(),
+                    false => unit.to_le_bytes(),
+                })
+                .collect()
+        };
+
+        // A byte order mark, as Excel writes, is skipped.
+        let bom = format!("\u{feff}{}", text);
+        let strict = LoadOptions {
+            strict_schema: true,
+            ..Default::default()
+        };
+        assert!(
+            TransactionReader::new("\u{feff}type,client,tx,amount\n".as_bytes(), strict).is_ok()
+        );
+        assert_eq!(read(bom.as_bytes(), InputEncoding::Utf8), expected);
+        assert_eq!(read(bom.as_bytes(), InputEncoding::Utf8Lossy), expected);
+        for (big_endian, bom) in [(false, false), (false, true), (true, true)] {
+            assert_eq!(
+                read(&utf16(big_endian, bom), InputEncoding::Utf16),
+                expected
+            );
+        }
+
+        let latin1 = b"type,client,tx,amount,note\ndeposit,1,1,1.5,caf\xe9\n";
+        assert_eq!(read(latin1, InputEncoding::Latin1), expected);
+        assert_eq!(read(latin1, InputEncoding::Utf8Lossy), expected);
+        assert!(read(latin1, InputEncoding::Utf8)[0]
+            .as_ref()
+            .is_err_and(|e| e.contains("invalid utf-8")));
+
+        // Positions aren't in the file, so can't be sought to.
+        let options = LoadOptions {
+            encoding: InputEncoding::Latin1,
+            ..Default::default()
+        };
+        let mut reader = TransactionReader::new(std::io::Cursor::new(latin1), options).unwrap();
+        let position = reader.input_position();
+        reader.next();
+        assert!(reader.seek(position).is_err());
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let options = LoadOptions {
+            max_record_len: 24,
+            max_fields: 5,
+            ..Default::default()
+        };
+        let data = format!(
+            "type,client,tx,amount\n\
+             deposit,1,1,1.0\n\
+             deposit,1,2,{}\n\
+             deposit,1,3,2.0\n\
+             deposit,1,4,1.0,,,,\n\
+             \"deposit\",1,\"5\n\",1.0\n\
+             deposit,1,6,{}\n\
+             deposit,1,7,1.0",
+            "9".repeat(100_000),
+            "1".repeat(30),
+        );
+        let mut reader =
+            TransactionReader::new(std::io::Cursor::new(&data), options.clone()).unwrap();
+        let mut results = Vec::new();
+        while let Some(result) = reader.next() {
+            let line = reader.record().position().unwrap().line();
+            results.push((line, result.map(|_| ()).map_err(|e| e.to_string())));
+        }
+        assert_eq!(
+            results,
+            [
+                (2, Ok(())),
+                (3, Err("record longer than 24 bytes".into())),
+                (4, Ok(())),
+                (5, Err("8 fields, more than the limit of 5".into())),
+                // Lines are limited, rather than records.
+                (6, Ok(())),
+                (8, Err("record longer than 24 bytes".into())),
+                (9, Ok(())),
+            ]
+        );
+
+        // The limits carry on from where a reader seeks to.
+        let mut reader =
+            TransactionReader::new(std::io::Cursor::new(&data), options.clone()).unwrap();
+        let start = reader.input_position();
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_err());
+        reader.seek(start).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().unwrap().is_ok());
+
+        // Positions are in the input, counting the whole of lines cut short,
+        // so they can be sought to, or used to copy records out of it.
+        let mut reader =
+            TransactionReader::new(std::io::Cursor::new(&data), options.clone()).unwrap();
+        let mut positions = Vec::new();
+        while reader.next().is_some() {
+            positions.push(reader.input_position());
+        }
+        let rest = |position: &csv::Position| &data[position.byte() as usize..];
+        assert!(rest(&positions[1]).starts_with("deposit,1,3,2.0\n"));
+        assert!(rest(&positions[5]).starts_with("deposit,1,7,1.0"));
+        assert_eq!(positions[6].byte() as usize, data.len());
+        let mut reader =
+            TransactionReader::new(std::io::Cursor::new(&data), options.clone()).unwrap();
+        reader.seek(positions[5].clone()).unwrap();
+        let transaction = reader.next().unwrap().unwrap();
+        assert_eq!(transaction.data.transaction_id(), 7.into());
+        assert_eq!(reader.record().position().unwrap().line(), 9);
+
+        let long_header = format!("type,client,tx,amount,{}\n", "x".repeat(24));
+        assert!(matches!(
+            TransactionReader::new(long_header.as_bytes(), options),
+            Err(TransactionError::RecordTooLong(24))
+        ));
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let data = "type,client,tx,amount,timestamp\n\
+                    deposit,1,1,1.0,1714555800\n\
+                    deposit,1,2,1.0,2024-05-01T09:30:00Z\n\
+                    deposit,1,3,1.0,2024-05-01T10:30:00+01:00\n\
+                    deposit,1,4,1.0,\n\
+                    deposit,1,5,1.0,yesterday\n";
+        let mut reader = TransactionReader::new(data.as_bytes(), LoadOptions::default()).unwrap();
+        let mut timestamps = Vec::new();
+        while let Some(result) = reader.next() {
+            timestamps.push(result.map(|_| reader.timestamp()).map_err(|e| e.name()));
+        }
+        assert_eq!(
+            timestamps,
+            vec![
+                Ok(Some(1714555800)),
+                Ok(Some(1714555800)),
+                Ok(Some(1714555800)),
+                Ok(None),
+                Err("invalid_timestamp"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_memos() {
+        let data = "type,client,tx,amount,memo\n\
+                    deposit,1,1,1.0,\"Invoice 7, March\"\n\
+                    dispute,1,1,,ignored\n\
+                    withdrawal,1,2,1.0,\n\
+                    deposit,1,3,1.0,Refund\n";
+        let mut reader = TransactionReader::new(data.as_bytes(), LoadOptions::default()).unwrap();
+        assert!(reader.has_memo());
+        let mut memos = Vec::new();
+        let mut writer = TransactionWriter::new(Vec::new()).with_memos();
+        while let Some(result) = reader.next() {
+            let transaction = result.unwrap();
+            memos.push(reader.memo().map(str::to_string));
+            writer.write_with_memo(&transaction, reader.memo()).unwrap();
+        }
+        assert_eq!(
+            memos,
+            vec![
+                Some("Invoice 7, March".to_string()),
+                None,
+                None,
+                Some("Refund".to_string()),
+            ]
+        );
+        writer.flush().unwrap();
+        assert_eq!(
+            String::from_utf8(writer.get_ref().clone()).unwrap(),
+            "type,client,tx,amount,currency,memo\n\
+             deposit,1,1,1.0000,,\"Invoice 7, March\"\n\
+             dispute,1,1,,,\n\
+             withdrawal,1,2,1.0000,,\n\
+             deposit,1,3,1.0000,,Refund\n"
+        );
+    }
+
+    #[test]
+    fn test_references() {
+        let data = "type,client,tx,amount,reference\n\
+                    deposit,1,1,1.0,pay-001\n\
+                    dispute,1,1,,case-9\n\
+                    withdrawal,1,2,1.0,\n\
+                    withdrawal,1,x,1.0,pay-002\n";
+        let mut reader = TransactionReader::new(data.as_bytes(), LoadOptions::default()).unwrap();
+        assert!(reader.has_reference());
+        let mut references = Vec::new();
+        while reader.next().is_some() {
+            references.push(reader.reference().map(str::to_string));
+        }
+        assert_eq!(
+            references,
+            vec![
+                Some("pay-001".to_string()),
+                Some("case-9".to_string()),
+                None,
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strict_schema() {
+        let strict = LoadOptions {
+            strict_schema: true,
+            ..Default::default()
+        };
+        let errors = |data: &str| -> Vec<String> {
+            load_transactions_with(data.as_bytes(), strict.clone())
+                .filter_map(|r| r.err().map(|e| e.to_string()))
+                .collect()
+        };
+        let valid = "type, client, tx, amount, currency\n\
+                     deposit, 1, 1, 1.0, USD\n\
+                     dispute, 1, 1, , USD\n";
+        assert!(errors(valid).is_empty());
+
+        for (header, error) in [
+            (
+                "type, client, tx",
+                "invalid header: missing column 'amount'",
+            ),
+            (
+                "type, client, tx, amount, tx",
+                "invalid header: duplicate column 'tx'",
+            ),
+            (
+                "type, client, tx, amount, note",
+                "invalid header: unknown column 'note'",
+            ),
+        ] {
+            assert_eq!(
+                errors(&format!("{}\ndeposit, 1, 1, 1.0\n", header)),
+                [error]
+            );
+        }
+
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 1.0\n\
+                    dispute, 1, 1\n\
+                    dispute, 1, 1, 1.0\n\
+                    deposit, 1, 2, 1.0, USD\n\
+                    resolve, 1, 1,\n";
+        assert_eq!(
+            errors(data),
+            [
+                "expected 4 fields, found 3",
+                "unexpected amount for a dispute",
+                "expected 4 fields, found 5",
+            ]
+        );
+        // Without strict_schema, every record is read as best we can, ignoring
+        // the amount on the dispute and the extra field.
+        assert_eq!(
+            load_transactions(data.as_bytes())
+                .filter(|r| r.is_ok())
+                .count(),
+            5
+        );
+    }
+
+    #[test]
+    fn test_seek() {
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 1.0\n\
+                    deposit, 1, 2, 2.0\n\
+                    deposit, 1, 3, 3.0\n";
+        let mut reader =
+            TransactionReader::new(std::io::Cursor::new(data), LoadOptions::default()).unwrap();
+        reader.next().unwrap().unwrap();
+        let position = reader.input_position();
+        let rest: Vec<_> = reader.map(|r| r.unwrap()).collect();
+
+        // Seeking a fresh reader to the recorded position picks up from the
+        // same record.
+        let mut reader =
+            TransactionReader::new(std::io::Cursor::new(data), LoadOptions::default()).unwrap();
+        reader.seek(position).unwrap();
+        assert_eq!(reader.map(|r| r.unwrap()).collect::<Vec<_>>(), rest);
+        assert_eq!(rest.len(), 2);
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let data = "type, client, tx, amount, currency\n\
+                    deposit, 1, 2, 3.0, USD\n\
+                    withdrawal, 4, 5, 6.0,\n\
+                    dispute, 1, 2, , USD\n\
+                    resolve, 1, 2\n\
+                    chargeback, 1, 2\n";
+        let transactions: Vec<_> = load_transactions(data.as_bytes())
+            .map(|r| r.unwrap())
+            .collect();
+        let mut writer = TransactionWriter::new(Vec::new());
+        for transaction in &transactions {
+            writer.write(transaction).unwrap();
+        }
+        writer.flush().unwrap();
+        let written: Vec<_> = load_transactions(writer.get_ref().as_slice())
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(written, transactions);
+    }
+
+    #[test]
+    fn test_client_filter() {
+        let filter = ClientFilter {
+            ranges: vec!["7".parse().unwrap(), "10..12".parse().unwrap()],
+        };
+        let included: Vec<u16> = (0..20)
+            .filter(|&id| filter.contains(ClientId(id)))
+            .collect();
+        assert_eq!(included, vec![7, 10, 11, 12]);
+        assert!(ClientFilter::default().contains(ClientId(3)));
+        assert!("12..10".parse::<ClientRange>().is_err());
+        assert!("x".parse::<ClientRange>().is_err());
+    }
 }
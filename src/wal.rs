@@ -0,0 +1,349 @@
+//! A write-ahead log of transactions. Each transaction is appended to the log
+//! before it's applied, so that the state after a crash can be recovered
+//! exactly by replaying the log, and so that there's a record of everything
+//! that was applied.
+//!
+//! The log is written in the same CSV format as the input, with each
+//! transaction's reference, if any, so that replaying skips the same ones.
+//! Rejected transactions are logged too: processing is deterministic, so
+//! they're rejected again on replay.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom};
+use std::num::NonZeroU64;
+use std::path::Path;
+
+use crate::clients::{Clients, ProcessError};
+use crate::store::StoreError;
+use crate::transaction::{
+    LoadOptions, Transaction, TransactionError, TransactionReader, TransactionWriter,
+};
+
+/// How often the log is synced to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Before applying every transaction. Nothing is lost on power failure,
+    /// but this is slow.
+    Always,
+    /// After every N transactions. Up to N transactions may be lost on power
+    /// failure, but none if only the process crashes.
+    Every(NonZeroU64),
+    /// Leave it to the operating system.
+    Never,
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum SyncPolicyParseError {
+    #[error("expected 'always', 'never', or 'every:N'")]
+    InvalidFormat,
+}
+
+impl std::str::FromStr for SyncPolicy {
+    type Err = SyncPolicyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "always" => Ok(SyncPolicy::Always),
+            None if s == "never" => Ok(SyncPolicy::Never),
+            Some(("every", n)) => n
+                .parse()
+                .map(SyncPolicy::Every)
+                .map_err(|_| SyncPolicyParseError::InvalidFormat),
+            _ => Err(SyncPolicyParseError::InvalidFormat),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WalError {
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("csv error: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("invalid transaction in log: {0}")]
+    Transaction(#[from] TransactionError),
+    #[error("store error: {0}")]
+    Store(#[from] StoreError),
+}
+
+pub struct Wal {
+    writer: TransactionWriter<File>,
+    sync: SyncPolicy,
+    // Transactions appended since the last sync.
+    unsynced: u64,
+}
+
+impl Wal {
+    /// Open the log at `path` for appending, creating it if it doesn't exist.
+    pub fn open(path: impl AsRef<Path>, sync: SyncPolicy) -> Result<Wal, WalError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let len = truncate_incomplete(&mut file)?;
+        let writer = if len == 0 {
+            TransactionWriter::new(file).with_references()
+        } else if has_references(&mut file)? {
+            TransactionWriter::append(file).with_references()
+        } else {
+            // Written before logs had references.
+            TransactionWriter::append(file)
+        };
+        Ok(Wal {
+            writer,
+            sync,
+            unsynced: 0,
+        })
+    }
+
+    /// Append a transaction, with its reference, if it has one; see
+    /// `Clients::set_reference`. Once this returns it's safe to apply it.
+    pub fn append(
+        &mut self,
+        transaction: &Transaction,
+        reference: Option<&str>,
+    ) -> Result<(), WalError> {
+        self.writer.write_with_reference(transaction, reference)?;
+        // Always hand the transaction to the operating system, so that it
+        // survives the process crashing.
+        self.writer.flush()?;
+        self.unsynced += 1;
+        let sync = match self.sync {
+            SyncPolicy::Always => true,
+            SyncPolicy::Every(n) => self.unsynced >= n.get(),
+            SyncPolicy::Never => false,
+        };
+        if sync {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// The length of the log so far, for replaying only what's appended after
+    /// this point with `replay_from`.
+    pub fn position(&self) -> Result<u64, WalError> {
+        // Every append is flushed to the file, so its length is up to date.
+        Ok(self.writer.get_ref().metadata()?.len())
+    }
+
+    /// Sync everything appended so far to disk.
+    pub fn sync(&mut self) -> Result<(), WalError> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+}
+
+/// Apply the transactions in the log at `path` to `clients`, returning how
+/// many there were. A missing log is treated as empty.
+pub fn replay(path: impl AsRef<Path>, clients: &mut Clients) -> Result<u64, WalError> {
+    replay_from(path, 0, clients)
+}
+
+/// Like `replay`, but only apply the transactions after `position`, as
+/// returned by `Wal::position`, e.g. those after a checkpoint.
+pub fn replay_from(
+    path: impl AsRef<Path>,
+    position: u64,
+    clients: &mut Clients,
+) -> Result<u64, WalError> {
+    let mut file = match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    truncate_incomplete(&mut file)?;
+    file.seek(SeekFrom::Start(0))?;
+    let mut reader = TransactionReader::new(std::io::BufReader::new(file), LoadOptions::default())?;
+    if position > 0 {
+        let mut start = csv::Position::new();
+        start.set_byte(position);
+        reader.seek(start)?;
+    }
+    let mut count = 0;
+    while let Some(transaction) = reader.next() {
+        let transaction = transaction?;
+        if let Some(reference) = reader.reference() {
+            clients.set_reference(reference);
+        }
+        match clients.process_transaction(transaction) {
+            Ok(_) | Err(ProcessError::Rejected(_) | ProcessError::Pending) => {}
+            Err(ProcessError::Store(e)) => return Err(e.into()),
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Whether the log in `file` has a reference column.
+fn has_references(file: &mut File) -> Result<bool, WalError> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut reader = csv::Reader::from_reader(file);
+    Ok(reader.headers()?.iter().any(|h| h.trim() == "reference"))
+}
+
+/// Drop anything after the last newline, which can only be a transaction that
+/// was being appended when the process crashed - and so was never applied.
+/// Returns the resulting length.
+fn truncate_incomplete(file: &mut File) -> Result<u64, WalError> {
+    let len = file.seek(SeekFrom::End(0))?;
+    // Records are short, so the last newline is near the end unless the file
+    // is corrupt. Scan backwards in blocks.
+    let mut end = len;
+    let mut block = [0; 4096];
+    while end > 0 {
+        let start = end.saturating_sub(block.len() as u64);
+        let block = &mut block[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(block)?;
+        if let Some(i) = block.iter().rposition(|&b| b == b'\n') {
+            end = start + i as u64 + 1;
+            break;
+        }
+        end = start;
+    }
+    if end < len {
+        file.set_len(end)?;
+    }
+    Ok(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{load_transactions, parse_record};
+    use std::io::Write;
+
+    const INPUT: &str = "type, client, tx, amount, currency
+deposit, 1, 1, 1.0, USD
+deposit, 1, 2, 2.0,
+withdrawal, 1, 3, 5.0,
+dispute, 1, 2
+";
+
+    fn summary(clients: &Clients) -> String {
+        let mut buf = Vec::new();
+        clients.write(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    fn log_transactions(path: &Path, input: &str) {
+        let mut wal = Wal::open(path, SyncPolicy::Always).unwrap();
+        for transaction in load_transactions(input.as_bytes()) {
+            wal.append(&transaction.unwrap(), None).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.csv");
+        // Appending over two runs, as if the first had been interrupted.
+        let (first, second) = INPUT.split_at(INPUT.find("withdrawal").unwrap());
+        log_transactions(&path, first);
+        log_transactions(&path, &format!("type, client, tx, amount\n{}", second));
+
+        let mut recovered = Clients::new();
+        assert_eq!(replay(&path, &mut recovered).unwrap(), 4);
+        let mut expected = Clients::new();
+        for transaction in load_transactions(INPUT.as_bytes()) {
+            let _ = expected.process_transaction(transaction.unwrap());
+        }
+        assert_eq!(summary(&recovered), summary(&expected));
+    }
+
+    #[test]
+    fn test_replay_incomplete() {
+        // A crash part way through appending leaves a partial last line, which
+        // must be ignored rather than e.g. read as a smaller amount.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.csv");
+        log_transactions(&path, "type, client, tx, amount\ndeposit, 1, 1, 1.0\n");
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"deposit,1,2,12")
+            .unwrap();
+
+        let mut recovered = Clients::new();
+        assert_eq!(replay(&path, &mut recovered).unwrap(), 1);
+        assert_eq!(
+            summary(&recovered),
+            "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n"
+        );
+
+        // Appending carries on from the last complete transaction.
+        log_transactions(&path, "type, client, tx, amount\ndeposit, 1, 2, 2.0\n");
+        let mut recovered = Clients::new();
+        assert_eq!(replay(&path, &mut recovered).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_replay_references() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.csv");
+        let mut wal = Wal::open(&path, SyncPolicy::Always).unwrap();
+        for (record, reference) in [
+            ("deposit,1,1,10.0", Some("a")),
+            // A partner's retry of the same deposit, skipped when it's applied.
+            ("deposit,1,2,10.0", Some("a")),
+            ("deposit,1,3,1.0", None),
+        ] {
+            wal.append(&parse_record(record).unwrap(), reference)
+                .unwrap();
+        }
+        drop(wal);
+
+        let mut recovered = Clients::new();
+        assert_eq!(replay(&path, &mut recovered).unwrap(), 3);
+        assert_eq!(recovered.duplicates(), 1);
+        assert_eq!(recovered.snapshot().unwrap().references.len(), 1);
+    }
+
+    #[test]
+    fn test_append_without_references() {
+        // A log written before logs had references is appended to as it is.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.csv");
+        std::fs::write(&path, "type,client,tx,amount,currency\ndeposit,1,1,1.0,\n").unwrap();
+        let mut wal = Wal::open(&path, SyncPolicy::Always).unwrap();
+        wal.append(&parse_record("deposit,1,2,2.0").unwrap(), Some("a"))
+            .unwrap();
+        drop(wal);
+
+        let mut recovered = Clients::new();
+        assert_eq!(replay(&path, &mut recovered).unwrap(), 2);
+        assert_eq!(
+            summary(&recovered),
+            "client,available,held,total,locked\n1,3.0000,0.0000,3.0000,false\n"
+        );
+    }
+
+    #[test]
+    fn test_replay_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut clients = Clients::new();
+        assert_eq!(replay(dir.path().join("wal.csv"), &mut clients).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_parse_sync_policy() {
+        assert_eq!("always".parse(), Ok(SyncPolicy::Always));
+        assert_eq!("never".parse(), Ok(SyncPolicy::Never));
+        assert_eq!(
+            "every:10".parse(),
+            Ok(SyncPolicy::Every(NonZeroU64::new(10).unwrap()))
+        );
+        assert_eq!(
+            "every:0".parse::<SyncPolicy>(),
+            Err(SyncPolicyParseError::InvalidFormat)
+        );
+        assert_eq!(
+            "sometimes".parse::<SyncPolicy>(),
+            Err(SyncPolicyParseError::InvalidFormat)
+        );
+    }
+}
@@ -0,0 +1,281 @@
+//! Notifying another service, e.g. fraud monitoring, of chargebacks and
+//! locked accounts as they happen.
+//!
+//! Each notification is POSTed as JSON to a configured URL. Delivery happens
+//! on a thread of its own, so a slow or unavailable endpoint doesn't hold up
+//! processing, and failed deliveries are retried with exponential backoff.
+//! If a secret is configured, each request carries an `X-Signature-256`
+//! header, `sha256=` followed by the hex HMAC-SHA256 of the body, so the
+//! receiver can check it came from us.
+
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, SystemTime};
+
+use crate::client::Client;
+use crate::clients::Listener;
+use crate::event::Event;
+use crate::metadata::{ClientInfo, ClientMetadata};
+use crate::transaction::ClientId;
+use crate::{Amount, Currency, SignedAmount, TransactionId};
+
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Sign each request with this key, if given.
+    pub secret: Option<Vec<u8>>,
+    /// How many times to retry a delivery that fails, before giving up on it.
+    pub retries: u32,
+    /// How long to wait before the first retry. Each later retry waits twice
+    /// as long as the one before.
+    pub backoff: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Notification {
+    /// "chargeback" or "account_locked".
+    pub event: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub currency: Option<Currency>,
+    /// The client's balances in `currency` afterwards.
+    pub available: SignedAmount,
+    pub held: Amount,
+    pub total: SignedAmount,
+    pub locked: bool,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The client's name, email, and tier, if client metadata was given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_info: Option<ClientInfo>,
+}
+
+enum Message {
+    Notify(Notification),
+    Stop,
+}
+
+/// A handle to the thread delivering notifications.
+pub struct Webhook {
+    sender: mpsc::Sender<Message>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+impl Notification {
+    /// The notification for `event`, if it's one that's notified.
+    pub fn new(
+        tenant: Option<&str>,
+        client_id: ClientId,
+        currency: Option<Currency>,
+        event: &Event,
+        client: &Client,
+    ) -> Option<Self> {
+        let (name, tx) = match *event {
+            Event::ChargedBack { transaction_id } => ("chargeback", transaction_id),
+            Event::AccountLocked { transaction_id } => ("account_locked", transaction_id),
+            _ => return None,
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Some(Self {
+            event: name,
+            tenant: tenant.map(str::to_string),
+            client: client_id,
+            tx,
+            currency,
+            available: client.available(currency),
+            held: client.held(currency),
+            total: client.total(currency),
+            locked: client.locked(),
+            timestamp,
+            client_info: None,
+        })
+    }
+}
+
+impl Webhook {
+    /// Start the thread delivering notifications to `config.url`.
+    pub fn start(config: WebhookConfig) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let thread = std::thread::spawn(move || {
+            let agent = ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(10))
+                .build();
+            for message in receiver {
+                let Message::Notify(notification) = message else {
+                    break;
+                };
+                deliver(&agent, &config, &notification);
+            }
+        });
+        Self { sender, thread }
+    }
+
+    /// A listener sending a notification for each chargeback and lock,
+    /// labelled with `tenant` if given, and with the client's details from
+    /// `metadata` if given.
+    pub fn listener(
+        &self,
+        tenant: Option<&str>,
+        metadata: Option<Arc<ClientMetadata>>,
+    ) -> Listener {
+        let sender = self.sender.clone();
+        let tenant = tenant.map(str::to_string);
+        Box::new(move |client_id, currency, event, client| {
+            if let Some(mut notification) =
+                Notification::new(tenant.as_deref(), client_id, currency, event, client)
+            {
+                notification.client_info = metadata.as_ref().map(|m| m.get(client_id));
+                // Only fails once `close` has been called, by which time
+                // nothing more is being processed.
+                let _ = sender.send(Message::Notify(notification));
+            }
+        })
+    }
+
+    /// Wait for every notification sent so far to be delivered, or given up
+    /// on.
+    pub fn close(self) {
+        let _ = self.sender.send(Message::Stop);
+        if self.thread.join().is_err() {
+            tracing::error!("webhook thread panicked");
+        }
+    }
+}
+
+fn deliver(agent: &ureq::Agent, config: &WebhookConfig, notification: &Notification) {
+    let body = serde_json::to_vec(notification).expect("notifications serialize");
+    let mut backoff = config.backoff;
+    for attempt in 0..=config.retries {
+        if attempt > 0 {
+            std::thread::sleep(backoff);
+            backoff *= 2;
+        }
+        match post(agent, config, &body) {
+            Ok(()) => return,
+            Err(e) => tracing::warn!(
+                attempt = attempt + 1,
+                client = %notification.client,
+                tx = %notification.tx,
+                "failed to deliver webhook: {}",
+                e
+            ),
+        }
+    }
+    tracing::error!(
+        client = %notification.client,
+        tx = %notification.tx,
+        "giving up on webhook for {}",
+        notification.event
+    );
+}
+
+fn post(agent: &ureq::Agent, config: &WebhookConfig, body: &[u8]) -> Result<(), Box<ureq::Error>> {
+    let mut request = agent
+        .post(&config.url)
+        .set("Content-Type", "application/json");
+    if let Some(secret) = &config.secret {
+        request = request.set("X-Signature-256", &signature(secret, body));
+    }
+    request.send_bytes(body).map_err(Box::new)?;
+    Ok(())
+}
+
+/// The value of the signature header for `body`.
+fn signature(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(body);
+    let hex: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("sha256={}", hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::Clients;
+    use crate::transaction::parse_record;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_signature() {
+        // From RFC 4231, test case 2.
+        assert_eq!(
+            signature(b"Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_deliver() {
+        let server = Arc::new(tiny_http::Server::http("127.0.0.1:0").unwrap());
+        let url = format!("http://{}/hook", server.server_addr().to_ip().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let thread = std::thread::spawn({
+            let server = server.clone();
+            let received = received.clone();
+            move || {
+                for (i, mut request) in server.incoming_requests().enumerate() {
+                    // Fail the first attempt, to check it's retried.
+                    let status = if i == 0 { 503 } else { 204 };
+                    let mut body = String::new();
+                    request.as_reader().read_to_string(&mut body).unwrap();
+                    let signature = request
+                        .headers()
+                        .iter()
+                        .find(|h| h.field.equiv("X-Signature-256"))
+                        .map(|h| h.value.to_string());
+                    received.lock().unwrap().push((body, signature));
+                    request.respond(tiny_http::Response::empty(status)).unwrap();
+                }
+            }
+        });
+
+        let webhook = Webhook::start(WebhookConfig {
+            url,
+            secret: Some(b"secret".to_vec()),
+            retries: 2,
+            backoff: Duration::from_millis(1),
+        });
+        let mut clients = Clients::new();
+        let metadata = ClientMetadata::read("client,name,tier\n7,Acme Ltd,gold\n".as_bytes());
+        clients.add_listener(webhook.listener(Some("acme"), Some(Arc::new(metadata.unwrap()))));
+        for record in ["deposit,7,1,10.0", "dispute,7,1", "chargeback,7,1"] {
+            clients
+                .process_transaction(parse_record(record).unwrap())
+                .unwrap();
+        }
+        webhook.close();
+        server.unblock();
+        thread.join().unwrap();
+
+        let received = received.lock().unwrap();
+        // The chargeback twice, as the first attempt failed, then the lock.
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[0], received[1]);
+        for (body, header) in received.iter() {
+            assert_eq!(header, &Some(signature(b"secret", body.as_bytes())));
+        }
+        let chargeback: serde_json::Value = serde_json::from_str(&received[1].0).unwrap();
+        assert_eq!(chargeback["event"], "chargeback");
+        assert_eq!(chargeback["tenant"], "acme");
+        assert_eq!(chargeback["client"], 7);
+        assert_eq!(chargeback["tx"], 1);
+        assert_eq!(chargeback["total"], "0.0000");
+        assert_eq!(chargeback["client_info"]["name"], "Acme Ltd");
+        assert_eq!(chargeback["client_info"]["tier"], "gold");
+        assert!(chargeback["timestamp"].as_u64().unwrap() > 0);
+        let lock: serde_json::Value = serde_json::from_str(&received[2].0).unwrap();
+        assert_eq!(lock["event"], "account_locked");
+        assert_eq!(lock["locked"], true);
+    }
+}
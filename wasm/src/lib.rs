@@ -0,0 +1,51 @@
+//! The engine, built for the browser with `wasm-pack build --target web`, so
+//! that analyst tools can run exactly the same logic as production.
+
+use wasm_bindgen::prelude::*;
+
+use transactions::clients::Clients;
+use transactions::transaction::{LoadOptions, TransactionError, TransactionReader};
+
+/// Process a CSV file of transactions, returning the summary as CSV, exactly
+/// as `transactions process` would write it. Invalid records and rejected
+/// transactions are skipped, as by default on the command line.
+#[wasm_bindgen(js_name = processCsv)]
+pub fn process_csv(input: &[u8]) -> Result<String, JsError> {
+    summarize(input).map_err(|e| JsError::new(&e.to_string()))
+}
+
+fn summarize(input: &[u8]) -> Result<String, TransactionError> {
+    let reader = TransactionReader::new(input, LoadOptions::default())?;
+    let mut clients = Clients::new();
+    for transaction in reader.flatten() {
+        // Rejections are expected, and leave the clients unchanged.
+        let _ = clients.process_transaction(transaction);
+    }
+    let mut summary = Vec::new();
+    clients
+        .write(&mut summary)
+        .expect("writing to memory doesn't fail");
+    Ok(String::from_utf8(summary).expect("summaries are UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     withdrawal,1,2,20.0\n\
+                     deposit,2,3,x\n\
+                     deposit,2,4,1.5\n";
+        assert_eq!(
+            summarize(input.as_bytes()).unwrap(),
+            "client,available,held,total,locked\n\
+             1,10.0000,0.0000,10.0000,false\n\
+             2,1.5000,0.0000,1.5000,false\n"
+        );
+        // A header too long to be real.
+        assert!(summarize("x".repeat(100_000).as_bytes()).is_err());
+    }
+}